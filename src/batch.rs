@@ -0,0 +1,380 @@
+use crate::catalog::Affinity;
+use crate::Value;
+
+// A result set laid out by column the way Apache Arrow lays out memory, for handing a query's
+// rows to columnar tools like polars or datafusion:
+//
+//     let batch = connection.query("SELECT name, weight FROM apples")?.record_batch();
+//     let weights = batch.column_by_name("WEIGHT").unwrap();
+//
+// Cargo.toml can't take the arrow crate as a dependency, so this doesn't make arrow's own types,
+// but each column's buffers are the ones an arrow array of its type is made from, and can be
+// moved into one without being rearranged. For example, with arrow:
+//
+//     let data = ArrayData::builder(DataType::Int64)
+//         .len(column.len())
+//         .add_buffer(Buffer::from_vec(column.values().to_vec()))
+//         .null_bit_buffer(Some(Buffer::from_vec(column.validity().to_vec())))
+//         .build()?;
+//
+// A Utf8 or Binary column's offsets go in as a buffer before its values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordBatch {
+    pub(crate) fields: Vec<Field>,
+    pub(crate) columns: Vec<Array>,
+    pub(crate) num_rows: usize,
+}
+
+impl RecordBatch {
+    // Lays out rows of values, all as long as `names`. A column's type is the one that fits all of
+    // its values, as `ArrowType::infer` decides, with a column of nothing but NULLs taking its type
+    // from the affinity given for it, if there is one.
+    pub(crate) fn new(
+        names: &[String],
+        affinities: &[Option<Affinity>],
+        rows: &[&[Value]],
+    ) -> Self {
+        let mut fields = Vec::with_capacity(names.len());
+        let mut columns = Vec::with_capacity(names.len());
+
+        for (index, name) in names.iter().enumerate() {
+            let values = rows.iter().map(|row| &row[index]).collect::<Vec<_>>();
+            let data_type = ArrowType::infer(&values, affinities.get(index).copied().flatten());
+            fields.push(Field {
+                name: name.clone(),
+                data_type,
+            });
+            columns.push(Array::new(data_type, &values));
+        }
+
+        RecordBatch {
+            fields,
+            columns,
+            num_rows: rows.len(),
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn schema(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn column(&self, index: usize) -> Option<&Array> {
+        self.columns.get(index)
+    }
+
+    pub fn column_by_name(&self, name: &str) -> Option<&Array> {
+        let index = self
+            .fields
+            .iter()
+            .position(|field| field.name.eq_ignore_ascii_case(name))?;
+        self.columns.get(index)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub(crate) name: String,
+    pub(crate) data_type: ArrowType,
+}
+
+impl Field {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data_type(&self) -> ArrowType {
+        self.data_type
+    }
+
+    // Every column can hold NULLs, since nothing stops a query from returning one.
+    pub fn nullable(&self) -> bool {
+        true
+    }
+}
+
+// The arrow types that sqlite's values fit into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowType {
+    Int64,
+    Float64,
+    Utf8,
+    Binary,
+}
+
+impl ArrowType {
+    // The type that holds every one of the values: Int64 when they are all integers, Float64 when
+    // they are all numbers, Utf8 when they are text, or text and numbers, and otherwise Binary,
+    // which keeps numbers and text as their text. NULLs fit any type, so they don't count, and
+    // when there is nothing else the affinity decides, with no affinity meaning Utf8.
+    pub(crate) fn infer(values: &[&Value], affinity: Option<Affinity>) -> ArrowType {
+        let mut data_type = None;
+        for value in values {
+            let next = match value {
+                Value::Null => continue,
+                Value::Int(_) => ArrowType::Int64,
+                Value::Real(_) => ArrowType::Float64,
+                Value::Text(_) => ArrowType::Utf8,
+                Value::Blob(_) => ArrowType::Binary,
+            };
+            data_type = Some(match (data_type, next) {
+                (None, next) => next,
+                (Some(data_type), next) if data_type == next => data_type,
+                (
+                    Some(ArrowType::Int64 | ArrowType::Float64),
+                    ArrowType::Int64 | ArrowType::Float64,
+                ) => ArrowType::Float64,
+                (
+                    Some(ArrowType::Int64 | ArrowType::Float64 | ArrowType::Utf8),
+                    ArrowType::Utf8,
+                ) => ArrowType::Utf8,
+                (Some(ArrowType::Utf8), ArrowType::Int64 | ArrowType::Float64) => ArrowType::Utf8,
+                _ => ArrowType::Binary,
+            });
+        }
+
+        data_type.unwrap_or(match affinity {
+            Some(Affinity::Integer) => ArrowType::Int64,
+            Some(Affinity::Real | Affinity::Numeric) => ArrowType::Float64,
+            Some(Affinity::Blob) => ArrowType::Binary,
+            Some(Affinity::Text) | None => ArrowType::Utf8,
+        })
+    }
+}
+
+// One column, as arrow's buffers:
+//
+// - validity, a bitmap with a bit for every value, lowest bit first, set when it isn't NULL
+// - offsets, for Utf8 and Binary only, where value i runs from offsets[i] to offsets[i + 1]
+// - values, the data itself: eight little-endian bytes for each Int64 or Float64, NULLs included
+//   as zeros, or the bytes of every string one after another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    data_type: ArrowType,
+    len: usize,
+    null_count: usize,
+    validity: Vec<u8>,
+    offsets: Vec<i32>,
+    values: Vec<u8>,
+}
+
+impl Array {
+    fn new(data_type: ArrowType, values: &[&Value]) -> Self {
+        let mut array = Array {
+            data_type,
+            len: values.len(),
+            null_count: 0,
+            validity: vec![0; values.len().div_ceil(8)],
+            offsets: Vec::new(),
+            values: Vec::new(),
+        };
+        if matches!(data_type, ArrowType::Utf8 | ArrowType::Binary) {
+            array.offsets.push(0);
+        }
+
+        for (index, value) in values.iter().enumerate() {
+            match value {
+                Value::Null => array.null_count += 1,
+                _ => array.validity[index / 8] |= 1 << (index % 8),
+            }
+
+            match (data_type, value) {
+                (ArrowType::Int64, Value::Int(n)) => {
+                    array.values.extend_from_slice(&n.to_le_bytes())
+                }
+                (ArrowType::Float64, Value::Int(n)) => {
+                    array.values.extend_from_slice(&(*n as f64).to_le_bytes())
+                }
+                (ArrowType::Float64, Value::Real(r)) => {
+                    array.values.extend_from_slice(&r.to_le_bytes())
+                }
+                (ArrowType::Int64 | ArrowType::Float64, _) => {
+                    array.values.extend_from_slice(&[0; 8])
+                }
+                (_, Value::Null) => {}
+                (_, Value::Blob(bytes)) => array.values.extend_from_slice(bytes),
+                (_, value) => array.values.extend_from_slice(value.to_string().as_bytes()),
+            }
+            if !array.offsets.is_empty() {
+                array.offsets.push(array.values.len() as i32);
+            }
+        }
+        array
+    }
+
+    pub fn data_type(&self) -> ArrowType {
+        self.data_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.null_count
+    }
+
+    pub fn is_null(&self, index: usize) -> bool {
+        self.validity[index / 8] & (1 << (index % 8)) == 0
+    }
+
+    pub fn validity(&self) -> &[u8] {
+        &self.validity
+    }
+
+    // Empty for Int64 and Float64, which have no offsets.
+    pub fn offsets(&self) -> &[i32] {
+        &self.offsets
+    }
+
+    pub fn values(&self) -> &[u8] {
+        &self.values
+    }
+
+    // The value at `index` as an Int64 or Float64, or None when it is NULL or the column holds
+    // something else.
+    pub fn i64(&self, index: usize) -> Option<i64> {
+        (self.data_type == ArrowType::Int64 && !self.is_null(index))
+            .then(|| i64::from_le_bytes(self.fixed(index)))
+    }
+
+    pub fn f64(&self, index: usize) -> Option<f64> {
+        (self.data_type == ArrowType::Float64 && !self.is_null(index))
+            .then(|| f64::from_le_bytes(self.fixed(index)))
+    }
+
+    // The value at `index` as bytes, from a Utf8 or Binary column, or None when it is NULL or
+    // the column holds numbers.
+    pub fn bytes(&self, index: usize) -> Option<&[u8]> {
+        if self.offsets.is_empty() || self.is_null(index) {
+            return None;
+        }
+        let start = self.offsets[index] as usize;
+        let end = self.offsets[index + 1] as usize;
+        Some(&self.values[start..end])
+    }
+
+    pub fn str(&self, index: usize) -> Option<&str> {
+        match self.data_type {
+            ArrowType::Utf8 => std::str::from_utf8(self.bytes(index)?).ok(),
+            _ => None,
+        }
+    }
+
+    fn fixed(&self, index: usize) -> [u8; 8] {
+        self.values[index * 8..index * 8 + 8].try_into().unwrap()
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // The first twelve people, laid out by column: each column has the type its values fit, the
+    // values in arrow's buffers, and a validity bit clear for each NULL.
+    #[test]
+    fn lays_out_a_table_by_column() {
+        let mut connection =
+            Connection::open_bytes(include_bytes!("../tests/fixtures/people.db").to_vec()).unwrap();
+        let batch = connection
+            .query("SELECT id, name, age, score, x, x'00ff' AS b, NULL AS nothing FROM people WHERE id <= 12")
+            .unwrap()
+            .record_batch();
+
+        assert_eq!((batch.num_rows(), batch.num_columns()), (12, 7));
+        let types = batch
+            .schema()
+            .iter()
+            .map(Field::data_type)
+            .collect::<Vec<_>>();
+        use ArrowType::*;
+        assert_eq!(types, [Int64, Utf8, Int64, Float64, Utf8, Binary, Utf8]);
+
+        let id = batch.column_by_name("ID").unwrap();
+        assert_eq!(
+            (id.len(), id.null_count(), id.validity()),
+            (12, 0, &[0xff, 0x0f][..])
+        );
+        assert!(id.offsets().is_empty());
+        let ids = (1..=12i64).flat_map(i64::to_le_bytes).collect::<Vec<_>>();
+        assert_eq!(id.values(), ids);
+
+        // Row 11's age is NULL, and has eight zero bytes in the values.
+        let age = batch.column_by_name("age").unwrap();
+        assert_eq!((age.null_count(), age.validity()), (1, &[0xff, 0x0b][..]));
+        assert_eq!(
+            (age.i64(0), age.i64(9), age.i64(10)),
+            (Some(27), Some(10), None)
+        );
+        assert_eq!(age.values()[80..88], [0; 8]);
+        assert_eq!(age.values().len(), 96);
+
+        // Scores are as sqlite3 prints them with %!.17g, and 0.0 is stored as an integer.
+        let score = batch.column(3).unwrap();
+        assert_eq!(
+            (score.f64(0), score.f64(4), score.f64(9), score.i64(0)),
+            (Some(0.30000000000000004), Some(1.5), Some(0.0), None)
+        );
+
+        // The x of rows 5 and 10 is NULL, and takes up no bytes.
+        let x = batch.column(4).unwrap();
+        assert_eq!((x.null_count(), x.validity()), (2, &[0xef, 0x0d][..]));
+        assert_eq!(x.offsets(), [0, 2, 4, 6, 8, 8, 10, 12, 14, 16, 16, 18, 20]);
+        assert_eq!(x.values(), b"x1x2x3x0x2x3x0x1x3x0");
+        assert_eq!(
+            (x.str(0), x.str(4), x.str(11)),
+            (Some("x1"), None, Some("x0"))
+        );
+
+        let b = batch.column(5).unwrap();
+        assert_eq!((b.bytes(11), b.str(11)), (Some(&[0, 0xff][..]), None));
+        assert_eq!(b.offsets()[12], 24);
+
+        let nothing = batch.column(6).unwrap();
+        assert_eq!(
+            (nothing.null_count(), nothing.validity()),
+            (12, &[0, 0][..])
+        );
+        assert_eq!(nothing.offsets(), [0; 13]);
+    }
+
+    // A column takes the type that holds all of its values, and one with nothing but NULLs takes
+    // its type from its affinity.
+    #[test]
+    fn infers_types() {
+        let (int, real) = (Value::Int(1), Value::Real(1.5));
+        let (text, blob) = (Value::Text("a".to_string()), Value::Blob(vec![1]));
+        let null = Value::Null;
+        for (values, affinity, expected) in [
+            (vec![&int, &null], None, ArrowType::Int64),
+            (vec![&int, &real], None, ArrowType::Float64),
+            (vec![&real, &text], None, ArrowType::Utf8),
+            (vec![&text, &int], None, ArrowType::Utf8),
+            (vec![&int, &blob], None, ArrowType::Binary),
+            (vec![&null], None, ArrowType::Utf8),
+            (vec![&null], Some(Affinity::Integer), ArrowType::Int64),
+            (vec![&null], Some(Affinity::Numeric), ArrowType::Float64),
+            (vec![], Some(Affinity::Blob), ArrowType::Binary),
+        ] {
+            assert_eq!(
+                ArrowType::infer(&values, affinity),
+                expected,
+                "{:?}",
+                values
+            );
+        }
+    }
+}
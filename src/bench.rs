@@ -0,0 +1,245 @@
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+
+use crate::btree;
+use crate::catalog::ObjectType;
+use crate::error::Result;
+use crate::output::quote_identifier;
+use crate::sql_engine::{Datum, Plan, ResultSink, SqlEngine};
+use crate::{like, Db, Rows, Value};
+
+// How each benchmark is run: untimed a number of times first, to warm up the page cache and the
+// CPU, then timed a number of times. A lookup benchmark looks up this many keys in each run.
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    pub warmups: usize,
+    pub repetitions: usize,
+    pub lookups: usize,
+    // Only benchmark the table with this name.
+    pub table: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            warmups: 1,
+            repetitions: 5,
+            lookups: 100,
+            table: None,
+        }
+    }
+}
+
+// Times the ways there are of reading a table, for each ordinary table in the database:
+//
+// - a full scan, with `SELECT * FROM t`
+// - looking up single rows by rowid
+// - a seek on each index, by the column it is sorted by first, with `SELECT * FROM t WHERE a = ?`
+//
+// The scans and seeks run as SQL, so they measure the planner's choices along with the b-tree and
+// the pager. WHERE can't look up a rowid yet, so rowid lookups go straight to the b-tree. The keys
+// looked up are spread evenly through the table, so every part of the tree is visited.
+pub(crate) fn run(db: &Db, options: &Options) -> Result<Vec<Measurement>> {
+    let mut measurements = Vec::new();
+
+    for table in db.catalog.objects().iter().filter(|record| {
+        record.object_type == ObjectType::Table
+            && record.module.is_none()
+            && !record.without_rowid
+            && !like("sqlite_%", &record.name)
+            && options
+                .table
+                .as_ref()
+                .is_none_or(|name| record.name.eq_ignore_ascii_case(name))
+    }) {
+        let name = quote_identifier(&table.name);
+
+        let scan = Plan::prepare(&format!("SELECT * FROM {}", name))?;
+        measurements.push(measure(
+            db,
+            format!("scan {}", table.name),
+            options,
+            || run_plan(db, &scan, &[]),
+        )?);
+
+        let rowids = sample(db, &format!("SELECT ROWID FROM {}", name), options.lookups)?
+            .into_iter()
+            .filter_map(|rowid| match rowid {
+                Value::Int(rowid) => u64::try_from(rowid).ok(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !rowids.is_empty() {
+            measurements.push(measure(
+                db,
+                format!("rowid lookup {} ({})", table.name, keys(rowids.len())),
+                options,
+                || {
+                    let mut rows = 0;
+                    for rowid in &rowids {
+                        let found = btree::seek_rowids(&db.pager, table.root_page, &[*rowid])?;
+                        rows += found.len() as u64;
+                    }
+                    Ok(rows)
+                },
+            )?);
+        }
+
+        // Indexes sorted by the same column first are all planned the same way.
+        let mut seen = Vec::new();
+        let indexes = db.catalog.table(&table.name).map(|table| table.indexes());
+        for index in indexes.unwrap_or_default() {
+            let Some(column) = index.columns.first().map(|column| &column.name) else {
+                continue;
+            };
+            if seen.contains(column) {
+                continue;
+            }
+            seen.push(column.clone());
+
+            let column = quote_identifier(column);
+            let values = sample(
+                db,
+                &format!("SELECT {} FROM {}", column, name),
+                options.lookups,
+            )?;
+            if values.is_empty() {
+                continue;
+            }
+            let seek = Plan::prepare(&format!("SELECT * FROM {} WHERE {} = ?", name, column))?;
+            measurements.push(measure(
+                db,
+                format!("index seek {} ({})", index.name, keys(values.len())),
+                options,
+                || {
+                    let mut rows = 0;
+                    for value in &values {
+                        rows += run_plan(db, &seek, std::slice::from_ref(value))?;
+                    }
+                    Ok(rows)
+                },
+            )?);
+        }
+    }
+
+    Ok(measurements)
+}
+
+// What the timed runs of one benchmark took, and what they read between them.
+#[derive(Debug, Clone)]
+pub(crate) struct Measurement {
+    name: String,
+    times: Vec<Duration>,
+    rows: u64,
+    pages: u64,
+}
+
+impl Display for Measurement {
+    // Rates are over all of the timed runs together.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.times.iter().sum::<Duration>();
+        let mean = total / self.times.len().max(1) as u32;
+        let best = self.times.iter().min().copied().unwrap_or_default();
+        let per_second = |n: u64| match total.as_secs_f64() {
+            0.0 => 0.0,
+            seconds => n as f64 / seconds,
+        };
+        write!(
+            f,
+            "{}: mean {}, best {}, {:.0} rows/s, {:.0} pages/s",
+            self.name,
+            format_duration(mean),
+            format_duration(best),
+            per_second(self.rows),
+            per_second(self.pages)
+        )
+    }
+}
+
+fn keys(count: usize) -> String {
+    match count {
+        1 => "1 key".to_string(),
+        count => format!("{} keys", count),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+// Runs a benchmark through its warmups and then its timed runs, each of which returns how many
+// rows it read.
+fn measure(
+    db: &Db,
+    name: String,
+    options: &Options,
+    mut run: impl FnMut() -> Result<u64>,
+) -> Result<Measurement> {
+    for _ in 0..options.warmups {
+        run()?;
+    }
+
+    let before = db.pager.stats();
+    let mut times = Vec::with_capacity(options.repetitions);
+    let mut rows = 0;
+    for _ in 0..options.repetitions {
+        let start = Instant::now();
+        rows += run()?;
+        times.push(start.elapsed());
+    }
+
+    Ok(Measurement {
+        name,
+        times,
+        rows,
+        pages: db.pager.stats().since(&before).pages_read,
+    })
+}
+
+fn run_plan(db: &Db, plan: &Plan, params: &[Value]) -> Result<u64> {
+    let mut sink = Discard::default();
+    SqlEngine::new(&mut sink).run(plan, params, db)?;
+    Ok(sink.rows)
+}
+
+// Up to `count` of the values of a one column query, spread evenly through its results, leaving
+// out NULLs, which no lookup can find.
+fn sample(db: &Db, sql: &str, count: usize) -> Result<Vec<Value>> {
+    let mut rows = Rows::default();
+    SqlEngine::new(&mut rows).execute(sql, db)?;
+    let values = rows
+        .rows
+        .iter()
+        .map(|row| row.values()[0].clone())
+        .filter(|value| !matches!(value, Value::Null))
+        .collect::<Vec<_>>();
+
+    if values.len() <= count {
+        return Ok(values);
+    }
+    Ok((0..count)
+        .map(|i| values[i * values.len() / count].clone())
+        .collect())
+}
+
+// Evaluates every value of every row, as printing them would, and then throws them away.
+#[derive(Default)]
+struct Discard {
+    rows: u64,
+}
+
+impl ResultSink for Discard {
+    fn start(&mut self, _columns: Vec<String>) -> Result<()> {
+        Ok(())
+    }
+
+    fn row<'a>(&mut self, values: impl Iterator<Item = Datum<'a>>) -> Result<()> {
+        values.for_each(drop);
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
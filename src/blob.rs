@@ -0,0 +1,286 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::{Error, Result};
+use crate::pager::{Pager, ReadLock};
+use crate::{btree, read_page, ByteReader, DataType, Db};
+
+// A BLOB (or text) value in one column of one row, read a piece at a time straight out of the
+// database, without the whole of it ever being in memory at once:
+//
+//     let mut blob = connection.open_blob("images", "data", 42)?;
+//     let mut file = File::create("42.png")?;
+//     io::copy(&mut blob, &mut file)?;
+//
+// The row is found by its rowid, and only the pages holding the part of the value that is read
+// are loaded, which for a large value are the overflow pages it spills onto. A handle keeps the
+// database locked against writers in other processes for as long as it is open, so the value
+// can't change under it.
+pub struct Blob<'a> {
+    payload: Payload<'a>,
+    // Where the value starts in the record, and how long it is.
+    start: usize,
+    len: usize,
+    // Where the next read through Read starts, from the start of the value.
+    position: u64,
+    _lock: ReadLock<'a>,
+}
+
+impl<'a> Blob<'a> {
+    // Fails, with the message sqlite3_blob_open gives, when there is no such row, or the value in
+    // the column isn't a BLOB or text.
+    pub(crate) fn open(db: &'a Db, table: &str, column: &str, rowid: i64) -> Result<Self> {
+        let record = db.get_table(table)?;
+        if record.module.is_some() {
+            return Err(Error::CantOpenBlob("virtual table".to_string()));
+        }
+        if record.without_rowid {
+            return Err(Error::CantOpenBlob("table without rowid".to_string()));
+        }
+        let index = record.get_column_index(column)?;
+
+        let lock = db.pager.read_lock().map_err(Error::locking)?;
+        let usable = (db.header.page_size - db.header.reserved_space as u32) as usize;
+        let mut payload = btree::find_payload(&db.pager, record.root_page, rowid, usable)?
+            .ok_or(Error::NoSuchRowid(rowid))?;
+
+        // The record's header is the size of the header, then the serial type of each column, all
+        // varints. A column past the end of it was added after the row was written, and has its
+        // default, which is never a BLOB.
+        let page = payload.page;
+        let corrupt = |err| Error::corrupt(page, 0, err);
+        let mut size = [0; 9];
+        let read = payload.read_at(0, &mut size)?;
+        let (header_size, _) = (&size[..read]).read_varint().map_err(corrupt)?;
+        let mut header = vec![0; header_size.min(payload.len() as u64) as usize];
+        payload.read_at(0, &mut header)?;
+        let mut types = &header[..];
+        types.read_varint().map_err(corrupt)?;
+
+        let mut start = header.len();
+        let mut data_type = DataType::Null;
+        for i in 0..=index {
+            if types.is_empty() {
+                break;
+            }
+            let (serial_type, _) = types.read_varint().map_err(corrupt)?;
+            let column_type = DataType::try_from(serial_type).map_err(corrupt)?;
+            match i == index {
+                true => data_type = column_type,
+                false => start += column_type.size(),
+            }
+        }
+
+        let len = match data_type {
+            DataType::Blob(len) | DataType::Text(len) => len,
+            DataType::Null => return Err(Error::CantOpenBlob("value of type null".to_string())),
+            DataType::Float => return Err(Error::CantOpenBlob("value of type real".to_string())),
+            _ => return Err(Error::CantOpenBlob("value of type integer".to_string())),
+        };
+        if start + len > payload.len() {
+            return Err(corrupt(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "value runs past the end of the record",
+            )));
+        }
+
+        Ok(Self {
+            payload,
+            start,
+            len,
+            position: 0,
+            _lock: lock,
+        })
+    }
+
+    // The size of the value in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Reads from `offset` bytes into the value, without moving the position Read and Seek use.
+    // Returns how many bytes were read, which is fewer than asked for only at the end of the value.
+    pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let n = buf.len().min(self.len.saturating_sub(offset));
+        self.payload.read_at(self.start + offset, &mut buf[..n])
+    }
+}
+
+impl Read for Blob<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = usize::try_from(self.position).unwrap_or(usize::MAX);
+        let n = self.read_at(offset, buf).map_err(io::Error::other)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Blob<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.len as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the blob",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+// The payload of a cell, the record it holds. Only as much of a large payload as fits is kept on
+// the b-tree page, and the rest goes in a chain of overflow pages, each of which starts with the
+// number of the next and is full of the payload after that. The chain is followed only as far as
+// a read needs, and the pages found on the way are remembered, so that reading back over them
+// doesn't follow it again.
+pub(crate) struct Payload<'a> {
+    pager: &'a Pager,
+    // The b-tree page the cell is on.
+    page: u32,
+    size: usize,
+    local: Vec<u8>,
+    // The overflow pages found so far, from the one the cell points to.
+    overflow: Vec<u32>,
+    usable: usize,
+}
+
+impl<'a> Payload<'a> {
+    pub(crate) fn new(
+        pager: &'a Pager,
+        page: u32,
+        size: usize,
+        local: Vec<u8>,
+        overflow: Option<u32>,
+        usable: usize,
+    ) -> Self {
+        Self {
+            pager,
+            page,
+            size,
+            local,
+            overflow: overflow.into_iter().collect(),
+            usable,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.size
+    }
+
+    // Reads from `offset` bytes into the payload, up to its end.
+    pub(crate) fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let end = self.size.min(offset.saturating_add(buf.len()));
+        let mut position = offset;
+        while position < end {
+            let n = match position.checked_sub(self.local.len()) {
+                None => {
+                    let n = end.min(self.local.len()) - position;
+                    buf[position - offset..][..n].copy_from_slice(&self.local[position..][..n]);
+                    n
+                }
+                Some(past) => {
+                    let capacity = self.usable - 4;
+                    let page_number = self.overflow_page(past / capacity)?;
+                    let page = read_page(self.pager, page_number)?;
+                    let within = 4 + past % capacity;
+                    let n = (end - position).min(capacity - past % capacity);
+                    let bytes = page.get(within..within + n).ok_or_else(|| Error::Corrupt {
+                        page: page_number,
+                        message: "overflow page is too short".to_string(),
+                    })?;
+                    buf[position - offset..][..n].copy_from_slice(bytes);
+                    n
+                }
+            };
+            position += n;
+        }
+        Ok(position.saturating_sub(offset))
+    }
+
+    // The number of the overflow page holding the `index`th piece of the payload after the part on
+    // the b-tree page.
+    fn overflow_page(&mut self, index: usize) -> Result<u32> {
+        while self.overflow.len() <= index {
+            let Some(&last) = self.overflow.last() else {
+                return Err(self.chain_too_short());
+            };
+            let next = u32::from_be_bytes(read_page(self.pager, last)?[..4].try_into().unwrap());
+            if next == 0 {
+                return Err(self.chain_too_short());
+            }
+            self.overflow.push(next);
+        }
+        Ok(self.overflow[index])
+    }
+
+    fn chain_too_short(&self) -> Error {
+        Error::Corrupt {
+            page: self.page,
+            message: format!(
+                "overflow chain ends before the end of a {} byte payload",
+                self.size
+            ),
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // Row 1 of big in this fixture has a 20000 byte blob and a 5001 character text, which spill
+    // over many 1024 byte overflow pages.
+    #[allow(dead_code)]
+    const OVERFLOW: &[u8] = include_bytes!("../tests/fixtures/overflow.db");
+
+    // The blob the fixture's row 1 was made with.
+    #[allow(dead_code)]
+    fn blob() -> Vec<u8> {
+        (0..20000).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    // Reads at an offset pick up the bytes there, across the ends of overflow pages, and stop at
+    // the end of the value. Read and Seek see the same bytes.
+    #[test]
+    fn reads_across_overflow_pages() {
+        let mut connection = Connection::open_bytes(OVERFLOW).unwrap();
+        let expected = blob();
+        let mut blob = connection.open_blob("big", "b", 1).unwrap();
+        assert_eq!(blob.len(), 20000);
+
+        for (offset, len) in [(0, 10), (900, 300), (5000, 3000), (19990, 100), (20000, 5)] {
+            let mut buf = vec![0; len];
+            let n = blob.read_at(offset, &mut buf).unwrap();
+            let end = (offset + len).min(expected.len());
+            assert_eq!(n, end - offset, "{} + {}", offset, len);
+            assert_eq!(buf[..n], expected[offset..end], "{} + {}", offset, len);
+        }
+
+        let mut tail = Vec::new();
+        blob.seek(SeekFrom::End(-1500)).unwrap();
+        blob.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, expected[18500..]);
+
+        let mut middle = vec![0; 2048];
+        blob.seek(SeekFrom::Start(10000)).unwrap();
+        blob.read_exact(&mut middle).unwrap();
+        assert_eq!(middle, expected[10000..12048]);
+        drop(blob);
+
+        // Text is read as its UTF-8 bytes, which end with the two of é.
+        let mut text = connection.open_blob("big", "t", 1).unwrap();
+        assert_eq!(text.len(), 5002);
+        let mut end = [0; 4];
+        assert_eq!(text.read_at(4998, &mut end).unwrap(), 4);
+        assert_eq!(&end, "qxé".as_bytes());
+    }
+}
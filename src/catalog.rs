@@ -0,0 +1,664 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::{MasterPageRecord, Value, ValueRef, SCHEMA_TABLE_NAMES};
+
+// The schema of a database as typed values, for programs that want to know what is in a database
+// without reading the CREATE statements themselves:
+//
+//     for table in connection.tables() {
+//         for column in table.columns() {
+//             println!("{}.{} {:?}", table.name(), column.name(), column.affinity());
+//         }
+//     }
+//
+// Table, index and column names are spelled as they were declared in the schema, and are looked up
+// without regard to case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub(crate) name: String,
+    pub(crate) columns: Vec<Column>,
+    pub(crate) indexes: Vec<Index>,
+    pub(crate) module: Option<String>,
+    pub(crate) without_rowid: bool,
+    pub(crate) foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    // The module of a virtual table, like `fts5` or `rtree`, or None for an ordinary table.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    pub fn without_rowid(&self) -> bool {
+        self.without_rowid
+    }
+
+    // The foreign keys from this table to others, in the order they were declared.
+    pub fn foreign_keys(&self) -> &[ForeignKey] {
+        &self.foreign_keys
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub(crate) name: String,
+    pub(crate) declared_type: String,
+    pub(crate) primary_key: Option<usize>,
+    pub(crate) not_null: bool,
+    pub(crate) collation: Option<String>,
+    pub(crate) default: Option<String>,
+    pub(crate) generated: bool,
+}
+
+impl Column {
+    // A column declared with nothing but its name, like a virtual table's.
+    pub(crate) fn undeclared(name: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            declared_type: String::new(),
+            primary_key: None,
+            not_null: false,
+            collation: None,
+            default: None,
+            generated: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The type from CREATE TABLE, in upper case, which is empty if it wasn't given one.
+    pub fn declared_type(&self) -> &str {
+        &self.declared_type
+    }
+
+    pub fn affinity(&self) -> Affinity {
+        Affinity::of(&self.declared_type)
+    }
+
+    // Where the column comes in the table's primary key, counting from 1, if it is part of it.
+    pub fn primary_key(&self) -> Option<usize> {
+        self.primary_key
+    }
+
+    pub fn not_null(&self) -> bool {
+        self.not_null
+    }
+
+    // The collating sequence from COLLATE, if it was given one, which is otherwise BINARY.
+    pub fn collation(&self) -> Option<&str> {
+        self.collation.as_deref()
+    }
+
+    // The DEFAULT, as it was written, without the brackets around it if it had them.
+    pub fn default(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    // Whether the column is worked out from the others. Only a table whose generated columns are
+    // all STORED can be read, as a virtual one leaves a gap in the record.
+    pub fn generated(&self) -> bool {
+        self.generated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub(crate) name: String,
+    pub(crate) columns: Vec<IndexColumn>,
+    pub(crate) unique: bool,
+    pub(crate) origin: IndexOrigin,
+    pub(crate) partial: bool,
+}
+
+impl Index {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The indexed columns, in the order they are sorted by. The indexes sqlite makes itself for
+    // UNIQUE constraints have no SQL to find them in, so these are empty.
+    pub fn columns(&self) -> &[IndexColumn] {
+        &self.columns
+    }
+
+    pub fn unique(&self) -> bool {
+        self.unique
+    }
+
+    // Whether the index has a WHERE, and so only some of the table's rows.
+    pub fn partial(&self) -> bool {
+        self.partial
+    }
+
+    // Whether the index is known to sort its keys in sqlite's default order, each column ascending
+    // by the BINARY collation, which is the only order a seek, a write or the integrity check
+    // knows how to follow. The columns of an index for a UNIQUE constraint aren't known, so
+    // neither is its order.
+    pub(crate) fn default_order(&self) -> bool {
+        !self.columns.is_empty()
+            && self.columns.iter().all(|column| {
+                !column.descending && column.collation().eq_ignore_ascii_case("BINARY")
+            })
+    }
+
+    pub fn origin(&self) -> IndexOrigin {
+        self.origin
+    }
+}
+
+// A column of an index. In the schema record, the collation is the one the index names, if it
+// names one. In a typed table it is the one the column is sorted by, which is otherwise the
+// table column's own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexColumn {
+    pub(crate) name: String,
+    pub(crate) collation: Option<String>,
+    pub(crate) descending: bool,
+}
+
+impl IndexColumn {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn collation(&self) -> &str {
+        self.collation.as_deref().unwrap_or("BINARY")
+    }
+
+    pub fn descending(&self) -> bool {
+        self.descending
+    }
+}
+
+// A view, which can't be read yet, but is listed with the rest of the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub(crate) name: String,
+    pub(crate) sql: String,
+}
+
+impl View {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The CREATE VIEW statement, as it is in the schema.
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+}
+
+// Some of a table's columns that refer to rows of a parent table. The parent's name is as it was
+// written in the key, and so are the column names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub(crate) parent: String,
+    pub(crate) from: Vec<String>,
+    pub(crate) to: Vec<String>,
+}
+
+impl ForeignKey {
+    pub fn parent(&self) -> &str {
+        &self.parent
+    }
+
+    // The columns of the child table, in the order they match the parent's.
+    pub fn from(&self) -> &[String] {
+        &self.from
+    }
+
+    // The parent's columns, which are empty when the key refers to its primary key.
+    pub fn to(&self) -> &[String] {
+        &self.to
+    }
+}
+
+// What made an index: CREATE INDEX, or sqlite itself for a constraint on the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexOrigin {
+    Create,
+    Unique,
+    PrimaryKey,
+}
+
+// The kind of value a column prefers to store, which sqlite works out from its declared type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+}
+
+impl Affinity {
+    // The rules are tried in order, by looking for parts of the type name, so `CHARINT` is an
+    // integer and `FLOATING POINT` is real. A column with no type keeps values as they are.
+    pub(crate) fn of(declared_type: &str) -> Affinity {
+        let has = |part: &str| {
+            declared_type
+                .as_bytes()
+                .windows(part.len())
+                .any(|window| window.eq_ignore_ascii_case(part.as_bytes()))
+        };
+
+        if has("INT") {
+            Affinity::Integer
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            Affinity::Text
+        } else if has("BLOB") || declared_type.is_empty() {
+            Affinity::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    // Converts a value to be stored in a column with this affinity, where that can be done without
+    // losing anything: text that reads as a number becomes one in a numeric column, and numbers
+    // become text in a text column. A numeric column keeps a real that is a whole number as an
+    // integer, and a REAL column keeps integers as reals.
+    pub(crate) fn apply(&self, value: Value) -> Value {
+        let value = match (self, value) {
+            (Affinity::Integer | Affinity::Real | Affinity::Numeric, Value::Text(text)) => {
+                let trimmed = text.trim();
+                match (trimmed.parse::<i64>(), trimmed.parse::<f64>()) {
+                    (Ok(n), _) => Value::Int(n),
+                    (_, Ok(r)) if r.is_finite() && trimmed.bytes().any(|b| b.is_ascii_digit()) => {
+                        Value::Real(r)
+                    }
+                    _ => Value::Text(text),
+                }
+            }
+            (Affinity::Text, value @ (Value::Int(_) | Value::Real(_))) => {
+                Value::Text(value.to_string())
+            }
+            (_, value) => value,
+        };
+        match (self, value) {
+            (Affinity::Real, Value::Int(n)) => Value::Real(n as f64),
+            (Affinity::Integer | Affinity::Numeric, Value::Real(r))
+                if r.fract() == 0.0 && r.abs() < 9.2e18 =>
+            {
+                Value::Int(r as i64)
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+// How text is compared: byte by byte, ignoring the case of ASCII letters, or ignoring spaces at
+// the end. Values that aren't both text compare the same way whatever the collation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Collation {
+    Binary,
+    NoCase,
+    RTrim,
+}
+
+impl Collation {
+    // The collation with a name, which with no name is BINARY. Only sqlite's own are known.
+    pub(crate) fn of(name: Option<&str>) -> Result<Collation> {
+        match name {
+            None => Ok(Collation::Binary),
+            Some(name) if name.eq_ignore_ascii_case("BINARY") => Ok(Collation::Binary),
+            Some(name) if name.eq_ignore_ascii_case("NOCASE") => Ok(Collation::NoCase),
+            Some(name) if name.eq_ignore_ascii_case("RTRIM") => Ok(Collation::RTrim),
+            Some(name) => Err(Error::Parse(format!(
+                "no such collation sequence: {}",
+                name
+            ))),
+        }
+    }
+
+    pub(crate) fn compare(self, a: ValueRef, b: ValueRef) -> Ordering {
+        match (self, a, b) {
+            (Collation::NoCase, ValueRef::Text(a), ValueRef::Text(b)) => a
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(b.bytes().map(|byte| byte.to_ascii_lowercase())),
+            (Collation::RTrim, ValueRef::Text(a), ValueRef::Text(b)) => {
+                a.trim_end_matches(' ').cmp(b.trim_end_matches(' '))
+            }
+            (_, a, b) => a.compare(b),
+        }
+    }
+}
+
+// The kind of object a row of the schema table describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ObjectType {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl ObjectType {
+    pub(crate) fn parse(name: &str) -> Result<ObjectType> {
+        match name {
+            "table" => Ok(ObjectType::Table),
+            "index" => Ok(ObjectType::Index),
+            "view" => Ok(ObjectType::View),
+            "trigger" => Ok(ObjectType::Trigger),
+            name => Err(Error::Corrupt {
+                page: 1,
+                message: format!("the schema has an object of unknown type {}", name),
+            }),
+        }
+    }
+}
+
+// The schema as it was when the database was opened. Each object's SQL is parsed once, as it is
+// read, and the tables and the indexes on them are kept by name, so finding one doesn't mean
+// searching the whole schema.
+#[derive(Debug)]
+pub(crate) struct Catalog {
+    // Every object, in the order it is in the schema table.
+    objects: Vec<Arc<MasterPageRecord>>,
+    tables: Vec<Table>,
+    views: Vec<View>,
+    // Where each typed table is in `tables`, and each of their indexes in the table's, by name in
+    // upper case.
+    table_names: HashMap<String, usize>,
+    index_names: HashMap<String, (usize, usize)>,
+    // Tables and views, and the schema table under each of its names, by name in upper case.
+    by_name: HashMap<String, Arc<MasterPageRecord>>,
+    // The indexes on each table, in the order they were created, by the table's name in upper case.
+    indexes: HashMap<String, Vec<Arc<MasterPageRecord>>>,
+}
+
+impl Catalog {
+    pub(crate) fn new(objects: Vec<Arc<MasterPageRecord>>) -> Result<Self> {
+        let mut by_name = HashMap::new();
+        for name in SCHEMA_TABLE_NAMES {
+            let table = Arc::new(MasterPageRecord::schema_table(name)?);
+            by_name.insert(name.to_ascii_uppercase(), table);
+        }
+        let mut indexes = HashMap::<_, Vec<_>>::new();
+        // A name sqlite would have refused twice refers to the first object that has it.
+        for object in &objects {
+            match object.object_type {
+                ObjectType::Table | ObjectType::View => {
+                    by_name
+                        .entry(object.name.to_ascii_uppercase())
+                        .or_insert_with(|| object.clone());
+                }
+                ObjectType::Index => indexes
+                    .entry(object.table_name.to_ascii_uppercase())
+                    .or_default()
+                    .push(object.clone()),
+                ObjectType::Trigger => {}
+            }
+        }
+
+        let mut catalog = Self {
+            objects,
+            tables: Vec::new(),
+            views: Vec::new(),
+            table_names: HashMap::new(),
+            index_names: HashMap::new(),
+            by_name,
+            indexes,
+        };
+        catalog.tables = catalog
+            .objects
+            .iter()
+            .filter(|object| object.object_type == ObjectType::Table)
+            .map(|table| catalog.typed_table(table))
+            .collect();
+        for (position, table) in catalog.tables.iter().enumerate() {
+            catalog
+                .table_names
+                .entry(table.name.to_ascii_uppercase())
+                .or_insert(position);
+            for (index, typed) in table.indexes.iter().enumerate() {
+                catalog
+                    .index_names
+                    .entry(typed.name.to_ascii_uppercase())
+                    .or_insert((position, index));
+            }
+        }
+        catalog.views = catalog
+            .objects
+            .iter()
+            .filter(|object| object.object_type == ObjectType::View)
+            .map(|view| View {
+                name: view.name.clone(),
+                sql: view.sql.clone(),
+            })
+            .collect();
+        Ok(catalog)
+    }
+
+    pub(crate) fn objects(&self) -> &[Arc<MasterPageRecord>] {
+        &self.objects
+    }
+
+    // The tables in the schema, in the order they were created, each with the indexes on it.
+    pub(crate) fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub(crate) fn table(&self, name: &str) -> Option<&Table> {
+        let position = self.table_names.get(&name.to_ascii_uppercase())?;
+        Some(&self.tables[*position])
+    }
+
+    // An index, with the collation each of its columns sorts by.
+    pub(crate) fn index(&self, name: &str) -> Option<&Index> {
+        let (table, index) = self.index_names.get(&name.to_ascii_uppercase())?;
+        Some(&self.tables[*table].indexes[*index])
+    }
+
+    pub(crate) fn views(&self) -> &[View] {
+        &self.views
+    }
+
+    // The table or view with a name, which may be one of the names of the schema table itself.
+    pub(crate) fn find(&self, name: &str) -> Option<&Arc<MasterPageRecord>> {
+        self.by_name.get(&name.to_ascii_uppercase())
+    }
+
+    // The indexes on a table, in the order they were created.
+    pub(crate) fn indexes_on(&self, table: &str) -> &[Arc<MasterPageRecord>] {
+        self.indexes
+            .get(&table.to_ascii_uppercase())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    // A table as the typed values programs see. A primary key that isn't the rowid has an index
+    // of its own, made along with the table. A WITHOUT ROWID table is that index, so it isn't in
+    // the schema, but it is listed all the same, as sqlite does.
+    fn typed_table(&self, table: &MasterPageRecord) -> Table {
+        let mut key_index = !table.indexed.is_empty() && table.rowid_alias.is_none();
+
+        // A column sorts by the collation the index names, or else by the column's own.
+        let sorted = |name: &String, collation: Option<&String>, descending| IndexColumn {
+            name: name.clone(),
+            collation: collation.cloned().or_else(|| {
+                let column = table
+                    .columns
+                    .iter()
+                    .find(|column| column.name.eq_ignore_ascii_case(name))?;
+                column.collation.clone()
+            }),
+            descending,
+        };
+        let key_columns = || {
+            table
+                .indexed
+                .iter()
+                .map(|column| sorted(&column.name, column.collation.as_ref(), column.descending))
+                .collect::<Vec<_>>()
+        };
+
+        let mut indexes = Vec::new();
+        if key_index && table.without_rowid {
+            indexes.push(Index {
+                name: format!("sqlite_autoindex_{}_1", table.name),
+                columns: key_columns(),
+                unique: true,
+                origin: IndexOrigin::PrimaryKey,
+                partial: false,
+            });
+            key_index = false;
+        }
+
+        for index in self.indexes_on(&table.name) {
+            // The primary key's index is taken to be the first that sqlite made, which it is
+            // unless a UNIQUE constraint comes before the key.
+            let (columns, origin) = if !index.sql.is_empty() {
+                let columns = index
+                    .indexed
+                    .iter()
+                    .map(|column| {
+                        sorted(&column.name, column.collation.as_ref(), column.descending)
+                    })
+                    .collect();
+                (columns, IndexOrigin::Create)
+            } else if key_index {
+                key_index = false;
+                (key_columns(), IndexOrigin::PrimaryKey)
+            } else {
+                (Vec::new(), IndexOrigin::Unique)
+            };
+            indexes.push(Index {
+                name: index.name.clone(),
+                columns,
+                unique: index.unique,
+                origin,
+                partial: index.partial.is_some(),
+            });
+        }
+
+        Table {
+            name: table.name.clone(),
+            columns: table.columns.clone(),
+            indexes,
+            module: table
+                .module
+                .as_ref()
+                .map(|module| module.name.to_ascii_lowercase()),
+            without_rowid: table.without_rowid,
+            foreign_keys: table.foreign_keys.clone(),
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // A table's columns in the catalog, laid out as sqlite3's `PRAGMA table_info` lists them.
+    #[allow(dead_code)]
+    fn table_info(table: &Table) -> Vec<String> {
+        let columns = table.columns().iter().enumerate();
+        columns
+            .map(|(cid, column)| {
+                format!(
+                    "{}|{}|{}|{}|{}|{}",
+                    cid,
+                    column.name(),
+                    column.declared_type(),
+                    column.not_null() as i64,
+                    column.default().unwrap_or(""),
+                    column.primary_key().unwrap_or(0)
+                )
+            })
+            .collect()
+    }
+
+    // The catalog describes tables and their indexes as sqlite3 does, names quoted any way
+    // included:
+    //
+    //     $ sqlite3 indexes.db "PRAGMA table_info(t)"
+    //     0|id|INTEGER|0||1
+    //     1|k|TEXT|0||0
+    //     ...
+    //     $ sqlite3 indexes.db "SELECT il.name, x.name, x.desc, x.coll, il.partial
+    //           FROM pragma_index_list('t') il, pragma_index_xinfo(il.name) x WHERE x.key"
+    #[test]
+    fn describes_tables_like_sqlite3() {
+        let bytes = include_bytes!("../tests/fixtures/indexes.db");
+        let connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let t = connection.table("t").unwrap();
+        assert_eq!(
+            table_info(&t),
+            [
+                "0|id|INTEGER|0||1",
+                "1|k|TEXT|0||0",
+                "2|n|INT|0||0",
+                "3|c|TEXT|0||0",
+                "4|r|TEXT|0||0"
+            ]
+        );
+        let mut indexes = t
+            .indexes()
+            .iter()
+            .map(|index| {
+                let column = &index.columns()[0];
+                format!(
+                    "{}|{}|{}|{}|{}",
+                    index.name(),
+                    column.name(),
+                    column.descending() as i64,
+                    column.collation(),
+                    index.partial() as i64
+                )
+            })
+            .collect::<Vec<_>>();
+        indexes.sort();
+        assert_eq!(
+            indexes,
+            [
+                "cn|c|0|NOCASE|0",
+                "kd|k|1|BINARY|0",
+                "nd|n|1|BINARY|0",
+                "np|n|0|BINARY|1",
+                "rb|r|0|BINARY|0"
+            ]
+        );
+        assert!(t.indexes().iter().all(|index| !index.unique()
+            && index.origin() == IndexOrigin::Create
+            && index.columns().len() == 1));
+
+        let bytes = include_bytes!("../tests/fixtures/names.db");
+        let connection = Connection::open_bytes(&bytes[..]).unwrap();
+        assert_eq!(
+            table_info(&connection.table("my t").unwrap()),
+            [
+                "0|select|TEXT|0||0",
+                "1|a \"b\"|INT|0||0",
+                "2|c`d|REAL|0||0",
+                "3|e|BLOB|0||0"
+            ]
+        );
+    }
+}
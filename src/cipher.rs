@@ -0,0 +1,543 @@
+use std::io;
+
+// Reads databases encrypted by SQLCipher 4 with its default settings: 4096 byte pages, a key
+// derived from the passphrase with PBKDF2-HMAC-SHA512 over 256000 iterations, pages encrypted
+// with AES-256-CBC, and each page authenticated with HMAC-SHA512.
+//
+// The first 16 bytes of the file are the salt for the key, in place of the start of the header,
+// which is why the page size can't be read from the file. The last 80 bytes of every page, the
+// reserved space the header declares, hold the IV the page was encrypted with followed by its
+// HMAC. The HMAC covers the encrypted bytes and the IV, followed by the page number as a 4 byte
+// little endian integer, and is checked before the page is decrypted.
+pub const PAGE_SIZE: u32 = 4096;
+
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 16;
+const HMAC_SIZE: usize = 64;
+pub const RESERVED_SIZE: usize = IV_SIZE + HMAC_SIZE;
+const KDF_ITERATIONS: u32 = 256_000;
+const HMAC_KDF_ITERATIONS: u32 = 2;
+// The HMAC key is derived from the encryption key, with the salt changed by this mask.
+const HMAC_SALT_MASK: u8 = 0x3a;
+
+pub struct Cipher {
+    aes: Aes256,
+    hmac_key: [u8; KEY_SIZE],
+}
+
+// The key schedule is secret, so it isn't printed.
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cipher")
+    }
+}
+
+impl Cipher {
+    // Derives the keys for a database from its passphrase and the salt at the start of the file.
+    pub fn new(passphrase: &str, salt: &[u8; SALT_SIZE]) -> Self {
+        let mut key = [0; KEY_SIZE];
+        pbkdf2_sha512(passphrase.as_bytes(), salt, KDF_ITERATIONS, &mut key);
+
+        let hmac_salt = salt.map(|byte| byte ^ HMAC_SALT_MASK);
+        let mut hmac_key = [0; KEY_SIZE];
+        pbkdf2_sha512(&key, &hmac_salt, HMAC_KDF_ITERATIONS, &mut hmac_key);
+
+        Self {
+            aes: Aes256::new(&key),
+            hmac_key,
+        }
+    }
+
+    // Decrypts a page in place. A page whose HMAC doesn't match was either written with another
+    // key or has been changed since, which the caller can't tell apart.
+    pub fn decrypt_page(&self, page_number: u32, page: &mut [u8]) -> io::Result<()> {
+        // The salt on the first page is stored in the clear, and isn't part of what is encrypted.
+        let start = if page_number == 1 { SALT_SIZE } else { 0 };
+        let end = page
+            .len()
+            .checked_sub(RESERVED_SIZE)
+            .filter(|end| *end > start)
+            .ok_or_else(|| invalid("page too small to be encrypted"))?;
+
+        let mut mac = HmacSha512::new(&self.hmac_key);
+        mac.update(&page[start..end + IV_SIZE]);
+        mac.update(&page_number.to_le_bytes());
+        if mac.finish()[..] != page[end + IV_SIZE..end + RESERVED_SIZE] {
+            return Err(invalid("HMAC check failed"));
+        }
+
+        let mut previous: [u8; 16] = page[end..end + IV_SIZE].try_into().unwrap();
+        for block in page[start..end].chunks_exact_mut(16) {
+            let ciphertext: [u8; 16] = (&*block).try_into().unwrap();
+            let mut plaintext = ciphertext;
+            self.aes.decrypt_block(&mut plaintext);
+            for (byte, previous) in plaintext.iter_mut().zip(previous) {
+                *byte ^= previous;
+            }
+            block.copy_from_slice(&plaintext);
+            previous = ciphertext;
+        }
+
+        if page_number == 1 {
+            page[..SALT_SIZE].copy_from_slice(b"SQLite format 3\0");
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn pbkdf2_sha512(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    let prf = HmacSha512::new(password);
+
+    for (block_number, chunk) in (1u32..).zip(out.chunks_mut(64)) {
+        let mut mac = prf.clone();
+        mac.update(salt);
+        mac.update(&block_number.to_be_bytes());
+        let mut u = mac.finish();
+        let mut block = u;
+
+        for _ in 1..iterations {
+            let mut mac = prf.clone();
+            mac.update(&u);
+            u = mac.finish();
+            for (byte, u) in block.iter_mut().zip(u) {
+                *byte ^= u;
+            }
+        }
+
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+#[derive(Clone)]
+struct HmacSha512 {
+    inner: Sha512,
+    outer: Sha512,
+}
+
+impl HmacSha512 {
+    fn new(key: &[u8]) -> Self {
+        let mut padded = [0u8; 128];
+        if key.len() > 128 {
+            let mut hash = Sha512::new();
+            hash.update(key);
+            padded[..64].copy_from_slice(&hash.finish());
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha512::new();
+        inner.update(&padded.map(|byte| byte ^ 0x36));
+        let mut outer = Sha512::new();
+        outer.update(&padded.map(|byte| byte ^ 0x5c));
+        Self { inner, outer }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.inner.update(bytes);
+    }
+
+    fn finish(self) -> [u8; 64] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+// SHA-512, as in FIPS 180-4.
+#[derive(Clone)]
+struct Sha512 {
+    state: [u64; 8],
+    buffer: [u8; 128],
+    buffered: usize,
+    length: u128,
+}
+
+#[rustfmt::skip]
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+impl Sha512 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+                0x510e527fade682d1,
+                0x9b05688c2b3e6c1f,
+                0x1f83d9abfb41bd6b,
+                0x5be0cd19137e2179,
+            ],
+            buffer: [0; 128],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.length += bytes.len() as u128;
+
+        while !bytes.is_empty() {
+            let n = (128 - self.buffered).min(bytes.len());
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&bytes[..n]);
+            self.buffered += n;
+            bytes = &bytes[n..];
+
+            if self.buffered == 128 {
+                let block = self.buffer;
+                self.compress(&block);
+                self.buffered = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 64] {
+        let bits = self.length * 8;
+        let mut padding = vec![0x80];
+        padding.resize((239 - self.buffered) % 128 + 1, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.update(&padding);
+
+        let mut out = [0; 64];
+        for (chunk, word) in out.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 128]) {
+        let mut w = [0u64; 80];
+        for (i, chunk) in block.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+// AES-256 decryption, as in FIPS 197. The S-boxes are worked out from their definition rather than
+// written out as tables.
+struct Aes256 {
+    round_keys: [[u8; 16]; 15],
+    inverse_sbox: [u8; 256],
+}
+
+impl Aes256 {
+    fn new(key: &[u8; 32]) -> Self {
+        let sbox = sbox();
+        let mut inverse_sbox = [0; 256];
+        for (i, value) in sbox.iter().enumerate() {
+            inverse_sbox[*value as usize] = i as u8;
+        }
+
+        // 60 words of key schedule, from the 8 words of the key.
+        let mut words = [[0u8; 4]; 60];
+        for (word, chunk) in words.iter_mut().zip(key.chunks_exact(4)) {
+            word.copy_from_slice(chunk);
+        }
+        let mut rcon = 1u8;
+        for i in 8..60 {
+            let mut word = words[i - 1];
+            if i % 8 == 0 {
+                word.rotate_left(1);
+                word = word.map(|byte| sbox[byte as usize]);
+                word[0] ^= rcon;
+                rcon = xtime(rcon);
+            } else if i % 8 == 4 {
+                word = word.map(|byte| sbox[byte as usize]);
+            }
+            for (byte, earlier) in word.iter_mut().zip(words[i - 8]) {
+                *byte ^= earlier;
+            }
+            words[i] = word;
+        }
+
+        let mut round_keys = [[0; 16]; 15];
+        for (round_key, chunk) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+            for (bytes, word) in round_key.chunks_exact_mut(4).zip(chunk) {
+                bytes.copy_from_slice(word);
+            }
+        }
+
+        Self {
+            round_keys,
+            inverse_sbox,
+        }
+    }
+
+    // The state is held column by column, the same order as the bytes of the block.
+    fn decrypt_block(&self, block: &mut [u8; 16]) {
+        add_round_key(block, &self.round_keys[14]);
+        for round in (1..14).rev() {
+            self.inverse_shift_sub(block);
+            add_round_key(block, &self.round_keys[round]);
+            inverse_mix_columns(block);
+        }
+        self.inverse_shift_sub(block);
+        add_round_key(block, &self.round_keys[0]);
+    }
+
+    fn inverse_shift_sub(&self, block: &mut [u8; 16]) {
+        let state = *block;
+        for column in 0..4 {
+            for row in 0..4 {
+                // Row r was shifted left by r, so it comes back from r columns to the left.
+                let from = ((column + 4 - row) % 4) * 4 + row;
+                block[column * 4 + row] = self.inverse_sbox[state[from] as usize];
+            }
+        }
+    }
+}
+
+fn add_round_key(block: &mut [u8; 16], round_key: &[u8; 16]) {
+    for (byte, key) in block.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+fn inverse_mix_columns(block: &mut [u8; 16]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+        column[0] = multiply(a, 14) ^ multiply(b, 11) ^ multiply(c, 13) ^ multiply(d, 9);
+        column[1] = multiply(a, 9) ^ multiply(b, 14) ^ multiply(c, 11) ^ multiply(d, 13);
+        column[2] = multiply(a, 13) ^ multiply(b, 9) ^ multiply(c, 14) ^ multiply(d, 11);
+        column[3] = multiply(a, 11) ^ multiply(b, 13) ^ multiply(c, 9) ^ multiply(d, 14);
+    }
+}
+
+// Multiplication by x in GF(2^8), modulo x^8 + x^4 + x^3 + x + 1.
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+// Each byte's multiplicative inverse in GF(2^8), put through the affine transform.
+fn sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    for (i, entry) in sbox.iter_mut().enumerate() {
+        let inverse = (1..=255u8)
+            .find(|candidate| multiply(i as u8, *candidate) == 1)
+            .unwrap_or(0);
+        *entry = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+    }
+    sbox
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[allow(dead_code)]
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The AES-256 example from FIPS 197, appendix C.3.
+    #[test]
+    fn aes_256() {
+        let key = unhex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let aes = Aes256::new(&key.try_into().unwrap());
+        let mut block = unhex("8ea2b7ca516745bfeafc49904b496089")
+            .try_into()
+            .unwrap();
+        aes.decrypt_block(&mut block);
+        assert_eq!(hex(&block), "00112233445566778899aabbccddeeff");
+    }
+
+    // The examples from FIPS 180-4, with the two block message fed a byte at a time so it is
+    // split across calls to update.
+    #[test]
+    fn sha512() {
+        let hash = |chunks: &[&[u8]]| {
+            let mut sha = Sha512::new();
+            for chunk in chunks {
+                sha.update(chunk);
+            }
+            hex(&sha.finish())
+        };
+        assert_eq!(
+            hash(&[b""]),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+             47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+        assert_eq!(
+            hash(&[b"abc"]),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        let message = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+                        hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+        let bytes = message.chunks(1).collect::<Vec<_>>();
+        assert_eq!(
+            hash(&bytes),
+            "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+             501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+        );
+    }
+
+    // Test cases 2 and 6 of RFC 4231, the second with a key longer than a block.
+    #[test]
+    fn hmac_sha512() {
+        let mut mac = HmacSha512::new(b"Jefe");
+        mac.update(b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&mac.finish()),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+
+        let mut mac = HmacSha512::new(&[0xaa; 131]);
+        mac.update(b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(
+            hex(&mac.finish()),
+            "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352\
+             6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+        );
+    }
+
+    // PBKDF2-HMAC-SHA512 as Python's hashlib.pbkdf2_hmac derives it, including a key longer
+    // than one hash, which takes a second block.
+    #[test]
+    fn pbkdf2() {
+        let derive = |password: &[u8], salt: &[u8], iterations: u32, len: usize| {
+            let mut out = vec![0; len];
+            pbkdf2_sha512(password, salt, iterations, &mut out);
+            hex(&out)
+        };
+        assert_eq!(
+            derive(b"password", b"salt", 1, 64),
+            "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252\
+             c02d470a285a0501bad999bfe943c08f050235d7d68b1da55e63f73b60a57fce"
+        );
+        assert_eq!(
+            derive(b"password", b"salt", 2, 64),
+            "e1d9c16aa681708a45f5c7c4e215ceb66e011a2e9f0040713f18aefdb866d53c\
+             f76cab2868a39b9f7840edce4fef5a82be67335c77a6068e04112754f27ccf4e"
+        );
+        assert_eq!(
+            derive(
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                80
+            ),
+            "8c0511f4c6e597c6ac6315d8f0362e225f3c501495ba23b868c005174dc4ee71\
+             115b59f9e60cd9532fa33e0f75aefe30225c583a186cd82bd4daea9724a3d3b8\
+             04f75bdd41494fa324cab24bcc680fb3"
+        );
+    }
+
+    // A two page database encrypted the way SQLCipher 4 does with its default settings, with
+    // the passphrase "secret", reads back with it and fails with any other.
+    #[test]
+    fn opens_sqlcipher_4() {
+        let path = std::env::temp_dir().join(format!("sqlcipher-{}.db", std::process::id()));
+        std::fs::write(&path, include_bytes!("../tests/fixtures/sqlcipher.db")).unwrap();
+
+        let opened = crate::Connection::open_encrypted(&path, "secret").and_then(|mut db| {
+            let rows = db.query("SELECT id, body FROM notes")?;
+            Ok(rows
+                .rows
+                .iter()
+                .map(|row| row.values().to_vec())
+                .collect::<Vec<_>>())
+        });
+        let wrong = crate::Connection::open_encrypted(&path, "wrong")
+            .and_then(|mut db| db.query("SELECT id, body FROM notes"));
+        std::fs::remove_file(&path).ok();
+
+        use crate::Value::{Blob, Int, Text};
+        assert_eq!(
+            opened.unwrap(),
+            [
+                vec![Int(1), Text("first".to_string())],
+                vec![Int(2), Text("second".to_string())],
+                vec![Int(3), Blob(vec![1, 2])],
+            ]
+        );
+        assert!(wrong.is_err());
+    }
+}
@@ -0,0 +1,847 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::pager::PagerOptions;
+use crate::sql_engine::{Batches, Datum, Plan, QueryStats, ResultSink, SqlEngine};
+use crate::vtab::{VirtualTable, VirtualTables};
+use crate::{Blob, Db, RecordBatch, Row, Table, Value, View};
+
+// How many prepared plans a connection keeps before it starts again from an empty cache.
+const PLAN_CACHE_SIZE: usize = 64;
+
+// An open database, for other programs to read from, and to change with INSERT and DELETE.
+//
+//     let mut connection = Connection::open("sample.db")?;
+//     let rows = connection.query("SELECT name FROM apples")?;
+//     for row in &rows.rows {
+//         let name: String = row.get("name")?;
+//         println!("{}", name);
+//     }
+//
+// Statements that are run more than once can be prepared, and given different values each time:
+//
+//     let mut statement = connection.prepare("SELECT name FROM apples WHERE color = ?")?;
+//     let names = statement.query_map(&["Red".into()], |row| row.get::<String>(0))?;
+//
+// A large result can be paged through a batch at a time, without running the query again for
+// each page:
+//
+//     let mut statement = connection.prepare("SELECT name FROM apples")?;
+//     let mut rows = statement.cursor(&[])?;
+//     loop {
+//         let batch = rows.next_batch(100)?;
+//         if batch.rows.is_empty() {
+//             break;
+//         }
+//     }
+//
+// A clone shares the open database, and can be sent to another thread to read from it at the same
+// time as the original.
+#[derive(Clone)]
+pub struct Connection {
+    db: Arc<Db>,
+    // How the file was opened, to open it again when its schema changes. None for a database
+    // that isn't in a file.
+    options: Option<PagerOptions>,
+    // Plans keyed by their SQL, so preparing the same text again skips the lexer and parser.
+    plans: HashMap<String, Arc<Plan>>,
+    virtual_tables: VirtualTables,
+    hooks: Hooks,
+}
+
+// What a write did to a row, as told to the update hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+}
+
+type TraceHook = Arc<dyn Fn(&str) + Send + Sync>;
+type UpdateHook = Arc<dyn Fn(Action, &str, i64) + Send + Sync>;
+
+// Callbacks set by the program, which a clone of the connection shares.
+#[derive(Clone, Default)]
+struct Hooks {
+    trace: Option<TraceHook>,
+    update: Option<UpdateHook>,
+}
+
+impl Connection {
+    pub fn open(path: impl AsRef<Path>) -> Result<Connection> {
+        Self::open_with(path, PagerOptions::default())
+    }
+
+    // Opens the database holding a SHARED lock on it until the connection is dropped, so that
+    // another process can't write to it part way through a read. Fails with Error::Busy while a
+    // writer has it locked.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Connection> {
+        let options = PagerOptions {
+            shared_lock: true,
+            read_only: true,
+            ..PagerOptions::default()
+        };
+        Self::open_with(path, options)
+    }
+
+    // Opens a database encrypted by SQLCipher 4, with its default settings. A wrong passphrase
+    // fails with Error::NotADatabase, like it does for sqlcipher.
+    pub fn open_encrypted(path: impl AsRef<Path>, passphrase: &str) -> Result<Connection> {
+        let options = PagerOptions {
+            key: Some(passphrase.to_string()),
+            ..PagerOptions::default()
+        };
+        Self::open_with(path, options)
+    }
+
+    // Opens a damaged database to get back as much of it as can be read. A cell that can't be
+    // parsed is left out of the rows, and so is everything under a page that can't be, with a
+    // warning for each that take_warnings hands back, where otherwise the whole query would fail.
+    pub fn open_salvage(path: impl AsRef<Path>) -> Result<Connection> {
+        let options = PagerOptions {
+            salvage: true,
+            ..PagerOptions::default()
+        };
+        Self::open_with(path, options)
+    }
+
+    // Opens a database from the bytes of its file, optionally gzip compressed, without touching
+    // the file system. This is the way in where there is no file system, like in a browser.
+    pub fn open_bytes(bytes: impl Into<Vec<u8>>) -> Result<Connection> {
+        let db = Db::from_bytes(bytes.into())?;
+        Ok(Self::from_db(db))
+    }
+
+    fn open_with(path: impl AsRef<Path>, options: PagerOptions) -> Result<Connection> {
+        let db = Db::new(path.as_ref().to_path_buf(), options.clone())?;
+        Ok(Connection {
+            options: Some(options),
+            ..Self::from_db(db)
+        })
+    }
+
+    // What was skipped by reads since the warnings were last taken, which is only ever anything
+    // for a connection opened with open_salvage. Clones of a connection share them.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.db.pager.take_warnings()
+    }
+
+    // Whether the schema in the file is no longer the one the connection read when it opened.
+    pub(crate) fn schema_changed(&self) -> Result<bool> {
+        self.db.schema_changed()
+    }
+
+    // Reads the schema again when it has changed, by this connection or another, opening a file
+    // afresh, and drops every cached plan along with the old schema. Returns whether it did.
+    // Clones go on with the schema they have until they run a statement themselves.
+    fn refresh_schema(&mut self) -> Result<bool> {
+        if !self.db.schema_changed()? {
+            return Ok(false);
+        }
+
+        let options = self.options.clone().unwrap_or_default();
+        self.db = Arc::new(self.db.reopen(options)?);
+        self.plans.clear();
+        Ok(true)
+    }
+
+    fn from_db(db: Db) -> Connection {
+        Connection {
+            db: Arc::new(db),
+            options: None,
+            plans: HashMap::new(),
+            virtual_tables: HashMap::new(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    // Makes a virtual table available to queries on this connection, and its clones made from
+    // now on, under a name that isn't already taken by a table in the database.
+    pub fn register_virtual_table(
+        &mut self,
+        name: &str,
+        table: impl VirtualTable + 'static,
+    ) -> Result<()> {
+        let key = name.to_ascii_uppercase();
+        if self.db.get_table(name).is_ok() || self.virtual_tables.contains_key(&key) {
+            return Err(Error::Parse(format!("table {} already exists", name)));
+        }
+
+        self.virtual_tables.insert(key, Arc::new(table));
+        Ok(())
+    }
+
+    // The tables in the database, as the connection read them when it opened. Virtual tables
+    // registered with the connection aren't among them.
+    pub fn tables(&self) -> Vec<Table> {
+        self.db.tables().to_vec()
+    }
+
+    pub fn table(&self, name: &str) -> Result<Table> {
+        self.db
+            .catalog
+            .table(name)
+            .cloned()
+            .ok_or_else(|| Error::NoSuchTable(name.to_string()))
+    }
+
+    // The views in the database, in the order they were created.
+    pub fn views(&self) -> Vec<View> {
+        self.db.catalog.views().to_vec()
+    }
+
+    // Opens the BLOB, or text, in a column of the row with `rowid`, to be read a piece at a time
+    // rather than all at once, as a value too large to want in memory whole can be. The handle
+    // borrows the connection, which can't run statements until it's dropped.
+    pub fn open_blob(&mut self, table: &str, column: &str, rowid: i64) -> Result<Blob<'_>> {
+        self.refresh_schema()?;
+        Blob::open(&self.db, table, column, rowid)
+    }
+
+    // Calls `f` with the SQL of each statement as it starts to run, as it was prepared, without
+    // the values bound to its parameters.
+    pub fn set_trace(&mut self, f: impl Fn(&str) + Send + Sync + 'static) {
+        self.hooks.trace = Some(Arc::new(f));
+    }
+
+    // Calls `f` with what was done, the table's name and the rowid for every row that a statement
+    // inserts, updates or deletes, so that a program can drop what it has cached about the row.
+    pub fn set_update_hook(&mut self, f: impl Fn(Action, &str, i64) + Send + Sync + 'static) {
+        self.hooks.update = Some(Arc::new(f));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks = Hooks::default();
+    }
+
+    // Rows changed by the most recent INSERT, UPDATE or DELETE.
+    pub fn changes(&self) -> u64 {
+        self.db.changes.load(Ordering::Relaxed)
+    }
+
+    // Rows changed since the connection was opened.
+    pub fn total_changes(&self) -> u64 {
+        self.db.total_changes.load(Ordering::Relaxed)
+    }
+
+    // The rowid of the most recently inserted row, or 0 if nothing has been inserted.
+    pub fn last_insert_rowid(&self) -> i64 {
+        self.db.last_insert_rowid.load(Ordering::Relaxed)
+    }
+
+    // Runs the SQL and collects what it returns. When there are several statements, the rows are
+    // the ones from the last statement that returned any columns.
+    pub fn query(&mut self, sql: &str) -> Result<Rows> {
+        self.prepare(sql)?.query(&[])
+    }
+
+    pub fn prepare(&mut self, sql: &str) -> Result<Statement<'_>> {
+        let plan = self.plan(sql)?;
+        Ok(Statement {
+            params: Vec::new(),
+            rows: None,
+            position: 0,
+            columns: Arc::from([]),
+            stats: QueryStats::default(),
+            plan,
+            connection: self,
+        })
+    }
+
+    fn plan(&mut self, sql: &str) -> Result<Arc<Plan>> {
+        if let Some(plan) = self.plans.get(sql) {
+            return Ok(plan.clone());
+        }
+
+        let plan = Arc::new(Plan::prepare(sql)?);
+        if self.plans.len() >= PLAN_CACHE_SIZE {
+            self.plans.clear();
+        }
+        self.plans.insert(sql.to_string(), plan.clone());
+        Ok(plan)
+    }
+}
+
+// A prepared statement. Values are bound to its parameters by number, starting at 1, and it
+// can be stepped through a row at a time or run to completion with `query`.
+pub struct Statement<'c> {
+    connection: &'c mut Connection,
+    plan: Arc<Plan>,
+    params: Vec<Value>,
+    // The rows of the current run, and how many of them step has returned.
+    rows: Option<Rows>,
+    position: usize,
+    // The column names of the last run, kept after it finishes.
+    columns: Arc<[String]>,
+    stats: QueryStats,
+}
+
+impl Statement<'_> {
+    pub fn parameter_count(&self) -> usize {
+        self.plan.parameter_count()
+    }
+
+    // The number of a named parameter, written with its prefix, as in `:name`.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.plan.parameter_index(name)
+    }
+
+    // Binds a value to a parameter. It stays bound until it's replaced or the bindings are
+    // cleared, even when the statement is reset.
+    pub fn bind(&mut self, index: usize, value: impl Into<Value>) -> Result<()> {
+        if index == 0 || index > self.parameter_count() {
+            return Err(Error::Range);
+        }
+
+        if self.params.len() < index {
+            self.params.resize(index, Value::Null);
+        }
+        self.params[index - 1] = value.into();
+        Ok(())
+    }
+
+    pub fn clear_bindings(&mut self) {
+        self.params.clear();
+    }
+
+    // Returns the next row, running the statement first if it isn't already running. Once the
+    // rows run out it returns None and resets, so the next step starts again from the top.
+    pub fn step(&mut self) -> Result<Option<&Row>> {
+        if self.rows.is_none() {
+            self.rows = Some(self.run()?);
+            self.position = 0;
+        }
+
+        let rows = self.rows.as_ref().unwrap();
+        if self.position == rows.rows.len() {
+            self.reset();
+            return Ok(None);
+        }
+
+        self.position += 1;
+        Ok(Some(&self.rows.as_ref().unwrap().rows[self.position - 1]))
+    }
+
+    pub fn reset(&mut self) {
+        self.rows = None;
+        self.position = 0;
+    }
+
+    // The names of the result columns, which are only known once the statement has been run.
+    pub fn column_names(&self) -> &[String] {
+        &self.columns
+    }
+
+    // Binds `params` to parameters 1 onwards, then runs the statement to completion.
+    pub fn query(&mut self, params: &[Value]) -> Result<Rows> {
+        self.bind_all(params)?;
+        self.reset();
+        self.run()
+    }
+
+    // Binds `params` to parameters 1 onwards and starts the statement, for its rows to be read a
+    // batch at a time.
+    pub fn cursor(&mut self, params: &[Value]) -> Result<RowCursor<'_>> {
+        self.bind_all(params)?;
+        self.reset();
+        self.replan_if_stale()?;
+        if let Some(trace) = &self.connection.hooks.trace {
+            trace(self.plan.sql());
+        }
+
+        // The rows are still to be read, so there is nothing yet to say about what they took.
+        self.stats = QueryStats::default();
+        let (columns, batches) = Batches::start(
+            &self.plan,
+            &self.params,
+            &self.connection.db,
+            &self.connection.virtual_tables,
+        )?;
+        self.columns = columns.clone();
+        Ok(RowCursor { columns, batches })
+    }
+
+    // Like `query`, but turns every row into a T.
+    pub fn query_map<T>(
+        &mut self,
+        params: &[Value],
+        mut f: impl FnMut(&Row) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        self.query(params)?.rows.iter().map(&mut f).collect()
+    }
+
+    fn bind_all(&mut self, params: &[Value]) -> Result<()> {
+        if params.len() > self.parameter_count() {
+            return Err(Error::Range);
+        }
+
+        self.params = params.to_vec();
+        Ok(())
+    }
+
+    pub fn sql(&self) -> &str {
+        self.plan.sql()
+    }
+
+    // What the last run took: pages and cells read, index seeks, and rows scanned and returned.
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
+
+    // A statement keeps its plan between runs, however its parameters are bound, unless the
+    // schema has changed since it was planned, when it's prepared again first.
+    fn replan_if_stale(&mut self) -> Result<()> {
+        if self.connection.refresh_schema()? {
+            let sql = self.plan.sql().to_string();
+            self.plan = self.connection.plan(&sql)?;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<Rows> {
+        self.replan_if_stale()?;
+        if let Some(trace) = &self.connection.hooks.trace {
+            trace(self.plan.sql());
+        }
+
+        let mut rows = Rows::default();
+        let mut engine =
+            SqlEngine::new(&mut rows).with_virtual_tables(&self.connection.virtual_tables);
+        if let Some(hook) = &self.connection.hooks.update {
+            engine = engine.with_update_hook(hook.as_ref());
+        }
+        let result = engine.run(&self.plan, &self.params, &self.connection.db);
+        self.stats = engine.stats();
+        result?;
+        self.columns = rows.columns.clone();
+        Ok(rows)
+    }
+}
+
+// The rows of a running statement, read a batch at a time. A plain scan of a table keeps its
+// place in the table between batches, so each batch reads only as far as its last row.
+pub struct RowCursor<'s> {
+    columns: Arc<[String]>,
+    batches: Batches<'s>,
+}
+
+impl RowCursor<'_> {
+    pub fn column_names(&self) -> &[String] {
+        &self.columns
+    }
+
+    // Up to `n` more rows. A batch with fewer than `n` rows is the last, and every batch after it
+    // is empty.
+    pub fn next_batch(&mut self, n: usize) -> Result<Rows> {
+        let rows = self.batches.next_batch(n, &self.columns)?;
+        Ok(Rows {
+            columns: self.columns.clone(),
+            rows,
+        })
+    }
+}
+
+// The result of a query: the names of its columns, then each row's values in the same order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rows {
+    pub columns: Arc<[String]>,
+    pub rows: Vec<Row>,
+}
+
+impl Rows {
+    // The rows laid out by column, the way arrow lays them out.
+    pub fn record_batch(&self) -> RecordBatch {
+        let values = self.rows.iter().map(Row::values).collect::<Vec<_>>();
+        RecordBatch::new(&self.columns, &[], &values)
+    }
+}
+
+impl ResultSink for Rows {
+    fn start(&mut self, columns: Vec<String>) -> Result<()> {
+        self.columns = columns.into();
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn row<'a>(&mut self, values: impl Iterator<Item = Datum<'a>>) -> Result<()> {
+        let values = values.map(Datum::into_value).collect();
+        self.rows.push(Row::new(self.columns.clone(), values));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use std::path::PathBuf;
+
+    // 1000 rows on 512 byte pages, whose column n is the id modulo 10, with an index on n.
+    #[allow(dead_code)]
+    const MANY: &[u8] = include_bytes!("../tests/fixtures/many.db");
+
+    #[allow(dead_code)]
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        #[allow(dead_code)]
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let name = format!("{}-{}.db", name, std::process::id());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // The ids in each batch a cursor hands out, until one comes back empty.
+    #[allow(dead_code)]
+    fn batches(connection: &mut Connection, sql: &str, n: usize) -> Vec<Vec<i64>> {
+        let mut statement = connection.prepare(sql).unwrap();
+        let mut cursor = statement.cursor(&[]).unwrap();
+        let mut batches = Vec::new();
+        loop {
+            let batch = cursor.next_batch(n).unwrap();
+            let ids = batch
+                .rows
+                .iter()
+                .map(|row| row.get(0).unwrap())
+                .collect::<Vec<i64>>();
+            if ids.is_empty() {
+                break;
+            }
+            batches.push(ids);
+        }
+        assert!(cursor.next_batch(n).unwrap().rows.is_empty());
+        batches
+    }
+
+    // Batches are full until the rows run out, the last one has what's left, and every one after
+    // it is empty, both for a scan that reads as it goes and for a query that's run up front.
+    #[test]
+    fn reads_in_batches() {
+        let mut connection = Connection::open_bytes(MANY).unwrap();
+        for (sql, n, sizes) in [
+            ("SELECT id FROM m", 300, vec![300, 300, 300, 100]),
+            ("SELECT id FROM m", 250, vec![250; 4]),
+            ("SELECT id FROM m", 2000, vec![1000]),
+            ("SELECT id FROM m WHERE n = 3", 40, vec![40, 40, 20]),
+            ("SELECT id FROM m WHERE n = 3", 100, vec![100]),
+            ("SELECT id FROM m WHERE n = 11", 10, vec![]),
+        ] {
+            let batches = batches(&mut connection, sql, n);
+            let lens = batches.iter().map(Vec::len).collect::<Vec<_>>();
+            assert_eq!(lens, sizes, "{} in batches of {}", sql, n);
+
+            let ids = batches.concat();
+            let expected = match sql.contains("WHERE") {
+                true => (3..1000).step_by(10).take(sizes.iter().sum()).collect(),
+                false => (1..=1000).collect::<Vec<_>>(),
+            };
+            assert_eq!(ids, expected, "{}", sql);
+        }
+    }
+
+    // A scan only reads the pages the batches so far have needed.
+    #[test]
+    fn scans_a_batch_at_a_time() {
+        let mut connection = Connection::open_bytes(MANY).unwrap();
+        let db = connection.db.clone();
+        let mut statement = connection.prepare("SELECT id FROM m").unwrap();
+        let mut cursor = statement.cursor(&[]).unwrap();
+
+        let before = db.pager.stats();
+        cursor.next_batch(10).unwrap();
+        let first = db.pager.stats().since(&before).pages_read;
+        while !cursor.next_batch(100).unwrap().rows.is_empty() {}
+        let all = db.pager.stats().since(&before).pages_read;
+        assert!(first <= 3, "{}", first);
+        assert!(all > 30, "{}", all);
+    }
+
+    // A prepared statement keeps its plan until the schema changes under it, here when another
+    // connection adds an index, and then it's planned again, this time to seek the index.
+    #[test]
+    fn replans_after_a_schema_change() {
+        let file = TempFile::new(
+            "replans",
+            include_bytes!("../tests/fixtures/many-unindexed.db"),
+        );
+        let mut connection = Connection::open(&file.0).unwrap();
+        let sql = "SELECT id FROM m WHERE n = 3";
+
+        let mut statement = connection.prepare(sql).unwrap();
+        let plan = statement.plan.clone();
+        let rows = statement.query(&[]).unwrap();
+        assert_eq!(rows.rows.len(), 100);
+        assert_eq!(statement.stats().index_seeks, 0);
+        statement.query(&[]).unwrap();
+        assert!(Arc::ptr_eq(&plan, &statement.plan));
+
+        // The same table with the index, as sqlite3 wrote it, whose schema cookie is different.
+        std::fs::write(&file.0, MANY).unwrap();
+
+        let rows = statement.query(&[]).unwrap();
+        assert_eq!(rows.rows.len(), 100);
+        assert_eq!(statement.stats().index_seeks, 1);
+        assert!(!Arc::ptr_eq(&plan, &statement.plan));
+        drop(statement);
+        assert_eq!(connection.plans.len(), 1);
+        assert_eq!(connection.table("m").unwrap().indexes().len(), 1);
+    }
+
+    // A database opened from its path answers a query with the columns and values sqlite3 gives,
+    // which prints the reals to 15 digits:
+    //
+    //     1|p1|27|0.3|x1
+    //     11|p11||1.3|x3
+    //     60|p60|10|1.0|
+    #[test]
+    fn opens_and_queries() {
+        let file = TempFile::new("queries", include_bytes!("../tests/fixtures/people.db"));
+        let mut connection = Connection::open(&file.0).unwrap();
+        let rows = connection
+            .query("SELECT id, name, age, score, x FROM people WHERE id IN (1, 11, 60)")
+            .unwrap();
+        assert_eq!(&*rows.columns, ["id", "name", "age", "score", "x"]);
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            rows.rows.iter().map(Row::values).collect::<Vec<_>>(),
+            [
+                &[
+                    Value::Int(1),
+                    text("p1"),
+                    Value::Int(27),
+                    Value::Real(0.30000000000000004),
+                    text("x1")
+                ][..],
+                &[
+                    Value::Int(11),
+                    text("p11"),
+                    Value::Null,
+                    Value::Real(1.2999999999999998),
+                    text("x3")
+                ],
+                &[
+                    Value::Int(60),
+                    text("p60"),
+                    Value::Int(10),
+                    Value::Real(1.0),
+                    Value::Null
+                ],
+            ]
+        );
+
+        let err = connection.query("SELECT * FROM nope").unwrap_err();
+        assert_eq!(err.to_string(), "no such table: nope");
+        assert!(Connection::open(file.0.with_extension("missing")).is_err());
+    }
+
+    // A statement prepared once runs again with each new binding, by number or by name, and is
+    // stepped a row at a time, giving what sqlite3 gives for each:
+    //
+    //     sqlite> SELECT id, name FROM people WHERE x = 'x2' AND age < 10 ORDER BY id;
+    //     18|p18
+    //     26|p26
+    //     34|p34
+    //     38|p38
+    //     46|p46
+    //     sqlite> SELECT count(*) FROM people WHERE x = 'x2' AND age < 100;
+    //     11
+    //     sqlite> SELECT id, name FROM people WHERE x = 'x2' AND age > 40 ORDER BY id;
+    //     42|p42
+    #[test]
+    fn binds_and_steps() {
+        let mut connection =
+            Connection::open_bytes(include_bytes!("../tests/fixtures/people.db").to_vec()).unwrap();
+        let mut statement = connection
+            .prepare("SELECT id, name FROM people WHERE x = :x AND age < ?2 ORDER BY id")
+            .unwrap();
+        assert_eq!(statement.parameter_count(), 2);
+        assert_eq!(statement.parameter_index(":x"), Some(1));
+        assert_eq!(statement.parameter_index(":y"), None);
+
+        statement.bind(1, "x2").unwrap();
+        statement.bind(2, 10).unwrap();
+        let mut ids = Vec::new();
+        while let Some(row) = statement.step().unwrap() {
+            ids.push(row.get::<i64>(0).unwrap());
+        }
+        assert_eq!(ids, [18, 26, 34, 38, 46]);
+        assert_eq!(statement.column_names(), ["id", "name"]);
+
+        // Running out of rows resets the statement, and the bindings stay.
+        assert_eq!(
+            statement.step().unwrap().unwrap().get::<String>(1).unwrap(),
+            "p18"
+        );
+        statement.reset();
+
+        let names = statement
+            .query_map(&["x2".into(), 100.into()], |row| row.get::<String>("name"))
+            .unwrap();
+        assert_eq!(names.len(), 11);
+        assert!(matches!(statement.bind(3, 1), Err(Error::Range)));
+
+        let mut statement = connection
+            .prepare("SELECT id, name FROM people WHERE x = ?1 AND age > ?2 ORDER BY id")
+            .unwrap();
+        let rows = statement.query(&["x2".into(), 40.into()]).unwrap();
+        assert_eq!(rows.rows.len(), 1);
+        assert_eq!(rows.rows[0].get::<i64>("id").unwrap(), 42);
+    }
+
+    // A read-only connection can't write, and holds its SHARED lock for as long as it is open, so
+    // no other connection can write either, as sqlite3 reports both:
+    //
+    //     $ sqlite3 -readonly people.db "INSERT INTO people(id) VALUES (100)"
+    //     Error: stepping, attempt to write a readonly database (8)
+    //     $ sqlite3 people.db "INSERT INTO people(id) VALUES (100)"   # while it is open
+    //     Error: stepping, database is locked (5)
+    #[test]
+    fn opens_read_only() {
+        let file = TempFile::new("read-only", include_bytes!("../tests/fixtures/people.db"));
+        let insert = "INSERT INTO people(id) VALUES (100)";
+        let mut reader = Connection::open_read_only(&file.0).unwrap();
+        let err = reader.query(insert).unwrap_err();
+        assert!(matches!(err, Error::ReadOnly), "{}", err);
+        assert_eq!(err.exit_code(), 8);
+
+        let mut writer = Connection::open(&file.0).unwrap();
+        let err = writer.query(insert).unwrap_err();
+        assert!(matches!(err, Error::Busy), "{}", err);
+        assert_eq!(err.exit_code(), 5);
+
+        drop(reader);
+        writer.query(insert).unwrap();
+    }
+
+    // Each connection counts what its writes changed, and knows the rowid it last inserted, as
+    // sqlite3 does:
+    //
+    //     sqlite> INSERT INTO people(name) VALUES ('a'), ('b');
+    //     sqlite> SELECT last_insert_rowid(), changes(), total_changes();
+    //     62|2|2
+    //     sqlite> DELETE FROM people WHERE id > 55;
+    //     sqlite> SELECT last_insert_rowid(), changes(), total_changes();
+    //     62|7|9
+    #[test]
+    fn counts_changes() {
+        let file = TempFile::new("changes", include_bytes!("../tests/fixtures/people.db"));
+        let mut connection = Connection::open(&file.0).unwrap();
+        let mut counts = |sql: &str| {
+            connection.query(sql).unwrap();
+            let rows = connection
+                .query("SELECT last_insert_rowid(), changes(), total_changes()")
+                .unwrap();
+            let counts = rows.rows[0].values().to_vec();
+            let accessors = [
+                Value::Int(connection.last_insert_rowid()),
+                Value::Int(connection.changes() as i64),
+                Value::Int(connection.total_changes() as i64),
+            ];
+            assert_eq!(counts, accessors);
+            counts
+        };
+
+        let expected = |counts: [i64; 3]| counts.map(Value::Int);
+        assert_eq!(
+            counts("INSERT INTO people(name) VALUES ('a'), ('b')"),
+            expected([62, 2, 2])
+        );
+        assert_eq!(
+            counts("DELETE FROM people WHERE id > 55"),
+            expected([62, 7, 9])
+        );
+    }
+
+    // The trace hook sees each statement as it runs, as Python's set_trace_callback does against
+    // sqlite3, and the update hook each row a write touches, in the order it touches them:
+    //
+    //     INSERT INTO people(name) VALUES ('a'), ('b')
+    //     DELETE FROM people WHERE id > 59
+    //     SELECT count(*) FROM people
+    //     (59,)
+    #[test]
+    fn calls_hooks() {
+        let file = TempFile::new("hooks", include_bytes!("../tests/fixtures/people.db"));
+        let mut connection = Connection::open(&file.0).unwrap();
+        let traced = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let updated = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let trace = traced.clone();
+        connection.set_trace(move |sql| trace.lock().unwrap().push(sql.to_string()));
+        let update = updated.clone();
+        connection.set_update_hook(move |action, table, rowid| {
+            update
+                .lock()
+                .unwrap()
+                .push((action, table.to_string(), rowid))
+        });
+
+        let statements = [
+            "INSERT INTO people(name) VALUES ('a'), ('b')",
+            "DELETE FROM people WHERE id > 59",
+            "SELECT count(*) FROM people",
+        ];
+        for sql in statements {
+            connection.query(sql).unwrap();
+        }
+        assert_eq!(*traced.lock().unwrap(), statements);
+        let people = |action, rowid| (action, "people".to_string(), rowid);
+        assert_eq!(
+            *updated.lock().unwrap(),
+            [
+                people(Action::Insert, 61),
+                people(Action::Insert, 62),
+                people(Action::Delete, 60),
+                people(Action::Delete, 61),
+                people(Action::Delete, 62),
+            ]
+        );
+        let rows = connection.query("SELECT count(*) FROM people").unwrap();
+        assert_eq!(rows.rows[0][0], Value::Int(59));
+    }
+
+    // A statement's stats say what its last run read, as sqlite3's `.stats stmt` does. A scan of
+    // the table misses the cache on its 37 pages, besides the schema's page 1 that sqlite3 reads
+    // while preparing, and steps through all 1000 rows, which sqlite3 counts as the 999 steps
+    // after the first. Finding the rows by the index takes no step of a full scan at all:
+    //
+    //     $ sqlite3 many.db ".stats stmt" "SELECT count(*) FROM m WHERE s LIKE '%5'"
+    //     Page cache misses:                   38
+    //     Fullscan Steps:                      999
+    //     $ sqlite3 many.db ".stats stmt" "SELECT count(*) FROM m WHERE n = 3"
+    //     Fullscan Steps:                      0
+    #[test]
+    fn reports_statement_stats() {
+        let mut connection = Connection::open_bytes(MANY).unwrap();
+        let mut stats = |sql: &str| {
+            let mut statement = connection.prepare(sql).unwrap();
+            let rows = statement.query(&[]).unwrap();
+            assert_eq!(rows.rows[0][0], Value::Int(100));
+            statement.stats()
+        };
+
+        let scan = stats("SELECT count(*) FROM m WHERE s LIKE '%5'");
+        assert_eq!(
+            (scan.pages_read, scan.index_seeks, scan.rows_scanned),
+            (37, 0, 1000)
+        );
+        assert_eq!(scan.rows_returned, 1);
+        let search = stats("SELECT count(*) FROM m WHERE n = 3");
+        assert_eq!((search.index_seeks, search.rows_scanned), (1, 100));
+        assert_eq!(search.rows_returned, 1);
+    }
+}
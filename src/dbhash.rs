@@ -0,0 +1,243 @@
+use crate::catalog::ObjectType;
+use crate::error::Result;
+use crate::output::quote_identifier;
+use crate::sql_engine::SqlEngine;
+use crate::{like, Db, Rows, Value};
+
+// Which parts of the database the hash covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Include {
+    All,
+    Content,
+    Schema,
+}
+
+// A SHA-1 hash of what the database holds, rather than of how its file lays it out, worked out
+// the same way as sqlite's dbhash program so the two can be compared. Two databases with the same
+// tables, rows and schema hash the same, however their pages are arranged, whatever is on their
+// freelists, and whatever their headers say.
+//
+// The rows of each ordinary table are hashed in rowid order, table after table in order of name
+// regardless of case, followed by the type, name, table and SQL of everything in the schema, in
+// the same order. sqlite's own tables and virtual tables are left out of the rows, as their
+// contents follow from the rest. With a pattern, only the tables whose names match it (as a LIKE
+// pattern) are hashed, along with the schema objects on them.
+pub fn hash(db: &Db, pattern: Option<&str>, include: Include) -> Result<String> {
+    let pattern = pattern.unwrap_or("%");
+    let mut sha1 = Sha1::new();
+
+    let mut records = db.catalog.objects().iter().collect::<Vec<_>>();
+    records.sort_by_key(|record| record.name.to_ascii_lowercase());
+
+    if include != Include::Schema {
+        for table in &records {
+            let hashed = table.object_type == ObjectType::Table
+                && table.module.is_none()
+                && !like("sqlite_%", &table.name)
+                && like(pattern, &table.name);
+            if hashed {
+                let sql = format!("SELECT * FROM {}", quote_identifier(&table.name));
+                hash_query(&mut sha1, db, &sql)?;
+            }
+        }
+    }
+
+    if include != Include::Content {
+        let mut rows = Rows::default();
+        SqlEngine::new(&mut rows)
+            .execute("SELECT type, name, tbl_name, sql FROM sqlite_schema", db)?;
+        let mut rows = rows
+            .rows
+            .iter()
+            .map(|row| row.values())
+            .filter(|values| like(pattern, &values[2].to_string()))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|values| values[1].to_string().to_ascii_lowercase());
+        for values in rows {
+            hash_values(&mut sha1, values);
+        }
+    }
+
+    Ok(sha1
+        .finish()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn hash_query(sha1: &mut Sha1, db: &Db, sql: &str) -> Result<()> {
+    let mut rows = Rows::default();
+    SqlEngine::new(&mut rows).execute(sql, db)?;
+    for row in &rows.rows {
+        hash_values(sha1, row.values());
+    }
+    Ok(())
+}
+
+// Each value is a tag for its type, followed by integers and reals as their 8 bytes, big endian,
+// or the bytes of text and blobs.
+fn hash_values(sha1: &mut Sha1, values: &[Value]) {
+    for value in values {
+        match value {
+            Value::Null => sha1.update(b"0"),
+            Value::Int(n) => {
+                sha1.update(b"1");
+                sha1.update(&n.to_be_bytes());
+            }
+            Value::Real(r) => {
+                sha1.update(b"2");
+                sha1.update(&r.to_bits().to_be_bytes());
+            }
+            Value::Text(s) => {
+                sha1.update(b"3");
+                sha1.update(s.as_bytes());
+            }
+            Value::Blob(b) => {
+                sha1.update(b"4");
+                sha1.update(b);
+            }
+        }
+    }
+}
+
+// SHA-1, as in FIPS 180-4.
+struct Sha1 {
+    state: [u32; 5],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Sha1 {
+    fn new() -> Self {
+        Self {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.length += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let take = (64 - self.buffer.len()).min(bytes.len());
+            self.buffer.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            if self.buffer.len() == 64 {
+                let block = std::mem::take(&mut self.buffer);
+                self.compress(block.as_slice().try_into().unwrap());
+                self.buffer = block;
+                self.buffer.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 20] {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.buffer.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0; 20];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn hex(digest: [u8; 20]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha1() {
+        let mut sha1 = Sha1::new();
+        sha1.update(b"abc");
+        assert_eq!(
+            hex(sha1.finish()),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+
+        // Fed a byte at a time, across many blocks.
+        let mut sha1 = Sha1::new();
+        for _ in 0..1000 {
+            sha1.update(b"a");
+        }
+        assert_eq!(
+            hex(sha1.finish()),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+
+    // The hashes dbhash gives for the fixtures, which between them have text, blobs, reals,
+    // integers at the edges of their range, NULLs and indexes.
+    #[test]
+    fn hashes_like_dbhash() {
+        let fixtures: [(&[u8], &str); 3] = [
+            (
+                include_bytes!("../tests/fixtures/indexes.db"),
+                "ae52414f06d19311584f4aa17eaa6afb374b3d36",
+            ),
+            (
+                include_bytes!("../tests/fixtures/names.db"),
+                "c670dd99307f61b0509b942ec1e7c0900a142d3f",
+            ),
+            (
+                include_bytes!("../tests/fixtures/people.db"),
+                "d69907b9984b6b385c48babc643b9cbff4176d45",
+            ),
+        ];
+        for (bytes, expected) in fixtures {
+            let db = Db::from_bytes(bytes.to_vec()).unwrap();
+            assert_eq!(hash(&db, None, Include::All).unwrap(), expected);
+        }
+
+        let db = Db::from_bytes(include_bytes!("../tests/fixtures/names.db").to_vec()).unwrap();
+        assert_eq!(
+            hash(&db, Some("plain"), Include::Schema).unwrap(),
+            "7ba29ace055bb86844b1ef6b8cee0da173f9c73b"
+        );
+    }
+}
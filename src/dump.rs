@@ -0,0 +1,216 @@
+use std::io::Write;
+
+use crate::catalog::ObjectType;
+use crate::error::{Error, Result};
+use crate::output::{push_insert, sql_literal};
+use crate::{like, Db, MasterPageRecord, ValueRef};
+
+// Writes the schema and contents of the database as SQL that the sqlite3 shell can read back in,
+// laid out the same way as its own .dump. With a pattern, only the objects whose name matches it
+// (as a LIKE pattern) are included, so an index is only dumped along with its table when both
+// names match.
+//
+// WITHOUT ROWID tables can't be read yet, so dumping one fails, as does dumping an fts5 table,
+// whose shadow tables include two of them, or a table whose SQL couldn't be understood. Either is
+// found out before anything is written, so a failed dump never leaves half a script behind.
+pub fn dump(db: &Db, pattern: Option<&str>, out: &mut impl Write) -> Result<()> {
+    let records = db.catalog.objects().to_vec();
+    let named =
+        |record: &MasterPageRecord| pattern.is_none_or(|pattern| like(pattern, &record.name));
+    // The shadow tables of a virtual table, named after it, go along with it.
+    let matches = |record: &MasterPageRecord| {
+        named(record)
+            || records.iter().any(|table| {
+                table.module.is_some()
+                    && named(table)
+                    && record
+                        .name
+                        .strip_prefix(table.name.as_str())
+                        .is_some_and(|rest| rest.starts_with('_'))
+            })
+    };
+
+    // Tables come first, so their rows are in place before any index is created. sqlite_sequence
+    // goes last, as it holds the autoincrement counters for the tables before it.
+    let mut tables = records
+        .iter()
+        .filter(|record| record.object_type == ObjectType::Table && matches(record))
+        .collect::<Vec<_>>();
+    tables.sort_by_key(|table| table.name == "sqlite_sequence");
+
+    for table in tables.iter().filter(|table| table.module.is_none()) {
+        table.usable()?;
+        if table.without_rowid {
+            return Err(Error::Unsupported(format!(
+                "dumping WITHOUT ROWID table {}",
+                table.name
+            )));
+        }
+    }
+
+    // Virtual tables go straight into the schema table, which defensive mode doesn't allow.
+    if records
+        .iter()
+        .any(|record| record.module.is_some() && matches(record))
+    {
+        writeln!(
+            out,
+            "/* WARNING: Script requires that SQLITE_DBCONFIG_DEFENSIVE be disabled */"
+        )?;
+    }
+    writeln!(out, "PRAGMA foreign_keys=OFF;")?;
+    writeln!(out, "BEGIN TRANSACTION;")?;
+
+    // sqlite's own tables can only be created with writable_schema on.
+    let mut writable_schema = false;
+
+    for table in tables {
+        // A virtual table's rows belong to its module, which keeps them in shadow tables that
+        // are dumped like any other.
+        if table.module.is_some() {
+            if !writable_schema {
+                writeln!(out, "PRAGMA writable_schema=ON;")?;
+                writable_schema = true;
+            }
+            writeln!(
+                out,
+                "INSERT INTO sqlite_schema(type,name,tbl_name,rootpage,sql)VALUES('table',{},{},0,{});",
+                sql_literal(ValueRef::Text(&table.name)),
+                sql_literal(ValueRef::Text(&table.table_name)),
+                sql_literal(ValueRef::Text(&table.sql))
+            )?;
+            continue;
+        }
+
+        if table.name == "sqlite_sequence" {
+            if !writable_schema {
+                writeln!(out, "PRAGMA writable_schema=ON;")?;
+                writable_schema = true;
+            }
+            writeln!(out, "CREATE TABLE IF NOT EXISTS {};", &table.sql[13..])?;
+            writeln!(out, "DELETE FROM sqlite_sequence;")?;
+        } else if table.name.starts_with("sqlite_stat") {
+            writeln!(out, "ANALYZE sqlite_schema;")?;
+        } else if table.name.starts_with("sqlite_") {
+            continue;
+        } else if table.sql.starts_with("CREATE TABLE \"")
+            || table.sql.starts_with("CREATE TABLE '")
+        {
+            writeln!(out, "CREATE TABLE IF NOT EXISTS {};", &table.sql[13..])?;
+        } else {
+            writeln!(out, "{};", table.sql)?;
+        }
+
+        let mut line = String::new();
+
+        for row in db.get_table_rows(table, None)? {
+            line.clear();
+            let values = (0..table.columns.len()).map(|column| {
+                if table.rowid_alias == Some(column) {
+                    ValueRef::Int(row.header.row_id as i64)
+                } else {
+                    table.stored_value(column, row.value(column))
+                }
+            });
+            push_insert(&mut line, &table.name, None, values);
+            writeln!(out, "{}", line)?;
+        }
+    }
+
+    // Then views, triggers and indexes, in that order, as sqlite3 sorts them by type.
+    let mut others = records
+        .iter()
+        .filter(|record| record.object_type != ObjectType::Table && matches(record))
+        .collect::<Vec<_>>();
+    others.sort_by_key(|record| match record.object_type {
+        ObjectType::View => 0,
+        ObjectType::Trigger => 1,
+        _ => 2,
+    });
+    for record in others {
+        writeln!(out, "{};", record.sql)?;
+    }
+
+    if writable_schema {
+        writeln!(out, "PRAGMA writable_schema=OFF;")?;
+    }
+    writeln!(out, "COMMIT;")?;
+
+    Ok(out.flush()?)
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::{Connection, Value};
+
+    // Every value of every row of a table, in rowid order.
+    #[allow(dead_code)]
+    fn contents(connection: &mut Connection, table: &str) -> Vec<Vec<Value>> {
+        let rows = connection
+            .query(&format!("SELECT * FROM \"{}\"", table))
+            .unwrap();
+        rows.rows
+            .iter()
+            .map(|row| {
+                (0..rows.columns.len())
+                    .map(|column| row.get_ref(column).unwrap().clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    // The dump of a table with an awkward name, quoted column names, and text, blobs, reals and
+    // integers at the edges of what can be written, reads back in to the same rows. Tables can't
+    // be created here, so the dump's rows go into the same tables emptied.
+    #[test]
+    fn reads_back_in() {
+        let bytes = include_bytes!("../tests/fixtures/names.db").to_vec();
+        let mut dumped = Vec::new();
+        dump(&Db::from_bytes(bytes.clone()).unwrap(), None, &mut dumped).unwrap();
+        let dumped = String::from_utf8(dumped).unwrap();
+
+        let mut original = Connection::open_bytes(bytes.clone()).unwrap();
+        let mut restored = Connection::open_bytes(bytes).unwrap();
+        for table in ["my t", "plain"] {
+            restored
+                .query(&format!("DELETE FROM \"{}\"", table))
+                .unwrap();
+        }
+        for line in dumped
+            .lines()
+            .filter(|line| line.starts_with("INSERT INTO"))
+        {
+            restored.query(line).unwrap();
+        }
+
+        for table in ["my t", "plain"] {
+            assert_eq!(
+                contents(&mut restored, table),
+                contents(&mut original, table),
+                "{}",
+                table
+            );
+        }
+    }
+
+    // A table that can't be read, whether it is WITHOUT ROWID or has SQL that couldn't be
+    // understood, fails the dump before any of it is written.
+    #[test]
+    fn fails_before_writing() {
+        let bytes = include_bytes!("../tests/fixtures/unreadable.db").to_vec();
+        let db = Db::from_bytes(bytes).unwrap();
+        for pattern in [None, Some("c"), Some("d")] {
+            let mut dumped = Vec::new();
+            assert!(dump(&db, pattern, &mut dumped).is_err(), "{:?}", pattern);
+            assert!(dumped.is_empty(), "{:?}", pattern);
+        }
+
+        let mut dumped = Vec::new();
+        dump(&db, Some("ok"), &mut dumped).unwrap();
+        assert!(String::from_utf8(dumped)
+            .unwrap()
+            .contains("INSERT INTO ok VALUES(1);"));
+    }
+}
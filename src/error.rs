@@ -0,0 +1,207 @@
+use std::io;
+use std::ops;
+use std::path::PathBuf;
+
+// Everything that can go wrong in the library, worded the way the sqlite3 shell words it so that
+// scripts written against it see the same messages and exit codes. The shell's own usage errors
+// still go through anyhow, and exit with 1 like most sqlite3 errors do.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // The database file couldn't be opened at all.
+    #[error("unable to open database \"{}\": unable to open database file", .0.display())]
+    CantOpen(PathBuf),
+    // The file opened, but it doesn't start with the SQLite header.
+    #[error("file is not a database")]
+    NotADatabase,
+    // Another process holds a lock on the database that keeps readers out.
+    #[error("database is locked")]
+    Busy,
+    // Reading the database file failed part way through.
+    #[error("disk I/O error: {0}")]
+    Io(#[from] io::Error),
+    // A page whose contents can't be right, like a cell pointer past the end of the page.
+    #[error("database disk image is malformed (page {page}: {message})")]
+    Corrupt { page: u32, message: String },
+    // A statement that couldn't be parsed, or compiled once it was.
+    #[error("{0}")]
+    Parse(String),
+    // Text in a statement that doesn't start any token, and where it is in the statement, in bytes.
+    #[error("unrecognized token: \"{text}\"")]
+    UnrecognizedToken {
+        text: String,
+        span: ops::Range<usize>,
+    },
+    // A token where the grammar doesn't allow one, with where it is in the statement, in bytes,
+    // when the parser was given that, and what the parser was looking for instead.
+    #[error("near \"{near}\": syntax error")]
+    Syntax {
+        near: String,
+        span: Option<ops::Range<usize>>,
+        expected: String,
+    },
+    #[error("no such table: {0}")]
+    NoSuchTable(String),
+    // Also what a Row returns for a column asked for by a name that it doesn't have.
+    #[error("no such column: {0}")]
+    NoSuchColumn(String),
+    #[error("no such rowid: {0}")]
+    NoSuchRowid(i64),
+    // A value that can't be read as a BLOB, or a table that no value can be: "value of type
+    // integer", "virtual table", "table without rowid".
+    #[error("cannot open {0}")]
+    CantOpenBlob(String),
+    #[error("no such function: {0}")]
+    NoSuchFunction(String),
+    // Valid SQL that the engine can't run yet.
+    #[error("not supported: {0}")]
+    Unsupported(String),
+    // A foreign key that refers to columns its parent doesn't have, or that aren't a key of it,
+    // as when the parent has no primary key for the key to refer to.
+    #[error("foreign key mismatch - \"{child}\" referencing \"{parent}\"")]
+    ForeignKeyMismatch { child: String, parent: String },
+    // Any statement that would change the database.
+    #[error("attempt to write a readonly database")]
+    ReadOnly,
+    // The SQL of an object in the schema couldn't be understood.
+    #[error("malformed database schema ({name}) - {message}")]
+    MalformedSchema { name: String, message: String },
+    // A dot command that doesn't exist, or was given the wrong arguments.
+    #[error("unknown command or invalid arguments:  \"{0}\". Enter \".help\" for help")]
+    UnknownCommand(String),
+    // A parameter number, or a column of a row, that doesn't exist.
+    #[error("column index out of range")]
+    Range,
+    // A value that can't be converted to the type it was asked for as.
+    #[error("datatype mismatch")]
+    Mismatch,
+    // A row that a write would have left breaking one of its table's constraints, like a second
+    // row with the same value in a UNIQUE column.
+    #[error("{0}")]
+    Constraint(String),
+    // An integer that doesn't fit in the type it was converted to.
+    #[error("integer overflow")]
+    IntegerOverflow,
+}
+
+impl Error {
+    // sqlite3 exits with the result code of the error, which is 1 (SQLITE_ERROR) for almost
+    // everything.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Busy => 5,
+            Error::ReadOnly => 8,
+            Error::Io(_) => 10,
+            Error::Corrupt { .. } | Error::MalformedSchema { .. } => 11,
+            Error::Mismatch => 20,
+            Error::Range => 25,
+            Error::NotADatabase => 26,
+            Error::Constraint(_) => 19,
+            _ => 1,
+        }
+    }
+
+    // A lock another process holds on the database is Busy, as it is for sqlite3.
+    pub(crate) fn locking(err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::WouldBlock => Error::Busy,
+            _ => Error::Io(err),
+        }
+    }
+
+    // Where a page couldn't be parsed, from the error its parser returned.
+    pub(crate) fn corrupt(page: u32, offset: usize, err: io::Error) -> Error {
+        let message = match err.kind() {
+            io::ErrorKind::UnexpectedEof => "runs past the end of the page".to_string(),
+            _ => err.to_string(),
+        };
+        Error::Corrupt {
+            page,
+            message: format!("offset {}: {}", offset, message),
+        }
+    }
+
+    // Where the error is in the statement, in bytes, when that's known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::UnrecognizedToken { span, .. } => Some(span.start),
+            Error::Syntax { span, .. } => span.as_ref().map(|span| span.start),
+            _ => None,
+        }
+    }
+
+    // Whether the error stopped a statement from being compiled, which sqlite3 reports as
+    // happening "in prepare".
+    pub fn is_prepare(&self) -> bool {
+        matches!(
+            self,
+            Error::Parse(_)
+                | Error::UnrecognizedToken { .. }
+                | Error::Syntax { .. }
+                | Error::NoSuchTable(_)
+                | Error::NoSuchColumn(_)
+                | Error::NoSuchFunction(_)
+                | Error::Unsupported(_)
+                | Error::ForeignKeyMismatch { .. }
+        )
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // Each kind of mistake in a statement is its own variant, worded as sqlite3 words it, and
+    // stops the statement in prepare with exit code 1, as it does there:
+    //
+    //     $ sqlite3 people.db "SELECT * FROM nope"
+    //     Error: in prepare, no such table: nope
+    #[test]
+    fn words_errors_like_sqlite3() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        let mut connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let mut error = |sql: &str| connection.query(sql).unwrap_err();
+
+        let err = error("SELECT * FROM nope");
+        assert!(matches!(&err, Error::NoSuchTable(table) if table == "nope"));
+        assert_eq!(err.to_string(), "no such table: nope");
+        let err = error("SELECT nope FROM people");
+        assert!(matches!(&err, Error::NoSuchColumn(column) if column == "nope"));
+        assert_eq!(err.to_string(), "no such column: nope");
+        let err = error("SELEC 1");
+        assert!(matches!(&err, Error::Syntax { .. }));
+        assert_eq!(err.to_string(), "near \"SELEC\": syntax error");
+        assert_eq!(err.offset(), Some(0));
+        let err = error("SELECT nope(1)");
+        assert!(matches!(&err, Error::NoSuchFunction(function) if function == "nope"));
+        assert_eq!(err.to_string(), "no such function: nope");
+        let err = error("SELECT 1 #");
+        assert!(matches!(&err, Error::UnrecognizedToken { .. }));
+        assert_eq!(err.to_string(), "unrecognized token: \"#\"");
+        assert_eq!(err.offset(), Some(9));
+
+        for sql in [
+            "SELECT * FROM nope",
+            "SELECT nope FROM people",
+            "SELEC 1",
+            "SELECT nope(1)",
+            "SELECT 1 #",
+        ] {
+            let err = error(sql);
+            assert!(err.is_prepare(), "{}", sql);
+            assert_eq!(err.exit_code(), 1, "{}", sql);
+        }
+
+        // A file that isn't a database is a different error with its own code, 26.
+        match Connection::open_bytes(&b"not a database"[..]) {
+            Err(err) => {
+                assert!(matches!(err, Error::NotADatabase));
+                assert_eq!(err.exit_code(), 26);
+            }
+            Ok(_) => panic!("opened a file that isn't a database"),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+use crate::catalog::{Affinity, ForeignKey, Table};
+use crate::error::{Error, Result};
+use crate::output::quote_identifier;
+use crate::sql_engine::SqlEngine;
+use crate::{Db, Rows, Value};
+
+// Looks for rows whose foreign keys refer to a parent row that isn't there, the way PRAGMA
+// foreign_key_check does, giving a row for each with the child table, the child row's rowid (NULL
+// in a WITHOUT ROWID table), the parent table, and which of the child's foreign keys it is. A key
+// with a NULL in it refers to nothing, so it can't be missing, and when the parent table doesn't
+// exist every other key is.
+//
+// Keys are numbered the way sqlite numbers them, from the last declared to the first, and the
+// keys missing from a row are given in that order. Tables are checked in the order they are in
+// the schema, or just the one that is named.
+pub(crate) fn check(db: &Db, table: Option<&str>) -> Result<Vec<Vec<Value>>> {
+    let tables = db.tables();
+    let mut problems = Vec::new();
+
+    for child in tables
+        .iter()
+        .filter(|child| table.is_none_or(|name| child.name().eq_ignore_ascii_case(name)))
+    {
+        let keys = child
+            .foreign_keys()
+            .iter()
+            .rev()
+            .map(|key| Key::new(db, tables, child, key))
+            .collect::<Result<Vec<_>>>()?;
+        if keys.is_empty() {
+            continue;
+        }
+
+        let mut columns = child
+            .columns()
+            .iter()
+            .map(|column| quote_identifier(column.name()))
+            .collect::<Vec<_>>();
+        if !child.without_rowid() {
+            columns.insert(0, "ROWID".to_string());
+        }
+        let mut rows = Rows::default();
+        SqlEngine::new(&mut rows).execute(
+            &format!(
+                "SELECT {} FROM {}",
+                columns.join(", "),
+                quote_identifier(child.name())
+            ),
+            db,
+        )?;
+
+        for row in &rows.rows {
+            let (rowid, values) = match child.without_rowid() {
+                true => (&Value::Null, row.values()),
+                false => (&row.values()[0], &row.values()[1..]),
+            };
+            for (fkid, key) in keys.iter().enumerate() {
+                if !key.missing(values) {
+                    continue;
+                }
+                problems.push(vec![
+                    Value::Text(child.name().to_string()),
+                    rowid.clone(),
+                    Value::Text(key.parent.clone()),
+                    Value::Int(fkid as i64),
+                ]);
+            }
+        }
+    }
+    Ok(problems)
+}
+
+// A foreign key, ready to look up rows of the child in the parent.
+struct Key {
+    parent: String,
+    // Where each of the key's columns is in the child's rows.
+    columns: Vec<usize>,
+    // The affinities of the parent's columns, and the keys in the parent, which is None when there
+    // is no parent table.
+    affinities: Vec<Affinity>,
+    keys: Option<HashSet<Vec<Value>>>,
+}
+
+impl Key {
+    fn new(db: &Db, tables: &[Table], child: &Table, key: &ForeignKey) -> Result<Self> {
+        // The parent's name is given as it is in the schema, when it is there to be found.
+        let parent = tables
+            .iter()
+            .find(|table| table.name().eq_ignore_ascii_case(key.parent()));
+        let parent_name = parent.map_or(key.parent(), |parent| parent.name());
+        let mismatch = || Error::ForeignKeyMismatch {
+            child: child.name().to_string(),
+            parent: parent_name.to_string(),
+        };
+
+        let columns = key
+            .from()
+            .iter()
+            .map(|name| {
+                child
+                    .columns()
+                    .iter()
+                    .position(|column| column.name().eq_ignore_ascii_case(name))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(mismatch)?;
+
+        let Some(parent) = parent else {
+            return Ok(Key {
+                parent: parent_name.to_string(),
+                columns,
+                affinities: Vec::new(),
+                keys: None,
+            });
+        };
+
+        // Leaving out the parent's columns means its primary key, which it has to have.
+        let mut to = key.to().to_vec();
+        if to.is_empty() {
+            let mut primary_key = parent
+                .columns()
+                .iter()
+                .filter_map(|column| Some((column.primary_key()?, column.name().to_string())))
+                .collect::<Vec<_>>();
+            primary_key.sort();
+            to = primary_key.into_iter().map(|(_, name)| name).collect();
+        }
+        if to.is_empty() || to.len() != columns.len() {
+            return Err(mismatch());
+        }
+        let affinities = to
+            .iter()
+            .map(|name| Some(parent.column(name)?.affinity()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(mismatch)?;
+
+        let mut rows = Rows::default();
+        let names = to
+            .iter()
+            .map(|name| quote_identifier(name))
+            .collect::<Vec<_>>();
+        SqlEngine::new(&mut rows).execute(
+            &format!(
+                "SELECT {} FROM {}",
+                names.join(", "),
+                quote_identifier(parent.name())
+            ),
+            db,
+        )?;
+        let keys = rows
+            .rows
+            .iter()
+            .map(|row| {
+                row.values()
+                    .iter()
+                    .zip(&affinities)
+                    .map(|(value, affinity)| key_value(value, *affinity))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Key {
+            parent: parent.name().to_string(),
+            columns,
+            affinities,
+            keys: Some(keys),
+        })
+    }
+
+    fn missing(&self, values: &[Value]) -> bool {
+        let values = self
+            .columns
+            .iter()
+            .map(|index| &values[*index])
+            .collect::<Vec<_>>();
+        if values.iter().any(|value| matches!(value, Value::Null)) {
+            return false;
+        }
+        let Some(keys) = &self.keys else {
+            return true;
+        };
+        let key = values
+            .iter()
+            .zip(&self.affinities)
+            .map(|(value, affinity)| key_value(value, *affinity))
+            .collect::<Vec<_>>();
+        !keys.contains(&key)
+    }
+}
+
+// A child's key is compared with the parent's once it has been given the affinity of the parent's
+// column, so that the text '1' refers to the integer 1 in an INTEGER column. A real that is a whole
+// number is equal to the integer, so it is made one, on both sides.
+fn key_value(value: &Value, affinity: Affinity) -> Value {
+    match affinity.apply(value.clone()) {
+        Value::Real(r) if r.fract() == 0.0 && r.abs() < 9.2e18 => Value::Int(r as i64),
+        value => value,
+    }
+}
@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::vtab::{Constraint, ConstraintOp, VirtualCursor, VirtualTable};
+use crate::{Db, MasterPageRecord, TableLeafRecord, Value, ValueRef};
+
+// Full text search tables, as made by sqlite's fts3, fts4 and fts5 modules.
+//
+// The text of every row is kept in a shadow table named `<table>_content`, whose first column is
+// the rowid and whose other columns are the table's, in order. MATCH is answered by splitting that
+// text into words the way the default tokenizers do, rather than by reading the full text index,
+// whose format is different for every module. A query costs a scan of the table, but finds the
+// same rows for what is supported: words, all of which must be found, "quoted phrases", prefixes
+// like `qu*`, OR between them, and words limited to one column with `column:word`. The porter
+// tokenizer's stemming isn't done, so a table created with it only matches words as written.
+pub(crate) struct Fts<'a> {
+    db: &'a Db,
+    columns: Vec<String>,
+    content: Arc<MasterPageRecord>,
+}
+
+pub(crate) fn is_fts(module: &str) -> bool {
+    ["FTS3", "FTS4", "FTS5"].contains(&module.to_ascii_uppercase().as_str())
+}
+
+impl<'a> Fts<'a> {
+    pub(crate) fn open(db: &'a Db, table: &MasterPageRecord) -> Result<Self> {
+        let module = table.module.as_ref().unwrap();
+
+        // A contentless table, or one whose content is in another table, has no content table.
+        let external = module.args.iter().any(|arg| {
+            arg.split_once('=')
+                .is_some_and(|(option, _)| option.trim().eq_ignore_ascii_case("CONTENT"))
+        });
+        if external {
+            return Err(Error::Unsupported(format!(
+                "{} table without its own content",
+                module.name.to_ascii_lowercase()
+            )));
+        }
+
+        Ok(Self {
+            db,
+            columns: table.column_names(),
+            content: db.get_table(&format!("{}_content", table.name))?,
+        })
+    }
+}
+
+impl VirtualTable for Fts<'_> {
+    fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>> {
+        Ok(Box::new(FtsCursor {
+            fts: self,
+            rows: Vec::new(),
+            position: 0,
+        }))
+    }
+}
+
+struct FtsCursor<'a> {
+    fts: &'a Fts<'a>,
+    rows: Vec<TableLeafRecord>,
+    position: usize,
+}
+
+impl VirtualCursor for FtsCursor<'_> {
+    fn filter(&mut self, _args: &[Value], constraints: &[Constraint]) -> Result<()> {
+        let mut rows = self.fts.db.get_table_rows(&self.fts.content, None)?;
+
+        for constraint in constraints {
+            if constraint.op != ConstraintOp::Match {
+                continue;
+            }
+            // MATCH against the table itself searches every column.
+            let column = (constraint.column < self.fts.columns.len()).then_some(constraint.column);
+            let query = match &constraint.values[0] {
+                Value::Null => String::new(),
+                value => value.to_string(),
+            };
+            let alternatives = parse_query(&query, &self.fts.columns, column);
+
+            rows.retain(|row| {
+                let words = (0..self.fts.columns.len())
+                    .map(|index| match row.value(index + 1) {
+                        ValueRef::Null => Vec::new(),
+                        value => tokenize(&value.to_string()),
+                    })
+                    .collect::<Vec<_>>();
+
+                alternatives.iter().any(|phrases| {
+                    phrases.iter().all(|phrase| {
+                        words.iter().enumerate().any(|(index, words)| {
+                            phrase.column.is_none_or(|column| column == index)
+                                && phrase.found_in(words)
+                        })
+                    })
+                })
+            });
+        }
+
+        self.rows = rows;
+        self.position = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.position += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.position >= self.rows.len()
+    }
+
+    fn column(&self, index: usize) -> Result<Value> {
+        Ok(self.rows[self.position].value(index + 1).to_value())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rows[self.position].header.row_id as i64)
+    }
+}
+
+// Words that have to be found one after the other, the last of which may only be a prefix.
+struct Phrase {
+    column: Option<usize>,
+    words: Vec<String>,
+    prefix: bool,
+}
+
+impl Phrase {
+    fn found_in(&self, words: &[String]) -> bool {
+        let last = self.words.len() - 1;
+        words.windows(self.words.len()).any(|window| {
+            window
+                .iter()
+                .zip(&self.words)
+                .enumerate()
+                .all(|(index, (word, wanted))| {
+                    if self.prefix && index == last {
+                        word.starts_with(wanted.as_str())
+                    } else {
+                        word == wanted
+                    }
+                })
+        })
+    }
+}
+
+// Parses a full text query into alternatives separated by OR, each a list of phrases that must all
+// be found. An empty query matches nothing.
+fn parse_query(query: &str, columns: &[String], column: Option<usize>) -> Vec<Vec<Phrase>> {
+    let mut alternatives = vec![Vec::new()];
+    let mut rest = query.trim_start();
+
+    while !rest.is_empty() {
+        let (mut text, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        let quoted = rest.starts_with('"');
+        rest = after.trim_start();
+
+        if !quoted {
+            match text {
+                "OR" => {
+                    alternatives.push(Vec::new());
+                    continue;
+                }
+                "AND" => continue,
+                _ => {}
+            }
+        }
+
+        let mut phrase_column = column;
+        if let Some((name, word)) = text.split_once(':').filter(|_| !quoted) {
+            if let Some(index) = columns.iter().position(|c| c.eq_ignore_ascii_case(name)) {
+                phrase_column = Some(index);
+                text = word;
+            }
+        }
+
+        let words = tokenize(text);
+        if !words.is_empty() {
+            alternatives.last_mut().unwrap().push(Phrase {
+                column: phrase_column,
+                words,
+                prefix: text.ends_with('*'),
+            });
+        }
+    }
+
+    alternatives.retain(|phrases| !phrases.is_empty());
+    alternatives
+}
+
+// Splits text into lower case words the way the unicode61 and simple tokenizers do: a word is a
+// run of letters and digits, and everything else separates words.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
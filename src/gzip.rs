@@ -0,0 +1,419 @@
+use std::io;
+
+// Reads gzip files, for databases that are shipped compressed. The whole file is inflated into
+// memory when it is opened, and read from there like a `:memory:` database.
+//
+// The decoder follows RFC 1951 (DEFLATE) and RFC 1952 (gzip), decoding Huffman codes a bit at a
+// time the way zlib's `puff` does. That is slower than a table driven decoder, but it only runs
+// once per file.
+
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+// Inflates every member of a gzip file, checking each one against the CRC and size in its trailer.
+pub fn decompress(mut bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    while !bytes.is_empty() {
+        let start = out.len();
+        let header = member_header_len(bytes)?;
+
+        let mut input = Bits::new(&bytes[header..]);
+        inflate(&mut input, &mut out)?;
+        let rest = &bytes[header + input.position()..];
+
+        let trailer = rest
+            .get(..8)
+            .ok_or_else(|| invalid("truncated gzip trailer"))?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(invalid("gzip checksum mismatch"));
+        }
+        bytes = &rest[8..];
+    }
+
+    Ok(out)
+}
+
+// The length of the header in front of a member's compressed data.
+fn member_header_len(bytes: &[u8]) -> io::Result<usize> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let truncated = || invalid("truncated gzip header");
+    if !is_gzip(bytes) {
+        return Err(invalid("not a gzip file"));
+    }
+    // Deflate is the only compression method gzip defines.
+    if bytes.get(2) != Some(&8) {
+        return Err(invalid("unknown gzip compression method"));
+    }
+    let flags = *bytes.get(3).ok_or_else(truncated)?;
+
+    // Magic, method, flags, modification time, extra flags and operating system.
+    let mut position = 10;
+    if flags & FEXTRA != 0 {
+        let len = bytes.get(position..position + 2).ok_or_else(truncated)?;
+        position += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    // The original file name and a comment are both zero terminated.
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let len = bytes
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                .ok_or_else(truncated)?;
+            position += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        position += 2;
+    }
+
+    if position > bytes.len() {
+        return Err(truncated());
+    }
+    Ok(position)
+}
+
+// Reads bits from the front of a byte slice, least significant bit first.
+struct Bits<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .bytes
+                .get(self.position)
+                .ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer = self.buffer.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Drops what is left of the current byte, for stored blocks which start on a byte boundary.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    // How many bytes have been used, counting a partly read byte as used.
+    fn position(&self) -> usize {
+        self.position
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // A code may be incomplete, but it can't have more codes of a length than there is room
+        // for.
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(invalid("oversubscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, input: &mut Bits) -> io::Result<u16> {
+        // Codes of each length are consecutive numbers, starting where the shorter ones left off.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = *count as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+fn inflate(input: &mut Bits, out: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => stored(input, out)?,
+            1 => {
+                let (lengths, distances) = fixed_codes()?;
+                codes(input, out, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(input)?;
+                codes(input, out, &lengths, &distances)?;
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn stored(input: &mut Bits, out: &mut Vec<u8>) -> io::Result<()> {
+    input.align();
+    let header = input
+        .bytes
+        .get(input.position..input.position + 4)
+        .ok_or_else(|| invalid("truncated stored block"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if len != !complement {
+        return Err(invalid("stored block length mismatch"));
+    }
+
+    let start = input.position + 4;
+    let data = input
+        .bytes
+        .get(start..start + len as usize)
+        .ok_or_else(|| invalid("truncated stored block"))?;
+    out.extend_from_slice(data);
+    input.position = start + len as usize;
+    Ok(())
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(input: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    // The code lengths for the code length code come in this order.
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_length_count = input.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid("too many Huffman codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for index in &ORDER[..code_length_count] {
+        code_lengths[*index] = input.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let (length, repeat) = match code_length_code.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *index
+                    .checked_sub(1)
+                    .and_then(|previous| lengths.get(previous))
+                    .ok_or_else(|| invalid("repeated length with no first length"))?;
+                (previous, 3 + input.bits(2)? as usize)
+            }
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        let run = lengths
+            .get_mut(index..index + repeat)
+            .ok_or_else(|| invalid("too many code lengths"))?;
+        run.fill(length);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(invalid("no end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn codes(
+    input: &mut Bits,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = lengths.decode(input)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length code"));
+                }
+                let length = LENGTH_BASE[symbol] as usize
+                    + input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = distances.decode(input)? as usize;
+                if symbol >= DISTANCE_BASE.len() {
+                    return Err(invalid("invalid distance code"));
+                }
+                let distance = DISTANCE_BASE[symbol] as usize
+                    + input.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+
+                // The copy can overlap what it is writing, so it goes a byte at a time.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // What the fixtures were compressed from, by Python's zlib.
+    #[allow(dead_code)]
+    fn text(lines: usize) -> Vec<u8> {
+        (0..lines)
+            .flat_map(|i| format!("line {}: the quick brown fox\n", i).into_bytes())
+            .collect()
+    }
+
+    // The kind of the first deflate block of a member with no optional header fields.
+    #[allow(dead_code)]
+    fn block_type(member: &[u8]) -> u8 {
+        (member[10] >> 1) & 3
+    }
+
+    #[test]
+    fn inflates_each_block_type() {
+        let stored = include_bytes!("../tests/fixtures/stored.gz");
+        let fixed = include_bytes!("../tests/fixtures/fixed.gz");
+        let dynamic = include_bytes!("../tests/fixtures/dynamic.gz");
+        for (member, kind) in [(&stored[..], 0), (&fixed[..], 1), (&dynamic[..], 2)] {
+            assert_eq!(block_type(member), kind);
+            assert_eq!(decompress(member).unwrap(), text(200), "{}", kind);
+        }
+    }
+
+    // Members follow one another, each with its own header and trailer, and inflate to what they
+    // hold one after the other. A header can carry a file name, as gzip itself writes.
+    #[test]
+    fn inflates_every_member() {
+        let mut expected = text(100);
+        expected.extend_from_slice(b"second member\n");
+        let multi = include_bytes!("../tests/fixtures/multi.gz");
+        assert_eq!(decompress(multi).unwrap(), expected);
+
+        let mut named = include_bytes!("../tests/fixtures/stored.gz").to_vec();
+        named[3] |= 0x08;
+        named.splice(10..10, b"text.txt\0".iter().copied());
+        named.extend_from_slice(multi);
+        let mut expected_named = text(200);
+        expected_named.extend_from_slice(&expected);
+        assert_eq!(decompress(&named).unwrap(), expected_named);
+    }
+
+    // A member cut short, or whose trailer doesn't match what it inflates to, fails.
+    #[test]
+    fn rejects_damaged_members() {
+        let dynamic = include_bytes!("../tests/fixtures/dynamic.gz");
+        for len in [5, 20, dynamic.len() - 4] {
+            assert!(decompress(&dynamic[..len]).is_err(), "{}", len);
+        }
+
+        let mut bad_crc = dynamic.to_vec();
+        let crc = bad_crc.len() - 8;
+        bad_crc[crc] ^= 1;
+        assert!(decompress(&bad_crc).is_err());
+    }
+}
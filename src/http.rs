@@ -0,0 +1,340 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+
+// A database file on a web server, read a piece at a time with HTTP range requests, so a query
+// only downloads the pages it touches. Requests go out over one kept-alive connection, which is
+// opened again if the server closes it.
+//
+// There is no TLS, so only plain `http://` URLs can be read, and an `https://` one fails. Objects
+// in S3 and Google Cloud Storage can be named with `s3://bucket/key` and `gs://bucket/key`, and
+// are read the same way from the store's HTTP endpoint. AWS itself is never reached without TLS,
+// so an S3 object is only read from the endpoint AWS_ENDPOINT_URL names, like a store on the local
+// network. Requests aren't signed, so the object has to be public, or the endpoint has to be one
+// that doesn't ask for credentials, and one that does fails with PermissionDenied.
+#[derive(Debug)]
+pub struct HttpFile {
+    host: String,
+    port: u16,
+    path: String,
+    len: u64,
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl HttpFile {
+    pub fn open(url: &str) -> io::Result<Self> {
+        let aws_endpoint = std::env::var("AWS_ENDPOINT_URL").ok();
+        let url = object_store_url(url, aws_endpoint)?.unwrap_or_else(|| url.to_string());
+        let url = url.as_str();
+
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} can't be read, as https isn't supported", url),
+                ))
+            }
+            _ => return Err(invalid_url(url)),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid_url(url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid_url(url));
+        }
+
+        let mut file = Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            len: 0,
+            connection: Mutex::new(None),
+        };
+
+        // The total size comes back with any range, so ask for the database header.
+        let mut header = [0; 100];
+        file.len = file.fetch(&mut header, 0)?;
+        Ok(file)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if offset + buf.len() as u64 > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.fetch(buf, offset).map(|_| ())
+    }
+
+    // Fills `buf` from `offset` onwards, and returns the size of the whole file. A server that
+    // closed the connection since the last request gets one more try on a new one, but a server
+    // that answered, even with an error status, isn't asked again.
+    fn fetch(&self, buf: &mut [u8], offset: u64) -> io::Result<u64> {
+        let mut connection = self.connection.lock().unwrap();
+
+        if let Some(stream) = connection.as_mut() {
+            match self.request(stream, buf, offset) {
+                Ok(Ok(len)) => return Ok(len),
+                Ok(Err(err)) => {
+                    *connection = None;
+                    return Err(err);
+                }
+                Err(_) => *connection = None,
+            }
+        }
+
+        let stream = connection.insert(BufReader::new(TcpStream::connect((
+            self.host.as_str(),
+            self.port,
+        ))?));
+        let result = self
+            .request(stream, buf, offset)
+            .and_then(|response| response);
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+
+    // Sends one range request. The outer error is the connection failing, and the inner one is
+    // the server's answer being an error.
+    fn request(
+        &self,
+        stream: &mut BufReader<TcpStream>,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<io::Result<u64>> {
+        let last = offset + buf.len() as u64 - 1;
+        write!(
+            stream.get_mut(),
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: keep-alive\r\n\r\n",
+            self.path,
+            self.host,
+            offset,
+            last
+        )?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+
+        let mut content_length = None;
+        let mut total = None;
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<u64>().ok();
+            } else if name.eq_ignore_ascii_case("content-range") {
+                // bytes FIRST-LAST/TOTAL
+                total = value
+                    .rsplit_once('/')
+                    .and_then(|(_, total)| total.parse().ok());
+            }
+        }
+
+        match status.as_str() {
+            "206" => {}
+            "200" => {
+                return Ok(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server doesn't support range requests",
+                )))
+            }
+            "404" | "410" => return Ok(Err(io::ErrorKind::NotFound.into())),
+            // S3 and Cloud Storage answer an unsigned request for an object that isn't public
+            // with 403.
+            "401" | "403" => {
+                return Ok(Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "the server refused access to {} (HTTP {}); requests aren't signed, so \
+                         only public objects can be read",
+                        self.path, status
+                    ),
+                )))
+            }
+            _ => {
+                return Ok(Err(io::Error::other(format!(
+                    "unexpected HTTP status {}",
+                    status
+                ))))
+            }
+        }
+
+        match (content_length, total) {
+            (Some(length), Some(total)) if length == buf.len() as u64 => {
+                stream.read_exact(buf)?;
+                Ok(Ok(total))
+            }
+            _ => Ok(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the server sent a different range than was asked for",
+            ))),
+        }
+    }
+}
+
+// The HTTP URL of an object in an object store, or None for a URL that isn't one. S3 objects are
+// fetched path style from AWS_ENDPOINT_URL, the variable the AWS tools use to point at another S3
+// compatible store, and without it there is nowhere to fetch them from but AWS, over https.
+fn object_store_url(url: &str, aws_endpoint: Option<String>) -> io::Result<Option<String>> {
+    let Some((scheme, object)) = url.split_once("://") else {
+        return Ok(None);
+    };
+    let endpoint = match scheme {
+        "s3" => aws_endpoint.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} can't be read from AWS, as https isn't supported; set AWS_ENDPOINT_URL \
+                     to an http endpoint to read it from another store",
+                    url
+                ),
+            )
+        })?,
+        "gs" => "http://storage.googleapis.com".to_string(),
+        _ => return Ok(None),
+    };
+    Ok(Some(format!(
+        "{}/{}",
+        endpoint.trim_end_matches('/'),
+        object
+    )))
+}
+
+fn invalid_url(url: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid URL: {}", url))
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use std::net::TcpListener;
+
+    // An https URL fails up front rather than being fetched over plain http.
+    #[test]
+    fn refuses_https() {
+        let err = HttpFile::open("https://example.com/x.db").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("https"), "{}", err);
+    }
+
+    // A server that wants a signed request, as S3 does for an object that isn't public, says
+    // access was refused instead of answering like a broken server.
+    #[test]
+    fn refused_access() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        });
+
+        let err = HttpFile::open(&format!("http://127.0.0.1:{}/bucket/x.db", port))
+            .err()
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("403"), "{}", err);
+    }
+
+    // An error status is the server's answer, so asking again on a new connection can't change
+    // it.
+    #[test]
+    fn doesnt_retry_error_statuses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let skip_request = |request: &mut BufReader<TcpStream>| {
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+            };
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = BufReader::new(stream.try_clone().unwrap());
+            skip_request(&mut request);
+            stream
+                .write_all(b"HTTP/1.1 206 Partial Content\r\nContent-Length: 100\r\nContent-Range: bytes 0-99/1000\r\n\r\n")
+                .unwrap();
+            stream.write_all(&[0; 100]).unwrap();
+            skip_request(&mut request);
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+
+            // Any second connection is a retry.
+            listener.set_nonblocking(true).unwrap();
+            let until = std::time::Instant::now() + std::time::Duration::from_millis(200);
+            while std::time::Instant::now() < until {
+                if let Ok((mut stream, _)) = listener.accept() {
+                    stream.set_nonblocking(false).unwrap();
+                    skip_request(&mut BufReader::new(stream.try_clone().unwrap()));
+                    stream
+                        .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                        .unwrap();
+                    return true;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            false
+        });
+
+        let file = HttpFile::open(&format!("http://127.0.0.1:{}/x.db", port)).unwrap();
+        assert_eq!(file.len(), 1000);
+        let err = file.read_exact_at(&mut [0; 100], 100).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!server.join().unwrap(), "the request was retried");
+    }
+
+    // S3 objects come from the endpoint they're pointed at, or not at all, while Cloud Storage
+    // serves public objects over plain http.
+    #[test]
+    fn finds_objects_in_stores() {
+        let err = object_store_url("s3://bucket/x.db", None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("AWS_ENDPOINT_URL"), "{}", err);
+        assert_eq!(
+            object_store_url("s3://bucket/x.db", Some("http://minio:9000/".to_string())).unwrap(),
+            Some("http://minio:9000/bucket/x.db".to_string())
+        );
+        assert_eq!(
+            object_store_url("gs://bucket/dir/x.db", None).unwrap(),
+            Some("http://storage.googleapis.com/bucket/dir/x.db".to_string())
+        );
+        assert_eq!(object_store_url("http://host/x.db", None).unwrap(), None);
+    }
+}
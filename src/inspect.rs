@@ -0,0 +1,889 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::btree::{too_deep, MAX_DEPTH};
+use crate::catalog::ObjectType;
+use crate::error::{Error, Result};
+use crate::output::sql_literal;
+use crate::pager::Pager;
+use crate::{
+    invalid, load_page, read_page, ByteReader, DbHeader, DbPageHeader, DbRecord, MasterPageRecord,
+    PageType, Value,
+};
+
+// Tools for looking at how a database is laid out on disk, rather than at what it holds.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeFormat {
+    Ascii,
+    Dot,
+}
+
+// One page of a b-tree, with everything below it.
+struct Node {
+    page: u32,
+    page_type: PageType,
+    cell_count: usize,
+    // The keys of the first and last cell on the page.
+    keys: Option<(String, String)>,
+    children: Vec<Node>,
+}
+
+fn walk(pager: &Pager, page: u32, depth: usize) -> Result<Node> {
+    if depth >= MAX_DEPTH {
+        return Err(too_deep(page));
+    }
+    let db_page = load_page(pager, page)?;
+
+    let key = |record: &DbRecord| match record {
+        DbRecord::TableLeafRecord(trecord) => trecord.header.row_id.to_string(),
+        DbRecord::InteriorTableRecord(irecord) => irecord.key.to_string(),
+        DbRecord::IndexLeafRecord(ilrecord) => index_key(&ilrecord.values),
+        DbRecord::InteriorIndexRecord(irecord) => index_key(&irecord.values),
+    };
+    let keys = match (db_page.records.first(), db_page.records.last()) {
+        (Some(first), Some(last)) => Some((key(first), key(last))),
+        _ => None,
+    };
+
+    // Interior pages point at a child from every cell, and the rightmost child from the header.
+    let children = db_page
+        .records
+        .iter()
+        .filter_map(|record| match record {
+            DbRecord::InteriorTableRecord(irecord) => Some(irecord.left_child_page),
+            DbRecord::InteriorIndexRecord(irecord) => Some(irecord.left_child),
+            _ => None,
+        })
+        .chain(db_page.header.rightmost_pointer)
+        .map(|child| walk(pager, child, depth + 1))
+        .collect::<Result<_>>()?;
+
+    Ok(Node {
+        page,
+        page_type: db_page.header.page_type,
+        cell_count: db_page.records.len(),
+        keys,
+        children,
+    })
+}
+
+fn index_key(values: &[Value]) -> String {
+    let values = values
+        .iter()
+        .map(|value| sql_literal(value.as_value_ref()))
+        .collect::<Vec<_>>();
+    format!("({})", values.join(","))
+}
+
+impl Node {
+    fn describe(&self) -> String {
+        let page_type = page_type_name(&self.page_type);
+        let cells = if self.cell_count == 1 {
+            "cell"
+        } else {
+            "cells"
+        };
+
+        match &self.keys {
+            Some((first, last)) => format!(
+                "page {}: {}, {} {}, keys {} .. {}",
+                self.page, page_type, self.cell_count, cells, first, last
+            ),
+            None => format!("page {}: {}, no cells", self.page, page_type),
+        }
+    }
+}
+
+// Prints the b-tree rooted at `root_page`, either as an indented tree or as a Graphviz digraph
+// that can be rendered with `dot -Tsvg`.
+pub fn print_btree(
+    pager: &Pager,
+    root_page: u32,
+    format: TreeFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let root = walk(pager, root_page, 0)?;
+
+    match format {
+        TreeFormat::Ascii => {
+            writeln!(out, "{}", root.describe())?;
+            write_ascii_children(&root, "", out)?;
+        }
+        TreeFormat::Dot => {
+            writeln!(out, "digraph btree {{")?;
+            writeln!(out, "  node [shape=box, fontname=monospace];")?;
+            write_dot(&root, out)?;
+            writeln!(out, "}}")?;
+        }
+    }
+
+    Ok(out.flush()?)
+}
+
+fn write_ascii_children(node: &Node, prefix: &str, out: &mut impl Write) -> io::Result<()> {
+    for (i, child) in node.children.iter().enumerate() {
+        let last = i + 1 == node.children.len();
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        writeln!(out, "{}{}{}", prefix, branch, child.describe())?;
+        write_ascii_children(child, &format!("{}{}", prefix, indent), out)?;
+    }
+
+    Ok(())
+}
+
+fn write_dot(node: &Node, out: &mut impl Write) -> io::Result<()> {
+    let label = node.describe().replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(out, "  p{} [label=\"{}\"];", node.page, label)?;
+
+    for child in &node.children {
+        writeln!(out, "  p{} -> p{};", node.page, child.page)?;
+        write_dot(child, out)?;
+    }
+
+    Ok(())
+}
+
+// A labelled span of bytes on a page.
+struct Region {
+    start: usize,
+    end: usize,
+    label: String,
+}
+
+// Prints an annotated hexdump of one page: what its header says, where each cell is, and the raw
+// bytes with every region labelled where it begins. Runs of zero bytes that nothing starts in are
+// collapsed to a `*`, like hexdump does.
+pub fn print_pagedump(
+    pager: &Pager,
+    header: &DbHeader,
+    page_number: u32,
+    out: &mut impl Write,
+) -> Result<()> {
+    let page = read_page(pager, page_number)?;
+    let usable_size = page.len() - header.reserved_space as usize;
+    let mut regions = Vec::new();
+
+    writeln!(out, "Page {} ({} bytes)", page_number, page.len())?;
+
+    // The first page starts with the database header, so its b-tree header sits after it.
+    let header_offset = if page_number == 1 {
+        regions.push(Region {
+            start: 0,
+            end: 100,
+            label: "database header".to_string(),
+        });
+        100
+    } else {
+        0
+    };
+
+    // Overflow and freelist pages have no header of their own, so all there is to show is bytes.
+    if !matches!(page[header_offset], 0x02 | 0x05 | 0x0a | 0x0d) {
+        writeln!(
+            out,
+            "Not a b-tree page (type byte 0x{:02x})",
+            page[header_offset]
+        )?;
+        writeln!(out)?;
+        return Ok(write_hexdump(&page, &regions, out)?);
+    }
+
+    let page_header = DbPageHeader::parse(&mut &page[header_offset..])
+        .map_err(|err| Error::corrupt(page_number, header_offset, err))?;
+    let header_size = match page_header.rightmost_pointer {
+        Some(_) => 12,
+        None => 8,
+    };
+    let content_start = match page_header.cell_content_area_offset {
+        0 => 65536,
+        offset => offset as usize,
+    };
+
+    writeln!(
+        out,
+        "Header at {}..{}",
+        header_offset,
+        header_offset + header_size
+    )?;
+    writeln!(
+        out,
+        "  page type            0x{:02x} ({})",
+        page[header_offset],
+        page_type_name(&page_header.page_type)
+    )?;
+    writeln!(
+        out,
+        "  first freeblock      {}",
+        page_header.first_freeblock
+    )?;
+    writeln!(out, "  cell count           {}", page_header.cell_count)?;
+    writeln!(out, "  cell content start   {}", content_start)?;
+    writeln!(
+        out,
+        "  fragmented bytes     {}",
+        page_header.fragmented_free_bytes
+    )?;
+    if let Some(rightmost_pointer) = page_header.rightmost_pointer {
+        writeln!(out, "  rightmost pointer    {}", rightmost_pointer)?;
+    }
+    regions.push(Region {
+        start: header_offset,
+        end: header_offset + header_size,
+        label: "page header".to_string(),
+    });
+
+    let pointers_start = header_offset + header_size;
+    let pointers_end = pointers_start + 2 * page_header.cells.len();
+    regions.push(Region {
+        start: pointers_start,
+        end: pointers_end,
+        label: "cell pointers".to_string(),
+    });
+    if pointers_end < content_start {
+        regions.push(Region {
+            start: pointers_end,
+            end: content_start,
+            label: "unallocated".to_string(),
+        });
+    }
+
+    writeln!(out, "Cells")?;
+    for (i, pointer) in page_header.cells.iter().enumerate() {
+        let start = *pointer as usize;
+        let cell = page
+            .get(start..)
+            .ok_or_else(|| invalid("cell pointer past the end of the page"))
+            .and_then(|cell| cell_extent(&page_header.page_type, cell, usable_size))
+            .map_err(|err| Error::corrupt(page_number, start, err))?;
+        let (size, mut key) = (cell.size, cell.key);
+        if let Some(overflow_page) = cell.overflow_page {
+            key.push_str(&format!(", overflows to page {}", overflow_page));
+        }
+        writeln!(
+            out,
+            "  cell {:<4} at {}..{} ({} bytes), {}",
+            i,
+            start,
+            start + size,
+            size,
+            key
+        )?;
+        regions.push(Region {
+            start,
+            end: start + size,
+            label: format!("cell {}", i),
+        });
+    }
+
+    // Freeblocks form a chain through the cell content area: each starts with the offset of the
+    // next one and its own size.
+    let mut freeblock = page_header.first_freeblock as usize;
+    while freeblock != 0 && freeblock + 4 <= usable_size {
+        let next = u16::from_be_bytes([page[freeblock], page[freeblock + 1]]) as usize;
+        let size = u16::from_be_bytes([page[freeblock + 2], page[freeblock + 3]]) as usize;
+        regions.push(Region {
+            start: freeblock,
+            end: freeblock + size,
+            label: "freeblock".to_string(),
+        });
+        if next <= freeblock {
+            break;
+        }
+        freeblock = next;
+    }
+
+    if usable_size < page.len() {
+        regions.push(Region {
+            start: usable_size,
+            end: page.len(),
+            label: "reserved".to_string(),
+        });
+    }
+
+    writeln!(out)?;
+    Ok(write_hexdump(&page, &regions, out)?)
+}
+
+fn page_type_name(page_type: &PageType) -> &'static str {
+    match page_type {
+        PageType::InteriorIndex => "interior index",
+        PageType::InteriorTable => "interior table",
+        PageType::LeafIndex => "leaf index",
+        PageType::LeafTable => "leaf table",
+    }
+}
+
+// Where a cell sits on its page. Only the start of a large payload is stored on the page, with the
+// rest spilling onto a chain of overflow pages, so how much stays local comes from the same
+// calculation sqlite uses.
+struct CellExtent {
+    // Bytes the cell takes up on the page.
+    size: usize,
+    key: String,
+    // The whole payload, and how much of it is on this page.
+    payload: usize,
+    local_payload: usize,
+    overflow_page: Option<u32>,
+}
+
+fn cell_extent(
+    page_type: &PageType,
+    mut cell: &[u8],
+    usable_size: usize,
+) -> io::Result<CellExtent> {
+    let start_len = cell.len();
+
+    let local_payload = |payload_size: usize, max_local: usize| {
+        if payload_size <= max_local {
+            return payload_size;
+        }
+        let min_local = (usable_size - 12) * 32 / 255 - 23;
+        let local = min_local + (payload_size - min_local) % (usable_size - 4);
+        if local <= max_local {
+            local
+        } else {
+            min_local
+        }
+    };
+    let index_max_local = (usable_size - 12) * 64 / 255 - 23;
+
+    let (key, payload, local) = match page_type {
+        PageType::InteriorTable => {
+            cell.skip(4)?;
+            let (key, _) = cell.read_varint()?;
+            (format!("key {}", key), 0, 0)
+        }
+        PageType::LeafTable => {
+            let (payload_size, _) = cell.read_varint()?;
+            let (row_id, _) = cell.read_varint()?;
+            let payload_size = payload_size as usize;
+            let local = local_payload(payload_size, usable_size - 35);
+            (format!("rowid {}", row_id), payload_size, local)
+        }
+        PageType::InteriorIndex => {
+            let left_child = cell.read_u32()?;
+            let (payload_size, _) = cell.read_varint()?;
+            let payload_size = payload_size as usize;
+            let local = local_payload(payload_size, index_max_local);
+            (format!("left child {}", left_child), payload_size, local)
+        }
+        PageType::LeafIndex => {
+            let (payload_size, _) = cell.read_varint()?;
+            let payload_size = payload_size as usize;
+            let local = local_payload(payload_size, index_max_local);
+            (
+                format!("payload {} bytes", payload_size),
+                payload_size,
+                local,
+            )
+        }
+    };
+
+    let mut size = start_len - cell.len() + local;
+    let overflow_page = if local < payload {
+        size += 4;
+        let mut pointer = cell.get(local..).unwrap_or_default();
+        Some(pointer.read_u32()?)
+    } else {
+        None
+    };
+
+    Ok(CellExtent {
+        size,
+        key,
+        payload,
+        local_payload: local,
+        overflow_page,
+    })
+}
+
+fn write_hexdump(page: &[u8], regions: &[Region], out: &mut impl Write) -> io::Result<()> {
+    let mut skipping = false;
+
+    for (line, bytes) in page.chunks(16).enumerate() {
+        let offset = line * 16;
+        let labels = regions
+            .iter()
+            .filter(|region| {
+                (offset..offset + 16).contains(&region.start) && region.end > region.start
+            })
+            .map(|region| format!("{} {}..{}", region.label, region.start, region.end))
+            .collect::<Vec<_>>();
+
+        if labels.is_empty() && bytes.iter().all(|byte| *byte == 0) {
+            if !skipping {
+                writeln!(out, "*")?;
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+
+        let hex = bytes
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| {
+                if i == 8 {
+                    format!(" {:02x}", byte)
+                } else {
+                    format!("{:02x}", byte)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = bytes
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        write!(out, "{:04x}  {:<48}  |{:<16}|", offset, hex, ascii)?;
+        if !labels.is_empty() {
+            write!(out, "  <- {}", labels.join(", "))?;
+        }
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+// How one b-tree uses its pages, added up over every page in it. This is what sqlite3_analyzer
+// gathers from the dbstat table, worked out here from the pages themselves.
+#[derive(Debug, Default)]
+struct SpaceUsed {
+    // Every page in the tree, overflow pages included, with whether it is a leaf.
+    pages: Vec<(u32, bool)>,
+    interior_pages: usize,
+    leaf_pages: usize,
+    overflow_pages: usize,
+    interior_unused: usize,
+    leaf_unused: usize,
+    overflow_unused: usize,
+    // Cells on leaf pages, and on every page, as index entries are also kept on interior pages.
+    leaf_entries: usize,
+    entries: usize,
+    payload: usize,
+    max_payload: usize,
+    overflow_entries: usize,
+    depth: usize,
+}
+
+fn measure(
+    pager: &Pager,
+    usable_size: usize,
+    page_number: u32,
+    depth: usize,
+    space: &mut SpaceUsed,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(too_deep(page_number));
+    }
+    let page = read_page(pager, page_number)?;
+    let header_offset = if page_number == 1 { 100 } else { 0 };
+    let page_header = DbPageHeader::parse(&mut &page[header_offset..])
+        .map_err(|err| Error::corrupt(page_number, header_offset, err))?;
+    let leaf = page_header.rightmost_pointer.is_none();
+
+    // Free space is the gap between the cell pointers and the cell content, the freeblocks inside
+    // the content, and the fragments too small to be freeblocks.
+    let header_size = if leaf { 8 } else { 12 };
+    let content_start = match page_header.cell_content_area_offset {
+        0 => 65536,
+        offset => offset as usize,
+    };
+    let cells_start = header_offset + header_size + 2 * page_header.cells.len();
+    let mut unused =
+        content_start.saturating_sub(cells_start) + page_header.fragmented_free_bytes as usize;
+    let mut freeblock = page_header.first_freeblock as usize;
+    while freeblock != 0 && freeblock + 4 <= usable_size {
+        unused += u16::from_be_bytes([page[freeblock + 2], page[freeblock + 3]]) as usize;
+        let next = u16::from_be_bytes([page[freeblock], page[freeblock + 1]]) as usize;
+        if next <= freeblock {
+            break;
+        }
+        freeblock = next;
+    }
+
+    space.pages.push((page_number, leaf));
+    space.depth = space.depth.max(depth);
+    if leaf {
+        space.leaf_pages += 1;
+        space.leaf_unused += unused;
+        space.leaf_entries += page_header.cells.len();
+    } else {
+        space.interior_pages += 1;
+        space.interior_unused += unused;
+    }
+    space.entries += page_header.cells.len();
+
+    let mut children = Vec::new();
+    for pointer in &page_header.cells {
+        let offset = *pointer as usize;
+        let cell = page
+            .get(offset..)
+            .filter(|_| offset >= cells_start)
+            .ok_or_else(|| invalid("cell pointer outside the cell content area"))
+            .map_err(|err| Error::corrupt(page_number, offset, err))?;
+        if !leaf {
+            let mut reader = cell;
+            let left_child = reader
+                .read_u32()
+                .map_err(|err| Error::corrupt(page_number, offset, err))?;
+            children.push(left_child);
+        }
+
+        let extent = cell_extent(&page_header.page_type, cell, usable_size)
+            .map_err(|err| Error::corrupt(page_number, offset, err))?;
+        space.payload += extent.payload;
+        space.max_payload = space.max_payload.max(extent.payload);
+
+        // Each overflow page holds the number of the next one, then as much of the rest of the
+        // payload as fits. A chain can't be longer than the file, so one that is must loop.
+        let mut overflow_page = extent.overflow_page;
+        let mut chain_length = 0;
+        let mut remaining = extent.payload - extent.local_payload;
+        if overflow_page.is_some() {
+            space.overflow_entries += 1;
+        }
+        while let Some(overflow_page_number) = overflow_page.filter(|page| *page != 0) {
+            chain_length += 1;
+            if chain_length > pager.page_count() {
+                return Err(Error::Corrupt {
+                    page: overflow_page_number,
+                    message: "overflow chain loops back on itself".to_string(),
+                });
+            }
+            let overflow = read_page(pager, overflow_page_number)?;
+            let stored = remaining.min(usable_size - 4);
+            remaining -= stored;
+
+            space.pages.push((overflow_page_number, false));
+            space.overflow_pages += 1;
+            space.overflow_unused += usable_size - 4 - stored;
+            let mut reader = &overflow[..];
+            let next = reader
+                .read_u32()
+                .map_err(|err| Error::corrupt(overflow_page_number, 0, err))?;
+            overflow_page = Some(next);
+        }
+    }
+
+    for child in children.into_iter().chain(page_header.rightmost_pointer) {
+        measure(pager, usable_size, child, depth + 1, space)?;
+    }
+
+    Ok(())
+}
+
+// Prints a report of how the space in the database is used, laid out like the one from
+// sqlite3_analyzer: totals for the whole file, then a section for every table and index.
+pub fn print_space_report(
+    pager: &Pager,
+    header: &DbHeader,
+    path: &std::path::Path,
+    records: &[Arc<MasterPageRecord>],
+    out: &mut impl Write,
+) -> Result<()> {
+    let page_size = header.page_size as usize;
+    let usable_size = page_size - header.reserved_space as usize;
+    let file_pages = header.database_size_in_pages as usize;
+
+    // The schema table isn't in the schema, but its tree takes up space like any other.
+    let mut trees = vec![("sqlite_schema", "sqlite_schema", false, 1)];
+    trees.extend(
+        records
+            .iter()
+            .filter(|record| record.root_page != 0)
+            .map(|record| {
+                (
+                    record.name.as_str(),
+                    record.table_name.as_str(),
+                    record.object_type == ObjectType::Index,
+                    record.root_page,
+                )
+            }),
+    );
+
+    let mut objects = Vec::new();
+    for (name, table_name, is_index, root_page) in trees {
+        let mut space = SpaceUsed::default();
+        measure(pager, usable_size, root_page, 1, &mut space)?;
+        objects.push((name, table_name, is_index, space));
+    }
+
+    let pages_used =
+        |space: &SpaceUsed| space.interior_pages + space.leaf_pages + space.overflow_pages;
+    let in_use = objects
+        .iter()
+        .map(|(.., space)| pages_used(space))
+        .sum::<usize>();
+    let user_payload = objects
+        .iter()
+        .filter(|(name, _, is_index, _)| !is_index && *name != "sqlite_schema")
+        .map(|(.., space)| space.payload)
+        .sum::<usize>();
+    let indices = records
+        .iter()
+        .filter(|record| record.object_type == ObjectType::Index);
+
+    writeln!(
+        out,
+        "/** Disk-Space Utilization Report For {}",
+        path.display()
+    )?;
+    writeln!(out)?;
+    write_stat(out, "Page size in bytes", page_size, "")?;
+    write_stat(out, "Pages in the whole file (measured)", file_pages, "")?;
+    write_stat(
+        out,
+        "Pages that store data",
+        in_use,
+        &percent(in_use, file_pages),
+    )?;
+    let freelist_pages = header.number_of_freelist_pages as usize;
+    write_stat(
+        out,
+        "Pages on the freelist (per header)",
+        freelist_pages,
+        &percent(freelist_pages, file_pages),
+    )?;
+    let tables = objects
+        .iter()
+        .filter(|(_, _, is_index, _)| !is_index)
+        .count();
+    write_stat(out, "Number of tables in the database", tables, "")?;
+    write_stat(out, "Number of indices", indices.clone().count(), "")?;
+    // Indexes sqlite makes itself, for PRIMARY KEY and UNIQUE constraints, have no SQL.
+    let defined = indices
+        .clone()
+        .filter(|index| !index.sql.is_empty())
+        .count();
+    write_stat(out, "Number of defined indices", defined, "")?;
+    write_stat(
+        out,
+        "Number of implied indices",
+        indices.count() - defined,
+        "",
+    )?;
+    write_stat(out, "Size of the file in bytes", file_pages * page_size, "")?;
+    write_stat(
+        out,
+        "Bytes of user payload stored",
+        user_payload,
+        &percent(user_payload, file_pages * page_size),
+    )?;
+
+    writeln!(out)?;
+    write_title(out, "Page counts for all tables and indices separately")?;
+    writeln!(out)?;
+    let mut by_size = objects.iter().collect::<Vec<_>>();
+    by_size.sort_by_key(|(name, .., space)| {
+        (std::cmp::Reverse(pages_used(space)), name.to_uppercase())
+    });
+    for (name, .., space) in by_size {
+        let pages = pages_used(space);
+        write_stat(
+            out,
+            &name.to_uppercase(),
+            pages,
+            &percent(pages, file_pages),
+        )?;
+    }
+
+    // Each table comes with its indexes straight after it.
+    objects.sort_by_key(|(name, table_name, is_index, _)| {
+        (table_name.to_uppercase(), *is_index, name.to_uppercase())
+    });
+    for (name, table_name, is_index, space) in &objects {
+        writeln!(out)?;
+        if *is_index {
+            let title = format!(
+                "Index {} of table {}",
+                name.to_uppercase(),
+                table_name.to_uppercase()
+            );
+            write_title(out, &title)?;
+        } else {
+            write_title(out, &format!("Table {}", name.to_uppercase()))?;
+        }
+        writeln!(out)?;
+        write_space_used(out, space, *is_index, page_size, file_pages)?;
+    }
+
+    Ok(out.flush()?)
+}
+
+fn write_space_used(
+    out: &mut impl Write,
+    space: &SpaceUsed,
+    is_index: bool,
+    page_size: usize,
+    file_pages: usize,
+) -> io::Result<()> {
+    let total_pages = space.interior_pages + space.leaf_pages + space.overflow_pages;
+    let storage = total_pages * page_size;
+    let total_unused = space.interior_unused + space.leaf_unused + space.overflow_unused;
+    // A table's interior pages only hold keys for finding rows, but an index keeps entries there.
+    let entries = if is_index {
+        space.entries
+    } else {
+        space.leaf_entries
+    };
+    // The pointer to the next page in an overflow chain counts as metadata, but the first is
+    // already counted in its cell.
+    let metadata = storage - space.payload - total_unused
+        + 4 * (space.overflow_pages - space.overflow_entries);
+    let divide = |num: usize, denom: usize| match denom {
+        0 => "0.0".to_string(),
+        denom => format!("{:.2}", num as f64 / denom as f64),
+    };
+
+    write_stat(
+        out,
+        "Percentage of total database",
+        percent(total_pages, file_pages),
+        "",
+    )?;
+    write_stat(out, "Number of entries", entries, "")?;
+    write_stat(out, "Bytes of storage consumed", storage, "")?;
+    write_stat(
+        out,
+        "Bytes of payload",
+        space.payload,
+        &percent(space.payload, storage),
+    )?;
+    write_stat(
+        out,
+        "Bytes of metadata",
+        metadata,
+        &percent(metadata, storage),
+    )?;
+    write_stat(out, "B-tree depth", space.depth, "")?;
+    write_stat(
+        out,
+        "Average payload per entry",
+        divide(space.payload, entries),
+        "",
+    )?;
+    write_stat(
+        out,
+        "Average unused bytes per entry",
+        divide(total_unused, entries),
+        "",
+    )?;
+    write_stat(
+        out,
+        "Average metadata per entry",
+        divide(metadata, entries),
+        "",
+    )?;
+    // The same sum as sqlite3_analyzer, which only leaves out a table's root, and rounds down.
+    let tables = if is_index { 0 } else { 1 };
+    let children = space.interior_pages + space.leaf_pages - tables;
+    if let Some(fanout) = children.checked_div(space.interior_pages) {
+        write_stat(out, "Average fanout", format!("{:.2}", fanout as f64), "")?;
+    }
+    if total_pages > 1 {
+        // A leaf page is out of sequence when it doesn't directly follow the page before it in
+        // the file, which means reading the tree in order has to seek.
+        let mut pages = space.pages.clone();
+        pages.sort();
+        let gaps = pages
+            .windows(2)
+            .filter(|pair| pair[1].1 && pair[1].0 != pair[0].0 + 1)
+            .count();
+        write_stat(
+            out,
+            "Non-sequential pages",
+            gaps,
+            &percent(gaps, total_pages - 1),
+        )?;
+    }
+    write_stat(out, "Maximum payload per entry", space.max_payload, "")?;
+    write_stat(
+        out,
+        "Entries that use overflow",
+        space.overflow_entries,
+        &percent(space.overflow_entries, entries),
+    )?;
+    if space.interior_pages > 0 {
+        write_stat(out, "Index pages used", space.interior_pages, "")?;
+    }
+    write_stat(out, "Primary pages used", space.leaf_pages, "")?;
+    write_stat(out, "Overflow pages used", space.overflow_pages, "")?;
+    write_stat(out, "Total pages used", total_pages, "")?;
+    if space.interior_unused > 0 {
+        let interior_space = space.interior_pages * page_size;
+        write_stat(
+            out,
+            "Unused bytes on index pages",
+            space.interior_unused,
+            &percent(space.interior_unused, interior_space),
+        )?;
+    }
+    let leaf_space = space.leaf_pages * page_size;
+    write_stat(
+        out,
+        "Unused bytes on primary pages",
+        space.leaf_unused,
+        &percent(space.leaf_unused, leaf_space),
+    )?;
+    let overflow_space = space.overflow_pages * page_size;
+    write_stat(
+        out,
+        "Unused bytes on overflow pages",
+        space.overflow_unused,
+        &percent(space.overflow_unused, overflow_space),
+    )?;
+    write_stat(
+        out,
+        "Unused bytes on all pages",
+        total_unused,
+        &percent(total_unused, storage),
+    )
+}
+
+// Writes a section title, padded out with stars to the width of the report.
+fn write_title(out: &mut impl Write, title: &str) -> io::Result<()> {
+    writeln!(out, "{:*<79}", format!("*** {} ", title))
+}
+
+// Writes one line of the report: the label padded with dots, then the value, then whatever goes
+// with it, which is usually a percentage.
+fn write_stat(
+    out: &mut impl Write,
+    label: &str,
+    value: impl std::fmt::Display,
+    extra: &str,
+) -> io::Result<()> {
+    let line = format!("{:.<50} {:<10} {}", label, value.to_string(), extra);
+    writeln!(out, "{}", line.trim_end())
+}
+
+// Formats a percentage the way sqlite3_analyzer does, with more precision near the ends of the
+// range where one decimal place would round away the difference.
+fn percent(num: usize, denom: usize) -> String {
+    if denom == 0 {
+        return String::new();
+    }
+
+    let value = num as f64 * 100.0 / denom as f64;
+    if value == 100.0 || value < 0.001 || (value > 1.0 && value < 99.0) {
+        format!("{:5.1}%", value)
+    } else if !(0.1..=99.9).contains(&value) {
+        format!("{:7.3}%", value)
+    } else {
+        format!("{:6.2}%", value)
+    }
+}
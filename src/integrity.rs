@@ -0,0 +1,668 @@
+use std::cmp::Ordering;
+
+use crate::catalog::{Index, IndexOrigin, ObjectType};
+use crate::pager::Pager;
+use crate::{read_page, ByteReader, DataSpecification, Db, DbHeader, MasterPageRecord, Value};
+
+// Looks for corruption the way PRAGMA integrity_check does, and reports it in the same words, so
+// the output of the two can be compared. Every b-tree is walked from its root, checking that:
+//
+// - each page is a b-tree page whose cells all lie inside it, with every byte of the cell content
+//   area used by one cell or freeblock, or counted as fragmented
+// - the rowids of a table, and the keys of an index, are in order across the whole tree
+// - every leaf of a tree is at the same depth
+// - overflow chains are as long as their cells' payloads need
+//
+// The freelist is walked too, and its length checked against the header, and then every page in
+// the file has to have been used exactly once, by a b-tree, an overflow chain or the freelist.
+// After that, each index should have an entry for every row of its table.
+//
+// A quick check leaves out the key order of indexes and the number of entries in them, which are
+// what take it the longest. Naming a table checks just that table and its indexes, without the
+// freelist or the pages no b-tree uses. The problems are given as the lines sqlite prints, up to
+// `max_errors` of them, and there are none when the database is fine.
+pub(crate) fn check(db: &Db, quick: bool, max_errors: usize, table: Option<&str>) -> Vec<String> {
+    // The header is read again, as writes since the database was opened change the page count
+    // and the freelist. One that can no longer be read is checked as it was.
+    let current = read_page(&db.pager, 1)
+        .ok()
+        .and_then(|page| DbHeader::parse(&mut page.as_ref()).ok());
+    let header = current.as_ref().unwrap_or(&db.header);
+    let page_size = header.page_size as usize;
+    let usable = page_size - header.reserved_space as usize;
+
+    // The page count in the header is the one sqlite trusts, when the header says it is up to
+    // date. A file shorter than that has lost pages.
+    let file_pages = db.pager.page_count();
+    let header_valid =
+        header.version_valid_for == header.file_change_counter && header.database_size_in_pages > 0;
+    let page_count = match header_valid {
+        true => header.database_size_in_pages,
+        false => file_pages,
+    };
+
+    let mut checker = Checker {
+        pager: &db.pager,
+        usable,
+        page_count,
+        referenced: vec![false; page_count as usize + 1],
+        messages: Vec::new(),
+        max_errors,
+        prefix: None,
+        entries: 0,
+    };
+    if page_count > file_pages {
+        checker.error(format!(
+            "the header says there are {} pages but the file has {}",
+            page_count, file_pages
+        ));
+    }
+
+    // The page that holds the byte sqlite locks is never used for anything.
+    let pending_page = (0x4000_0000 / page_size) as u32 + 1;
+    if pending_page <= page_count {
+        checker.referenced[pending_page as usize] = true;
+    }
+    // Pointer map pages, which only auto-vacuum databases have, come every so many pages from
+    // page 2, and are only used by the pointer map.
+    if header.largest_root_btree_page_number != 0 {
+        let step = usable / 5 + 1;
+        for page in (2..=page_count as usize).step_by(step) {
+            checker.referenced[page] = true;
+        }
+    }
+
+    let partial = table.is_some();
+    if !partial {
+        checker.prefix = Some("Freelist: ".to_string());
+        checker.check_list(
+            true,
+            header.first_freelist_trunk_page,
+            header.number_of_freelist_pages,
+        );
+        checker.prefix = None;
+    }
+
+    // The schema table is checked along with the rest, unless only one table is.
+    let trees = db
+        .catalog
+        .objects()
+        .iter()
+        .filter(|record| record.root_page != 0)
+        .filter(|record| match table {
+            Some(table) => record.table_name.eq_ignore_ascii_case(table),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    let mut entries = Vec::new();
+    if !partial {
+        checker.check_tree(1, false);
+    }
+    for record in &trees {
+        let ordered = !quick && keys_sort_as_bytes(db, record);
+        entries.push(checker.check_tree(record.root_page, ordered));
+    }
+
+    if !partial {
+        for page in 1..=page_count {
+            if !checker.referenced[page as usize] {
+                checker.error(format!("Page {}: never used", page));
+            }
+        }
+    }
+
+    let mut rows = Vec::new();
+    if !checker.messages.is_empty() {
+        rows.push(format!(
+            "*** in database main ***\n{}",
+            checker.messages.join("\n")
+        ));
+    }
+
+    // Every row of a table has one entry in each of its indexes, unless the index is partial.
+    if !quick {
+        let counted = trees.iter().zip(&entries);
+        for (index, count) in counted.clone() {
+            if index.object_type != ObjectType::Index || index.partial.is_some() {
+                continue;
+            }
+            let table = counted.clone().find(|(table, _)| {
+                table.object_type == ObjectType::Table && table.name == index.table_name
+            });
+            let Some((table, table_count)) = table else {
+                continue;
+            };
+            if !table.without_rowid && count != table_count && rows.len() < max_errors {
+                rows.push(format!("wrong # of entries in index {}", index.name));
+            }
+        }
+    }
+    rows
+}
+
+// Keys are only compared when the index sorts them in sqlite's default order, which is all that
+// is known here. An index that sorts by another collation or in descending order is left alone,
+// as is the key of a WITHOUT ROWID table or an index sqlite made itself, when the table asks for
+// either.
+fn keys_sort_as_bytes(db: &Db, record: &MasterPageRecord) -> bool {
+    if record.object_type == ObjectType::Table && !record.without_rowid {
+        return false;
+    }
+    // A WITHOUT ROWID table is the index of its primary key.
+    let index = match record.object_type {
+        ObjectType::Table => db.catalog.table(&record.name).and_then(|table| {
+            table
+                .indexes()
+                .iter()
+                .find(|index| index.origin() == IndexOrigin::PrimaryKey)
+        }),
+        _ => db.catalog.index(&record.name),
+    };
+    index.is_some_and(Index::default_order)
+}
+
+struct Checker<'a> {
+    pager: &'a Pager,
+    usable: usize,
+    page_count: u32,
+    // Which pages something has been found to use, by page number.
+    referenced: Vec<bool>,
+    messages: Vec<String>,
+    max_errors: usize,
+    // Where the problems being found are, which starts each message about them.
+    prefix: Option<String>,
+    // Entries seen in the tree being checked, which are the cells of its leaves, and for an index
+    // the cells of its interior pages too.
+    entries: u64,
+}
+
+// The bounds of the keys a page may hold, given by the pages above it. Pages are walked from their
+// last cell to their first, as sqlite walks them, so it is the largest key that is passed down.
+enum Bound {
+    Rowid(i64),
+    Key(Option<Vec<Value>>),
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        if self.messages.len() < self.max_errors {
+            let prefix = self.prefix.as_deref().unwrap_or_default();
+            self.messages.push(format!("{}{}", prefix, message));
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.messages.len() >= self.max_errors
+    }
+
+    // Marks a page used, reporting it if it can't be, which means nothing more should be read
+    // from it.
+    fn check_ref(&mut self, page: u32) -> bool {
+        if page == 0 || page > self.page_count {
+            self.error(format!("invalid page number {}", page));
+            return true;
+        }
+        if self.referenced[page as usize] {
+            self.error(format!("2nd reference to page {}", page));
+            return true;
+        }
+        self.referenced[page as usize] = true;
+        false
+    }
+
+    // Checks a whole tree, comparing index keys if it is `ordered`, and returns how many entries
+    // it holds.
+    fn check_tree(&mut self, root: u32, ordered: bool) -> u64 {
+        self.entries = 0;
+        let mut bound = None;
+        self.check_page(root, root, &mut bound, ordered);
+        self.prefix = None;
+        self.entries
+    }
+
+    // Follows a chain of overflow pages, or the freelist's trunk pages and the leaves each one
+    // lists, which should add up to `expected` pages.
+    fn check_list(&mut self, freelist: bool, first: u32, expected: u32) {
+        let errors = self.messages.len();
+        let mut remaining = expected as i64;
+        let mut page = first;
+
+        while page != 0 && !self.done() {
+            if self.check_ref(page) {
+                break;
+            }
+            remaining -= 1;
+            let Ok(data) = self.pager.read_page(page) else {
+                self.error(format!("failed to get page {}", page));
+                break;
+            };
+            let data = data.into_owned();
+
+            if freelist {
+                let leaves = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+                if leaves > self.usable / 4 - 2 {
+                    self.error(format!("freelist leaf count too big on page {}", page));
+                    remaining -= 1;
+                } else {
+                    for leaf in data[8..8 + leaves * 4].chunks(4) {
+                        self.check_ref(u32::from_be_bytes(leaf.try_into().unwrap()));
+                    }
+                    remaining -= leaves as i64;
+                }
+            }
+            page = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        }
+
+        if remaining != 0 && errors == self.messages.len() {
+            self.error(format!(
+                "{} is {} but should be {}",
+                if freelist {
+                    "size"
+                } else {
+                    "overflow list length"
+                },
+                expected as i64 - remaining,
+                expected
+            ));
+        }
+    }
+
+    // Checks a page and everything below it, returning the depth of the tree under it, or 0 if it
+    // couldn't be read. `bound` is the largest key the page may hold, and is left as the smallest
+    // it does.
+    fn check_page(
+        &mut self,
+        tree: u32,
+        page: u32,
+        bound: &mut Option<Bound>,
+        ordered: bool,
+    ) -> u32 {
+        if page == 0 || self.check_ref(page) {
+            return 0;
+        }
+        let saved_prefix = self
+            .prefix
+            .replace(format!("Tree {} page {}: ", tree, page));
+
+        let depth = self.check_page_contents(tree, page, bound, ordered);
+        self.prefix = saved_prefix;
+        depth
+    }
+
+    fn check_page_contents(
+        &mut self,
+        tree: u32,
+        page: u32,
+        bound: &mut Option<Bound>,
+        ordered: bool,
+    ) -> u32 {
+        let data = match self.pager.read_page(page) {
+            Ok(data) => data.into_owned(),
+            Err(_) => {
+                self.error("unable to get the page. error code=11".to_string());
+                return 0;
+            }
+        };
+        let usable = self.usable;
+        let header = if page == 1 { 100 } else { 0 };
+
+        let page_type = data[header];
+        let (leaf, table) = match page_type {
+            0x02 => (false, false),
+            0x05 => (false, true),
+            0x0a => (true, false),
+            0x0d => (true, true),
+            _ => {
+                self.error("btreeInitPage() returns error code 11".to_string());
+                return 0;
+            }
+        };
+        let read_u16 = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let cell_count = read_u16(header + 3) as usize;
+        let content_start = match read_u16(header + 5) {
+            0 => 65536,
+            offset => offset as usize,
+        };
+        let cell_pointers = header + if leaf { 8 } else { 12 };
+        if cell_pointers + 2 * cell_count > usable {
+            self.error("btreeInitPage() returns error code 11".to_string());
+            return 0;
+        }
+
+        // A page's keys have to fit under the one above it. The first key on a page, walking
+        // backwards, may equal that bound, as an interior table page's divider is the largest
+        // rowid in its left child.
+        let mut max_rowid = match bound {
+            Some(Bound::Rowid(rowid)) => *rowid,
+            _ => i64::MAX,
+        };
+        let mut max_key = match bound.take() {
+            Some(Bound::Key(key)) => key,
+            _ => None,
+        };
+        let mut can_be_equal = true;
+        let mut depth = None;
+
+        if !leaf {
+            let right = u32::from_be_bytes(data[header + 8..header + 12].try_into().unwrap());
+            let mut child_bound = Some(match table {
+                true => Bound::Rowid(max_rowid),
+                false => Bound::Key(max_key.take()),
+            });
+            let child_depth = self.check_page(tree, right, &mut child_bound, ordered);
+            match child_bound {
+                Some(Bound::Rowid(rowid)) => max_rowid = rowid,
+                Some(Bound::Key(key)) => max_key = key,
+                None => {}
+            }
+            depth = Some(child_depth);
+            can_be_equal = false;
+        }
+
+        let mut cells = Vec::new();
+        let mut coverage = true;
+        for index in (0..cell_count).rev() {
+            if self.done() {
+                break;
+            }
+            self.prefix = Some(format!("Tree {} page {} cell {}: ", tree, page, index));
+
+            let offset = read_u16(cell_pointers + 2 * index) as usize;
+            if offset < content_start || offset > usable - 4 {
+                self.error(format!(
+                    "Offset {} out of range {}..{}",
+                    offset,
+                    content_start,
+                    usable - 4
+                ));
+                coverage = false;
+                continue;
+            }
+            let Some(cell) = parse_cell(&data[offset..usable], page_type, usable) else {
+                self.error("Extends off end of page".to_string());
+                coverage = false;
+                continue;
+            };
+            if offset + cell.size > usable {
+                self.error("Extends off end of page".to_string());
+                coverage = false;
+                continue;
+            }
+
+            if table {
+                let out_of_order = match can_be_equal {
+                    true => cell.rowid > max_rowid,
+                    false => cell.rowid >= max_rowid,
+                };
+                if out_of_order {
+                    self.error(format!("Rowid {} out of order", cell.rowid));
+                }
+                max_rowid = cell.rowid;
+            }
+
+            let spilled = cell.payload_size > cell.local.len() as u64;
+            let mut chain_sound = true;
+            if spilled {
+                let overflow = cell.payload_size - cell.local.len() as u64;
+                let pages = overflow.div_ceil(usable as u64 - 4);
+                let errors = self.messages.len();
+                self.check_list(false, cell.overflow, pages as u32);
+                chain_sound = errors == self.messages.len();
+            }
+
+            if ordered && !table && chain_sound {
+                let key = match spilled {
+                    true => self.read_payload(&cell),
+                    false => Some(cell.local.to_vec()),
+                };
+                if let Some(key) = key.as_deref().and_then(decode_record) {
+                    let out_of_order =
+                        max_key.as_ref().is_some_and(|max_key| {
+                            match compare_records(&key, max_key) {
+                                Ordering::Greater => true,
+                                Ordering::Equal => !can_be_equal,
+                                Ordering::Less => false,
+                            }
+                        });
+                    if out_of_order {
+                        self.error("Index key out of order".to_string());
+                    }
+                    max_key = Some(key);
+                }
+            }
+            can_be_equal = false;
+
+            if leaf || !table {
+                self.entries += 1;
+            }
+            if !leaf {
+                let mut child_bound = Some(match table {
+                    true => Bound::Rowid(max_rowid),
+                    false => Bound::Key(max_key.take()),
+                });
+                let child_depth = self.check_page(tree, cell.left_child, &mut child_bound, ordered);
+                match child_bound {
+                    Some(Bound::Rowid(rowid)) => max_rowid = rowid,
+                    Some(Bound::Key(key)) => max_key = key,
+                    None => {}
+                }
+                // A child that couldn't be read has been reported already.
+                if child_depth != 0 {
+                    if depth.is_some_and(|depth| depth != 0 && depth != child_depth) {
+                        self.error("Child page depth differs".to_string());
+                    }
+                    depth = Some(child_depth);
+                }
+            }
+            cells.push((offset, offset + cell.size));
+        }
+
+        *bound = Some(match table {
+            true => Bound::Rowid(max_rowid),
+            false => Bound::Key(max_key),
+        });
+        self.prefix = None;
+
+        // Every byte from the start of the cell content area to the end of the page is in one
+        // cell or one freeblock, or is a fragment too small to be a freeblock, which the header
+        // counts.
+        if coverage && !self.done() {
+            let mut freeblock = read_u16(header + 1) as usize;
+            while freeblock > 0 && freeblock + 4 <= usable {
+                let size = read_u16(freeblock + 2) as usize;
+                cells.push((freeblock, freeblock + size));
+                freeblock = read_u16(freeblock) as usize;
+            }
+            cells.sort_unstable();
+
+            let mut fragmented = 0;
+            let mut end = content_start;
+            let mut overlapping = false;
+            for (start, cell_end) in cells {
+                if start < end {
+                    self.error(format!("Multiple uses for byte {} of page {}", start, page));
+                    overlapping = true;
+                    break;
+                }
+                fragmented += start - end;
+                end = cell_end;
+            }
+            fragmented += usable.saturating_sub(end);
+            let reported = data[header + 7] as usize;
+            if !overlapping && fragmented != reported {
+                self.error(format!(
+                    "Fragmentation of {} bytes reported as {} on page {}",
+                    fragmented, reported, page
+                ));
+            }
+        }
+
+        depth.unwrap_or(0) + 1
+    }
+
+    // The whole payload of a cell that spills onto overflow pages, once its chain has been found
+    // to be sound.
+    fn read_payload(&self, cell: &Cell) -> Option<Vec<u8>> {
+        let mut payload = cell.local.to_vec();
+        let mut page = cell.overflow;
+        while payload.len() < cell.payload_size as usize && page != 0 {
+            let data = self.pager.read_page(page).ok()?;
+            let wanted = (cell.payload_size as usize - payload.len()).min(self.usable - 4);
+            payload.extend_from_slice(&data[4..4 + wanted]);
+            page = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        }
+        Some(payload)
+    }
+}
+
+struct Cell<'a> {
+    left_child: u32,
+    rowid: i64,
+    payload_size: u64,
+    // The part of the payload on the page itself, and the first overflow page for the rest.
+    local: &'a [u8],
+    overflow: u32,
+    // How many bytes of the page the cell takes up.
+    size: usize,
+}
+
+// Reads a cell from the start of `bytes`, or gives None if the page ends before it does.
+fn parse_cell(bytes: &[u8], page_type: u8, usable: usize) -> Option<Cell<'_>> {
+    let mut reader = bytes;
+    let left_child = match page_type {
+        0x02 | 0x05 => reader.read_u32().ok()?,
+        _ => 0,
+    };
+    if page_type == 0x05 {
+        let (rowid, _) = reader.read_varint().ok()?;
+        return Some(Cell {
+            left_child,
+            rowid: rowid as i64,
+            payload_size: 0,
+            local: &[],
+            overflow: 0,
+            size: bytes.len() - reader.len(),
+        });
+    }
+
+    let (payload_size, _) = reader.read_varint().ok()?;
+    let rowid = match page_type {
+        0x0d => reader.read_varint().ok()?.0 as i64,
+        _ => 0,
+    };
+    let header = bytes.len() - reader.len();
+
+    // How much of the payload stays on the page, as in the file format's description of cells.
+    let max_local = match page_type {
+        0x0d => usable - 35,
+        _ => (usable - 12) * 64 / 255 - 23,
+    } as u64;
+    let min_local = ((usable - 12) * 32 / 255 - 23) as u64;
+    let local = if payload_size <= max_local {
+        payload_size
+    } else {
+        let k = min_local + (payload_size - min_local) % (usable as u64 - 4);
+        if k <= max_local {
+            k
+        } else {
+            min_local
+        }
+    } as usize;
+
+    let local_bytes = reader.get(..local)?;
+    let (overflow, size) = if (local as u64) < payload_size {
+        let pointer = reader.get(local..local + 4)?;
+        (
+            u32::from_be_bytes(pointer.try_into().unwrap()),
+            header + local + 4,
+        )
+    } else {
+        (0, (header + local).max(4))
+    };
+    Some(Cell {
+        left_child,
+        rowid,
+        payload_size,
+        local: local_bytes,
+        overflow,
+        size,
+    })
+}
+
+fn decode_record(payload: &[u8]) -> Option<Vec<Value>> {
+    let mut reader = payload;
+    let specification = DataSpecification::parse_header(&mut reader).ok()?;
+    specification
+        .types
+        .iter()
+        .map(|data_type| data_type.parse(&mut reader).ok())
+        .collect()
+}
+
+// Orders index keys the way sqlite does, value by value. A key that is a prefix of another comes
+// first.
+fn compare_records(a: &[Value], b: &[Value]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ordering = a.compare(b);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // The fixtures are a table of 60 rows on 512 byte pages, its root on page 2 over leaves on
+    // pages 3 to 17, and a freelist of pages 18 to 33, each broken one way. The problems are the
+    // ones sqlite3 reports for the same files.
+    #[allow(dead_code)]
+    fn problems(bytes: &[u8]) -> Vec<String> {
+        let db = Db::from_bytes(bytes.to_vec()).unwrap();
+        check(&db, false, 100, None)
+    }
+
+    #[test]
+    fn finds_nothing_wrong() {
+        for bytes in [
+            &include_bytes!("../tests/fixtures/people.db")[..],
+            include_bytes!("../tests/fixtures/indexes.db"),
+            include_bytes!("../tests/fixtures/many.db"),
+        ] {
+            assert_eq!(problems(bytes), Vec::<String>::new());
+        }
+    }
+
+    // Page 5, a leaf of the table, has a type byte that isn't any kind of b-tree page.
+    #[test]
+    fn bad_page_type() {
+        assert_eq!(
+            problems(include_bytes!("../tests/fixtures/bad-page-type.db")),
+            ["*** in database main ***\nTree 2 page 5: btreeInitPage() returns error code 11"]
+        );
+    }
+
+    // The root points back at itself in place of leaf 6, which is then never reached.
+    #[test]
+    fn cycle() {
+        assert_eq!(
+            problems(include_bytes!("../tests/fixtures/cycle.db")),
+            ["*** in database main ***\n\
+              Tree 2 page 2 cell 3: 2nd reference to page 2\n\
+              Page 6: never used"]
+        );
+    }
+
+    // The freelist trunk lists leaf 7 of the table in place of free page 20.
+    #[test]
+    fn free_page_in_use() {
+        assert_eq!(
+            problems(include_bytes!("../tests/fixtures/free-page-in-use.db")),
+            ["*** in database main ***\n\
+              Tree 2 page 2 cell 4: 2nd reference to page 7\n\
+              Page 20: never used"]
+        );
+    }
+}
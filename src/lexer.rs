@@ -1,6 +1,11 @@
 // NOTE: Note to future self, we should have a Token, it is a composite of a TokenType, and some additional
 //       metadata.
-#[derive(Debug, PartialEq, Eq, Clone)]
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, PartialEq, Clone)]
 #[allow(dead_code)]
 pub enum Token {
     // KEYWORDS
@@ -13,6 +18,19 @@ pub enum Token {
     Null,
     Index,
     On,
+    In,
+    Virtual,
+    Using,
+    Match,
+    Like,
+    Order,
+    By,
+    Group,
+    Limit,
+    Offset,
+    Distinct,
+    And,
+    Or,
 
     // PUNCTUATION
     LParen,
@@ -22,10 +40,25 @@ pub enum Token {
     Comma,
     Star,
     Equals,
+    NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
 
     // LITERALS
     StringLiteral(String),
+    IntegerLiteral(i64),
+    RealLiteral(f64),
+    BlobLiteral(Vec<u8>),
     Identifier(String),
+    // A parameter to bind a value to, as written: `?`, `?NNN`, `:name`, `@name` or `$name`.
+    Variable(String),
 
     // CONSTRAINTS
     Primary,
@@ -35,22 +68,153 @@ pub enum Token {
     Eof,
 }
 
+impl Token {
+    // Whether the token is a word that only means something in some places, like ASC or ROWID,
+    // which the lexer leaves as a name. Such words can be written in any case.
+    pub fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Identifier(name) if name.eq_ignore_ascii_case(word))
+    }
+}
+
+// Writes a token back out as SQL, for quoting it in a syntax error.
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Create => "CREATE",
+            Token::Table => "TABLE",
+            Token::Select => "SELECT",
+            Token::From => "FROM",
+            Token::Where => "WHERE",
+            Token::Not => "NOT",
+            Token::Null => "NULL",
+            Token::Index => "INDEX",
+            Token::On => "ON",
+            Token::In => "IN",
+            Token::Virtual => "VIRTUAL",
+            Token::Using => "USING",
+            Token::Match => "MATCH",
+            Token::Like => "LIKE",
+            Token::Order => "ORDER",
+            Token::By => "BY",
+            Token::Group => "GROUP",
+            Token::Limit => "LIMIT",
+            Token::Offset => "OFFSET",
+            Token::Distinct => "DISTINCT",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Semicolon => ";",
+            Token::Dot => ".",
+            Token::Comma => ",",
+            Token::Star => "*",
+            Token::Equals => "=",
+            Token::NotEquals => "!=",
+            Token::Less => "<",
+            Token::LessEquals => "<=",
+            Token::Greater => ">",
+            Token::GreaterEquals => ">=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Concat => "||",
+            Token::StringLiteral(value) => return write!(f, "'{}'", value.replace('\'', "''")),
+            Token::IntegerLiteral(value) => return write!(f, "{}", value),
+            Token::RealLiteral(value) => return write!(f, "{:?}", value),
+            Token::BlobLiteral(bytes) => {
+                f.write_str("X'")?;
+                for byte in bytes {
+                    write!(f, "{:02X}", byte)?;
+                }
+                return f.write_str("'");
+            }
+            Token::Identifier(name) if is_bare(name) => name,
+            Token::Identifier(name) => return write!(f, "\"{}\"", name.replace('"', "\"\"")),
+            Token::Variable(text) => text,
+            Token::Primary => "PRIMARY",
+            Token::Key => "KEY",
+            Token::AutoIncrement => "AUTOINCREMENT",
+            Token::Eof => "",
+        };
+
+        f.write_str(text)
+    }
+}
+
+// The keyword a word is, whatever its case, if it is one.
+fn keyword(word: &str) -> Option<Token> {
+    Some(match word.to_ascii_uppercase().as_str() {
+        "AUTOINCREMENT" => Token::AutoIncrement,
+        "CREATE" => Token::Create,
+        "TABLE" => Token::Table,
+        "PRIMARY" => Token::Primary,
+        "KEY" => Token::Key,
+        "SELECT" => Token::Select,
+        "FROM" => Token::From,
+        "WHERE" => Token::Where,
+        "NOT" => Token::Not,
+        "NULL" => Token::Null,
+        "INDEX" => Token::Index,
+        "ON" => Token::On,
+        "IN" => Token::In,
+        "VIRTUAL" => Token::Virtual,
+        "USING" => Token::Using,
+        "MATCH" => Token::Match,
+        "LIKE" => Token::Like,
+        "ORDER" => Token::Order,
+        "BY" => Token::By,
+        "GROUP" => Token::Group,
+        "LIMIT" => Token::Limit,
+        "OFFSET" => Token::Offset,
+        "DISTINCT" => Token::Distinct,
+        "AND" => Token::And,
+        "OR" => Token::Or,
+        _ => return None,
+    })
+}
+
+// Whether a name can be written without quotes: a word that isn't a keyword.
+fn is_bare(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && keyword(name).is_none()
+}
+
 #[derive(Debug)]
 pub struct Lexer {
     input: String,
+    // Where the next character is in the input, in bytes. Lexing only ever moves forwards through
+    // the input, so it takes time in proportion to its length.
     position: usize,
+    // Where the token being lexed starts, once any whitespace and comments before it are skipped.
+    start: usize,
+    // Where each token that has been lexed was in the input, counted in bytes.
+    spans: Vec<Range<usize>>,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Lexer {
-        Lexer { input, position: 0 }
+        Lexer {
+            input,
+            position: 0,
+            start: 0,
+            spans: Vec::new(),
+        }
+    }
+
+    // The span of each token that lex returned, in the same order.
+    pub fn spans(&self) -> &[Range<usize>] {
+        &self.spans
     }
 
-    pub fn lex(&mut self) -> Vec<Token> {
+    pub fn lex(&mut self) -> Result<Vec<Token>> {
         let mut tokens = Vec::new();
 
         loop {
-            let token = self.next_token();
+            let token = self.next_token()?;
+            self.spans.push(self.start..self.position);
 
             if token == Token::Eof {
                 tokens.push(token);
@@ -60,118 +224,202 @@ impl Lexer {
             tokens.push(token);
         }
 
-        tokens
+        Ok(tokens)
     }
 
-    pub fn next_token(&mut self) -> Token {
-        if self.position >= self.input.len() {
-            return Token::Eof;
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    // Moves past the next character if it is `c`, returning whether it was.
+    fn advance_if(&mut self, c: char) -> bool {
+        let matches = self.peek() == Some(c);
+        if matches {
+            self.position += c.len_utf8();
         }
+        matches
+    }
 
-        let mut current_char = self.input.chars().nth(self.position).unwrap();
+    // Moves past characters for as long as they match, returning the text they make up.
+    fn advance_while(&mut self, mut matches: impl FnMut(char) -> bool) -> &str {
+        let start = self.position;
+        while self.peek().is_some_and(&mut matches) {
+            self.advance();
+        }
+        &self.input[start..self.position]
+    }
 
-        match current_char {
-            '(' => {
-                self.position += 1;
-                Token::LParen
+    // Moves past whitespace and comments, up to where the next token starts.
+    fn skip_trivia(&mut self) {
+        loop {
+            self.advance_while(char::is_whitespace);
+            let rest = &self.input[self.position..];
+            if rest.starts_with("--") {
+                // A comment runs to the end of the line, or of the input.
+                self.advance_while(|c| c != '\n');
+            } else if rest.starts_with("/*") {
+                // A block comment that isn't closed runs to the end of the input, as in sqlite.
+                self.position += 2;
+                match self.input[self.position..].find("*/") {
+                    Some(end) => self.position += end + 2,
+                    None => self.position = self.input.len(),
+                }
+            } else {
+                break;
             }
-            ')' => {
-                self.position += 1;
-                Token::RParen
+        }
+    }
+
+    // Moves past quoted text, after its opening quote, up to and including its closing quote,
+    // returning the text between them. The closing quote written twice stands for one of itself,
+    // as in `'it''s'`.
+    fn quoted(&mut self, close: char) -> Result<String> {
+        let mut text = String::new();
+        loop {
+            text.push_str(self.advance_while(|c| c != close));
+            if self.advance().is_none() {
+                return Err(self.unrecognized());
             }
-            ';' => {
-                self.position += 1;
-                Token::Semicolon
+            // Brackets can't be doubled, so `]` always ends a name.
+            if close == ']' || !self.advance_if(close) {
+                return Ok(text);
             }
-            '.' => {
-                self.position += 1;
-                Token::Dot
+            text.push(close);
+        }
+    }
+
+    // Moves past the rest of a number, after its first character, which is a digit or a `.`. It's
+    // a real when it has a fraction or an exponent, as in `1.5`, `.5` or `1e3`, or is too big to be
+    // an integer, as in sqlite.
+    fn number(&mut self) -> Result<Token> {
+        self.advance_while(|c| c.is_ascii_digit());
+        let mut real = self.input[self.start..].starts_with('.');
+        if !real && self.advance_if('.') {
+            self.advance_while(|c| c.is_ascii_digit());
+            real = true;
+        }
+        if self.advance_if('e') || self.advance_if('E') {
+            if !self.advance_if('+') {
+                self.advance_if('-');
             }
-            ',' => {
-                self.position += 1;
-                Token::Comma
+            if self.advance_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.unrecognized());
             }
+            real = true;
+        }
+        // A number can't run into a name, as in `1a`.
+        if self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.advance_while(|c| c.is_alphanumeric() || c == '_');
+            return Err(self.unrecognized());
+        }
+
+        let number = &self.input[self.start..self.position];
+        match number.parse() {
+            Ok(value) if !real => Ok(Token::IntegerLiteral(value)),
+            _ => number
+                .parse()
+                .map(Token::RealLiteral)
+                .map_err(|_| self.unrecognized()),
+        }
+    }
+
+    // The error for the text of the token being lexed, which isn't one.
+    fn unrecognized(&self) -> Error {
+        Error::UnrecognizedToken {
+            text: self.input[self.start..self.position].to_string(),
+            span: self.start..self.position,
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token> {
+        self.skip_trivia();
+        self.start = self.position;
+
+        let Some(current_char) = self.advance() else {
+            return Ok(Token::Eof);
+        };
+
+        match current_char {
+            '(' => Ok(Token::LParen),
+            ')' => Ok(Token::RParen),
+            ';' => Ok(Token::Semicolon),
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number(),
+            '.' => Ok(Token::Dot),
+            ',' => Ok(Token::Comma),
+            // `==` is another way of writing `=`, and `<>` of `!=`.
             '=' => {
-                self.position += 1;
-                Token::Equals
+                self.advance_if('=');
+                Ok(Token::Equals)
             }
-            '-' => {
-                self.position += 1;
-                current_char = self.input.chars().nth(self.position).unwrap();
-                if current_char == '-' {
-                    self.position += 1;
-                    while current_char != '\n' {
-                        self.position += 1;
-                        current_char = self.input.chars().nth(self.position).unwrap();
-                    }
-                    self.next_token()
-                } else {
-                    panic!("Unexpected character: {}", current_char);
+            '!' => match self.advance_if('=') {
+                true => Ok(Token::NotEquals),
+                false => Err(self.unrecognized()),
+            },
+            '<' if self.advance_if('=') => Ok(Token::LessEquals),
+            '<' if self.advance_if('>') => Ok(Token::NotEquals),
+            '<' => Ok(Token::Less),
+            '>' if self.advance_if('=') => Ok(Token::GreaterEquals),
+            '>' => Ok(Token::Greater),
+            '*' => Ok(Token::Star),
+            '+' => Ok(Token::Plus),
+            '-' => Ok(Token::Minus),
+            '/' => Ok(Token::Slash),
+            '%' => Ok(Token::Percent),
+            '|' => match self.advance_if('|') {
+                true => Ok(Token::Concat),
+                false => Err(self.unrecognized()),
+            },
+            '?' | ':' | '@' | '$' => {
+                // `?` is followed by an optional number, and the others by a name, which keeps the
+                // case it was written in.
+                self.advance_while(|c| match current_char {
+                    '?' => c.is_ascii_digit(),
+                    _ => c.is_alphanumeric() || c == '_',
+                });
+
+                let variable = &self.input[self.start..self.position];
+                if current_char != '?' && variable.len() == 1 {
+                    return Err(self.unrecognized());
                 }
+                Ok(Token::Variable(variable.to_string()))
             }
-            '*' => {
-                self.position += 1;
-                Token::Star
-            }
-            '\'' => {
-                self.position += 1;
-                let mut string_literal = String::new();
-                current_char = self.input.chars().nth(self.position).unwrap();
-                while current_char != '\'' {
-                    string_literal.push(current_char);
-                    self.position += 1;
-                    current_char = self.input.chars().nth(self.position).unwrap();
+            '\'' => self.quoted('\'').map(Token::StringLiteral),
+            // A name can be quoted in the standard way, in brackets like Access, or in backticks
+            // like MySQL, so that it can be a keyword or have spaces in it. It keeps its case.
+            '"' => self.quoted('"').map(Token::Identifier),
+            '[' => self.quoted(']').map(Token::Identifier),
+            '`' => self.quoted('`').map(Token::Identifier),
+            // A blob is written as hex digits in quotes after an X, two to a byte.
+            'x' | 'X' if self.advance_if('\'') => {
+                let hex = self.quoted('\'')?;
+                if hex.len() % 2 != 0 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(self.unrecognized());
                 }
-                self.position += 1;
-                Token::StringLiteral(string_literal)
+                Ok(Token::BlobLiteral(
+                    (0..hex.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                        .collect(),
+                ))
             }
-            '\"' => {
-                self.position += 1;
-                let mut string_literal = String::new();
-                current_char = self.input.chars().nth(self.position).unwrap();
-                while current_char != '\"' {
-                    string_literal.push(current_char);
-                    self.position += 1;
-                    current_char = self.input.chars().nth(self.position).unwrap();
-                }
-                self.position += 1;
-                Token::StringLiteral(string_literal)
-            }
-            _ => {
-                if current_char.is_alphabetic() {
-                    let mut identifier = String::new();
-                    while current_char.is_alphabetic() || current_char == '_' {
-                        identifier.push(current_char);
-                        self.position += 1;
-
-                        if self.position >= self.input.len() {
-                            break;
-                        }
-
-                        current_char = self.input.chars().nth(self.position).unwrap();
-                    }
-                    match identifier.to_ascii_uppercase().as_str() {
-                        "AUTOINCREMENT" => Token::AutoIncrement,
-                        "CREATE" => Token::Create,
-                        "TABLE" => Token::Table,
-                        "PRIMARY" => Token::Primary,
-                        "KEY" => Token::Key,
-                        "SELECT" => Token::Select,
-                        "FROM" => Token::From,
-                        "WHERE" => Token::Where,
-                        "NOT" => Token::Not,
-                        "NULL" => Token::Null,
-                        "INDEX" => Token::Index,
-                        "ON" => Token::On,
-                        _ => Token::Identifier(identifier.to_ascii_uppercase()),
-                    }
-                } else if current_char.is_whitespace() {
-                    self.position += 1;
-                    self.next_token()
-                } else {
-                    panic!("Unexpected character: {}", current_char);
-                }
+            // Digits can follow the first letter, as in `fts5` or `c0`.
+            c if c.is_alphabetic() || c == '_' => {
+                self.advance_while(|c| c.is_alphanumeric() || c == '_');
+                // A name keeps the case it was written in, for saying what can't be found.
+                let identifier = &self.input[self.start..self.position];
+                Ok(
+                    keyword(identifier)
+                        .unwrap_or_else(|| Token::Identifier(identifier.to_string())),
+                )
             }
+            c if c.is_ascii_digit() => self.number(),
+            _ => Err(self.unrecognized()),
         }
     }
 }
@@ -194,27 +442,27 @@ mod tests {
         let expected = vec![
             Token::Create,
             Token::Table,
-            Token::Identifier("EMPLOYEE".to_string()),
+            Token::Identifier("Employee".to_string()),
             Token::LParen,
-            Token::Identifier("ID".to_string()),
+            Token::Identifier("id".to_string()),
             Token::Identifier("INTEGER".to_string()),
             Token::Primary,
             Token::Key,
             Token::Comma,
-            Token::Identifier("NAME".to_string()),
+            Token::Identifier("name".to_string()),
             Token::Identifier("TEXT".to_string()),
             Token::Comma,
-            Token::Identifier("AGE".to_string()),
+            Token::Identifier("age".to_string()),
             Token::Identifier("INTEGER".to_string()),
             Token::Comma,
-            Token::Identifier("JOB_TITLE".to_string()),
+            Token::Identifier("job_title".to_string()),
             Token::Identifier("TEXT".to_string()),
             Token::RParen,
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -228,12 +476,12 @@ mod tests {
             Token::Select,
             Token::Star,
             Token::From,
-            Token::Identifier("EMPLOYEE".to_string()),
+            Token::Identifier("Employee".to_string()),
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -247,12 +495,12 @@ mod tests {
             Token::Select,
             Token::Star,
             Token::From,
-            Token::Identifier("EMPLOYEE".to_string()),
+            Token::Identifier("Employee".to_string()),
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -269,12 +517,12 @@ mod tests {
             Token::Star,
             Token::RParen,
             Token::From,
-            Token::Identifier("EMPLOYEE".to_string()),
+            Token::Identifier("Employee".to_string()),
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -286,20 +534,49 @@ mod tests {
 
         let expected = vec![
             Token::Select,
-            Token::Identifier("NAME".to_string()),
+            Token::Identifier("name".to_string()),
             Token::Comma,
-            Token::Identifier("COLOR".to_string()),
+            Token::Identifier("color".to_string()),
             Token::From,
-            Token::Identifier("APPLES".to_string()),
+            Token::Identifier("apples".to_string()),
             Token::Where,
-            Token::Identifier("COLOR".to_string()),
+            Token::Identifier("color".to_string()),
             Token::Equals,
             Token::StringLiteral("Yellow".to_string()),
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let input = "a < b <= c > d >= e != f <> g == h";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = vec![
+            Token::Identifier("a".to_string()),
+            Token::Less,
+            Token::Identifier("b".to_string()),
+            Token::LessEquals,
+            Token::Identifier("c".to_string()),
+            Token::Greater,
+            Token::Identifier("d".to_string()),
+            Token::GreaterEquals,
+            Token::Identifier("e".to_string()),
+            Token::NotEquals,
+            Token::Identifier("f".to_string()),
+            Token::NotEquals,
+            Token::Identifier("g".to_string()),
+            Token::Equals,
+            Token::Identifier("h".to_string()),
+            Token::Eof,
+        ];
+
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -311,38 +588,38 @@ mod tests {
         let expected = vec![
             Token::Create,
             Token::Table,
-            Token::StringLiteral("superheroes".to_string()),
+            Token::Identifier("superheroes".to_string()),
             Token::LParen,
-            Token::Identifier("ID".to_string()),
-            Token::Identifier("INTEGER".to_string()),
+            Token::Identifier("id".to_string()),
+            Token::Identifier("integer".to_string()),
             Token::Primary,
             Token::Key,
             Token::AutoIncrement,
             Token::Comma,
-            Token::Identifier("NAME".to_string()),
-            Token::Identifier("TEXT".to_string()),
+            Token::Identifier("name".to_string()),
+            Token::Identifier("text".to_string()),
             Token::Not,
             Token::Null,
             Token::Comma,
-            Token::Identifier("EYE_COLOR".to_string()),
-            Token::Identifier("TEXT".to_string()),
+            Token::Identifier("eye_color".to_string()),
+            Token::Identifier("text".to_string()),
             Token::Comma,
-            Token::Identifier("HAIR_COLOR".to_string()),
-            Token::Identifier("TEXT".to_string()),
+            Token::Identifier("hair_color".to_string()),
+            Token::Identifier("text".to_string()),
             Token::Comma,
-            Token::Identifier("APPEARANCE_COUNT".to_string()),
-            Token::Identifier("INTEGER".to_string()),
+            Token::Identifier("appearance_count".to_string()),
+            Token::Identifier("integer".to_string()),
             Token::Comma,
-            Token::Identifier("FIRST_APPEARANCE".to_string()),
-            Token::Identifier("TEXT".to_string()),
+            Token::Identifier("first_appearance".to_string()),
+            Token::Identifier("text".to_string()),
             Token::Comma,
-            Token::Identifier("FIRST_APPEARANCE_YEAR".to_string()),
-            Token::Identifier("TEXT".to_string()),
+            Token::Identifier("first_appearance_year".to_string()),
+            Token::Identifier("text".to_string()),
             Token::RParen,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
 
@@ -355,17 +632,210 @@ mod tests {
         let expected = [
             Token::Create,
             Token::Index,
-            Token::Identifier("IDX_SUPERHEROES_FIRST_APPEARED".to_string()),
+            Token::Identifier("idx_superheroes_first_appeared".to_string()),
             Token::On,
-            Token::Identifier("SUPERHEROES".to_string()),
+            Token::Identifier("superheroes".to_string()),
             Token::LParen,
-            Token::Identifier("FIRST_APPEARANCE".to_string()),
+            Token::Identifier("first_appearance".to_string()),
             Token::RParen,
             Token::Semicolon,
             Token::Eof,
         ];
 
-        let tokens = lexer.lex();
+        let tokens = lexer.lex().unwrap();
         assert_eq!(tokens, expected);
     }
+
+    #[test]
+    fn select_where_in() {
+        let input = "SELECT name FROM apples WHERE id IN (1, 2, 'three');";
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = [
+            Token::Select,
+            Token::Identifier("name".to_string()),
+            Token::From,
+            Token::Identifier("apples".to_string()),
+            Token::Where,
+            Token::Identifier("id".to_string()),
+            Token::In,
+            Token::LParen,
+            Token::IntegerLiteral(1),
+            Token::Comma,
+            Token::IntegerLiteral(2),
+            Token::Comma,
+            Token::StringLiteral("three".to_string()),
+            Token::RParen,
+            Token::Semicolon,
+            Token::Eof,
+        ];
+
+        let tokens = lexer.lex().unwrap();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn create_virtual_table() {
+        let input = "CREATE VIRTUAL TABLE docs USING fts5(c0, c1)";
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = [
+            Token::Create,
+            Token::Virtual,
+            Token::Table,
+            Token::Identifier("docs".to_string()),
+            Token::Using,
+            Token::Identifier("fts5".to_string()),
+            Token::LParen,
+            Token::Identifier("c0".to_string()),
+            Token::Comma,
+            Token::Identifier("c1".to_string()),
+            Token::RParen,
+            Token::Eof,
+        ];
+
+        let tokens = lexer.lex().unwrap();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn quoted_names() {
+        let input = "SELECT \"my \"\"col\"\"\", [select], `a``b`, 'it''s' FROM \"t\"";
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = [
+            Token::Select,
+            Token::Identifier("my \"col\"".to_string()),
+            Token::Comma,
+            Token::Identifier("select".to_string()),
+            Token::Comma,
+            Token::Identifier("a`b".to_string()),
+            Token::Comma,
+            Token::StringLiteral("it's".to_string()),
+            Token::From,
+            Token::Identifier("t".to_string()),
+            Token::Eof,
+        ];
+
+        let tokens = lexer.lex().unwrap();
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens[1].to_string(), "\"my \"\"col\"\"\"");
+        assert_eq!(tokens[3].to_string(), "\"select\"");
+        assert_eq!(tokens[7].to_string(), "'it''s'");
+    }
+
+    #[test]
+    fn block_comments() {
+        let input = "SELECT /* a\n * b */ 1 /**/, 2 /* not closed";
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = [
+            Token::Select,
+            Token::IntegerLiteral(1),
+            Token::Comma,
+            Token::IntegerLiteral(2),
+            Token::Eof,
+        ];
+
+        assert_eq!(lexer.lex().unwrap(), expected);
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        for input in [
+            "SELECT 'a",
+            "SELECT \"a",
+            "SELECT [a",
+            "SELECT `a",
+            "SELECT 'a''",
+        ] {
+            assert!(Lexer::new(input.to_string()).lex().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let input = "1 1.5 .5 2. 1e3 2.5E-2 -3 9223372036854775808";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let expected = vec![
+            Token::IntegerLiteral(1),
+            Token::RealLiteral(1.5),
+            Token::RealLiteral(0.5),
+            Token::RealLiteral(2.0),
+            Token::RealLiteral(1000.0),
+            Token::RealLiteral(0.025),
+            Token::Minus,
+            Token::IntegerLiteral(3),
+            Token::RealLiteral(9223372036854775808.0),
+            Token::Eof,
+        ];
+
+        assert_eq!(lexer.lex().unwrap(), expected);
+
+        for input in ["1a", "1e", "1.5x"] {
+            assert!(Lexer::new(input.to_string()).lex().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn arithmetic_operators() {
+        let mut lexer = Lexer::new("a+b-c*d/e%f||g".to_string());
+
+        let name = |name: &str| Token::Identifier(name.to_string());
+        let expected = vec![
+            name("a"),
+            Token::Plus,
+            name("b"),
+            Token::Minus,
+            name("c"),
+            Token::Star,
+            name("d"),
+            Token::Slash,
+            name("e"),
+            Token::Percent,
+            name("f"),
+            Token::Concat,
+            name("g"),
+            Token::Eof,
+        ];
+
+        assert_eq!(lexer.lex().unwrap(), expected);
+        assert!(Lexer::new("a | b".to_string()).lex().is_err());
+    }
+
+    #[test]
+    fn blobs() {
+        let mut lexer = Lexer::new("X'00fF' x'' x".to_string());
+
+        let expected = vec![
+            Token::BlobLiteral(vec![0x00, 0xff]),
+            Token::BlobLiteral(Vec::new()),
+            Token::Identifier("x".to_string()),
+            Token::Eof,
+        ];
+
+        assert_eq!(lexer.lex().unwrap(), expected);
+
+        for input in ["X'0'", "X'0g'", "X'00"] {
+            assert!(Lexer::new(input.to_string()).lex().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let input = "SELECT 'café' -- why\n, n\tFROM t";
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+        let texts = lexer
+            .spans()
+            .iter()
+            .map(|span| &input[span.clone()])
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens.len(), texts.len());
+        assert_eq!(texts, ["SELECT", "'café'", ",", "n", "FROM", "t", ""]);
+    }
 }
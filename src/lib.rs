@@ -0,0 +1,2540 @@
+// A reader for SQLite database files, with a small SQL engine on top. `Connection` is the way in
+// for other programs; the sqlite3 style shell in `shell` is built on the same pieces.
+use crate::btree::{BTreeCursor, Leaf, Leaves};
+use crate::error::Result;
+use crate::pager::{Pager, PagerOptions};
+use crate::trace::Target;
+use std::fmt::Display;
+use std::io::{self, prelude::*};
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub use crate::batch::{Array, ArrowType, Field, RecordBatch};
+pub use crate::blob::Blob;
+pub use crate::catalog::{Affinity, Column, ForeignKey, Index, IndexOrigin, Table, View};
+pub use crate::connection::{Action, Connection, RowCursor, Rows, Statement};
+pub use crate::error::Error;
+pub use crate::pool::{Pool, PooledConnection};
+pub use crate::row::{ColumnIndex, FromValue, Row};
+pub use crate::sql_engine::QueryStats;
+pub use crate::vtab::{Constraint, ConstraintOp, VirtualCursor, VirtualTable};
+
+mod batch;
+mod bench;
+mod blob;
+mod btree;
+mod catalog;
+mod cipher;
+mod connection;
+mod dbhash;
+mod dump;
+mod error;
+mod foreign_key;
+mod fts;
+mod gzip;
+mod http;
+mod inspect;
+mod integrity;
+mod lexer;
+mod line_editor;
+mod output;
+mod pager;
+mod parquet;
+mod parser;
+mod pool;
+mod pragma;
+mod row;
+mod rtree;
+pub mod shell;
+mod sql_engine;
+mod trace;
+mod visit;
+mod vtab;
+mod wasm;
+mod write;
+
+// This crate's own release, which is what `--version` and `sqlite_version()` report, whatever
+// version wrote the database. The source id says which release of SQLite's file format it reads
+// and writes, the one whose output it is checked against.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const SOURCE_ID: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION"),
+    ", compatible with the file format of SQLite 3.51.2"
+);
+// That release of SQLite, as sqlite writes it into the header of a database it changes, which is
+// what this crate writes there too, as the files it writes are the ones that release would.
+const SQLITE_VERSION_NUMBER: u32 = 3051002;
+
+// Every read takes &self: the pager reads with positioned reads and keeps its counters in atomics,
+// so one Db can be shared between threads that all read from it at once.
+pub(crate) struct Db {
+    // Where the database was opened from, resolved to an absolute path.
+    path: PathBuf,
+    // Shared with the Db that replaces this one when the schema of a database in memory changes.
+    pager: Arc<Pager>,
+    header: DbHeader,
+    catalog: catalog::Catalog,
+    // Rows changed by the most recent write statement, and by every statement since the database
+    // was opened, and the rowid of the last row inserted. Clones of a connection share them, as
+    // they share the database.
+    changes: AtomicU64,
+    total_changes: AtomicU64,
+    last_insert_rowid: AtomicI64,
+}
+
+impl Db {
+    // Opens the database at `path`, or a new empty database held in memory if the path is
+    // `:memory:`, as it is for sqlite3. A path that is an `http://`, `s3://` or `gs://` URL reads
+    // the database from a web server or object store, over plain http and without credentials,
+    // so only public objects can be read, and S3 ones only from the endpoint AWS_ENDPOINT_URL
+    // names.
+    fn new(path: PathBuf, options: PagerOptions) -> Result<Self> {
+        let url = path.to_str().filter(|path| {
+            ["http://", "https://", "s3://", "gs://"]
+                .iter()
+                .any(|scheme| path.starts_with(scheme))
+        });
+
+        let salvage = options.salvage;
+        let mut pager = if path.as_os_str() == MEMORY_PATH {
+            Pager::memory(empty_database())?
+        } else if let Some(url) = url {
+            // A server that can't be reached or doesn't have the file is like a missing file, but
+            // one that can't serve ranges, or won't serve the file without credentials, says why.
+            Pager::http(url).map_err(|err| match err.kind() {
+                io::ErrorKind::Unsupported
+                | io::ErrorKind::InvalidData
+                | io::ErrorKind::PermissionDenied => Error::Io(err),
+                _ => Error::CantOpen(path.clone()),
+            })?
+        } else {
+            // A file that is there but can't be read as a database is told apart from one that
+            // can't be opened at all. The pager is the only thing that opens it, as closing any
+            // other handle on it would let go of the locks the process holds on it.
+            Pager::open(&path, options).map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => {
+                    Error::CantOpen(path.clone())
+                }
+                io::ErrorKind::WouldBlock => Error::Busy,
+                _ => Error::NotADatabase,
+            })?
+        };
+        pager.set_salvage(salvage);
+        Self::from_pager(path, Arc::new(pager))
+    }
+
+    // Opens a database from the bytes of its file, which may be gzip compressed, for programs that
+    // already have it in memory or have no file system to read it from.
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let image = if gzip::is_gzip(&bytes) {
+            gzip::decompress(&bytes).map_err(|_| Error::NotADatabase)?
+        } else {
+            bytes
+        };
+        let pager = Pager::memory(image).map_err(|_| Error::NotADatabase)?;
+        Self::from_pager(PathBuf::from(MEMORY_PATH), Arc::new(pager))
+    }
+
+    fn from_pager(path: PathBuf, pager: Arc<Pager>) -> Result<Self> {
+        let lock = pager.read_lock().map_err(Error::locking)?;
+        let page = pager.read_page(1).map_err(|_| Error::NotADatabase)?;
+        if !page.starts_with(b"SQLite format 3\0") {
+            return Err(Error::NotADatabase);
+        }
+        let header =
+            DbHeader::parse(&mut page.as_ref()).map_err(|err| Error::corrupt(1, 0, err))?;
+        drop(page);
+        // A header that says there are more pages than the file holds, when sqlite would trust the
+        // count in it, means the file was cut short, which sqlite won't read past either. What is
+        // left of it can still be salvaged.
+        let header_pages = header.database_size_in_pages;
+        if header.version_valid_for == header.file_change_counter
+            && header_pages > pager.page_count()
+            && !pager.salvage()
+        {
+            return Err(Error::Corrupt {
+                page: 1,
+                message: format!(
+                    "the header says there are {} pages, but the file holds {}",
+                    header_pages,
+                    pager.page_count()
+                ),
+            });
+        }
+        // Like sqlite, a database whose pages have fewer than 480 bytes left for cells once the
+        // reserved space is taken out isn't one it can read.
+        if pager.usable_size() < 480 {
+            return Err(Error::NotADatabase);
+        }
+
+        // The schema table is an ordinary table b-tree rooted at page 1, which spills onto more
+        // pages once there are enough objects in the schema.
+        let objects = BTreeCursor::scan(&pager, 1)
+            .map(|record| MasterPageRecord::parse(&record?).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        // Nor does it read a schema with an object rooted past the last page.
+        let lost = objects
+            .iter()
+            .find(|object| object.root_page > pager.page_count());
+        if let Some(object) = lost.filter(|_| !pager.salvage()) {
+            return Err(Error::MalformedSchema {
+                name: object.name.clone(),
+                message: "invalid rootpage".to_string(),
+            });
+        }
+        drop(lock);
+        let catalog = catalog::Catalog::new(objects)?;
+
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+
+        Ok(Self {
+            path,
+            pager,
+            header,
+            catalog,
+            changes: AtomicU64::new(0),
+            total_changes: AtomicU64::new(0),
+            last_insert_rowid: AtomicI64::new(0),
+        })
+    }
+
+    // Rereads the database header to see whether another connection has changed the schema, which
+    // bumps the schema cookie, since this one was opened.
+    fn schema_changed(&self) -> Result<bool> {
+        let page = read_page(&self.pager, 1)?;
+        let header =
+            DbHeader::parse(&mut page.as_ref()).map_err(|err| Error::corrupt(1, 0, err))?;
+        Ok(header.schema_cookie != self.header.schema_cookie)
+    }
+
+    // The database with its schema read again, once it has changed. A file is opened afresh, but
+    // a database in memory has nowhere to be read from but its pager, which the two share. What
+    // the counts of changes say carries over.
+    fn reopen(&self, options: PagerOptions) -> Result<Db> {
+        let db = match self.pager.in_memory() {
+            true => Self::from_pager(self.path.clone(), self.pager.clone())?,
+            false => Self::new(self.path.clone(), options)?,
+        };
+        db.changes
+            .store(self.changes.load(Ordering::Relaxed), Ordering::Relaxed);
+        db.total_changes.store(
+            self.total_changes.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        db.last_insert_rowid.store(
+            self.last_insert_rowid.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        Ok(db)
+    }
+
+    fn get_table(&self, table_name: &str) -> Result<Arc<MasterPageRecord>> {
+        // A table is found by its own name. Every object has a table name too, but an index's or
+        // a trigger's is the name of the table it belongs to.
+        let record = self
+            .catalog
+            .find(table_name)
+            .ok_or_else(|| Error::NoSuchTable(table_name.to_string()))?;
+        if record.object_type == catalog::ObjectType::View {
+            return Err(Error::Unsupported(format!("reading view {}", record.name)));
+        }
+        record.usable()?;
+        Ok(record.clone())
+    }
+
+    fn get_table_rows(
+        &self,
+        table: &MasterPageRecord,
+        row_ids: Option<&[u64]>,
+    ) -> Result<Vec<TableLeafRecord>> {
+        has_rowids(table)?;
+
+        Ok(match row_ids {
+            // Unindexed scans visit every leaf, so they can be split across threads.
+            None => self.scan_table_parallel(table.root_page)?,
+            Some(row_ids) => {
+                let mut row_ids = row_ids.to_vec();
+                row_ids.sort_unstable();
+                row_ids.dedup();
+                let _span = trace::span(Target::BTree, || {
+                    format!(
+                        "seek of {} rowids in table on page {}",
+                        row_ids.len(),
+                        table.root_page
+                    )
+                });
+
+                btree::seek_rowids(&self.pager, table.root_page, &row_ids)?
+                    .into_iter()
+                    .map(into_table_leaf_record)
+                    .collect()
+            }
+        })
+    }
+
+    // Partitions the leaves of the table across a pool of scoped threads sharing the pager, and
+    // concatenates the results. Leaves are ordered by rowid, so joining the partitions back
+    // together in order keeps the rows in rowid order.
+    fn scan_table_parallel(&self, root_page: u32) -> Result<Vec<TableLeafRecord>> {
+        let _span = trace::span(Target::BTree, || {
+            format!("scan of table on page {}", root_page)
+        });
+        let leaves = Leaves::new(&self.pager, root_page).collect::<Result<Vec<_>>>()?;
+        self.read_leaves(leaves)
+    }
+
+    // The rows on a batch of a table's leaves, in order. The leaves that haven't been read yet
+    // are split across as many threads as there are cores.
+    fn read_leaves(&self, leaves: Vec<Leaf>) -> Result<Vec<TableLeafRecord>> {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        self.read_leaves_on(leaves, threads)
+    }
+
+    fn read_leaves_on(&self, leaves: Vec<Leaf>, threads: usize) -> Result<Vec<TableLeafRecord>> {
+        let pager = &self.pager;
+        let read = move |leaf: Leaf| -> Result<Vec<TableLeafRecord>> {
+            match leaf {
+                Leaf::Read(records) => {
+                    Ok(records.into_iter().map(into_table_leaf_record).collect())
+                }
+                Leaf::Unread(page) => BTreeCursor::scan(pager, page)
+                    .map(|record| record.map(into_table_leaf_record))
+                    .collect(),
+            }
+        };
+
+        let threads = threads.min(leaves.len());
+
+        // Without a second thread to share the work with, or on targets with no threads at all,
+        // the leaves are read right here.
+        if threads <= 1 {
+            let mut rows = Vec::new();
+            for leaf in leaves {
+                rows.extend(read(leaf)?);
+            }
+            return Ok(rows);
+        }
+
+        let chunk_size = leaves.len().div_ceil(threads);
+        let mut leaves = leaves.into_iter();
+        let chunks = (0..threads)
+            .map(|_| leaves.by_ref().take(chunk_size).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            let workers = chunks
+                .into_iter()
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut rows = Vec::new();
+                        for leaf in chunk {
+                            rows.extend(read(leaf)?);
+                        }
+                        Ok::<_, Error>(rows)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let mut rows = Vec::new();
+            for worker in workers {
+                rows.extend(worker.join().unwrap()?);
+            }
+            Ok(rows)
+        })
+    }
+
+    // The tables in the schema, in the order they were created, each with the indexes on it.
+    fn tables(&self) -> &[catalog::Table] {
+        self.catalog.tables()
+    }
+
+    // Every object in the schema whose table name matches a LIKE style pattern, or all of them if
+    // there is no pattern, in the order they appear in the schema table.
+    fn schema_objects<'a>(
+        &'a self,
+        pattern: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a MasterPageRecord> + 'a {
+        self.catalog
+            .objects()
+            .iter()
+            .map(|record| record.as_ref())
+            .filter(move |record| pattern.is_none_or(|pattern| like(pattern, &record.table_name)))
+    }
+
+    // The number of rows in a table, counted without reading any of them.
+    fn count_rows(&self, table: &MasterPageRecord) -> Result<u64> {
+        let _span = trace::span(Target::BTree, || {
+            format!("count of table on page {}", table.root_page)
+        });
+        btree::count_entries(&self.pager, table.root_page, !table.without_rowid)
+    }
+
+    // The smallest or largest rowid in a table, read from the first or last leaf, or NULL when the
+    // table is empty.
+    fn rowid_bound(&self, table: &MasterPageRecord, max: bool) -> Result<Value> {
+        let record = match max {
+            true => btree::last(&self.pager, table.root_page, true)?,
+            false => BTreeCursor::scan(&self.pager, table.root_page)
+                .next()
+                .transpose()?,
+        };
+        Ok(record.map_or(Value::Null, |record| {
+            Value::Int(into_table_leaf_record(record).header.row_id as i64)
+        }))
+    }
+
+    // The smallest or largest value of an index's first column, read from one end of the index.
+    // NULLs sort first, so the smallest is the first entry after them, and they are the largest
+    // only when there is nothing else.
+    fn indexed_bound(&self, index_record: &MasterPageRecord, max: bool) -> Result<Value> {
+        let record = match max {
+            true => btree::last(&self.pager, index_record.root_page, false)?,
+            false => {
+                BTreeCursor::seek_index_after(&self.pager, index_record.root_page, Value::Null)
+                    .next()
+                    .transpose()?
+            }
+        };
+        Ok(record.map_or(Value::Null, |record| btree::first_key(&record).clone()))
+    }
+
+    // The leaves of a table with a rowid, in rowid order, to be read a batch at a time with
+    // `read_leaves`.
+    fn table_leaves(&self, table: &MasterPageRecord) -> Result<Leaves<'_>> {
+        has_rowids(table)?;
+        trace::event!(Target::BTree, "scan of table on page {}", table.root_page);
+        Ok(Leaves::new(&self.pager, table.root_page))
+    }
+
+    // The rowids of the rows whose indexed column has any of the values. An index is only any use
+    // for finding a column's values if it is sorted by that column first.
+    fn index_row_ids(&self, index_record: &MasterPageRecord, values: Vec<Value>) -> RowIds<'_> {
+        let root_page = index_record.root_page;
+        trace::event!(
+            Target::BTree,
+            "seek of {} keys in index on page {}",
+            values.len(),
+            root_page
+        );
+        Box::new(
+            values
+                .into_iter()
+                .flat_map(move |value| BTreeCursor::seek_index(&self.pager, root_page, value))
+                .map(entry_row_id),
+        )
+    }
+
+    // The rowids of the rows whose indexed column is in any of the ranges, each of which is
+    // bounded below and above.
+    fn index_range_row_ids(
+        &self,
+        index_record: &MasterPageRecord,
+        ranges: Vec<(Bound<Value>, Bound<Value>)>,
+    ) -> RowIds<'_> {
+        let root_page = index_record.root_page;
+        trace::event!(
+            Target::BTree,
+            "seek of {} ranges in index on page {}",
+            ranges.len(),
+            root_page
+        );
+        Box::new(
+            ranges
+                .into_iter()
+                .flat_map(move |(low, high)| {
+                    BTreeCursor::seek_index_range(&self.pager, root_page, low, high)
+                })
+                .map(entry_row_id),
+        )
+    }
+
+    // The rowids of the rows whose index entries have one of the values as their second column,
+    // whatever their first. Each distinct value of the first column is found in turn, by seeking
+    // past the one before it, and the values are looked up under it, so the index is read a few
+    // pages per distinct value rather than from end to end.
+    fn skip_scan_row_ids(&self, index_record: &MasterPageRecord, values: Vec<Value>) -> RowIds<'_> {
+        let root_page = index_record.root_page;
+        trace::event!(
+            Target::BTree,
+            "skip scan for {} keys in index on page {}",
+            values.len(),
+            root_page
+        );
+
+        // NULLs sort first, so every entry is at least NULL.
+        let mut first = Some(BTreeCursor::seek_index_range(
+            &self.pager,
+            root_page,
+            Bound::Unbounded,
+            Bound::Unbounded,
+        ));
+        let firsts = std::iter::from_fn(move || {
+            let record = match first.take()?.next()? {
+                Ok(record) => record,
+                Err(err) => return Some(Err(err)),
+            };
+            let key = btree::first_key(&record).clone();
+            first = Some(BTreeCursor::seek_index_after(
+                &self.pager,
+                root_page,
+                key.clone(),
+            ));
+            Some(Ok(key))
+        });
+
+        Box::new(firsts.flat_map(move |key| -> RowIds<'_> {
+            let key = match key {
+                Ok(key) => key,
+                Err(err) => return Box::new(std::iter::once(Err(err))),
+            };
+            let values = values.clone();
+            Box::new(
+                values
+                    .into_iter()
+                    .flat_map(move |value| {
+                        let prefix = vec![key.clone(), value];
+                        BTreeCursor::seek_index_prefix(&self.pager, root_page, prefix)
+                    })
+                    .map(entry_row_id),
+            )
+        }))
+    }
+}
+
+// The rowids of rows, as they are found one at a time.
+pub(crate) type RowIds<'a> = Box<dyn Iterator<Item = Result<u64>> + 'a>;
+
+// WITHOUT ROWID tables are stored as index b-trees, which rows can't be read out of yet.
+fn has_rowids(table: &MasterPageRecord) -> Result<()> {
+    if table.without_rowid {
+        return Err(Error::Unsupported(format!(
+            "reading WITHOUT ROWID table {}",
+            table.name
+        )));
+    }
+    Ok(())
+}
+
+// The rowid of the row an index entry belongs to, which is always the last column of the entry.
+fn entry_row_id(record: Result<DbRecord>) -> Result<u64> {
+    let mut values = match record? {
+        DbRecord::IndexLeafRecord(ilrecord) => ilrecord.values,
+        DbRecord::InteriorIndexRecord(irecord) => irecord.values,
+        _ => unreachable!(),
+    };
+    values.pop().unwrap_or(Value::Null).try_into()
+}
+
+const MEMORY_PATH: &str = ":memory:";
+
+// The names the schema table can be queried by. sqlite_master is the one it had before 3.33.
+const SCHEMA_TABLE_NAMES: [&str; 2] = ["sqlite_schema", "sqlite_master"];
+
+// The image of a database with nothing in it: the header, and a first page holding an empty schema
+// table. It is what sqlite3 would write for a new database, less the version it was written by.
+fn empty_database() -> Vec<u8> {
+    const PAGE_SIZE: u16 = 4096;
+
+    let mut page = vec![0; PAGE_SIZE as usize];
+    page[..16].copy_from_slice(b"SQLite format 3\0");
+    page[16..18].copy_from_slice(&PAGE_SIZE.to_be_bytes());
+    // Rollback journal for both the read and write versions, no reserved space, and the payload
+    // fractions that every database has.
+    page[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    // The change counter, and the size of the database in pages.
+    page[24..28].copy_from_slice(&1u32.to_be_bytes());
+    page[28..32].copy_from_slice(&1u32.to_be_bytes());
+    // Schema format 4 and UTF-8 text.
+    page[44..48].copy_from_slice(&4u32.to_be_bytes());
+    page[56..60].copy_from_slice(&1u32.to_be_bytes());
+    page[92..96].copy_from_slice(&1u32.to_be_bytes());
+
+    // The schema table is an empty leaf, whose cell content area starts at the end of the page.
+    page[100] = 0x0d;
+    page[105..107].copy_from_slice(&PAGE_SIZE.to_be_bytes());
+    page
+}
+
+// Reads a page by a number found in the database, which is corrupt if it points outside the file.
+fn read_page(pager: &Pager, page: u32) -> Result<std::borrow::Cow<'_, [u8]>> {
+    pager.read_page(page).map_err(|err| match err.kind() {
+        io::ErrorKind::InvalidData => Error::corrupt(page, 0, err),
+        _ => Error::Io(err),
+    })
+}
+
+fn load_page(pager: &Pager, page: u32) -> Result<DbPage> {
+    let bytes = read_page(pager, page)?;
+    // A cell that runs into the reserved space at the end of the page is corrupt.
+    let bytes = bytes.get(..pager.usable_size()).unwrap_or(&bytes);
+
+    // The first page starts with the database header, so its b-tree header sits after it.
+    let header_offset = if page == 1 { 100 } else { 0 };
+    let page = match pager.salvage() {
+        true => {
+            let (page, errors) = DbPage::parse_salvaging(pager, bytes, page, header_offset)?;
+            for err in errors {
+                pager.warn(format!("{}, skipping the cell", err));
+            }
+            page
+        }
+        false => DbPage::parse(pager, bytes, page, header_offset)?,
+    };
+    pager.count_cells(page.records.len());
+    Ok(page)
+}
+
+// Matches text against a LIKE pattern, where `%` matches any run of characters and `_` matches
+// exactly one. Like sqlite, the match is case insensitive for ASCII characters only.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    // Backtracking matcher: on a mismatch, let the most recent `%` swallow one more character.
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '_' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+fn into_table_leaf_record(record: DbRecord) -> TableLeafRecord {
+    match record {
+        DbRecord::TableLeafRecord(trecord) => trecord,
+        _ => unreachable!(),
+    }
+}
+
+// Big-endian reads from the bytes of a page. Every read fails rather than panics when it would run
+// past the end of what there is, so a damaged page is an error and not a crash.
+trait ByteReader {
+    fn read_u8(&mut self) -> io::Result<u8>;
+    fn read_u16(&mut self) -> io::Result<u16>;
+    fn read_u32(&mut self) -> io::Result<u32>;
+    fn read_u64(&mut self) -> io::Result<u64>;
+    fn read_i8(&mut self) -> io::Result<i8>;
+    fn read_i16(&mut self) -> io::Result<i16>;
+    fn read_i32(&mut self) -> io::Result<i32>;
+    fn read_i64(&mut self) -> io::Result<i64>;
+    fn read_varint(&mut self) -> io::Result<(u64, usize)>;
+    fn skip(&mut self, n: usize) -> io::Result<()>;
+    fn read_bytes(&mut self, n: u64) -> io::Result<Vec<u8>>;
+}
+
+macro_rules! read_be {
+    ($($name:ident -> $t:ty),*) => {$(
+        fn $name(&mut self) -> io::Result<$t> {
+            let mut buf = [0; std::mem::size_of::<$t>()];
+            self.read_exact(&mut buf)?;
+            Ok(<$t>::from_be_bytes(buf))
+        }
+    )*};
+}
+
+impl<R: Read> ByteReader for R {
+    read_be!(
+        read_u8 -> u8,
+        read_u16 -> u16,
+        read_u32 -> u32,
+        read_u64 -> u64,
+        read_i8 -> i8,
+        read_i16 -> i16,
+        read_i32 -> i32,
+        read_i64 -> i64
+    );
+
+    fn read_varint(&mut self) -> io::Result<(u64, usize)> {
+        let mut n = 0;
+        let mut size = 0;
+
+        // A varint is up to 9 bytes. The first 8 contribute their low 7 bits while the high bit
+        // says whether another byte follows; a 9th byte contributes all 8 of its bits.
+        loop {
+            let byte = self.read_u8()? as u64;
+            size += 1;
+
+            if size == 9 {
+                n = (n << 8) | byte;
+                break;
+            }
+
+            n = (n << 7) | (byte & 0x7f);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        Ok((n, size))
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        let skipped = io::copy(&mut self.take(n as u64), &mut io::sink())?;
+        if skipped < n as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    // The buffer only grows as bytes actually arrive, so a length read from a damaged cell can't
+    // make it allocate more than the page holds.
+    fn read_bytes(&mut self, n: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.take(n).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+}
+
+// The error for bytes that can't mean what they are supposed to. Parsers return these without
+// knowing which page they are on, and load_page says where they came from.
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FileFormat {
+    Legacy = 1,
+    Wal = 2,
+}
+
+impl TryFrom<u8> for FileFormat {
+    type Error = io::Error;
+
+    fn try_from(byte: u8) -> io::Result<Self> {
+        match byte {
+            1 => Ok(FileFormat::Legacy),
+            2 => Ok(FileFormat::Wal),
+            _ => Err(invalid(format!("invalid file format version {}", byte))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SchemaFormat {
+    One = 1,
+    Two = 2,
+    Three = 3,
+    Four = 4,
+}
+
+impl TryFrom<u32> for SchemaFormat {
+    type Error = io::Error;
+
+    fn try_from(n: u32) -> io::Result<Self> {
+        match n {
+            1 => Ok(SchemaFormat::One),
+            2 => Ok(SchemaFormat::Two),
+            3 => Ok(SchemaFormat::Three),
+            4 => Ok(SchemaFormat::Four),
+            _ => Err(invalid(format!("invalid schema format {}", n))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TextEncoding {
+    Utf8 = 1,
+    Utf16le = 2,
+    Utf16be = 3,
+}
+
+impl TryFrom<u32> for TextEncoding {
+    type Error = io::Error;
+
+    fn try_from(n: u32) -> io::Result<Self> {
+        match n {
+            1 => Ok(TextEncoding::Utf8),
+            2 => Ok(TextEncoding::Utf16le),
+            3 => Ok(TextEncoding::Utf16be),
+            _ => Err(invalid(format!("invalid text encoding {}", n))),
+        }
+    }
+}
+
+impl TextEncoding {
+    fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf8",
+            TextEncoding::Utf16le => "utf16le",
+            TextEncoding::Utf16be => "utf16be",
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct DbHeader {
+    page_size: u32,
+    file_format_write_version: FileFormat,
+    file_format_read_version: FileFormat,
+    reserved_space: u8,
+    max_embedded_payload_fraction: u8,
+    min_embedded_payload_fraction: u8,
+    leaf_payload_fraction: u8,
+    file_change_counter: u32,
+    database_size_in_pages: u32,
+    first_freelist_trunk_page: u32,
+    number_of_freelist_pages: u32,
+    schema_cookie: u32,
+    schema_format: SchemaFormat,
+    default_page_cache_size: u32,
+    largest_root_btree_page_number: u32,
+    text_encoding: TextEncoding,
+    user_version: u32,
+    incremental_vacuum_mode: bool,
+    application_id: u32,
+    version_valid_for: u32,
+    sqlite_version_number: u32,
+}
+
+impl DbHeader {
+    fn parse<R: Read + ByteReader>(reader: &mut R) -> io::Result<Self> {
+        // Every valid SQLite database file begins with the following 16 bytes (in hex):
+        // 53 51 4c 69 74 65 20 66 6f 72 6d 61 74 20 33 00.
+        // This byte sequence corresponds to the UTF-8 string "SQLite format 3" including the nul
+        // terminator character at the end.
+        let mut magic = [0; 16];
+        reader.read_exact(&mut magic)?;
+        if magic
+            != [
+                0x53, 0x51, 0x4c, 0x69, 0x74, 0x65, 0x20, 0x66, 0x6f, 0x72, 0x6d, 0x61, 0x74, 0x20,
+                0x33, 0x00,
+            ]
+        {
+            return Err(invalid("missing the SQLite header string"));
+        }
+
+        // The two-byte value beginning at offset 16 determines the page size of the database.
+        let page_size = reader.read_u16()?;
+
+        // The value 65536 will not fit in a two-byte integer, so to specify a 65536-byte page size, the
+        // value at offset 16 is 0x00 0x01. This value can be interpreted as a big-endian 1 and thought
+        // of as a magic number to represent the 65536 page size.
+        let page_size: u32 = if page_size == 1 {
+            65536
+        } else {
+            page_size as u32
+        };
+
+        // The file format write version and file format read version at offsets 18 and 19 are intended
+        // to allow for enhancements of the file format in future versions of SQLite. In current
+        // versions of SQLite, both of these values are 1 for rollback journalling modes and 2 for WAL
+        // journalling mode.
+        let file_format_write_version = reader.read_u8()?;
+        let file_format_read_version = reader.read_u8()?;
+
+        // The "reserved space" size in the 1-byte integer at offset 20 is the number of bytes of space
+        // at the end of each page to reserve for extensions. This value is usually 0. The value can be odd.
+        let reserved_space = reader.read_u8()?;
+
+        // The maximum and minimum embedded payload fractions and the leaf payload fraction values must
+        // be 64, 32, and 32.
+        let max_embedded_payload_fraction = reader.read_u8()?;
+        let min_embedded_payload_fraction = reader.read_u8()?;
+        let leaf_payload_fraction = reader.read_u8()?;
+
+        if (
+            max_embedded_payload_fraction,
+            min_embedded_payload_fraction,
+            leaf_payload_fraction,
+        ) != (64, 32, 32)
+        {
+            return Err(invalid("invalid payload fractions"));
+        }
+
+        // The file change counter is a 4-byte big-endian integer at offset 24 that is incremented
+        // whenever the database file is unlocked after having been modified.
+        let file_change_counter = reader.read_u32()?;
+
+        // The 4-byte big-endian integer at offset 28 into the header stores the size of the database
+        // file in pages
+        // TODO: See specification regarding invalid size with regards to legacy sqlite
+        let database_size_in_pages = reader.read_u32()?;
+
+        // The 4-byte big-endian integer at offset 32 stores the page number of the first page of the
+        // freelist, or zero if the freelist is empty. The 4-byte big-endian integer at offset 36 stores
+        // the total number of pages on the freelist.
+        let first_freelist_trunk_page = reader.read_u32()?;
+        let number_of_freelist_pages = reader.read_u32()?;
+
+        // The schema cookie is a 4-byte big-endian integer at offset 40 that is incremented whenever
+        // the database schema changes
+        let schema_cookie = reader.read_u32()?;
+
+        // The schema format number is a 4-byte big-endian integer at offset 44.
+        // The formats are:
+        //      1. Format 1 (versions back to 3.0.0)
+        //      2. Format 2 (versions 3.1.3 onwards)
+        //      3. Format 3 (versions 3.1.4 onwards)
+        //      4. Format 4 (versions 3.3.0 onwards)
+        let schema_format_number = reader.read_u32()?;
+
+        // The 4-byte big-endian signed integer at offset 48 is the suggested cache size in pages for
+        // the database file.
+        let default_page_cache_size = reader.read_u32()?;
+
+        // If the integer at offset 52 is zero then pointer-map (ptrmap) pages are omitted from the
+        // database file and neither auto_vacuum nor incremental_vacuum are supported. If the integer at
+        // offset 52 is non-zero then it is the page number of the largest root page in the database file
+
+        let largest_root_btree_page_number = reader.read_u32()?;
+
+        // The 4-byte big-endian integer at offset 56 determines the encoding used for all text strings
+        // stored in the database. A value of 1 means UTF-8. A value of 2 means UTF-16le. A value of 3
+        // means UTF-16be. No other values are allowed.
+        let text_encoding = reader.read_u32()?;
+
+        // The 4-byte big-endian integer at offset 60 is the user version which is set and queried by
+        // the user_version pragma. The user version is not used by SQLite.
+        let user_version = reader.read_u32()?;
+
+        // the integer at offset 64 is true for incremental_vacuum and false for auto_vacuum. If
+        // the integer at offset 52 is zero then the integer at offset 64 must also be zero.
+        let incremental_vacuum_mode = reader.read_u32()? != 0;
+        if largest_root_btree_page_number == 0 && incremental_vacuum_mode {
+            return Err(invalid("incremental vacuum is set without auto vacuum"));
+        }
+
+        // The 4-byte big-endian integer at offset 68 is an "Application ID" that can be set by the
+        // PRAGMA application_id command in order to identify the database as belonging to or associated
+        // with a particular application.
+        let application_id = reader.read_u32()?;
+
+        // Skip 20 bytes for the reserved area
+        reader.skip(20)?;
+
+        // The 4-byte big-endian integer at offset 92 is the value of the change counter when the version
+        // number was stored. The integer at offset 92 indicates which transaction the version number is
+        // valid for and is sometimes called the "version-valid-for number".
+        let version_valid_for = reader.read_u32()?;
+
+        // The 4-byte big-endian integer at offset 96 stores the SQLITE_VERSION_NUMBER value for the
+        // SQLite library that most recently modified the database file.
+        let sqlite_version_number = reader.read_u32()?;
+
+        Ok(Self {
+            page_size,
+            file_format_write_version: file_format_write_version.try_into()?,
+            file_format_read_version: file_format_read_version.try_into()?,
+            reserved_space,
+            max_embedded_payload_fraction,
+            min_embedded_payload_fraction,
+            leaf_payload_fraction,
+            file_change_counter,
+            database_size_in_pages,
+            first_freelist_trunk_page,
+            number_of_freelist_pages,
+            schema_cookie,
+            schema_format: schema_format_number.try_into()?,
+            default_page_cache_size,
+            largest_root_btree_page_number,
+            text_encoding: text_encoding.try_into()?,
+            user_version,
+            incremental_vacuum_mode,
+            application_id,
+            version_valid_for,
+            sqlite_version_number,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum PageType {
+    InteriorIndex,
+    InteriorTable,
+    LeafIndex,
+    LeafTable,
+}
+
+impl PageType {
+    // Whether the page belongs to a table b-tree rather than an index b-tree.
+    fn is_table(&self) -> bool {
+        matches!(self, PageType::InteriorTable | PageType::LeafTable)
+    }
+}
+
+impl TryFrom<u8> for PageType {
+    type Error = io::Error;
+
+    fn try_from(byte: u8) -> io::Result<Self> {
+        match byte {
+            0x02 => Ok(PageType::InteriorIndex),
+            0x05 => Ok(PageType::InteriorTable),
+            0x0a => Ok(PageType::LeafIndex),
+            0x0d => Ok(PageType::LeafTable),
+            _ => Err(invalid(format!("invalid page type 0x{:02x}", byte))),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct DbPageHeader {
+    page_type: PageType,
+    first_freeblock: u16,
+    cell_count: u16,
+    cell_content_area_offset: u16,
+    fragmented_free_bytes: u8,
+    rightmost_pointer: Option<u32>,
+    cells: Vec<u16>,
+}
+
+impl DbPageHeader {
+    fn parse<R: Read + ByteReader>(reader: &mut R) -> io::Result<Self> {
+        // The one-byte flag at offset 0 indicating the b-tree page type.
+        //      0x02 interior index b-tree page.
+        //      0x05 interior table b-tree page.
+        //      0x0a leaf index b-tree page.
+        //      0x0d leaf table b-tree page.
+        // Any other value for the b-tree page type is an error.
+        let flag = reader.read_u8()?;
+        let page_type = flag.try_into()?;
+
+        // The two-byte integer at offset 1 gives the start of the first freeblock on the page, or
+        // is zero if there are no freeblocks.
+        let first_freeblock = reader.read_u16()?;
+
+        // The two-byte integer at offset 3 gives the number of cells on the page.
+        let cell_count = reader.read_u16()?;
+
+        // The two-byte integer at offset 5 gives the start of the cell content area within the page.
+        let cell_content_area_offset = reader.read_u16()?;
+
+        // The one-byte integer at offset 7 gives the number of fragmented free bytes within the cell
+        // content area at the end of the page.
+        let fragmented_free_bytes = reader.read_u8()?;
+
+        // The four-byte integer at offset 8 gives the page number of the right-most page in the tree
+        // that is the parent of this page. If this is a root page, then the value is zero.
+        let rightmost_pointer = match page_type {
+            PageType::InteriorIndex | PageType::InteriorTable => Some(reader.read_u32()?),
+            PageType::LeafIndex | PageType::LeafTable => None,
+        };
+
+        // The cell content area consists of a sequence of cells. Each cell has a 2-byte integer
+        // giving the size of the cell, followed by the cell content itself. The cell content format
+        // depends on the b-tree page type.
+        let mut cells = Vec::new();
+        for _ in 0..cell_count {
+            cells.push(reader.read_u16()?);
+        }
+
+        Ok(Self {
+            page_type,
+            first_freeblock,
+            cell_count,
+            cell_content_area_offset,
+            fragmented_free_bytes,
+            rightmost_pointer,
+            cells,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct DbPage {
+    header: DbPageHeader,
+    records: Vec<DbRecord>,
+}
+
+impl DbPage {
+    // Parses a page held in memory. `header_offset` is where the b-tree page header starts, which
+    // is 100 for the first page of the database and 0 otherwise. Cell pointers are always relative
+    // to the start of the page. Anything that can't be parsed is reported with the page number and
+    // the offset of the header or cell it was in. The pager is for the overflow pages of cells
+    // that don't fit on the page.
+    fn parse(pager: &Pager, page: &[u8], page_number: u32, header_offset: usize) -> Result<Self> {
+        let (page, mut errors) = Self::parse_salvaging(pager, page, page_number, header_offset)?;
+        match errors.is_empty() {
+            true => Ok(page),
+            false => Err(errors.remove(0)),
+        }
+    }
+
+    // Parses a page, leaving out the cells that can't be parsed, which are returned as errors
+    // alongside it. Only a page header that can't be parsed fails the whole page.
+    fn parse_salvaging(
+        pager: &Pager,
+        page: &[u8],
+        page_number: u32,
+        header_offset: usize,
+    ) -> Result<(Self, Vec<Error>)> {
+        let header = DbPageHeader::parse(&mut page.get(header_offset..).unwrap_or_default())
+            .map_err(|err| Error::corrupt(page_number, header_offset, err))?;
+
+        // Cells live between the end of the cell pointer array and the end of the page.
+        let header_size = match header.rightmost_pointer {
+            Some(_) => 12,
+            None => 8,
+        };
+        let cells_start = header_offset + header_size + 2 * header.cells.len();
+
+        let parse_record = match header.page_type {
+            PageType::LeafTable => DbRecord::parse_table_leaf_record::<&[u8]>,
+            PageType::LeafIndex => DbRecord::parse_index_leaf_record::<&[u8]>,
+            PageType::InteriorTable => DbRecord::parse_table_index_record::<&[u8]>,
+            PageType::InteriorIndex => DbRecord::parse_index_interior_record::<&[u8]>,
+        };
+
+        let mut records = Vec::with_capacity(header.cells.len());
+        let mut errors = Vec::new();
+        for cell in &header.cells {
+            let offset = *cell as usize;
+            let record = page
+                .get(offset..)
+                .filter(|_| offset >= cells_start)
+                .ok_or_else(|| invalid("cell pointer outside the cell content area"))
+                .and_then(|mut cell| parse_record(&mut cell, pager));
+            match record {
+                Ok(record) => records.push(record),
+                Err(err) => errors.push(Error::corrupt(page_number, offset, err)),
+            }
+        }
+
+        Ok((Self { header, records }, errors))
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+enum DbRecord {
+    TableLeafRecord(TableLeafRecord),
+    IndexLeafRecord(IndexLeafRecord),
+    InteriorTableRecord(InteriorTableRecord),
+    InteriorIndexRecord(InteriorIndexRecord),
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct IndexLeafRecord {
+    length: u64,
+    payload: Vec<u8>,
+    data_specification: DataSpecification,
+    values: Vec<Value>,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct InteriorIndexRecord {
+    left_child: u32,
+    length: u64,
+    key: Vec<u8>,
+    data_specification: DataSpecification,
+    values: Vec<Value>,
+}
+
+impl Record for InteriorIndexRecord {
+    fn parse<R: Read + ByteReader>(reader: &mut R, pager: &Pager) -> io::Result<Self> {
+        let left_child = reader.read_u32()?;
+        let (length, _) = reader.read_varint()?;
+        let key = read_payload(reader, length, pager, false)?;
+
+        let mut key_reader = key.as_slice();
+        let data_specification = DataSpecification::parse_header(&mut key_reader)?;
+
+        let values = data_specification
+            .types
+            .iter()
+            .map(|data_type| data_type.parse(&mut key_reader))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            left_child,
+            length,
+            key,
+            data_specification,
+            values,
+        })
+    }
+}
+
+impl Record for IndexLeafRecord {
+    fn parse<R: Read + ByteReader>(reader: &mut R, pager: &Pager) -> io::Result<Self> {
+        let (length, _) = reader.read_varint()?;
+        let payload = read_payload(reader, length, pager, false)?;
+
+        let mut key_reader = payload.as_slice();
+        let data_specification = DataSpecification::parse_header(&mut key_reader)?;
+
+        let values = data_specification
+            .types
+            .iter()
+            .map(|data_type| data_type.parse(&mut key_reader))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            length,
+            payload,
+            data_specification,
+            values,
+        })
+    }
+}
+
+impl DbRecord {
+    fn parse_table_leaf_record<R: Read + ByteReader>(
+        reader: &mut R,
+        pager: &Pager,
+    ) -> io::Result<Self> {
+        let record = TableLeafRecord::parse(reader, pager)?;
+        Ok(Self::TableLeafRecord(record))
+    }
+
+    fn parse_index_leaf_record<R: Read + ByteReader>(
+        reader: &mut R,
+        pager: &Pager,
+    ) -> io::Result<Self> {
+        let record = IndexLeafRecord::parse(reader, pager)?;
+        Ok(Self::IndexLeafRecord(record))
+    }
+
+    fn parse_table_index_record<R: Read + ByteReader>(
+        reader: &mut R,
+        pager: &Pager,
+    ) -> io::Result<Self> {
+        let record = InteriorTableRecord::parse(reader, pager)?;
+        Ok(Self::InteriorTableRecord(record))
+    }
+
+    fn parse_index_interior_record<R: Read + ByteReader>(
+        reader: &mut R,
+        pager: &Pager,
+    ) -> io::Result<Self> {
+        let record = InteriorIndexRecord::parse(reader, pager)?;
+        Ok(Self::InteriorIndexRecord(record))
+    }
+}
+
+trait Record: Sized {
+    fn parse<R: Read + ByteReader>(reader: &mut R, pager: &Pager) -> io::Result<Self>;
+}
+
+// Reads the payload of a cell, of `size` bytes. Only as much of a large payload as fits is kept
+// on the page, and it's followed by the number of the first of a chain of overflow pages, each of
+// which starts with the number of the next and is then full of the rest of the payload.
+fn read_payload<R: Read + ByteReader>(
+    reader: &mut R,
+    size: u64,
+    pager: &Pager,
+    table_leaf: bool,
+) -> io::Result<Vec<u8>> {
+    let usable = pager.usable_size();
+    // A payload can't be bigger than the database it's in.
+    let size = usize::try_from(size)
+        .ok()
+        .filter(|&size| size as u64 <= pager.page_count() as u64 * usable as u64)
+        .ok_or_else(|| invalid(format!("payload size {} out of range", size)))?;
+    let local = write::local_payload(size, usable, table_leaf);
+    let mut payload = reader.read_bytes(local as u64)?;
+    if local == size {
+        return Ok(payload);
+    }
+
+    payload.reserve(size - local);
+    let mut next = reader.read_u32()?;
+    while payload.len() < size {
+        if next == 0 {
+            return Err(invalid(format!(
+                "overflow chain ends before the end of a {} byte payload",
+                size
+            )));
+        }
+        let page = pager.read_page(next)?;
+        let n = (size - payload.len()).min(usable - 4);
+        let bytes = page
+            .get(4..4 + n)
+            .ok_or_else(|| invalid(format!("overflow page {} is too short", next)))?;
+        payload.extend_from_slice(bytes);
+        next = u32::from_be_bytes(page[..4].try_into().unwrap());
+    }
+    Ok(payload)
+}
+
+#[derive(Debug, Clone)]
+enum DataType {
+    Null,
+    Int8,
+    Int16,
+    Int24,
+    Int32,
+    Int48,
+    Int64,
+    Float,
+    Zero,
+    One,
+    Blob(usize),
+    Text(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    Null,
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_value_ref().fmt(f)
+    }
+}
+
+// A value borrowed from the payload of a record. Text and blobs point straight into the payload,
+// so reading a column doesn't copy it unless the caller asks for an owned Value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueRef<'a> {
+    Int(i64),
+    Real(f64),
+    Text(&'a str),
+    Blob(&'a [u8]),
+    Null,
+}
+
+impl ValueRef<'_> {
+    fn to_value(self) -> Value {
+        match self {
+            ValueRef::Int(n) => Value::Int(n),
+            ValueRef::Real(r) => Value::Real(r),
+            ValueRef::Text(s) => Value::Text(s.to_string()),
+            ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+            ValueRef::Null => Value::Null,
+        }
+    }
+}
+
+impl Value {
+    fn as_value_ref(&self) -> ValueRef<'_> {
+        match self {
+            Value::Int(n) => ValueRef::Int(*n),
+            Value::Real(r) => ValueRef::Real(*r),
+            Value::Text(s) => ValueRef::Text(s),
+            Value::Blob(b) => ValueRef::Blob(b),
+            Value::Null => ValueRef::Null,
+        }
+    }
+}
+
+// SQLite never stores a NaN (it is written as NULL instead), so equality on decoded values is
+// reflexive and they can be used as keys. Reals hash by their bits to agree with that equality.
+impl Eq for Value {}
+impl Eq for ValueRef<'_> {}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_value_ref().hash(state)
+    }
+}
+
+impl std::hash::Hash for ValueRef<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ValueRef::Int(n) => n.hash(state),
+            ValueRef::Real(r) => r.to_bits().hash(state),
+            ValueRef::Text(s) => s.hash(state),
+            ValueRef::Blob(b) => b.hash(state),
+            ValueRef::Null => {}
+        }
+    }
+}
+
+impl Value {
+    // Orders values the way sqlite does with the BINARY collation: NULLs first, then numbers by
+    // value, then text and then blobs, byte by byte.
+    pub(crate) fn compare(&self, other: &Value) -> std::cmp::Ordering {
+        self.as_value_ref().compare(other.as_value_ref())
+    }
+}
+
+impl ValueRef<'_> {
+    pub(crate) fn compare(self, other: ValueRef<'_>) -> std::cmp::Ordering {
+        let class = |value: ValueRef| match value {
+            ValueRef::Null => 0,
+            ValueRef::Int(_) | ValueRef::Real(_) => 1,
+            ValueRef::Text(_) => 2,
+            ValueRef::Blob(_) => 3,
+        };
+        match (self, other) {
+            (ValueRef::Int(a), ValueRef::Int(b)) => a.cmp(&b),
+            (ValueRef::Int(a), ValueRef::Real(b)) => compare_int_real(a, b),
+            (ValueRef::Real(a), ValueRef::Int(b)) => compare_int_real(b, a).reverse(),
+            // There are no NaNs, and -0.0 is the same number as 0.0.
+            (ValueRef::Real(a), ValueRef::Real(b)) => {
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            }
+            (ValueRef::Text(a), ValueRef::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (ValueRef::Blob(a), ValueRef::Blob(b)) => a.cmp(b),
+            (a, b) => class(a).cmp(&class(b)),
+        }
+    }
+}
+
+// 2^63, the first real too large to be an integer. Every integer is at least its negative.
+const I64_BOUND: f64 = 9_223_372_036_854_775_808.0;
+
+// Compares an integer with a real exactly, as sqlite does, rather than by turning the integer into
+// a real, which would round integers beyond 2^53 and find them equal to their neighbours.
+fn compare_int_real(int: i64, real: f64) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    if real < -I64_BOUND {
+        return Ordering::Greater;
+    }
+    if real >= I64_BOUND {
+        return Ordering::Less;
+    }
+    let whole = real.floor();
+    int.cmp(&(whole as i64)).then_with(|| match real > whole {
+        true => Ordering::Less,
+        false => Ordering::Equal,
+    })
+}
+
+impl PartialEq<Value> for ValueRef<'_> {
+    fn eq(&self, other: &Value) -> bool {
+        *self == other.as_value_ref()
+    }
+}
+
+impl Display for ValueRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueRef::Int(n) => write!(f, "{}", n),
+            ValueRef::Real(r) => write!(f, "{}", format_real(*r)),
+            ValueRef::Text(s) => write!(f, "{}", s),
+            ValueRef::Blob(b) => write!(f, "{:x?}", b),
+            ValueRef::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+// Formats a real the way sqlite3 prints one, which is printf's `%!.15g`: at most 15 significant
+// digits, switching to an exponent for very large or small magnitudes, and always with a decimal
+// point so it still reads as a real.
+fn format_real(r: f64) -> String {
+    if r.is_infinite() {
+        return if r > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if r == 0.0 {
+        return "0.0".to_string();
+    }
+
+    // sqlite rounds to 15 digits from a longer expansion of the value, with halves rounded up,
+    // where Rust would round them to even.
+    let scientific = format!("{:.19e}", r.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let mut exponent = exponent.parse::<i32>().unwrap();
+    let mut digits = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|digit| digit - b'0')
+        .collect::<Vec<_>>();
+    let round_up = digits[15] >= 5;
+    digits.truncate(15);
+    if round_up {
+        match digits.iter().rposition(|digit| *digit != 9) {
+            Some(position) => {
+                digits[position] += 1;
+                digits[position + 1..].fill(0);
+            }
+            // All nines carry into a new leading digit.
+            None => {
+                digits.fill(0);
+                digits[0] = 1;
+                exponent += 1;
+            }
+        }
+    }
+    let digits = digits
+        .iter()
+        .map(|digit| (b'0' + digit) as char)
+        .collect::<String>();
+
+    let trim = |digits: &str| {
+        let digits = digits.trim_end_matches('0');
+        match digits.strip_suffix('.') {
+            Some(digits) => format!("{}.0", digits),
+            None => digits.to_string(),
+        }
+    };
+
+    let sign = if r < 0.0 { "-" } else { "" };
+    if (0..15).contains(&exponent) {
+        let (whole, fraction) = digits.split_at(exponent as usize + 1);
+        format!("{}{}", sign, trim(&format!("{}.{}", whole, fraction)))
+    } else if (-4..0).contains(&exponent) {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}{}", sign, trim(&format!("0.{}{}", zeros, digits)))
+    } else {
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}e{}{:02}",
+            sign,
+            trim(&format!("{}.{}", &digits[..1], &digits[1..])),
+            exponent_sign,
+            exponent.abs()
+        )
+    }
+}
+
+// Rust values convert into Values losslessly, with booleans stored as 1 and 0. Integers that may
+// be too wide for an i64 can only be tried.
+macro_rules! value_from_int {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Value {
+            fn from(n: $t) -> Self {
+                Value::Int(n as i64)
+            }
+        }
+    )*};
+}
+
+value_from_int!(i8, i16, i32, i64, u8, u16, u32, bool);
+
+macro_rules! value_try_from_int {
+    ($($t:ty),*) => {$(
+        impl TryFrom<$t> for Value {
+            type Error = Error;
+
+            fn try_from(n: $t) -> Result<Self> {
+                i64::try_from(n).map(Value::Int).map_err(|_| Error::IntegerOverflow)
+            }
+        }
+    )*};
+}
+
+value_try_from_int!(u64, usize, isize, i128, u128);
+
+impl From<f32> for Value {
+    fn from(r: f32) -> Self {
+        Value::Real(r as f64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(r: f64) -> Self {
+        Value::Real(r)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(b: Vec<u8>) -> Self {
+        Value::Blob(b)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(b: &[u8]) -> Self {
+        Value::Blob(b.to_vec())
+    }
+}
+
+// None is stored as NULL.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// Reading a Value back as a Rust type fails with a mismatch when it holds a different kind of
+// value, and with an overflow when an integer doesn't fit in the type asked for.
+macro_rules! int_try_from_value {
+    ($($t:ty),*) => {$(
+        impl TryFrom<Value> for $t {
+            type Error = Error;
+
+            fn try_from(value: Value) -> Result<Self> {
+                match value {
+                    Value::Int(n) => <$t>::try_from(n).map_err(|_| Error::IntegerOverflow),
+                    _ => Err(Error::Mismatch),
+                }
+            }
+        }
+    )*};
+}
+
+int_try_from_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Integers widen to reals, which is exact for any integer below 2^53.
+impl TryFrom<Value> for f64 {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Real(r) => Ok(r),
+            Value::Int(n) => Ok(n as f64),
+            _ => Err(Error::Mismatch),
+        }
+    }
+}
+
+// SQLite has no boolean type, so true and false are stored as the integers 1 and 0. Like a WHERE
+// clause, any other non-zero number reads as true.
+impl TryFrom<Value> for bool {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Int(n) => Ok(n != 0),
+            Value::Real(r) => Ok(r != 0.0),
+            _ => Err(Error::Mismatch),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Text(s) => Ok(s),
+            Value::Blob(b) => String::from_utf8(b).map_err(|_| Error::Mismatch),
+            _ => Err(Error::Mismatch),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Blob(b) => Ok(b),
+            Value::Text(s) => Ok(s.into_bytes()),
+            _ => Err(Error::Mismatch),
+        }
+    }
+}
+
+impl DataType {
+    // The number of payload bytes a value of this type occupies.
+    fn size(&self) -> usize {
+        match self {
+            DataType::Null | DataType::Zero | DataType::One => 0,
+            DataType::Int8 => 1,
+            DataType::Int16 => 2,
+            DataType::Int24 => 3,
+            DataType::Int32 => 4,
+            DataType::Int48 => 6,
+            DataType::Int64 | DataType::Float => 8,
+            DataType::Blob(size) | DataType::Text(size) => *size,
+        }
+    }
+
+    pub fn parse(&self, reader: &mut &[u8]) -> io::Result<Value> {
+        let bytes = self.take(reader)?;
+        Ok(self.parse_ref(bytes)?.to_value())
+    }
+
+    // Splits the bytes of a value of this type off the front of a record body.
+    fn take<'a>(&self, reader: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+        if reader.len() < self.size() {
+            return Err(invalid("value runs past the end of the record"));
+        }
+        let (bytes, rest) = reader.split_at(self.size());
+        *reader = rest;
+        Ok(bytes)
+    }
+
+    // Decodes a value from exactly `self.size()` bytes, borrowing text and blobs from them.
+    fn parse_ref<'a>(&self, mut bytes: &'a [u8]) -> io::Result<ValueRef<'a>> {
+        Ok(match self {
+            DataType::Null => ValueRef::Null,
+            DataType::Int8 => ValueRef::Int(bytes.read_i8()? as i64),
+            DataType::Int16 => ValueRef::Int(bytes.read_i16()? as i64),
+            DataType::Int24 => {
+                let mut buf = [0; 4];
+                bytes.read_exact(&mut buf[1..])?;
+                // Shifting back down from the top of an i32 extends the sign.
+                ValueRef::Int((i32::from_be_bytes(buf) << 8 >> 8) as i64)
+            }
+            DataType::Int32 => ValueRef::Int(bytes.read_i32()? as i64),
+            DataType::Int48 => {
+                let mut buf = [0; 8];
+                bytes.read_exact(&mut buf[2..])?;
+                ValueRef::Int(i64::from_be_bytes(buf) << 16 >> 16)
+            }
+            DataType::Int64 => ValueRef::Int(bytes.read_i64()?),
+            DataType::Float => ValueRef::Real(f64::from_bits(bytes.read_u64()?)),
+            DataType::Zero => ValueRef::Int(0),
+            DataType::One => ValueRef::Int(1),
+            DataType::Blob(_) => ValueRef::Blob(bytes),
+            DataType::Text(_) => ValueRef::Text(
+                std::str::from_utf8(bytes).map_err(|_| invalid("text is not valid UTF-8"))?,
+            ),
+        })
+    }
+}
+
+impl TryFrom<u64> for DataType {
+    type Error = io::Error;
+
+    fn try_from(byte: u64) -> io::Result<Self> {
+        Ok(match byte {
+            0x00 => DataType::Null,
+            0x01 => DataType::Int8,
+            0x02 => DataType::Int16,
+            0x03 => DataType::Int24,
+            0x04 => DataType::Int32,
+            0x05 => DataType::Int48,
+            0x06 => DataType::Int64,
+            0x07 => DataType::Float,
+            0x08 => DataType::Zero,
+            0x09 => DataType::One,
+            byte if byte >= 12 && byte % 2 == 0 => DataType::Blob(((byte - 12) / 2) as usize),
+            byte if byte >= 13 && byte % 2 == 1 => DataType::Text(((byte - 13) / 2) as usize),
+            // 10 and 11 are reserved for internal use, and never appear in a database file.
+            byte => return Err(invalid(format!("invalid serial type {}", byte))),
+        })
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct TableLeafRecord {
+    header: TableLeafRecordHeader,
+    data_specification: DataSpecification,
+    // The record body, after the column header. Values are decoded from it on demand.
+    payload: Vec<u8>,
+    // Where each column's value starts in the payload.
+    offsets: Vec<usize>,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct DataSpecification {
+    size: usize,
+    types: Vec<DataType>,
+}
+
+impl DataSpecification {
+    fn parse<R: Read + ByteReader>(reader: &mut R, size: usize) -> io::Result<Self> {
+        let mut types = vec![];
+        let payload_reader = reader.read_bytes(size as u64)?;
+        let mut payload_reader = payload_reader.as_slice();
+
+        while !payload_reader.is_empty() {
+            let (data_type, _) = payload_reader.read_varint()?;
+            types.push(data_type.try_into()?);
+        }
+
+        Ok(Self {
+            size: size.saturating_sub(1),
+            types,
+        })
+    }
+
+    // Reads the column header from the start of a record's payload, which begins with its own
+    // size, leaving the reader at the first value.
+    fn parse_header(reader: &mut &[u8]) -> io::Result<Self> {
+        let available = reader.len();
+        let (header_size, header_size_count) = reader.read_varint()?;
+        match usize::try_from(header_size) {
+            Ok(header_size) if (header_size_count..=available).contains(&header_size) => {
+                Self::parse(reader, header_size - header_size_count)
+            }
+            _ => Err(invalid(format!(
+                "record header size {} out of range",
+                header_size
+            ))),
+        }
+    }
+}
+
+impl Record for TableLeafRecord {
+    fn parse<R: Read + ByteReader>(reader: &mut R, pager: &Pager) -> io::Result<Self> {
+        let (size, _) = reader.read_varint()?;
+        let (row_id, _) = reader.read_varint()?;
+        let header = TableLeafRecordHeader { size, row_id };
+        let mut payload = read_payload(reader, size, pager, true)?;
+
+        let mut payload_reader = payload.as_slice();
+        let data_specification = DataSpecification::parse_header(&mut payload_reader)?;
+        let header_size = payload.len() - payload_reader.len();
+
+        // Every value is decoded once here, so that reading a column later can't fail.
+        let mut offsets = Vec::with_capacity(data_specification.types.len());
+        for data_type in &data_specification.types {
+            offsets.push(payload.len() - payload_reader.len() - header_size);
+            data_type.parse_ref(data_type.take(&mut payload_reader)?)?;
+        }
+
+        payload.drain(..header_size);
+
+        Ok(Self {
+            header,
+            data_specification,
+            payload,
+            offsets,
+        })
+    }
+}
+
+impl TableLeafRecord {
+    // Borrows the value of a column from the payload. Columns past the end of the record, which
+    // happens for rows written before an ALTER TABLE ADD COLUMN, read as NULL. Every value was
+    // checked when the record was parsed, so decoding it again can't fail.
+    fn value(&self, column: usize) -> ValueRef<'_> {
+        match self.data_specification.types.get(column) {
+            Some(data_type) => {
+                let start = self.offsets[column];
+                data_type
+                    .parse_ref(&self.payload[start..start + data_type.size()])
+                    .unwrap_or(ValueRef::Null)
+            }
+            None => ValueRef::Null,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct TableLeafRecordHeader {
+    size: u64,
+    row_id: u64,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct InteriorTableRecord {
+    left_child_page: u32,
+    key: u64,
+}
+
+impl Record for InteriorTableRecord {
+    fn parse<R: Read + ByteReader>(reader: &mut R, _pager: &Pager) -> io::Result<Self> {
+        let left_child_page = reader.read_u32()?;
+        let key = reader.read_varint()?.0;
+
+        Ok(Self {
+            left_child_page,
+            key,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+struct MasterPageRecord {
+    object_type: catalog::ObjectType,
+    name: String,
+    table_name: String,
+    root_page: u32,
+    sql: String,
+    // The columns of a table, as they were declared. An index's are in `indexed`.
+    columns: Vec<catalog::Column>,
+    // The column declared INTEGER PRIMARY KEY, if any. Its value isn't stored in the record, as it
+    // is the rowid itself.
+    rowid_alias: Option<usize>,
+    module: Option<Module>,
+    // Whether the table was declared WITHOUT ROWID, which keeps its rows in an index b-tree.
+    without_rowid: bool,
+    // Whether an index was declared UNIQUE.
+    unique: bool,
+    // Whether a table has CHECK constraints, and whether its key is AUTOINCREMENT, which writing
+    // to it would have to keep to.
+    checked: bool,
+    autoincrement: bool,
+    // How an index sorts each of its columns, in the order of `columns`, or for a table, how its
+    // primary key does.
+    indexed: Vec<catalog::IndexColumn>,
+    // The WHERE of a partial index, which only has the rows it is true of.
+    partial: Option<parser::Ast>,
+    // The foreign keys of a table, in the order they were declared.
+    foreign_keys: Vec<catalog::ForeignKey>,
+    // Why the object's SQL couldn't be understood, if it couldn't. The object stays in the schema,
+    // so that the rest of the database can still be read, but it can't be used.
+    malformed: Option<String>,
+}
+
+// The module that implements a virtual table, and the arguments the table was created with.
+#[derive(Debug, Clone)]
+struct Module {
+    name: String,
+    args: Vec<String>,
+}
+
+// The columns of an index or a primary key, as the parser gives them.
+fn indexed_columns(columns: Vec<parser::Ast>) -> Vec<catalog::IndexColumn> {
+    columns
+        .into_iter()
+        .filter_map(|column| match column {
+            parser::Ast::IndexedColumn {
+                name,
+                collation,
+                descending,
+            } => Some(catalog::IndexColumn {
+                name,
+                collation,
+                descending,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+impl MasterPageRecord {
+    fn parse(record: &DbRecord) -> Result<Self> {
+        let record = match record {
+            DbRecord::TableLeafRecord(record) => record,
+            _ => {
+                return Err(Error::Corrupt {
+                    page: 1,
+                    message: "the schema is not stored in a table b-tree".to_string(),
+                })
+            }
+        };
+
+        let column = |index: usize| record.value(index).to_value();
+        let object_type = catalog::ObjectType::parse(&String::try_from(column(0))?)?;
+        let name: String = column(1).try_into()?;
+        let table_name: String = column(2).try_into()?;
+        let root_page: u32 = column(3).try_into()?;
+        // Indexes that sqlite creates itself, for UNIQUE and PRIMARY KEY constraints, have no SQL.
+        let sql = Option::<String>::from_value(&column(4))?.unwrap_or_default();
+
+        let mut record = Self {
+            object_type,
+            name,
+            table_name,
+            root_page,
+            sql,
+            columns: Vec::new(),
+            rowid_alias: None,
+            module: None,
+            without_rowid: false,
+            unique: false,
+            checked: false,
+            autoincrement: false,
+            indexed: Vec::new(),
+            partial: None,
+            foreign_keys: Vec::new(),
+            malformed: None,
+        };
+        // The indexes sqlite makes itself are always unique.
+        record.unique = record.object_type == catalog::ObjectType::Index && record.sql.is_empty();
+        // Only tables and indexes have columns to find. A view or trigger is kept for its SQL.
+        let analysed = matches!(
+            record.object_type,
+            catalog::ObjectType::Table | catalog::ObjectType::Index
+        );
+        if analysed && !record.sql.is_empty() {
+            match record.analyse_sql() {
+                Ok(()) => {}
+                Err(err) if err.is_prepare() => {
+                    // Whatever was found before the SQL stopped making sense isn't to be trusted.
+                    record.columns.clear();
+                    record.indexed.clear();
+                    record.rowid_alias = None;
+                    record.malformed = Some(err.to_string());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(record)
+    }
+
+    // Fails for an object whose SQL couldn't be understood when the schema was read.
+    fn usable(&self) -> Result<()> {
+        match &self.malformed {
+            Some(message) => Err(Error::MalformedSchema {
+                name: self.name.clone(),
+                message: message.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Finds the order of the columns in a table or index, which column of a table (if any) is an
+    // alias for the rowid, and how the table is stored or implemented.
+    fn analyse_sql(&mut self) -> Result<()> {
+        let mut lexer = lexer::Lexer::new(self.sql.clone());
+        let tokens = lexer.lex()?;
+        let mut parser = parser::Parser::new(tokens).with_source(&self.sql, lexer.spans());
+        let ast = parser.parse_create()?;
+
+        match ast {
+            parser::Ast::CreateTable {
+                name: _,
+                column_defs,
+                without_rowid,
+            } => {
+                // A primary key declared after the columns gives their places in it.
+                let table_key = column_defs.iter().find_map(|def| match def {
+                    parser::Ast::PrimaryKey(columns) => Some(indexed_columns(columns.clone())),
+                    _ => None,
+                });
+
+                for def in &column_defs {
+                    let parser::Ast::ColumnDef {
+                        name,
+                        data_type,
+                        constraints,
+                    } = def
+                    else {
+                        continue;
+                    };
+                    // A virtual column has no place in the record, so the values after it
+                    // would be read from the wrong places.
+                    if constraints.contains(&parser::Constraint::Generated { stored: false }) {
+                        return Err(Error::Unsupported(format!("the generated column {}", name)));
+                    }
+                    let key_constraint =
+                        constraints.iter().find_map(|constraint| match constraint {
+                            parser::Constraint::PrimaryKey { descending } => Some(*descending),
+                            _ => None,
+                        });
+                    let primary_key = match &table_key {
+                        Some(columns) => columns
+                            .iter()
+                            .position(|column| column.name.eq_ignore_ascii_case(name))
+                            .map(|position| position + 1),
+                        None => key_constraint.map(|_| 1),
+                    };
+                    if table_key.is_none() {
+                        if let Some(descending) = key_constraint {
+                            self.indexed.push(catalog::IndexColumn {
+                                name: name.clone(),
+                                collation: None,
+                                descending,
+                            });
+                        }
+                    }
+                    self.columns.push(catalog::Column {
+                        name: name.clone(),
+                        declared_type: data_type.clone(),
+                        primary_key,
+                        not_null: constraints.contains(&parser::Constraint::NotNull),
+                        collation: constraints.iter().find_map(|constraint| match constraint {
+                            parser::Constraint::Collate(name) => Some(name.clone()),
+                            _ => None,
+                        }),
+                        default: constraints.iter().find_map(|constraint| match constraint {
+                            parser::Constraint::Default(text) => Some(text.clone()),
+                            _ => None,
+                        }),
+                        generated: constraints
+                            .contains(&parser::Constraint::Generated { stored: true }),
+                    });
+                    self.checked |= constraints.contains(&parser::Constraint::Check);
+                    self.autoincrement |= constraints.contains(&parser::Constraint::AutoIncrement);
+                }
+
+                // Only a primary key that is one column of type INTEGER is the rowid.
+                let key = self
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, column)| column.primary_key.is_some())
+                    .map(|(index, column)| (index, column.declared_type.as_str()))
+                    .collect::<Vec<_>>();
+                // Except that, for no better reason than being what sqlite has always done,
+                // `INTEGER PRIMARY KEY DESC` on the column itself isn't.
+                let descending_column =
+                    table_key.is_none() && self.indexed.first().is_some_and(|key| key.descending);
+                if let [(index, "INTEGER")] = key[..] {
+                    self.rowid_alias = (!without_rowid && !descending_column).then_some(index);
+                }
+                if let Some(columns) = table_key {
+                    self.indexed = columns;
+                }
+
+                self.without_rowid = without_rowid;
+                self.checked |= column_defs.contains(&parser::Ast::Check);
+
+                // A foreign key is either REFERENCES on one column, or FOREIGN KEY after them.
+                for def in &column_defs {
+                    match def {
+                        parser::Ast::ColumnDef {
+                            name, constraints, ..
+                        } => {
+                            for constraint in constraints {
+                                if let parser::Constraint::References { table, columns } =
+                                    constraint
+                                {
+                                    self.foreign_keys.push(catalog::ForeignKey {
+                                        parent: table.clone(),
+                                        from: vec![name.clone()],
+                                        to: columns.clone(),
+                                    });
+                                }
+                            }
+                        }
+                        parser::Ast::ForeignKey {
+                            columns,
+                            table,
+                            table_columns,
+                        } => self.foreign_keys.push(catalog::ForeignKey {
+                            parent: table.clone(),
+                            from: columns.clone(),
+                            to: table_columns.clone(),
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+            // The columns of a virtual table are the arguments that aren't options like
+            // `tokenize=porter`, as they are for the fts and rtree modules. An fts5 column can be
+            // followed by UNINDEXED.
+            parser::Ast::CreateVirtualTable {
+                name: _,
+                module,
+                args,
+            } => {
+                self.columns = args
+                    .iter()
+                    .filter(|arg| !arg.contains('='))
+                    .filter_map(|arg| arg.split_whitespace().next())
+                    .map(|column| catalog::Column::undeclared(column.trim_matches(['\'', '"'])))
+                    .collect();
+                self.module = Some(Module { name: module, args });
+            }
+            parser::Ast::CreateIndex {
+                name: _,
+                table_name: _,
+                columns,
+                unique,
+                r#where,
+            } => {
+                self.indexed = indexed_columns(columns);
+                self.unique = unique;
+                self.partial = r#where.map(|expr| *expr);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // The schema table itself, under one of its names, which isn't in the schema. Its definition
+    // is the one sqlite gives it.
+    fn schema_table(name: &str) -> Result<Self> {
+        let mut record = Self {
+            object_type: catalog::ObjectType::Table,
+            name: name.to_string(),
+            table_name: name.to_string(),
+            root_page: 1,
+            sql: format!(
+                "CREATE TABLE {}(type text, name text, tbl_name text, rootpage int, sql text)",
+                name
+            ),
+            columns: Vec::new(),
+            rowid_alias: None,
+            module: None,
+            without_rowid: false,
+            unique: false,
+            checked: false,
+            autoincrement: false,
+            indexed: Vec::new(),
+            partial: None,
+            foreign_keys: Vec::new(),
+            malformed: None,
+        };
+        record.analyse_sql()?;
+        Ok(record)
+    }
+
+    // The record a virtual table would have in the schema, so that its columns are found the same
+    // way as a real table's.
+    fn virtual_table(name: &str, columns: Vec<String>) -> Self {
+        Self {
+            object_type: catalog::ObjectType::Table,
+            name: name.to_string(),
+            table_name: name.to_string(),
+            root_page: 0,
+            sql: String::new(),
+            columns: columns
+                .iter()
+                .map(|name| catalog::Column::undeclared(name))
+                .collect(),
+            rowid_alias: None,
+            module: None,
+            without_rowid: false,
+            unique: false,
+            checked: false,
+            autoincrement: false,
+            indexed: Vec::new(),
+            partial: None,
+            foreign_keys: Vec::new(),
+            malformed: None,
+        }
+    }
+
+    // The names of a table's columns, in order.
+    fn column_names(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect()
+    }
+
+    fn get_column_index(&self, column_name: &str) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(column_name))
+            .ok_or_else(|| Error::NoSuchColumn(column_name.to_string()))
+    }
+
+    // The affinity of a column from its declared type. The columns of a virtual table aren't
+    // declared, so they have BLOB affinity, which leaves values as they are.
+    fn affinity(&self, column: usize) -> catalog::Affinity {
+        self.columns
+            .get(column)
+            .map_or(catalog::Affinity::Blob, catalog::Column::affinity)
+    }
+
+    // The value of a column as read from a record of this table. A REAL column stores a real that
+    // is a whole number as an integer, to save space, so reads one back as a real.
+    fn stored_value<'a>(&self, column: usize, value: ValueRef<'a>) -> ValueRef<'a> {
+        match value {
+            ValueRef::Int(n) if self.affinity(column) == catalog::Affinity::Real => {
+                ValueRef::Real(n as f64)
+            }
+            value => value,
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // Table big(id INTEGER PRIMARY KEY, t TEXT, b BLOB) on 1024 byte pages, with an index big_t on
+    // t. Rows 1 and 3 are far larger than a page, so their records spill onto overflow pages, in
+    // the table and in the index, and row 2 fits in its cell.
+    #[allow(dead_code)]
+    const OVERFLOW: &[u8] = include_bytes!("../tests/fixtures/overflow.db");
+
+    // The values the fixture was made with.
+    #[allow(dead_code)]
+    fn text(len: usize, step: usize) -> String {
+        let letters = (0..len).map(|i| (b'a' + (i * step % 26) as u8) as char);
+        letters.chain(['é']).collect()
+    }
+
+    #[allow(dead_code)]
+    fn blob(len: usize, step: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * step % 251) as u8).collect()
+    }
+
+    // Every byte of a value that spills over many pages is read back, whether the row is found by
+    // a scan of the table, by its rowid, or by a seek of an index whose entry spills too.
+    #[test]
+    fn reads_rows_larger_than_a_page() {
+        let mut connection = Connection::open_bytes(OVERFLOW).unwrap();
+        let expected = [
+            (1, text(5000, 7), blob(20000, 7)),
+            (2, "small".to_string(), vec![0, 0xff]),
+            (3, text(3000, 3), blob(1000, 3)),
+        ];
+
+        let rows = connection.query("SELECT id, t, b FROM big").unwrap();
+        let read = rows
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get(0).unwrap(),
+                    row.get(1).unwrap(),
+                    row.get(2).unwrap(),
+                )
+            })
+            .collect::<Vec<(i64, String, Vec<u8>)>>();
+        assert_eq!(read, expected);
+
+        for (id, t, b) in &expected {
+            for sql in [
+                format!("SELECT t, b FROM big WHERE id = {}", id),
+                format!("SELECT t, b FROM big WHERE t = '{}'", t),
+            ] {
+                let rows = connection.query(&sql).unwrap();
+                assert_eq!(rows.rows.len(), 1, "{}", id);
+                assert_eq!(&rows.rows[0].get::<String>(0).unwrap(), t, "{}", id);
+                assert_eq!(&rows.rows[0].get::<Vec<u8>>(1).unwrap(), b, "{}", id);
+            }
+        }
+
+        let rows = connection
+            .query("SELECT length(t), length(b) FROM big WHERE id = 1")
+            .unwrap();
+        assert_eq!(rows.rows[0].get::<i64>(0).unwrap(), 5001);
+        assert_eq!(rows.rows[0].get::<i64>(1).unwrap(), 20000);
+    }
+
+    // Table m(id INTEGER PRIMARY KEY, n INT, s TEXT) on 512 byte pages, with an index m_n on n.
+    // Its 1000 rows, where n is id % 10 and s is 'row ' and id to four digits, are on 36 leaves
+    // under one interior page.
+    #[allow(dead_code)]
+    const MANY: &[u8] = include_bytes!("../tests/fixtures/many.db");
+
+    // However many threads the leaves are split across, the rows come back in rowid order, as
+    // sqlite3 reads them.
+    #[test]
+    fn scans_leaves_in_parallel() {
+        let db = Db::from_bytes(MANY.to_vec()).unwrap();
+        for threads in [1, 2, 4, 36, 100] {
+            let leaves = Leaves::new(&db.pager, 2)
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(leaves.len(), 36);
+            let rows = db.read_leaves_on(leaves, threads).unwrap();
+            assert_eq!(rows.len(), 1000, "{} threads", threads);
+            for (row, id) in rows.iter().zip(1..) {
+                assert_eq!(row.header.row_id, id);
+                assert_eq!(row.value(1), ValueRef::Int(id as i64 % 10));
+                assert_eq!(row.value(2), ValueRef::Text(&format!("row {:04}", id)));
+            }
+        }
+    }
+
+    // Text and blobs are borrowed from the payload of the record they're in rather than copied
+    // out, and a column added by ALTER TABLE after a row was written is NULL in that row, as
+    // sqlite3 reads the fixture, whose table t(a TEXT) had a column b added after its first row.
+    #[test]
+    fn borrows_values_from_records() {
+        let db = Db::from_bytes(include_bytes!("../tests/fixtures/altered.db").to_vec()).unwrap();
+        let rows = BTreeCursor::scan(&db.pager, 2)
+            .map(|record| into_table_leaf_record(record.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+
+        let borrowed = |row: &TableLeafRecord, bytes: &[u8]| {
+            row.payload.as_ptr_range().contains(&bytes.as_ptr())
+        };
+        for (row, (a, b)) in rows.iter().zip([
+            ("one", ValueRef::Null),
+            ("two", ValueRef::Int(2)),
+            ("three", ValueRef::Blob(&[1, 2])),
+        ]) {
+            let ValueRef::Text(text) = row.value(0) else {
+                panic!("{:?}", row.value(0));
+            };
+            assert_eq!(text, a);
+            assert!(borrowed(row, text.as_bytes()));
+            assert_eq!(row.value(1), b);
+            if let ValueRef::Blob(blob) = row.value(1) {
+                assert!(borrowed(row, blob));
+            }
+        }
+    }
+
+    // Rust values bind as the values sqlite3's Python module stores for the same ones, and read
+    // back as the types they went in as:
+    //
+    //     >>> [c.execute("SELECT typeof(?1), quote(?1)", (v,)).fetchone() for v in ...]
+    //     [('integer', '1'), ('integer', '9223372036854775807'), ('real', '1.5'),
+    //      ('text', "'é'"), ('blob', "X'00FF'"), ('null', 'NULL')]
+    #[test]
+    fn converts_rust_values() {
+        let mut connection = Connection::open_bytes(MANY).unwrap();
+        let params = [
+            Value::from(true),
+            Value::try_from(i64::MAX as u64).unwrap(),
+            Value::from(1.5f32),
+            Value::from("é"),
+            Value::from(&[0u8, 0xff][..]),
+            Value::from(None::<i64>),
+        ];
+        let mut statement = connection.prepare("SELECT ?1, ?2, ?3, ?4, ?5, ?6").unwrap();
+        let rows = statement.query(&params).unwrap();
+        let row = &rows.rows[0];
+        assert_eq!(
+            row.values(),
+            [
+                Value::Int(1),
+                Value::Int(i64::MAX),
+                Value::Real(1.5),
+                Value::Text("é".to_string()),
+                Value::Blob(vec![0, 0xff]),
+                Value::Null,
+            ]
+        );
+        assert!(row.get::<bool>(0).unwrap());
+        assert_eq!(row.get::<u64>(1).unwrap(), i64::MAX as u64);
+        assert_eq!(row.get::<f64>(2).unwrap(), 1.5);
+        assert_eq!(row.get::<String>(3).unwrap(), "é");
+        assert_eq!(row.get::<Vec<u8>>(4).unwrap(), [0, 0xff]);
+        assert_eq!(row.get::<Option<String>>(5).unwrap(), None);
+
+        // What doesn't fit, or isn't that kind of value, is an error rather than a wrapped or
+        // made up value.
+        assert!(matches!(
+            Value::try_from(u64::MAX),
+            Err(Error::IntegerOverflow)
+        ));
+        assert!(matches!(row.get::<i32>(1), Err(Error::IntegerOverflow)));
+        assert!(matches!(row.get::<i64>(3), Err(Error::Mismatch)));
+        assert!(matches!(row.get::<String>(4), Err(Error::Mismatch)));
+        assert_eq!(row.get::<f64>(0).unwrap(), 1.0);
+    }
+
+    // A file cut short is an error, not a panic, wherever it is cut. sqlite3 calls any of them
+    // malformed, with code 11, as the header says the file should be longer:
+    //
+    //     $ head -c 8192 people.db > cut.db; sqlite3 cut.db "SELECT count(*) FROM people"
+    //     Error: in prepare, database disk image is malformed (11)
+    #[test]
+    fn reports_files_cut_short() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        for len in (4096..bytes.len()).step_by(509).chain([8192]) {
+            match Connection::open_bytes(&bytes[..len]) {
+                Err(err) => {
+                    assert!(
+                        matches!(err, Error::Corrupt { page: 1, .. }),
+                        "{}: {}",
+                        len,
+                        err
+                    );
+                    assert_eq!(err.exit_code(), 11);
+                }
+                Ok(_) => panic!("opened people.db cut to {} bytes", len),
+            }
+        }
+
+        // Once the page count in the header is out of date, sqlite goes by the length of the
+        // file instead, and finds the index rooted on the lost page 3:
+        //
+        //     Error: in prepare, malformed database schema (people_age) - invalid rootpage (11)
+        let mut cut = bytes[..8192].to_vec();
+        cut[92..96].copy_from_slice(&0u32.to_be_bytes());
+        match Connection::open_bytes(cut) {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "malformed database schema (people_age) - invalid rootpage"
+            ),
+            Ok(_) => panic!("opened people.db without its index"),
+        }
+    }
+
+    // A cell pointer past the end of the page, or a record whose header claims more bytes than
+    // it has, is an error on the page it is on rather than a panic or a read out of bounds. It is
+    // what sqlite3 says once it checks cells as it reads them:
+    //
+    //     $ sqlite3 bad.db "PRAGMA cell_size_check=ON" "SELECT id, name FROM people WHERE id < 3"
+    //     Error: stepping, database disk image is malformed (11)
+    #[test]
+    fn reports_corrupt_cells() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        // Page 2 is the table's only leaf, with the pointer to its first cell at offset 8.
+        let page = 4096;
+        let mut pointer = bytes.to_vec();
+        pointer[page + 8..page + 10].copy_from_slice(&5000u16.to_be_bytes());
+        let mut record = bytes.to_vec();
+        let cell = u16::from_be_bytes([bytes[page + 10], bytes[page + 11]]) as usize;
+        record[page + cell + 2..page + cell + 5].fill(0xff);
+
+        for bytes in [pointer, record] {
+            let mut connection = Connection::open_bytes(bytes).unwrap();
+            let err = connection
+                .query("SELECT id, name FROM people WHERE id < 3")
+                .unwrap_err();
+            assert!(matches!(err, Error::Corrupt { page: 2, .. }), "{}", err);
+            assert_eq!(err.exit_code(), 11);
+        }
+    }
+
+    // An object whose SQL the engine can't make sense of, like a table with a generated column,
+    // doesn't stop the rest of the database from being read. The schema and the table that can
+    // be read come back as sqlite3 gives them:
+    //
+    //     $ sqlite3 unreadable.db "SELECT type, name FROM sqlite_schema" "SELECT * FROM ok"
+    //     table|ok
+    //     table|c
+    //     table|d
+    //     1
+    #[test]
+    fn reads_around_unreadable_objects() {
+        let bytes = include_bytes!("../tests/fixtures/unreadable.db");
+        let mut connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let rows = connection
+            .query("SELECT type, name FROM sqlite_schema")
+            .unwrap();
+        let objects = rows
+            .rows
+            .iter()
+            .map(|row| row.get::<String>(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(objects, ["ok", "c", "d"]);
+        let rows = connection.query("SELECT * FROM ok").unwrap();
+        assert_eq!(rows.rows[0][0], Value::Int(1));
+
+        // Only a query of the object itself fails, saying which object it is.
+        match connection.query("SELECT x FROM c") {
+            Err(err @ Error::MalformedSchema { .. }) => assert_eq!(
+                err.to_string(),
+                "malformed database schema (c) - not supported: the generated column y"
+            ),
+            result => panic!("{:?}", result.map(|rows| rows.rows)),
+        }
+    }
+
+    // Rowids found by an index come in any order, and some more than once. They are fetched in one
+    // pass down the table, reading each page on the way once, and the rows come back as sqlite3's
+    // `SELECT * FROM m WHERE id IN (...)` gives them.
+    #[test]
+    fn fetches_rowids_in_one_pass() {
+        let db = Db::from_bytes(MANY.to_vec()).unwrap();
+        let table = db.get_table("m").unwrap();
+        let before = db.pager.stats();
+        let rows = db
+            .get_table_rows(&table, Some(&[999, 7, 500, 5, 6, 5, 2000]))
+            .unwrap();
+        let rows = rows
+            .iter()
+            .map(|row| (row.header.row_id, row.value(1), row.value(2)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                (5, ValueRef::Int(5), ValueRef::Text("row 0005")),
+                (6, ValueRef::Int(6), ValueRef::Text("row 0006")),
+                (7, ValueRef::Int(7), ValueRef::Text("row 0007")),
+                (500, ValueRef::Int(0), ValueRef::Text("row 0500")),
+                (999, ValueRef::Int(9), ValueRef::Text("row 0999")),
+            ]
+        );
+        // The root, and the leaves with 5 to 7, 500 and 999 on them.
+        assert_eq!(db.pager.stats().since(&before).pages_read, 4);
+    }
+}
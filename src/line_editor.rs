@@ -0,0 +1,406 @@
+// A line editor for the interactive shell, with only what the shell needs of readline: moving
+// about the line and editing it, going back through the lines entered before, and completing the
+// word before the cursor on Tab. The terminal is put into raw mode while a line is read, so that
+// each key arrives as it's pressed, and put back before the line is returned. Where it can't be,
+// the line is read as the terminal gives it, with none of that.
+use std::io::{self, BufRead, Read, Write};
+
+use crate::shell::print_in_columns;
+
+// Given the line up to the cursor, where the word being completed starts in it, and the words it
+// could be.
+pub(crate) type Completer<'a> = dyn Fn(&str) -> (usize, Vec<String>) + 'a;
+
+#[derive(Debug, Default)]
+pub(crate) struct LineEditor {
+    history: Vec<String>,
+}
+
+impl LineEditor {
+    pub(crate) fn add_history(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    // The next line, without its newline, or None at the end of the input, which is Ctrl-D on an
+    // empty line. Ctrl-C throws the line away and fails with Interrupted.
+    pub(crate) fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: &Completer,
+    ) -> io::Result<Option<String>> {
+        let Ok(_raw) = RawMode::enable() else {
+            return read_plain_line(prompt);
+        };
+        self.edit(prompt, complete)
+    }
+
+    fn edit(&mut self, prompt: &str, complete: &Completer) -> io::Result<Option<String>> {
+        let mut out = io::stdout().lock();
+        let mut input = io::stdin().lock();
+        let mut line = Line::default();
+        // Which line of the history is shown, where its length is the line being entered, which
+        // is kept while going back through the others.
+        let mut shown = self.history.len();
+        let mut entering = Vec::new();
+
+        write!(out, "{}", prompt)?;
+        out.flush()?;
+        loop {
+            let key = read_key(&mut input)?;
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    return Ok(Some(line.chars.iter().collect()));
+                }
+                Key::Eof if line.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(None);
+                }
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Char(c) => {
+                    line.chars.insert(line.cursor, c);
+                    line.cursor += 1;
+                }
+                Key::Backspace if line.cursor > 0 => {
+                    line.cursor -= 1;
+                    line.chars.remove(line.cursor);
+                }
+                Key::Delete | Key::Eof if line.cursor < line.chars.len() => {
+                    line.chars.remove(line.cursor);
+                }
+                Key::Left => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+                Key::Home => line.cursor = 0,
+                Key::End => line.cursor = line.chars.len(),
+                Key::KillLine => {
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Key::Up | Key::Down => {
+                    let next = match key {
+                        Key::Up if shown > 0 => shown - 1,
+                        Key::Down if shown < self.history.len() => shown + 1,
+                        _ => continue,
+                    };
+                    if shown == self.history.len() {
+                        entering = line.chars.clone();
+                    }
+                    shown = next;
+                    line.chars = match self.history.get(shown) {
+                        Some(previous) => previous.chars().collect(),
+                        None => entering.clone(),
+                    };
+                    line.cursor = line.chars.len();
+                }
+                Key::Tab => line.complete(complete, prompt, &mut out)?,
+                _ => {}
+            }
+            line.refresh(prompt, &mut out)?;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    // Redraws the line from the start, clearing whatever was after it, and puts the cursor back.
+    fn refresh(&self, prompt: &str, out: &mut impl Write) -> io::Result<()> {
+        let text = self.chars.iter().collect::<String>();
+        write!(out, "\r{}{}\x1b[K", prompt, text)?;
+        let after = self.chars.len() - self.cursor;
+        if after > 0 {
+            write!(out, "\x1b[{}D", after)?;
+        }
+        out.flush()
+    }
+
+    // Completes the word before the cursor: with the only word it could be, followed by a space,
+    // or as far as all of the words it could be agree, or when they don't agree any further, by
+    // listing them under the line. When there is nothing it could be, the terminal beeps.
+    fn complete(
+        &mut self,
+        complete: &Completer,
+        prompt: &str,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let before = self.chars[..self.cursor].iter().collect::<String>();
+        let (start, candidates) = complete(&before);
+        let word = before[start..].chars().count();
+
+        let replacement = match candidates.as_slice() {
+            [] => return write!(out, "\x07"),
+            [only] => format!("{} ", only),
+            [first, rest @ ..] => {
+                let common = rest
+                    .iter()
+                    .fold(first.chars().count(), |common, candidate| {
+                        first
+                            .chars()
+                            .zip(candidate.chars())
+                            .take(common)
+                            .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+                            .count()
+                    });
+                if common <= word {
+                    write!(out, "\r\n")?;
+                    let names = candidates.iter().map(String::as_str).collect::<Vec<_>>();
+                    print_in_columns(&names, out)?;
+                    return write!(out, "{}", prompt);
+                }
+                first.chars().take(common).collect()
+            }
+        };
+
+        let replacement = replacement.chars().collect::<Vec<_>>();
+        let count = replacement.len();
+        self.chars
+            .splice(self.cursor - word..self.cursor, replacement);
+        self.cursor = self.cursor - word + count;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    // Ctrl-U, which deletes everything before the cursor.
+    KillLine,
+    // Ctrl-C.
+    Interrupt,
+    // Ctrl-D, or the end of the input.
+    Eof,
+    Other,
+}
+
+// Reads one key press, which is a byte for most, a sequence of bytes starting with escape for the
+// arrows and the like, and as many bytes as it takes in UTF-8 for a character.
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(Key::Eof);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        // Ctrl-H and DEL are both backspace, depending on the terminal.
+        8 | 127 => Key::Backspace,
+        // The emacs keys readline has too: Ctrl-A, E, B, F, P and N.
+        1 => Key::Home,
+        5 => Key::End,
+        2 => Key::Left,
+        6 => Key::Right,
+        16 => Key::Up,
+        14 => Key::Down,
+        21 => Key::KillLine,
+        3 => Key::Interrupt,
+        4 => Key::Eof,
+        0x1b => match read_byte(input)? {
+            Some(b'[' | b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                // Like `ESC [ 3 ~`, which is Delete.
+                Some(digit @ b'0'..=b'9') => {
+                    let mut number = (digit - b'0') as u32;
+                    loop {
+                        match read_byte(input)? {
+                            Some(digit @ b'0'..=b'9') => {
+                                number = number * 10 + (digit - b'0') as u32
+                            }
+                            Some(b'~') => break,
+                            _ => return Ok(Key::Other),
+                        }
+                    }
+                    match number {
+                        1 | 7 => Key::Home,
+                        4 | 8 => Key::End,
+                        3 => Key::Delete,
+                        _ => Key::Other,
+                    }
+                }
+                _ => Key::Other,
+            },
+            _ => Key::Other,
+        },
+        byte if byte >= 0x20 => {
+            let len = match byte.leading_ones() {
+                0 => 1,
+                n @ 2..=4 => n as usize,
+                _ => return Ok(Key::Other),
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(input)? {
+                    Some(byte) => bytes.push(byte),
+                    None => return Ok(Key::Other),
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_plain_line(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+// The terminal in raw mode, until this is dropped: without echo, without waiting for a whole line,
+// and with Ctrl-C and the like arriving as keys rather than signals. Output is left as it is, so a
+// newline still starts the next line at its start.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+struct RawMode {
+    original: sys::Termios,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let mut termios = unsafe { std::mem::zeroed::<sys::Termios>() };
+        if unsafe { sys::tcgetattr(sys::STDIN, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+
+        termios.c_lflag &= !(sys::ICANON | sys::ECHO | sys::ISIG | sys::IEXTEN);
+        termios.c_iflag &= !(sys::IXON | sys::ICRNL);
+        termios.c_cc[sys::VMIN] = 1;
+        termios.c_cc[sys::VTIME] = 0;
+        if unsafe { sys::tcsetattr(sys::STDIN, sys::TCSAFLUSH, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { original })
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { sys::tcsetattr(sys::STDIN, sys::TCSAFLUSH, &self.original) };
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+struct RawMode;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::os::raw::c_int;
+
+    pub const STDIN: c_int = 0;
+    pub const TCSAFLUSH: c_int = 2;
+
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub use linux::*;
+    #[cfg(target_os = "macos")]
+    pub use macos::*;
+
+    #[cfg(target_os = "linux")]
+    mod linux {
+        pub type Flag = u32;
+
+        pub const ISIG: Flag = 0o000001;
+        pub const ICANON: Flag = 0o000002;
+        pub const ECHO: Flag = 0o000010;
+        pub const IEXTEN: Flag = 0o100000;
+        pub const ICRNL: Flag = 0o000400;
+        pub const IXON: Flag = 0o002000;
+        pub const VTIME: usize = 5;
+        pub const VMIN: usize = 6;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct Termios {
+            pub c_iflag: Flag,
+            pub c_oflag: Flag,
+            pub c_cflag: Flag,
+            pub c_lflag: Flag,
+            pub c_line: u8,
+            pub c_cc: [u8; 32],
+            pub c_ispeed: u32,
+            pub c_ospeed: u32,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    mod macos {
+        pub type Flag = u64;
+
+        pub const ISIG: Flag = 0x80;
+        pub const ICANON: Flag = 0x100;
+        pub const ECHO: Flag = 0x8;
+        pub const IEXTEN: Flag = 0x400;
+        pub const ICRNL: Flag = 0x100;
+        pub const IXON: Flag = 0x200;
+        pub const VMIN: usize = 16;
+        pub const VTIME: usize = 17;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct Termios {
+            pub c_iflag: Flag,
+            pub c_oflag: Flag,
+            pub c_cflag: Flag,
+            pub c_lflag: Flag,
+            pub c_cc: [u8; 20],
+            pub c_ispeed: u64,
+            pub c_ospeed: u64,
+        }
+    }
+}
@@ -0,0 +1,911 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Write as _};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+use crate::ValueRef;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // Values separated by `|`, one row per line. This is what the codecrafters tests expect.
+    #[default]
+    List,
+    // Left aligned columns under a dashed header line.
+    Column,
+    // An ASCII table drawn with `+`, `-` and `|`.
+    Table,
+    // A table drawn with unicode box drawing characters.
+    Box,
+    // A JSON array with an object for each row, keyed by column name.
+    Json,
+    // A JSON object for each row, one to a line, so that rows can be read as they come.
+    Jsonl,
+    // Comma separated values, quoted as RFC 4180 has it.
+    Csv,
+    // An INSERT statement for each row, into the table named by `.mode insert TABLE`.
+    Insert,
+    // Each value on a line of its own, after its column's name, with a blank line between rows.
+    Line,
+}
+
+impl Mode {
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::List => "list",
+            Mode::Column => "column",
+            Mode::Table => "table",
+            Mode::Box => "box",
+            Mode::Json => "json",
+            Mode::Jsonl => "jsonl",
+            Mode::Csv => "csv",
+            Mode::Insert => "insert",
+            Mode::Line => "line",
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(Mode::List),
+            "column" => Ok(Mode::Column),
+            "table" => Ok(Mode::Table),
+            "box" => Ok(Mode::Box),
+            "json" => Ok(Mode::Json),
+            "jsonl" => Ok(Mode::Jsonl),
+            "csv" => Ok(Mode::Csv),
+            "insert" => Ok(Mode::Insert),
+            "line" => Ok(Mode::Line),
+            _ => bail!("mode should be one of: box column csv insert json jsonl line list table"),
+        }
+    }
+}
+
+// Everything that controls how a result set is printed. It outlives any single statement, so the
+// CLI flags and dot commands can change it.
+#[derive(Debug, Clone)]
+pub struct OutputSettings {
+    pub mode: Mode,
+    // Written between the values of a row, and after every row, in list mode.
+    pub column_separator: String,
+    pub row_separator: String,
+    // Fixed widths for the columnar modes, set with `.width`. Zero, or a missing entry, sizes the
+    // column to fit its values, and a negative width right aligns the column.
+    pub widths: Vec<i32>,
+    // Print how much work the pager did after every statement, set with `.stats on`.
+    pub stats: bool,
+    // Follow each statement's output with a comment saying what it took, set with `.stats stmt`.
+    pub stmt_stats: bool,
+    // Print how many rows each statement changed, set with `.changes on`.
+    pub changes: bool,
+    // Start list, csv and column output with a row of column names, set with `.headers on`. Table
+    // and box output always have one.
+    pub headers: bool,
+    // Whether `.headers` or a flag has set `headers`, which `.mode column` then leaves alone
+    // rather than switching headers on.
+    pub headers_set: bool,
+    // The table that insert mode inserts into, which is "table" unless `.mode insert` names one.
+    pub insert_table: String,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            column_separator: "|".to_string(),
+            row_separator: "\n".to_string(),
+            widths: Vec::new(),
+            stats: false,
+            stmt_stats: false,
+            changes: false,
+            headers: false,
+            headers_set: false,
+            insert_table: "table".to_string(),
+        }
+    }
+}
+
+impl OutputSettings {
+    // Switches mode the way sqlite3 does, which for csv also switches to its separators: a comma
+    // between values and CRLF after each row. `.separator` can change them afterwards, say to end
+    // rows with a bare LF. Going back to list mode puts back list mode's own separators, whatever
+    // they were changed to.
+    pub fn set_mode(&mut self, mode: Mode) {
+        match mode {
+            Mode::Csv => {
+                self.column_separator = ",".to_string();
+                self.row_separator = "\r\n".to_string();
+            }
+            Mode::List => {
+                self.column_separator = "|".to_string();
+                self.row_separator = "\n".to_string();
+            }
+            _ => {}
+        }
+        self.mode = mode;
+    }
+}
+
+// Writes an INSERT statement putting the values into the table, and naming the columns they go
+// into if it is given them, as in insert mode and .dump.
+pub(crate) fn push_insert<'v>(
+    out: &mut String,
+    table: &str,
+    columns: Option<&[String]>,
+    values: impl IntoIterator<Item = ValueRef<'v>>,
+) {
+    out.push_str("INSERT INTO ");
+    out.push_str(&quote_identifier(table));
+    if let Some(columns) = columns {
+        out.push('(');
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(&quote_identifier(column));
+        }
+        out.push(')');
+    }
+    out.push_str(" VALUES(");
+    for (i, value) in values.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&sql_literal(value));
+    }
+    out.push_str(");");
+}
+
+// Quotes a table or column name for use in SQL, but only when it needs it: when it isn't a plain identifier
+// or it is a keyword.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if plain && !is_keyword(name) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+// The words sqlite reserves, which a name has to be quoted to be, and the shell completes.
+#[rustfmt::skip]
+pub(crate) const KEYWORDS: &[&str] = &[
+    "ABORT", "ACTION", "ADD", "AFTER", "ALL", "ALTER", "ALWAYS", "ANALYZE", "AND", "AS", "ASC",
+    "ATTACH", "AUTOINCREMENT", "BEFORE", "BEGIN", "BETWEEN", "BY", "CASCADE", "CASE", "CAST",
+    "CHECK", "COLLATE", "COLUMN", "COMMIT", "CONFLICT", "CONSTRAINT", "CREATE", "CROSS",
+    "CURRENT", "CURRENT_DATE", "CURRENT_TIME", "CURRENT_TIMESTAMP", "DATABASE", "DEFAULT",
+    "DEFERRABLE", "DEFERRED", "DELETE", "DESC", "DETACH", "DISTINCT", "DO", "DROP", "EACH",
+    "ELSE", "END", "ESCAPE", "EXCEPT", "EXCLUDE", "EXCLUSIVE", "EXISTS", "EXPLAIN", "FAIL",
+    "FILTER", "FIRST", "FOLLOWING", "FOR", "FOREIGN", "FROM", "FULL", "GENERATED", "GLOB",
+    "GROUP", "GROUPS", "HAVING", "IF", "IGNORE", "IMMEDIATE", "IN", "INDEX", "INDEXED",
+    "INITIALLY", "INNER", "INSERT", "INSTEAD", "INTERSECT", "INTO", "IS", "ISNULL", "JOIN",
+    "KEY", "LAST", "LEFT", "LIKE", "LIMIT", "MATCH", "MATERIALIZED", "NATURAL", "NO", "NOT",
+    "NOTHING", "NOTNULL", "NULL", "NULLS", "OF", "OFFSET", "ON", "OR", "ORDER", "OTHERS",
+    "OUTER", "OVER", "PARTITION", "PLAN", "PRAGMA", "PRECEDING", "PRIMARY", "QUERY", "RAISE",
+    "RANGE", "RECURSIVE", "REFERENCES", "REGEXP", "REINDEX", "RELEASE", "RENAME", "REPLACE",
+    "RESTRICT", "RETURNING", "RIGHT", "ROLLBACK", "ROW", "ROWS", "SAVEPOINT", "SELECT", "SET",
+    "TABLE", "TEMP", "TEMPORARY", "THEN", "TIES", "TO", "TRANSACTION", "TRIGGER", "UNBOUNDED",
+    "UNION", "UNIQUE", "UPDATE", "USING", "VACUUM", "VALUES", "VIEW", "VIRTUAL", "WHEN",
+    "WHERE", "WINDOW", "WITH", "WITHOUT",
+];
+
+fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name.to_ascii_uppercase().as_str())
+}
+
+// Renders a value as an SQL literal that reads back as the same value. Text with control
+// characters in it goes through unistr(), which is how sqlite3 writes it.
+pub fn sql_literal(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Int(n) => n.to_string(),
+        ValueRef::Real(r) if r.is_infinite() => {
+            if r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string()
+        }
+        // Like sqlite3, whole numbers that fit in an integer are written as one with ".0" after
+        // it, so they still read as reals.
+        ValueRef::Real(r)
+            if r.fract() == 0.0 && (-9.223372036854776e18..9.223372036854776e18).contains(&r) =>
+        {
+            format!("{}.0", r as i64)
+        }
+        // sqlite3 writes twenty significant digits, though past the seventeenth they're only as
+        // close as sqlite works them out, so for some very large or small reals they don't read
+        // back as exactly the same value. Those are written with the shortest digits that do.
+        ValueRef::Real(r) => {
+            let literal = real_literal(r);
+            if literal.parse::<f64>() == Ok(r) {
+                literal
+            } else {
+                format!("{:?}", r)
+            }
+        }
+        ValueRef::Blob(b) => {
+            let mut literal = String::with_capacity(b.len() * 2 + 3);
+            literal.push_str("X'");
+            for byte in b {
+                write!(literal, "{:02x}", byte).unwrap();
+            }
+            literal.push('\'');
+            literal
+        }
+        ValueRef::Text(s) if s.chars().any(|c| c.is_ascii_control()) => {
+            let mut literal = String::from("unistr('");
+            for c in s.chars() {
+                match c {
+                    '\'' => literal.push_str("''"),
+                    '\\' => literal.push_str("\\\\"),
+                    c if c.is_ascii_control() => write!(literal, "\\u{:04x}", c as u32).unwrap(),
+                    c => literal.push(c),
+                }
+            }
+            literal.push_str("')");
+            literal
+        }
+        ValueRef::Text(s) => format!("'{}'", s.replace('\'', "''")),
+    }
+}
+
+// Writes a real that isn't a whole number the way sqlite3 writes one into SQL, which is printf's
+// `%!.20g`: twenty significant digits, more than it takes to read back the same bits.
+fn real_literal(r: f64) -> String {
+    let sign = if r < 0.0 { "-" } else { "" };
+    let (digits, point) = real_digits(r.abs(), 20);
+    let digits = String::from_utf8(digits).unwrap();
+    let exponent = point - 1;
+    if !(-4..20).contains(&exponent) {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}.{}e{}{:02}",
+            sign,
+            &digits[..1],
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        )
+    } else if point <= 0 {
+        format!(
+            "{}0.{}{}",
+            sign,
+            "0".repeat(point.unsigned_abs() as usize),
+            digits
+        )
+    } else if digits.len() > point as usize {
+        let (whole, fraction) = digits.split_at(point as usize);
+        format!("{}{}.{}", sign, whole, fraction)
+    } else {
+        let zeros = "0".repeat(point as usize - digits.len());
+        format!("{}{}{}.0", sign, digits, zeros)
+    }
+}
+
+// The significant digits of a positive real, rounded to at most `round` of them with trailing
+// zeros dropped, and how many of them come before the decimal point, worked out as sqlite works
+// them out: the value is scaled into the range of a u64 with double-double arithmetic, so the
+// digits past the seventeenth or so aren't exactly the value's, but they're the ones sqlite3
+// writes.
+fn real_digits(r: f64, round: usize) -> (Vec<u8>, i32) {
+    let mut x = (r, 0.0);
+    let mut exponent = 0;
+    if x.0 > 9.223372036854775e18 {
+        while x.0 > 9.223372036854774e118 {
+            exponent += 100;
+            double_mul(&mut x, 1.0e-100, -1.9991899802602883e-117);
+        }
+        while x.0 > 9.223372036854774e28 {
+            exponent += 10;
+            double_mul(&mut x, 1.0e-10, -3.643219731549774e-27);
+        }
+        while x.0 > 9.223372036854775e18 {
+            exponent += 1;
+            double_mul(&mut x, 1.0e-1, -5.551115123125783e-18);
+        }
+    } else {
+        while x.0 < 9.223372036854775e-83 {
+            exponent -= 100;
+            double_mul(&mut x, 1.0e100, -1.5902891109759918e83);
+        }
+        while x.0 < 92233720.36854775 {
+            exponent -= 10;
+            double_mul(&mut x, 1.0e10, 0.0);
+        }
+        while x.0 < 9.223372036854775e17 {
+            exponent -= 1;
+            double_mul(&mut x, 1.0e1, 0.0);
+        }
+    }
+    let v = if x.1 < 0.0 {
+        x.0 as u64 - (-x.1) as u64
+    } else {
+        x.0 as u64 + x.1 as u64
+    };
+
+    let mut digits = v.to_string().into_bytes();
+    let mut point = digits.len() as i32 + exponent;
+    if round < digits.len() {
+        let round_up = digits[round] >= b'5';
+        digits.truncate(round);
+        if round_up {
+            match digits.iter().rposition(|digit| *digit != b'9') {
+                Some(position) => {
+                    digits[position] += 1;
+                    digits[position + 1..].fill(b'0');
+                }
+                // All nines carry into a new leading digit.
+                None => {
+                    digits.fill(b'0');
+                    digits.insert(0, b'1');
+                    point += 1;
+                }
+            }
+        }
+    }
+    while digits.last() == Some(&b'0') {
+        digits.pop();
+    }
+    (digits, point)
+}
+
+// Multiplies the double-double `x` by `y + yy`, where `yy` is the error in `y`, using Dekker's
+// splitting of each factor into high and low halves.
+fn double_mul(x: &mut (f64, f64), y: f64, yy: f64) {
+    let split = |v: f64| f64::from_bits(v.to_bits() & 0xffff_ffff_fc00_0000);
+    let (hx, hy) = (split(x.0), split(y));
+    let (tx, ty) = (x.0 - hx, y - hy);
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let cc = p - c + q + tx * ty;
+    let cc = x.0 * yy + x.1 * y + cc;
+    let high = c + cc;
+    x.1 = c - high + cc;
+    x.0 = high;
+}
+
+// Expands the backslash escapes that sqlite3 allows in double quoted dot command arguments, so
+// that `.separator "\t"` separates columns with a tab. Unknown escapes are left as they are.
+pub fn unescape(arg: &str) -> String {
+    let mut result = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('\'') => result.push('\''),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+// Writes a string as a JSON string literal. Like sqlite3, only the characters JSON doesn't allow
+// in a string are escaped, so other unicode characters are written as they are.
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c.is_ascii_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Writes a value as JSON. Reals have the digits sqlite3 gives them, as in SQL, so they read back
+// as the same value. JSON has no way to write bytes, so a blob becomes a string of hex digits, and
+// it has no infinity, so infinite reals are written as the largest numbers there are.
+fn push_json_value(out: &mut String, value: ValueRef) {
+    match value {
+        ValueRef::Null => out.push_str("null"),
+        ValueRef::Int(n) => write!(out, "{}", n).unwrap(),
+        ValueRef::Real(r) if r.is_infinite() => out.push_str(if r > 0.0 {
+            "1.7976931348623157e308"
+        } else {
+            "-1.7976931348623157e308"
+        }),
+        ValueRef::Real(_) => out.push_str(&sql_literal(value)),
+        ValueRef::Text(s) => push_json_string(out, s),
+        ValueRef::Blob(b) => {
+            out.push('"');
+            for byte in b {
+                write!(out, "{:02x}", byte).unwrap();
+            }
+            out.push('"');
+        }
+    }
+}
+
+// A row as an object, keyed by column name.
+pub(crate) fn push_json_object<'v>(
+    out: &mut String,
+    columns: &[String],
+    values: impl IntoIterator<Item = ValueRef<'v>>,
+) {
+    out.push('{');
+    for (i, (column, value)) in columns.iter().zip(values).enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_json_string(out, column);
+        out.push(':');
+        push_json_value(out, value);
+    }
+    out.push('}');
+}
+
+// Writes a value as a csv field. Like sqlite3, text and blobs are quoted when they are empty or
+// have anything in them but printable ASCII, a quote of either kind, or the separator, so that spreadsheets read them back
+// as the same value. Numbers are never quoted, and NULL is an empty field.
+fn write_csv_field(out: &mut impl Write, value: ValueRef, separator: &str) -> io::Result<()> {
+    let bytes = value_bytes(value);
+    let separator = separator.as_bytes();
+    let quote = matches!(value, ValueRef::Text(_) | ValueRef::Blob(_))
+        && (bytes.is_empty()
+            || separator.is_empty()
+            || bytes.windows(separator.len()).any(|w| w == separator)
+            || bytes
+                .iter()
+                .any(|&b| b <= b' ' || b == b'"' || b == b'\'' || b >= 0x7f));
+    if !quote {
+        return out.write_all(&bytes);
+    }
+
+    out.write_all(b"\"")?;
+    for piece in bytes.split_inclusive(|&b| b == b'"') {
+        out.write_all(piece)?;
+        if piece.ends_with(b"\"") {
+            out.write_all(b"\"")?;
+        }
+    }
+    out.write_all(b"\"")
+}
+
+// A value as sqlite3 prints it in the modes that write values as they are, rather than as SQL or
+// JSON: NULL as nothing at all, and a blob as its bytes. sqlite3 hands every value to printf as a
+// C string, so text and blobs end at their first zero byte.
+fn value_bytes<'v>(value: ValueRef<'v>) -> Cow<'v, [u8]> {
+    let bytes = match value {
+        ValueRef::Null => return Cow::Borrowed(&[]),
+        ValueRef::Int(_) | ValueRef::Real(_) => return Cow::Owned(value.to_string().into_bytes()),
+        ValueRef::Text(s) => s.as_bytes(),
+        ValueRef::Blob(b) => b,
+    };
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Cow::Borrowed(&bytes[..end])
+}
+
+// Writes result sets in the configured mode. List, csv, insert, line and the JSON modes stream
+// every row straight through, but the columnar modes have to see every row before they know how
+// wide to make each column, so they buffer the rendered values and only draw the table in
+// `finish`.
+pub struct ResultWriter<'a, W: Write> {
+    out: W,
+    settings: &'a OutputSettings,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    line: String,
+    // Rows of the current result set written so far, by the modes that stream.
+    written: usize,
+}
+
+impl<'a, W: Write> ResultWriter<'a, W> {
+    pub fn new(out: W, settings: &'a OutputSettings) -> Self {
+        Self {
+            out,
+            settings,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            line: String::new(),
+            written: 0,
+        }
+    }
+
+    // Begins a new result set, with these columns.
+    pub fn start(&mut self, columns: Vec<String>) {
+        self.columns = columns;
+        self.rows.clear();
+        self.written = 0;
+    }
+
+    pub fn write_row<'v>(
+        &mut self,
+        values: impl IntoIterator<Item = ValueRef<'v>>,
+    ) -> io::Result<()> {
+        match self.settings.mode {
+            // These write straight through, as a blob's bytes needn't be UTF-8.
+            Mode::List | Mode::Csv => {
+                let separator = &self.settings.column_separator;
+                let write_value = |out: &mut W, i: usize, value: ValueRef| {
+                    if i > 0 {
+                        out.write_all(separator.as_bytes())?;
+                    }
+                    match self.settings.mode {
+                        Mode::Csv => write_csv_field(out, value, separator),
+                        _ => out.write_all(&value_bytes(value)),
+                    }
+                };
+
+                if self.written == 0 && self.settings.headers {
+                    let names = self.columns.iter().map(|name| ValueRef::Text(name));
+                    for (i, name) in names.enumerate() {
+                        write_value(&mut self.out, i, name)?;
+                    }
+                    self.out.write_all(self.settings.row_separator.as_bytes())?;
+                }
+
+                for (i, value) in values.into_iter().enumerate() {
+                    write_value(&mut self.out, i, value)?;
+                }
+                self.written += 1;
+                self.out.write_all(self.settings.row_separator.as_bytes())
+            }
+            // The array is opened by the first row, so an empty result set writes nothing.
+            Mode::Json => {
+                self.line.clear();
+                self.line
+                    .push_str(if self.written == 0 { "[" } else { ",\n" });
+                push_json_object(&mut self.line, &self.columns, values);
+                self.written += 1;
+                self.out.write_all(self.line.as_bytes())
+            }
+            Mode::Jsonl => {
+                self.line.clear();
+                push_json_object(&mut self.line, &self.columns, values);
+                self.line.push('\n');
+                self.written += 1;
+                self.out.write_all(self.line.as_bytes())
+            }
+            // With headers on, every statement names the columns, as the table may not have them
+            // in the same order.
+            Mode::Insert => {
+                self.line.clear();
+                let columns = self.settings.headers.then_some(self.columns.as_slice());
+                push_insert(&mut self.line, &self.settings.insert_table, columns, values);
+                self.line.push('\n');
+                self.written += 1;
+                self.out.write_all(self.line.as_bytes())
+            }
+            // The names are right aligned to the longest of them, and never narrower than five
+            // characters, as sqlite3 lays them out.
+            Mode::Line => {
+                if self.written > 0 {
+                    self.out.write_all(b"\n")?;
+                }
+                let width = self
+                    .columns
+                    .iter()
+                    .map(|name| name.chars().count())
+                    .max()
+                    .unwrap_or(0)
+                    .max(5);
+                for (name, value) in self.columns.iter().zip(values) {
+                    write!(self.out, "{:>width$} = ", name)?;
+                    self.out.write_all(&value_bytes(value))?;
+                    self.out.write_all(b"\n")?;
+                }
+                self.written += 1;
+                Ok(())
+            }
+            // The values are measured to size the columns, so bytes that aren't UTF-8 are
+            // replaced.
+            Mode::Column | Mode::Table | Mode::Box => {
+                let row = values
+                    .into_iter()
+                    .map(|value| String::from_utf8_lossy(&value_bytes(value)).into_owned())
+                    .collect();
+                self.rows.push(row);
+                Ok(())
+            }
+        }
+    }
+
+    // Draws the buffered result set, if the mode buffers, and flushes everything written so far.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.settings.mode == Mode::Json && self.written > 0 {
+            self.out.write_all(b"]\n")?;
+        }
+
+        let columns = std::mem::take(&mut self.columns);
+        let rows = std::mem::take(&mut self.rows);
+        write_table(&mut self.out, self.settings, &columns, &rows)?;
+        self.out.flush()
+    }
+}
+
+fn write_table(
+    out: &mut impl Write,
+    settings: &OutputSettings,
+    columns: &[String],
+    rows: &[Vec<String>],
+) -> io::Result<()> {
+    // Like sqlite3, an empty result set prints nothing at all, not even the header.
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let layout = columns
+        .iter()
+        .enumerate()
+        .map(
+            |(i, column)| match settings.widths.get(i).copied().unwrap_or(0) {
+                0 => {
+                    let widest = rows
+                        .iter()
+                        .filter_map(|row| row.get(i))
+                        .chain(std::iter::once(column))
+                        .flat_map(|value| value.lines())
+                        .map(|line| line.chars().count())
+                        .max()
+                        .unwrap_or(0);
+                    (widest.min(MAX_AUTO_WIDTH), Align::Left)
+                }
+                width if width < 0 => (width.unsigned_abs() as usize, Align::Right),
+                width => (width as usize, Align::Left),
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let wrap_row = |row: &[String]| {
+        row.iter()
+            .zip(&layout)
+            .map(|(value, (width, _))| wrap(value, *width))
+            .collect::<Vec<_>>()
+    };
+    // Unlike values, column names too long for their column are cut short rather than wrapped.
+    let header = columns
+        .iter()
+        .zip(&layout)
+        .map(|(column, (width, _))| vec![column.chars().take(*width).collect::<String>()])
+        .collect::<Vec<_>>();
+    let rows = rows.iter().map(|row| wrap_row(row)).collect::<Vec<_>>();
+
+    // Once any value has been wrapped onto several lines, rows are divided from each other so it
+    // is clear where one ends and the next begins.
+    let divide_rows = rows
+        .iter()
+        .any(|row| row.iter().any(|lines| lines.len() > 1));
+
+    match settings.mode {
+        Mode::List | Mode::Json | Mode::Jsonl | Mode::Csv | Mode::Insert | Mode::Line => {}
+        Mode::Column => {
+            let dashes = layout
+                .iter()
+                .map(|(width, _)| vec!["-".repeat(*width)])
+                .collect::<Vec<_>>();
+            if settings.headers {
+                write_cells(out, &header, &layout, ["", "  ", ""], false)?;
+                write_cells(out, &dashes, &layout, ["", "  ", ""], false)?;
+            }
+            for (i, row) in rows.iter().enumerate() {
+                if divide_rows && i > 0 {
+                    writeln!(out)?;
+                }
+                write_cells(out, row, &layout, ["", "  ", ""], false)?;
+            }
+        }
+        Mode::Table => {
+            write_border(out, &layout, ["+", "+", "+"], "-")?;
+            write_cells(out, &header, &layout, ["| ", " | ", " |"], true)?;
+            write_border(out, &layout, ["+", "+", "+"], "-")?;
+            for (i, row) in rows.iter().enumerate() {
+                if divide_rows && i > 0 {
+                    write_border(out, &layout, ["+", "+", "+"], "-")?;
+                }
+                write_cells(out, row, &layout, ["| ", " | ", " |"], false)?;
+            }
+            write_border(out, &layout, ["+", "+", "+"], "-")?;
+        }
+        Mode::Box => {
+            write_border(out, &layout, ["┌", "┬", "┐"], "─")?;
+            write_cells(out, &header, &layout, ["│ ", " │ ", " │"], true)?;
+            write_border(out, &layout, ["├", "┼", "┤"], "─")?;
+            for (i, row) in rows.iter().enumerate() {
+                if divide_rows && i > 0 {
+                    write_border(out, &layout, ["├", "┼", "┤"], "─")?;
+                }
+                write_cells(out, row, &layout, ["│ ", " │ ", " │"], false)?;
+            }
+            write_border(out, &layout, ["└", "┴", "┘"], "─")?;
+        }
+    }
+
+    Ok(())
+}
+
+// Columns that size themselves are capped at this width, and longer values wrap onto more lines,
+// which is what the sqlite3 shell does by default.
+const MAX_AUTO_WIDTH: usize = 60;
+
+// Splits a value into the lines it takes up in a column of the given width. Embedded newlines
+// always start a new line, and anything longer than the width is cut into width sized pieces.
+fn wrap(value: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+
+    for line in value.split('\n') {
+        let chars = line.chars().collect::<Vec<_>>();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(
+            chars
+                .chunks(width)
+                .map(|chunk| chunk.iter().collect::<String>()),
+        );
+    }
+
+    lines
+}
+
+// Writes one row of a columnar table, which takes as many lines as its tallest cell. Every value
+// is padded out to its column's width, and the three strings go before the first value, between
+// values, and after the last one. Headers are centered rather than following the column.
+fn write_cells(
+    out: &mut impl Write,
+    cells: &[Vec<String>],
+    layout: &[(usize, Align)],
+    [start, separator, end]: [&str; 3],
+    centered: bool,
+) -> io::Result<()> {
+    let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+
+    for line_number in 0..height {
+        let mut line = String::from(start);
+
+        for (i, (cell, (width, align))) in cells.iter().zip(layout).enumerate() {
+            if i > 0 {
+                line.push_str(separator);
+            }
+
+            let value = cell.get(line_number).map_or("", String::as_str);
+            let padding = width.saturating_sub(value.chars().count());
+
+            // Centered values lean left when the padding can't be split evenly.
+            let left = match (centered, align) {
+                (true, _) => padding / 2,
+                (false, Align::Left) => 0,
+                (false, Align::Right) => padding,
+            };
+            write!(
+                line,
+                "{}{}{}",
+                " ".repeat(left),
+                value,
+                " ".repeat(padding - left)
+            )
+            .unwrap();
+        }
+
+        line.push_str(end);
+        writeln!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+// Writes a horizontal rule of a table, with `fill` spanning each column and its padding.
+fn write_border(
+    out: &mut impl Write,
+    layout: &[(usize, Align)],
+    [start, middle, end]: [&str; 3],
+    fill: &str,
+) -> io::Result<()> {
+    let segments = layout
+        .iter()
+        .map(|(width, _)| fill.repeat(width + 2))
+        .collect::<Vec<_>>();
+
+    writeln!(out, "{}{}{}", start, segments.join(middle), end)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // Fields are quoted where sqlite3 -csv quotes them:
+    //
+    //     "it's",plain,"","a,b","say ""hi""","tab	x",1.5,,"é"
+    #[test]
+    fn quotes_csv_fields() {
+        let text = |s: &'static str| ValueRef::Text(s);
+        let values = [
+            text("it's"),
+            text("plain"),
+            text(""),
+            text("a,b"),
+            text("say \"hi\""),
+            text("tab\tx"),
+            ValueRef::Real(1.5),
+            ValueRef::Null,
+            text("é"),
+            ValueRef::Blob(b"ab"),
+        ];
+        let mut out = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            write_csv_field(&mut out, value, ",").unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"it's\",plain,\"\",\"a,b\",\"say \"\"hi\"\"\",\"tab\tx\",1.5,,\"é\",ab"
+        );
+
+        let mut out = Vec::new();
+        write_csv_field(&mut out, text("a|b"), "|").unwrap();
+        assert_eq!(out, b"\"a|b\"");
+    }
+
+    // Reals are written as sqlite3 writes them into SQL: whole numbers that fit in an integer as
+    // one, and anything else to twenty significant digits, which past the seventeenth are the
+    // digits sqlite works out rather than the value's own. sqlite3 writes 1e300 as
+    // 9.99999999999999956e+299, which is a different real, so that's written as it was.
+    #[test]
+    fn writes_reals_like_sqlite3() {
+        for (real, expected) in [
+            (3.0, "3.0"),
+            (-0.0, "0.0"),
+            (1e18, "1000000000000000000.0"),
+            (-(2f64.powi(63)), "-9223372036854775808.0"),
+            (2f64.powi(63), "9223372036854775810.0"),
+            (1e19, "10000000000000000000.0"),
+            (1e21, "1.0e+21"),
+            (-2.5, "-2.5"),
+            (0.1, "0.1000000000000000055"),
+            (0.1 + 0.2, "0.3000000000000000445"),
+            (1.0 / 3.0, "0.3333333333333333148"),
+            (123456.789, "123456.7890000000043"),
+            (1e-5, "1.000000000000000082e-05"),
+            (1e300, "1e300"),
+            (5e-324, "4.940656458412465441e-324"),
+            (f64::MAX, "1.797693134862315692e+308"),
+            (f64::INFINITY, "9.0e+999"),
+        ] {
+            assert_eq!(sql_literal(ValueRef::Real(real)), expected, "{:?}", real);
+        }
+    }
+}
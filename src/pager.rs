@@ -0,0 +1,1609 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cipher::{self, Cipher};
+use crate::gzip;
+use crate::http::HttpFile;
+use crate::trace::{self, Target};
+
+#[derive(Debug, Default, Clone)]
+pub struct PagerOptions {
+    // Slice pages out of a read-only memory map of the file rather than reading them into a
+    // buffer.
+    pub mmap: bool,
+    // Hold a SHARED lock on the file for as long as it is open, rather than only while a statement
+    // reads it, so that no other process can write to it in the meantime.
+    pub shared_lock: bool,
+    // The passphrase of a database encrypted by SQLCipher.
+    pub key: Option<String>,
+    // Read what can be read of a damaged database, skipping the cells and pages that can't be
+    // parsed rather than failing, and keeping a warning for each.
+    pub salvage: bool,
+    // Open the file for reading only, even when it could be written.
+    pub read_only: bool,
+}
+
+// The pager is the only thing that touches the database file. Pages are always fetched whole,
+// with a single positioned read (or a borrowed slice of the memory map), and parsed from memory.
+//
+// Writes happen inside a transaction. The pages it changes are held in memory, where reads find
+// them in place of what is in the file, until it commits and each of them is written out once,
+// however many times it was changed in between.
+//
+// A commit to a file goes through a rollback journal, as sqlite3's does, so that a crash or a
+// power cut part way through it can be undone: what the pages held before goes into the journal
+// first, then the pages are written, and deleting the journal is the moment the commit happens.
+// sqlite3 rolls back a journal this leaves behind, and so does this, before it next reads the file.
+#[derive(Debug)]
+pub struct Pager {
+    source: Source,
+    page_size: u32,
+    // The bytes at the end of every page that are reserved for extensions, which the database
+    // header gives, and which never hold any part of a cell.
+    reserved: u8,
+    // How many whole pages the database holds, counting those the transaction in progress has
+    // added.
+    page_count: AtomicU32,
+    // Whether the database can be written: a file opened for writing, or an image in memory. An
+    // encrypted database or one on a server can only be read.
+    writable: bool,
+    // The pages changed by the transaction in progress, and the page count before it began.
+    dirty: Mutex<BTreeMap<u32, Vec<u8>>>,
+    committed_page_count: AtomicU32,
+    // Pages that have been committed to a database in memory, which the image it started from
+    // doesn't have.
+    written: Mutex<HashMap<u32, Vec<u8>>>,
+    // Held for as long as a transaction is open, so that only one writes at a time.
+    writer: Mutex<()>,
+    // Where the rollback journal of a database in a file goes, next to it.
+    journal: Option<PathBuf>,
+    // The Synchronous level commits are made at, which can be changed while the database is open.
+    synchronous: AtomicU8,
+    // The lock this pager holds on the file. The lock other processes see is the file's.
+    lock: Mutex<Lock>,
+    // Decrypts each page as it is read, for an encrypted database.
+    cipher: Option<Cipher>,
+    counters: Counters,
+    salvage: bool,
+    // What was skipped while salvaging, since the warnings were last taken.
+    warnings: Mutex<Vec<String>>,
+}
+
+// Counts what the pager has done so far. Scans share the pager between threads, so these are
+// atomics, and they are only ever read as a snapshot. The work the b-tree code does with the pages
+// is counted here too, as the pager is what every reader shares.
+#[derive(Debug, Default)]
+struct Counters {
+    pages_read: AtomicU64,
+    cache_hits: AtomicU64,
+    bytes_read: AtomicU64,
+    cells_decoded: AtomicU64,
+    index_seeks: AtomicU64,
+    pages_written: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PagerStats {
+    // Every page handed out by the pager.
+    pub pages_read: u64,
+    // Pages served straight out of the memory map, an in-memory database, or the pages already
+    // fetched from a server, without a read from the file.
+    pub cache_hits: u64,
+    // Bytes actually read from the file, or fetched from the server.
+    pub bytes_read: u64,
+    // Cells parsed out of b-tree pages.
+    pub cells_decoded: u64,
+    // Keys looked up in an index b-tree.
+    pub index_seeks: u64,
+    // Pages written out by transactions as they committed.
+    pub pages_written: u64,
+}
+
+impl PagerStats {
+    // What happened between an earlier snapshot and this one.
+    pub fn since(&self, earlier: &PagerStats) -> PagerStats {
+        PagerStats {
+            pages_read: self.pages_read - earlier.pages_read,
+            cache_hits: self.cache_hits - earlier.cache_hits,
+            bytes_read: self.bytes_read - earlier.bytes_read,
+            cells_decoded: self.cells_decoded - earlier.cells_decoded,
+            index_seeks: self.index_seeks - earlier.index_seeks,
+            pages_written: self.pages_written - earlier.pages_written,
+        }
+    }
+}
+
+// How hard a commit works to survive a power cut, as sqlite3's `PRAGMA synchronous`. The
+// operating system can hold writes in its cache and put them on the disk in any order, so the
+// journal is flushed before any page is written over, and the database before the journal is
+// deleted.
+//
+// - Off flushes nothing, leaving it to the operating system. A crash of the process alone can
+//   still be rolled back, but a power cut can corrupt the database.
+// - Normal flushes the journal once, along with the count of the pages in it, and then the
+//   database.
+// - Full flushes the pages in the journal before their count, so that the count is never on the
+//   disk without them.
+// - Extra flushes the directory after the journal is deleted too, so that a commit can't be
+//   undone by a power cut once it has returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Synchronous::Off,
+            1 => Synchronous::Normal,
+            2 => Synchronous::Full,
+            _ => Synchronous::Extra,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    File(Arc<OpenFile>),
+    // The file stays open alongside its map, so that a lock on it lasts as long as the map does,
+    // and so that pages can be written to it. The map sees every write to the file, from here or
+    // another process, but only covers it as long as it was when it was mapped, and pages it has
+    // grown by since are read from the file.
+    Mmap {
+        map: Mmap,
+        file: Arc<OpenFile>,
+    },
+    // A database that only exists in memory, like `:memory:`.
+    Memory(Vec<u8>),
+    // Pages fetched from a server are kept, so reading one again doesn't go back over the
+    // network.
+    Http {
+        file: HttpFile,
+        cache: Mutex<HashMap<u32, Vec<u8>>>,
+    },
+}
+
+// How many pages fetched from a server are kept, which is 16MiB of 4096 byte pages. Pages past
+// that are fetched every time they are read.
+const HTTP_CACHE_PAGES: usize = 4096;
+
+impl Pager {
+    pub fn open(path: &Path, options: PagerOptions) -> io::Result<Self> {
+        let file = OpenFile::open(path)?;
+        let writable = file.writable && !options.read_only;
+        // A compressed database is inflated whole and read from memory, which makes it a snapshot
+        // that needs no lock once it has been read.
+        let mut magic = [0; 2];
+        let mut reader = &file.file;
+        if reader.read_exact(&mut magic).is_ok() && gzip::is_gzip(&magic) {
+            let mut bytes = Vec::new();
+            reader.seek(SeekFrom::Start(0))?;
+            reader.read_to_end(&mut bytes)?;
+            return Self::memory(gzip::decompress(&bytes)?);
+        }
+
+        // A commit that never finished is rolled back before anything is read from the file, as
+        // even the page size in its header may be one it was part way through writing.
+        let journal = journal_path(path);
+        if writable {
+            let mut lock = Lock {
+                level: LockLevel::None,
+                holders: 0,
+            };
+            let result = raise(Some(&file), &mut lock, LockLevel::Shared)
+                .and_then(|()| roll_back_hot_journal(&file, &journal, &mut lock));
+            lower(Some(&file), &mut lock, LockLevel::None);
+            result?;
+        }
+
+        let source = if options.mmap {
+            Source::Mmap {
+                map: Mmap::map(&file.file)?,
+                file,
+            }
+        } else {
+            Source::File(file)
+        };
+
+        let mut pager = Self::from_source(source, options.key.is_some())?;
+        pager.writable = writable && options.key.is_none();
+        pager.journal = Some(journal);
+        // The shared_lock option takes the lock for a holder that never lets it go.
+        if options.shared_lock {
+            pager.acquire(LockLevel::Shared)?;
+        }
+        if let Some(key) = &options.key {
+            let mut salt = [0; 16];
+            pager.source.read_exact_at(&mut salt, 0)?;
+            pager.cipher = Some(Cipher::new(key, &salt));
+        }
+        Ok(pager)
+    }
+
+    // A pager over a database image held in memory rather than in a file.
+    pub fn memory(image: Vec<u8>) -> io::Result<Self> {
+        let mut pager = Self::from_source(Source::Memory(image), false)?;
+        pager.writable = true;
+        Ok(pager)
+    }
+
+    // A pager over a database on a web server or in an object store, which fetches each page
+    // the first time it is read.
+    pub fn http(url: &str) -> io::Result<Self> {
+        let source = Source::Http {
+            file: HttpFile::open(url)?,
+            cache: Mutex::new(HashMap::new()),
+        };
+        Self::from_source(source, false)
+    }
+
+    fn from_source(source: Source, encrypted: bool) -> io::Result<Self> {
+        // The page size lives at offset 16 of the database header, and the size of the reserved
+        // space at the end of each page at offset 20, and both are needed before any page can be
+        // read. In an encrypted database the header is encrypted too, so they are always the
+        // defaults.
+        let mut header = [0; 5];
+        source.read_exact_at(&mut header, 16)?;
+        let (page_size, reserved) = match u16::from_be_bytes([header[0], header[1]]) {
+            _ if encrypted => (cipher::PAGE_SIZE, cipher::RESERVED_SIZE as u8),
+            1 => (65536, header[4]),
+            n => (n as u32, header[4]),
+        };
+        if !page_size.is_power_of_two() || page_size < 512 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid page size {}", page_size),
+            ));
+        }
+
+        let len = match &source {
+            Source::File(file) | Source::Mmap { file, .. } => file.file.metadata()?.len(),
+            Source::Http { file, .. } => file.len(),
+            source => source.as_slice().len() as u64,
+        };
+        let page_count = u32::try_from(len / page_size as u64).unwrap_or(u32::MAX);
+
+        Ok(Self {
+            source,
+            page_size,
+            reserved,
+            page_count: AtomicU32::new(page_count),
+            writable: false,
+            dirty: Mutex::new(BTreeMap::new()),
+            committed_page_count: AtomicU32::new(page_count),
+            written: Mutex::new(HashMap::new()),
+            writer: Mutex::new(()),
+            journal: None,
+            synchronous: AtomicU8::new(Synchronous::Full as u8),
+            lock: Mutex::new(Lock {
+                level: LockLevel::None,
+                holders: 0,
+            }),
+            cipher: None,
+            counters: Counters::default(),
+            salvage: false,
+            warnings: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.page_count.load(Ordering::Relaxed)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    // Whether the database is only held in memory, with no file to read it from again.
+    pub fn in_memory(&self) -> bool {
+        matches!(self.source, Source::Memory(_))
+    }
+
+    pub fn synchronous(&self) -> Synchronous {
+        Synchronous::from_u8(self.synchronous.load(Ordering::Relaxed))
+    }
+
+    pub fn set_synchronous(&self, synchronous: Synchronous) {
+        self.synchronous.store(synchronous as u8, Ordering::Relaxed);
+    }
+
+    // Starts a transaction, waiting for any other in this process to finish first. What it writes
+    // is thrown away unless it commits. It holds a RESERVED lock on the file, which fails with
+    // WouldBlock while another process is writing to it.
+    pub fn begin(&self) -> io::Result<Transaction<'_>> {
+        if !self.writable {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the database is read only",
+            ));
+        }
+        let writer = self.writer.lock().unwrap();
+        self.acquire(LockLevel::Reserved)?;
+        Ok(Transaction {
+            pager: self,
+            _writer: writer,
+            committed: false,
+        })
+    }
+
+    // Holds a SHARED lock on the file until the guard is dropped, which keeps other processes from
+    // writing to it. Fails with WouldBlock while one is about to.
+    pub fn read_lock(&self) -> io::Result<ReadLock<'_>> {
+        self.acquire(LockLevel::Shared)?;
+        Ok(ReadLock { pager: self })
+    }
+
+    // Takes the file's lock up to `level`, for one more holder. The first holder takes the SHARED
+    // lock.
+    fn acquire(&self, level: LockLevel) -> io::Result<()> {
+        let mut lock = self.lock.lock().unwrap();
+        lock.holders += 1;
+
+        let mut result = Ok(());
+        if lock.holders == 1 {
+            result = self
+                .raise(&mut lock, LockLevel::Shared)
+                .and_then(|()| self.reread(&mut lock));
+        }
+        if let Err(err) = result.and_then(|()| self.raise(&mut lock, level)) {
+            drop(lock);
+            self.release();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Another process may have written to the file while nobody here held its lock, so this rolls
+    // back any commit of its that never finished, and counts the pages again.
+    fn reread(&self, lock: &mut Lock) -> io::Result<()> {
+        let Some(file) = self.file() else {
+            return Ok(());
+        };
+        if let (true, Some(journal)) = (self.writable, &self.journal) {
+            roll_back_hot_journal(file, journal, lock)?;
+        }
+        let page_count =
+            u32::try_from(file.file.metadata()?.len() / self.page_size as u64).unwrap_or(u32::MAX);
+        self.page_count.store(page_count, Ordering::Relaxed);
+        self.committed_page_count
+            .store(page_count, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Lets go of what one holder needed of the lock: a writer's hold on more than SHARED, and the
+    // SHARED lock itself once nobody holds it.
+    fn release(&self) {
+        let mut lock = self.lock.lock().unwrap();
+        lock.holders -= 1;
+        let level = match lock.holders {
+            0 => LockLevel::None,
+            _ => LockLevel::Shared,
+        };
+        lower(self.file(), &mut lock, level);
+    }
+
+    fn raise(&self, lock: &mut Lock, level: LockLevel) -> io::Result<()> {
+        raise(self.file(), lock, level)
+    }
+
+    // The file other pagers and processes may be using too. A database in memory, or on a
+    // server, is only this pager's.
+    fn file(&self) -> Option<&OpenFile> {
+        match &self.source {
+            Source::File(file) | Source::Mmap { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+
+    // Replaces a page, or adds one just past the end of the database, as part of the transaction
+    // in progress.
+    pub fn write_page(&self, page_number: u32, page: Vec<u8>) -> io::Result<()> {
+        let page_count = self.page_count();
+        if page_number == 0 || page_number > page_count + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} is past the end of the database", page_number),
+            ));
+        }
+        if page_number > page_count {
+            self.page_count.store(page_number, Ordering::Relaxed);
+        }
+
+        trace::event!(Target::Pager, "write page {}", page_number);
+        self.dirty.lock().unwrap().insert(page_number, page);
+        Ok(())
+    }
+
+    // Writes out every page the transaction changed, through the journal, with the first page
+    // last, so that the header and the change counter in it, which tells other readers the file
+    // has changed, are only written once the rest is.
+    // Readers in other processes are waited out with the PENDING lock, which keeps new ones from
+    // starting, and then the EXCLUSIVE lock, which fails with WouldBlock while any are left.
+    fn commit(&self) -> io::Result<()> {
+        self.raise(&mut self.lock.lock().unwrap(), LockLevel::Exclusive)?;
+        let dirty = std::mem::take(&mut *self.dirty.lock().unwrap());
+        let pages = dirty.len() as u64;
+        let synchronous = self.synchronous();
+        match &self.source {
+            Source::File(file) | Source::Mmap { file, .. } => {
+                let file = &file.file;
+                if let Some(journal) = &self.journal {
+                    self.write_journal(journal, &dirty, synchronous)?;
+                }
+                if let Err(err) = self.write_pages(file, &dirty, synchronous) {
+                    // Put back what was written over while the journal is there to do it from.
+                    if let Some(journal) = &self.journal {
+                        play_back(file, journal).ok();
+                    }
+                    return Err(err);
+                }
+                if let Some(journal) = &self.journal {
+                    std::fs::remove_file(journal)?;
+                    if synchronous >= Synchronous::Extra {
+                        sync_directory(journal)?;
+                    }
+                }
+            }
+            Source::Memory(_) => self.written.lock().unwrap().extend(dirty),
+            Source::Http { .. } => unreachable!("a database on a server is never written"),
+        }
+
+        self.counters
+            .pages_written
+            .fetch_add(pages, Ordering::Relaxed);
+        self.committed_page_count
+            .store(self.page_count(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Writes what each page the transaction changes held before it into a new journal, with a
+    // header sqlite3 can read too. Pages it added aren't in it, as the file is cut back to the
+    // page count in the header to undo them.
+    fn write_journal(
+        &self,
+        path: &Path,
+        dirty: &BTreeMap<u32, Vec<u8>>,
+        synchronous: Synchronous,
+    ) -> io::Result<()> {
+        let page_count = self.committed_page_count.load(Ordering::Relaxed);
+        let nonce = nonce();
+
+        let mut records = Vec::new();
+        let mut count = 0u32;
+        for &page_number in dirty
+            .keys()
+            .filter(|&&page_number| page_number <= page_count)
+        {
+            let offset = (page_number as u64 - 1) * self.page_size as u64;
+            let written = self.written.lock().unwrap().get(&page_number).cloned();
+            let page = match written {
+                Some(page) => page,
+                None => self
+                    .fetch_page(page_number, offset, self.page_size as usize)?
+                    .into_owned(),
+            };
+            records.extend_from_slice(&page_number.to_be_bytes());
+            records.extend_from_slice(&page);
+            records.extend_from_slice(&checksum(nonce, &page).to_be_bytes());
+            count += 1;
+        }
+
+        // Without flushing, the count is left for whoever rolls it back to work out from the
+        // journal's length. With Full it is only written once the pages are on the disk.
+        let mut header = Vec::with_capacity(JOURNAL_HEADER_SIZE);
+        header.extend_from_slice(&JOURNAL_MAGIC);
+        let records_in_header = match synchronous {
+            Synchronous::Off => u32::MAX,
+            Synchronous::Normal => count,
+            Synchronous::Full | Synchronous::Extra => 0,
+        };
+        for field in [
+            records_in_header,
+            nonce,
+            page_count,
+            JOURNAL_HEADER_SIZE as u32,
+            self.page_size,
+        ] {
+            header.extend_from_slice(&field.to_be_bytes());
+        }
+        header.resize(JOURNAL_HEADER_SIZE, 0);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        write_all_at(&file, &header, 0)?;
+        write_all_at(&file, &records, JOURNAL_HEADER_SIZE as u64)?;
+        if synchronous >= Synchronous::Full {
+            file.sync_data()?;
+            write_all_at(&file, &count.to_be_bytes(), 8)?;
+        }
+        if synchronous >= Synchronous::Normal {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn write_pages(
+        &self,
+        file: &File,
+        dirty: &BTreeMap<u32, Vec<u8>>,
+        synchronous: Synchronous,
+    ) -> io::Result<()> {
+        let first = dirty.get_key_value(&1);
+        for (page_number, page) in dirty.range(2..).chain(first) {
+            write_all_at(
+                file,
+                page,
+                (*page_number as u64 - 1) * self.page_size as u64,
+            )?;
+        }
+        if synchronous >= Synchronous::Normal {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self) {
+        self.dirty.lock().unwrap().clear();
+        self.page_count.store(
+            self.committed_page_count.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn stats(&self) -> PagerStats {
+        PagerStats {
+            pages_read: self.counters.pages_read.load(Ordering::Relaxed),
+            cache_hits: self.counters.cache_hits.load(Ordering::Relaxed),
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            cells_decoded: self.counters.cells_decoded.load(Ordering::Relaxed),
+            index_seeks: self.counters.index_seeks.load(Ordering::Relaxed),
+            pages_written: self.counters.pages_written.load(Ordering::Relaxed),
+        }
+    }
+
+    // The bytes of a page that b-tree cells can be in: all of them but the reserved space.
+    pub fn usable_size(&self) -> usize {
+        (self.page_size - self.reserved as u32) as usize
+    }
+
+    pub fn set_salvage(&mut self, salvage: bool) {
+        self.salvage = salvage;
+    }
+
+    pub fn salvage(&self) -> bool {
+        self.salvage
+    }
+
+    pub fn warn(&self, warning: String) {
+        self.warnings.lock().unwrap().push(warning);
+    }
+
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.lock().unwrap())
+    }
+
+    pub fn count_cells(&self, cells: usize) {
+        self.counters
+            .cells_decoded
+            .fetch_add(cells as u64, Ordering::Relaxed);
+    }
+
+    pub fn count_index_seek(&self) {
+        self.counters.index_seeks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Pages are numbered from 1. The first page includes the 100 byte database header. A page
+    // number that isn't in the file, which only a corrupt pointer can lead to, is InvalidData.
+    pub fn read_page(&self, page_number: u32) -> io::Result<Cow<'_, [u8]>> {
+        if page_number == 0 || page_number > self.page_count() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {} is not in the file", page_number),
+            ));
+        }
+
+        let offset = (page_number as u64 - 1) * self.page_size as u64;
+        let len = self.page_size as usize;
+
+        self.counters.pages_read.fetch_add(1, Ordering::Relaxed);
+        trace::event!(Target::Pager, "read page {}", page_number);
+
+        // A page that has been written is read back as it was written, which is never encrypted.
+        let written = match self.dirty.lock().unwrap().get(&page_number) {
+            Some(page) => Some(page.clone()),
+            None => self.written.lock().unwrap().get(&page_number).cloned(),
+        };
+        if let Some(page) = written {
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Cow::Owned(page));
+        }
+
+        let page = self.fetch_page(page_number, offset, len)?;
+        match &self.cipher {
+            Some(cipher) => {
+                let mut page = page.into_owned();
+                cipher.decrypt_page(page_number, &mut page)?;
+                Ok(Cow::Owned(page))
+            }
+            None => Ok(page),
+        }
+    }
+
+    // The bytes of a page as they are stored.
+    fn fetch_page(&self, page_number: u32, offset: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        match &self.source {
+            Source::Http { cache, .. } => {
+                if let Some(page) = cache.lock().unwrap().get(&page_number) {
+                    self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Cow::Owned(page.clone()));
+                }
+
+                let mut buf = vec![0; len];
+                self.source.read_exact_at(&mut buf, offset)?;
+                self.counters
+                    .bytes_read
+                    .fetch_add(len as u64, Ordering::Relaxed);
+
+                let mut cache = cache.lock().unwrap();
+                if cache.len() < HTTP_CACHE_PAGES {
+                    cache.insert(page_number, buf.clone());
+                }
+                Ok(Cow::Owned(buf))
+            }
+            Source::Mmap { .. } | Source::Memory(_) if self.source.holds(offset, len) => {
+                self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+                let start = offset as usize;
+                Ok(Cow::Borrowed(&self.source.as_slice()[start..start + len]))
+            }
+            Source::File(_) | Source::Mmap { .. } | Source::Memory(_) => {
+                let mut buf = vec![0; len];
+                self.source.read_exact_at(&mut buf, offset)?;
+                self.counters
+                    .bytes_read
+                    .fetch_add(len as u64, Ordering::Relaxed);
+                Ok(Cow::Owned(buf))
+            }
+        }
+    }
+}
+
+// A write to the database, which holds the pager's writer until it's committed or dropped. Dropping
+// it without committing throws away every page it wrote.
+pub struct Transaction<'a> {
+    pager: &'a Pager,
+    _writer: MutexGuard<'a, ()>,
+    committed: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> io::Result<()> {
+        self.committed = true;
+        let result = self.pager.commit();
+        if result.is_err() {
+            self.pager.rollback();
+        }
+        self.pager.release();
+        result
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.pager.rollback();
+            self.pager.release();
+        }
+    }
+}
+
+// A read of the database, which holds a SHARED lock on its file until it's dropped.
+pub struct ReadLock<'a> {
+    pager: &'a Pager,
+}
+
+impl Drop for ReadLock<'_> {
+    fn drop(&mut self) {
+        self.pager.release();
+    }
+}
+
+// The lock the shared_lock option took, which nothing else lets go of, goes with the pager. The
+// file stays open while another pager in the process has it, so its lock would outlive this one.
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let mut lock = self.lock.lock().unwrap();
+        lower(self.file(), &mut lock, LockLevel::None);
+    }
+}
+
+// The locks sqlite3 takes on a database file, in the order it takes them. Each allows what the ones
+// before it do: SHARED to read, RESERVED to change pages in memory while others go on reading,
+// PENDING to keep new readers out while the ones there are finish, and EXCLUSIVE to write to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    None,
+    Shared,
+    Reserved,
+    Pending,
+    Exclusive,
+}
+
+// The lock a pager holds on its file, and how many reads and transactions in progress are holding
+// it.
+#[derive(Debug)]
+struct Lock {
+    level: LockLevel,
+    holders: usize,
+}
+
+// A database file, opened once however many pagers in the process open it. POSIX locks belong to
+// the process rather than to a handle on the file, so closing any handle on it lets go of every
+// lock the process holds, and two handles in one process never conflict with each other. So, like
+// sqlite3, every pager on the same file shares one handle, and keeps track of the lock on it with
+// the others: the file's lock is the strongest any of them holds, and SHARED is only let go of
+// once none of them holds it.
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    // Whether the file could be opened for writing.
+    writable: bool,
+    // The device and inode the file is on, where the platform has them.
+    id: Option<(u64, u64)>,
+    lock: Mutex<FileLock>,
+}
+
+// The lock the process holds on a file, and how many of its pagers hold it at SHARED or above.
+#[derive(Debug)]
+struct FileLock {
+    level: LockLevel,
+    readers: usize,
+}
+
+// The files the process's pagers have open, to be found again by another pager that opens one.
+static OPEN_FILES: Mutex<Vec<Weak<OpenFile>>> = Mutex::new(Vec::new());
+
+impl OpenFile {
+    // The file at a path, sharing the handle another pager already has on it. Like sqlite3, a
+    // file that can't be written is opened for reading instead, and writes to it fail. It is
+    // opened for writing even when a pager only wants to read it, so that another pager can
+    // write to it through the same handle.
+    fn open(path: &Path) -> io::Result<Arc<Self>> {
+        let mut files = OPEN_FILES.lock().unwrap();
+        files.retain(|file| file.strong_count() > 0);
+        if let Some(id) = std::fs::metadata(path).ok().as_ref().and_then(file_id) {
+            let open = files
+                .iter()
+                .filter_map(Weak::upgrade)
+                .find(|file| file.id == Some(id));
+            if let Some(file) = open {
+                return Ok(file);
+            }
+        }
+
+        let (file, writable) = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => (file, true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(err),
+            Err(_) => (File::open(path)?, false),
+        };
+        let file = Arc::new(Self {
+            id: file_id(&file.metadata()?),
+            file,
+            writable,
+            lock: Mutex::new(FileLock {
+                level: LockLevel::None,
+                readers: 0,
+            }),
+        });
+        files.push(Arc::downgrade(&file));
+        Ok(file)
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// A rollback journal is the journal header, padded to a sector, then a record for each page: its
+// number, what it held, and a checksum of it, which is seeded with the header's nonce so that
+// records left over from an older journal don't pass for this one's.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+const JOURNAL_HEADER_SIZE: usize = 512;
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut journal = path.as_os_str().to_owned();
+    journal.push("-journal");
+    PathBuf::from(journal)
+}
+
+// sqlite3 only adds up every 200th byte of the page, from the end, which is enough to notice a
+// record that was never completely written.
+fn checksum(nonce: u32, page: &[u8]) -> u32 {
+    (1..)
+        .map(|i| page.len() as isize - 200 * i)
+        .take_while(|&i| i > 0)
+        .fold(nonce, |sum, i| sum.wrapping_add(page[i as usize] as u32))
+}
+
+fn nonce() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or_default();
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+// Undoes a commit that never finished: writes back each page in the journal up to the first that
+// was never completely written, cuts the file back to its size before the commit, and deletes the
+// journal once the file is on the disk. A journal without a valid header, which sqlite3 leaves
+// behind in some of its journal modes, has nothing to undo.
+//
+// sqlite3 starts the journal again with a new header, at the next sector, each time it flushes it
+// part way through a transaction, so a journal may be made of several. The sector and page sizes,
+// and the page count to cut back to, are those of the first.
+fn play_back(file: &File, path: &Path) -> io::Result<()> {
+    let journal = std::fs::read(path)?;
+    let u32_at = |offset: usize| {
+        journal
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    // The count of records after a header, and the nonce their checksums start from.
+    let header = |offset: usize| match journal.get(offset..offset + 8) {
+        Some(magic) if magic == JOURNAL_MAGIC => Some((u32_at(offset + 8)?, u32_at(offset + 12)?)),
+        _ => None,
+    };
+    let (page_count, sector_size, page_size) = match (u32_at(16), u32_at(20), u32_at(24)) {
+        (Some(page_count), Some(sector_size), Some(page_size))
+            if header(0).is_some()
+                && sector_size.is_power_of_two()
+                && page_size.is_power_of_two()
+                && page_size >= 512 =>
+        {
+            (page_count, sector_size as usize, page_size as usize)
+        }
+        _ => return std::fs::remove_file(path),
+    };
+
+    let record_size = page_size + 8;
+    let mut offset = 0;
+    'headers: while let Some((records, nonce)) = header(offset) {
+        offset += sector_size;
+        let records = match records {
+            u32::MAX => (journal.len().saturating_sub(offset)) / record_size,
+            records => records as usize,
+        };
+        for _ in 0..records {
+            let Some(record) = journal.get(offset..offset + record_size) else {
+                break 'headers;
+            };
+            let page_number = u32::from_be_bytes(record[..4].try_into().unwrap());
+            let page = &record[4..4 + page_size];
+            let sum = u32::from_be_bytes(record[4 + page_size..].try_into().unwrap());
+            if page_number == 0 || sum != checksum(nonce, page) {
+                break 'headers;
+            }
+            if page_number <= page_count {
+                write_all_at(file, page, (page_number as u64 - 1) * page_size as u64)?;
+            }
+            offset += record_size;
+        }
+        offset = offset.next_multiple_of(sector_size);
+    }
+
+    file.set_len(page_count as u64 * page_size as u64)?;
+    file.sync_data()?;
+    std::fs::remove_file(path)
+}
+
+// Flushes the directory a file was deleted from, so that the file stays deleted after a power cut.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => File::open(directory)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Raises a pager's lock a level at a time, as sqlite3 does, so that another process sees the same
+// steps from this one as it would from sqlite3. Without a file to lock, only `lock` is raised.
+//
+// Another pager on the same file that holds more than SHARED keeps this one from going past
+// SHARED, and from reading at all once it is about to write, just as another process would. Only
+// the first pager to read the file takes the SHARED lock on it, and a pager can't take the
+// EXCLUSIVE lock while another one is reading.
+fn raise(file: Option<&OpenFile>, lock: &mut Lock, level: LockLevel) -> io::Result<()> {
+    let Some(file) = file else {
+        lock.level = lock.level.max(level);
+        return Ok(());
+    };
+    if lock.level >= level {
+        return Ok(());
+    }
+
+    let mut shared = file.lock.lock().unwrap();
+    let busy = || io::Error::new(io::ErrorKind::WouldBlock, "database is locked");
+    if lock.level != shared.level
+        && (shared.level >= LockLevel::Pending || level > LockLevel::Shared)
+    {
+        return Err(busy());
+    }
+    while lock.level < level {
+        let next = match lock.level {
+            LockLevel::None => LockLevel::Shared,
+            LockLevel::Shared => LockLevel::Reserved,
+            LockLevel::Reserved => LockLevel::Pending,
+            LockLevel::Pending | LockLevel::Exclusive => LockLevel::Exclusive,
+        };
+        if next == LockLevel::Exclusive && shared.readers > 1 {
+            return Err(busy());
+        }
+        if LOCKING && shared.level < next {
+            lock_file(&file.file, next)?;
+        }
+        if next == LockLevel::Shared {
+            shared.readers += 1;
+        }
+        shared.level = shared.level.max(next);
+        lock.level = next;
+    }
+    Ok(())
+}
+
+// Lowers a pager's lock to SHARED or to none at all. The file's lock only goes down with it when
+// this pager held more than SHARED, or was the last to hold any lock on it.
+fn lower(file: Option<&OpenFile>, lock: &mut Lock, level: LockLevel) {
+    if lock.level <= level {
+        return;
+    }
+    let Some(file) = file else {
+        lock.level = level;
+        return;
+    };
+
+    let mut shared = file.lock.lock().unwrap();
+    // A lock that can't be let go of is let go of when the file is closed.
+    if lock.level > LockLevel::Shared {
+        if LOCKING {
+            unlock_file(&file.file, shared.level, LockLevel::Shared).ok();
+        }
+        shared.level = LockLevel::Shared;
+    }
+    if level == LockLevel::None {
+        shared.readers -= 1;
+        if shared.readers == 0 {
+            if LOCKING {
+                unlock_file(&file.file, shared.level, LockLevel::None).ok();
+            }
+            shared.level = LockLevel::None;
+        }
+    }
+    lock.level = level;
+}
+
+// A journal is hot, and has to be rolled back, when the writer it belongs to is gone, which is
+// when nobody holds the RESERVED lock. Rolling it back writes to the file, so it takes the
+// EXCLUSIVE lock too, which fails while anyone else is reading it, before going back to the
+// SHARED lock the caller holds.
+fn roll_back_hot_journal(file: &OpenFile, journal: &Path, lock: &mut Lock) -> io::Result<()> {
+    if !std::fs::metadata(journal).is_ok_and(|journal| journal.len() > 0) {
+        return Ok(());
+    }
+    match raise(Some(file), lock, LockLevel::Reserved) {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+        result => result?,
+    }
+
+    trace::event!(Target::Pager, "roll back {}", journal.display());
+    let result =
+        raise(Some(file), lock, LockLevel::Exclusive).and_then(|()| play_back(&file.file, journal));
+    lower(Some(file), lock, LockLevel::Shared);
+    result
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut read = 0;
+    while read < buf.len() {
+        let n =
+            std::os::windows::fs::FileExt::seek_read(file, &mut buf[read..], offset + read as u64)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        read += n;
+    }
+    Ok(())
+}
+
+// Targets like wasm32-unknown-unknown have no files, only databases in memory.
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_file: &File, _buf: &mut [u8], _offset: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = std::os::windows::fs::FileExt::seek_write(file, buf, offset)?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        buf = &buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_all_at(_file: &File, _buf: &[u8], _offset: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+impl Source {
+    // The whole database, for the sources that hold it in memory.
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Source::File(_) | Source::Http { .. } => &[],
+            Source::Mmap { map, .. } => map.as_slice(),
+            Source::Memory(image) => image,
+        }
+    }
+
+    // Whether the `len` bytes at `offset` can be borrowed from memory rather than read. A file
+    // that has grown since it was mapped has pages past the end of its map.
+    fn holds(&self, offset: u64, len: usize) -> bool {
+        match self {
+            Source::File(_) | Source::Http { .. } => false,
+            Source::Mmap { .. } | Source::Memory(_) => {
+                offset + len as u64 <= self.as_slice().len() as u64
+            }
+        }
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self {
+            Source::File(file) => read_exact_at(&file.file, buf, offset),
+            Source::Mmap { file, .. } if !self.holds(offset, buf.len()) => {
+                read_exact_at(&file.file, buf, offset)
+            }
+            Source::Http { file, .. } => file.read_exact_at(buf, offset),
+            Source::Mmap { .. } | Source::Memory(_) => {
+                let start = offset as usize;
+                let bytes = self
+                    .as_slice()
+                    .get(start..start + buf.len())
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+}
+
+// sqlite3 locks a database with POSIX advisory locks on bytes far past the end of any real
+// database. A reader takes a read lock on the pending byte, which fails if a writer is waiting to
+// get an exclusive lock, then read locks the shared range and lets the pending byte go again. A
+// writer write locks the reserved byte, which only one can hold, then the pending byte, and can
+// only write lock the shared range, which is its exclusive lock, once nobody else holds any of it.
+//
+// POSIX locks belong to the process, so closing any other handle on the same file, from anywhere
+// in the process, releases them too. That is why every pager on a file shares an OpenFile.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const PENDING_BYTE: sys::off_t = 0x4000_0000;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const RESERVED_BYTE: sys::off_t = PENDING_BYTE + 1;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const SHARED_FIRST: sys::off_t = PENDING_BYTE + 2;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const SHARED_SIZE: sys::off_t = 510;
+
+const LOCKING: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+// Takes the lock on the file that goes with `level`, from the one below it. Fails with WouldBlock
+// when another process holds a lock that conflicts with it.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn lock_file(file: &File, level: LockLevel) -> io::Result<()> {
+    match level {
+        LockLevel::None => Ok(()),
+        LockLevel::Shared => {
+            set_lock(file, sys::F_RDLCK, PENDING_BYTE, 1)?;
+            let shared = set_lock(file, sys::F_RDLCK, SHARED_FIRST, SHARED_SIZE);
+            set_lock(file, sys::F_UNLCK, PENDING_BYTE, 1)?;
+            shared
+        }
+        LockLevel::Reserved => set_lock(file, sys::F_WRLCK, RESERVED_BYTE, 1),
+        LockLevel::Pending => set_lock(file, sys::F_WRLCK, PENDING_BYTE, 1),
+        LockLevel::Exclusive => set_lock(file, sys::F_WRLCK, SHARED_FIRST, SHARED_SIZE),
+    }
+}
+
+// Lowers the lock on the file from one level to SHARED or to none at all.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn unlock_file(file: &File, from: LockLevel, to: LockLevel) -> io::Result<()> {
+    match to {
+        LockLevel::None => set_lock(
+            file,
+            sys::F_UNLCK,
+            PENDING_BYTE,
+            SHARED_FIRST + SHARED_SIZE - PENDING_BYTE,
+        ),
+        _ => {
+            if from == LockLevel::Exclusive {
+                set_lock(file, sys::F_RDLCK, SHARED_FIRST, SHARED_SIZE)?;
+            }
+            set_lock(file, sys::F_UNLCK, PENDING_BYTE, 2)
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set_lock(
+    file: &File,
+    lock_type: std::os::raw::c_short,
+    start: sys::off_t,
+    len: sys::off_t,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let lock = sys::Flock::new(lock_type, start, len);
+    match unsafe { sys::fcntl(file.as_raw_fd(), sys::F_SETLK, &lock) } {
+        -1 => match io::Error::last_os_error() {
+            // Which of these a conflicting lock gives depends on the platform.
+            err if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::PermissionDenied
+            ) =>
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "database is locked",
+                ))
+            }
+            err => Err(err),
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn lock_file(_file: &File, _level: LockLevel) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "locking is only supported on linux and macos",
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn unlock_file(_file: &File, _from: LockLevel, _to: LockLevel) -> io::Result<()> {
+    Ok(())
+}
+
+// A read-only, shared mapping of a whole file, which is the file's own pages in the page cache,
+// so it sees what is written to the file as soon as it is written, by any process.
+#[derive(Debug)]
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The mapping is read-only and never remapped, so it can be shared between threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    #[cfg(unix)]
+    fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot map an empty file",
+            ));
+        }
+
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn map(_file: &File) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "mmap is only supported on unix platforms",
+        ))
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            sys::munmap(self.ptr as *mut _, self.len);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    // off_t, which on Linux is as wide as a long, so 32 bits on a 32 bit target, and is 64 bits
+    // everywhere else.
+    #[cfg(target_os = "linux")]
+    #[allow(non_camel_case_types)]
+    pub type off_t = std::os::raw::c_long;
+    #[cfg(not(target_os = "linux"))]
+    #[allow(non_camel_case_types)]
+    pub type off_t = i64;
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_SHARED: c_int = 1;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: off_t,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub use linux::*;
+    #[cfg(target_os = "macos")]
+    pub use macos::*;
+
+    #[cfg(target_os = "linux")]
+    mod linux {
+        use super::off_t;
+        use std::os::raw::{c_int, c_short};
+
+        pub const F_SETLK: c_int = 6;
+        pub const F_RDLCK: c_short = 0;
+        pub const F_WRLCK: c_short = 1;
+        pub const F_UNLCK: c_short = 2;
+
+        #[repr(C)]
+        pub struct Flock {
+            l_type: c_short,
+            l_whence: c_short,
+            l_start: off_t,
+            l_len: off_t,
+            l_pid: c_int,
+        }
+
+        impl Flock {
+            pub fn new(l_type: c_short, l_start: off_t, l_len: off_t) -> Flock {
+                Flock {
+                    l_type,
+                    l_whence: 0,
+                    l_start,
+                    l_len,
+                    l_pid: 0,
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    mod macos {
+        use super::off_t;
+        use std::os::raw::{c_int, c_short};
+
+        pub const F_SETLK: c_int = 8;
+        pub const F_RDLCK: c_short = 1;
+        pub const F_WRLCK: c_short = 3;
+        pub const F_UNLCK: c_short = 2;
+
+        #[repr(C)]
+        pub struct Flock {
+            l_start: off_t,
+            l_len: off_t,
+            l_pid: c_int,
+            l_type: c_short,
+            l_whence: c_short,
+        }
+
+        impl Flock {
+            pub fn new(l_type: c_short, l_start: off_t, l_len: off_t) -> Flock {
+                Flock {
+                    l_start,
+                    l_len,
+                    l_pid: 0,
+                    l_type,
+                    l_whence: 0,
+                }
+            }
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // A copy of a database in the temporary directory, deleted along with its journal when it's
+    // dropped.
+    #[allow(dead_code)]
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        #[allow(dead_code)]
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let name = format!("{}-{}.db", name, std::process::id());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+            std::fs::remove_file(journal_path(&self.0)).ok();
+        }
+    }
+
+    // Whether the process holds a POSIX lock on the file, as /proc/locks lists them.
+    #[cfg(target_os = "linux")]
+    #[allow(dead_code)]
+    fn locked(path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        let inode = std::fs::metadata(path).unwrap().ino().to_string();
+        let pid = std::process::id().to_string();
+        std::fs::read_to_string("/proc/locks")
+            .unwrap()
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .any(|fields| {
+                fields.get(1) == Some(&"POSIX")
+                    && fields.get(4) == Some(&pid.as_str())
+                    && fields
+                        .get(5)
+                        .and_then(|id| id.rsplit(':').next())
+                        .is_some_and(|id| id == inode)
+            })
+    }
+
+    // Opening a database that is already open in the process, and closing it again, leaves the
+    // locks the first opening holds where they were.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn opening_again_keeps_locks() {
+        let file = TempFile::new("keeps-locks", include_bytes!("../tests/fixtures/people.db"));
+        let db = crate::Db::new(file.0.clone(), PagerOptions::default()).unwrap();
+        let read = db.pager.read_lock().unwrap();
+        assert!(locked(&file.0));
+
+        drop(crate::Db::new(file.0.clone(), PagerOptions::default()).unwrap());
+        assert!(locked(&file.0));
+
+        drop(read);
+        assert!(!locked(&file.0));
+    }
+
+    // Pagers on the same file share its lock, so one can't write to it while another reads it.
+    #[test]
+    fn pagers_share_the_lock() {
+        let file = TempFile::new("shares-lock", include_bytes!("../tests/fixtures/people.db"));
+        let reader = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        let writer = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        let write = |pager: &Pager| {
+            let page = pager.read_page(2).unwrap().into_owned();
+            let transaction = pager.begin()?;
+            pager.write_page(2, page)?;
+            transaction.commit()
+        };
+
+        let read = reader.read_lock().unwrap();
+        let err = write(&writer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        // The writer let go of its lock along with the transaction, so others can still read.
+        drop(reader.read_lock().unwrap());
+
+        drop(read);
+        write(&writer).unwrap();
+    }
+
+    // A commit sqlite3 was killed part way through, having spilled changed pages into the file and
+    // grown it from 8 pages to 104, is undone when the database is opened, from the journal it
+    // left behind.
+    #[test]
+    fn rolls_back_crashed_commit() {
+        let file = TempFile::new("crashed", include_bytes!("../tests/fixtures/crash.db"));
+        let journal = journal_path(&file.0);
+        std::fs::write(
+            &journal,
+            include_bytes!("../tests/fixtures/crash.db-journal"),
+        )
+        .unwrap();
+
+        let mut connection = crate::Connection::open(&file.0).unwrap();
+        assert!(!journal.exists());
+        assert_eq!(std::fs::metadata(&file.0).unwrap().len(), 8 * 512);
+        let rows = connection
+            .query("SELECT count(*), sum(length(s)) FROM t")
+            .unwrap();
+        assert_eq!(
+            rows.rows[0].values(),
+            [crate::Value::Int(200), crate::Value::Int(1400)]
+        );
+        let rows = connection.query("PRAGMA integrity_check").unwrap();
+        assert_eq!(rows.rows[0].get::<String>(0).unwrap(), "ok");
+    }
+
+    // A journal is only hot while nobody is writing, so it is left alone while a transaction
+    // holds the RESERVED lock, and rolled back by the next read once that is let go of.
+    #[test]
+    fn detects_hot_journal() {
+        let file = TempFile::new("hot-journal", include_bytes!("../tests/fixtures/crash.db"));
+        let journal = journal_path(&file.0);
+        let writer = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        let transaction = writer.begin().unwrap();
+        std::fs::write(
+            &journal,
+            include_bytes!("../tests/fixtures/crash.db-journal"),
+        )
+        .unwrap();
+
+        let reader = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        assert!(journal.exists());
+        assert_eq!(reader.page_count(), 104);
+
+        drop(transaction);
+        drop(reader.read_lock().unwrap());
+        assert!(!journal.exists());
+        assert_eq!(reader.page_count(), 8);
+        assert_eq!(std::fs::metadata(&file.0).unwrap().len(), 8 * 512);
+    }
+
+    // A memory map sees what another pager writes to the file, pages the file has grown by since
+    // it was mapped are read from the file, and nothing written is kept aside to go stale.
+    #[test]
+    fn mapped_file_sees_writes() {
+        let file = TempFile::new(
+            "mapped-writes",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        let options = PagerOptions {
+            mmap: true,
+            ..PagerOptions::default()
+        };
+        let mapped = Pager::open(&file.0, options).unwrap();
+        let writer = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        let pages = mapped.page_count();
+
+        let size = mapped.page_size as usize;
+        let transaction = writer.begin().unwrap();
+        writer.write_page(2, vec![2; size]).unwrap();
+        writer.write_page(pages + 1, vec![3; size]).unwrap();
+        transaction.commit().unwrap();
+
+        let read = mapped.read_lock().unwrap();
+        assert_eq!(mapped.page_count(), pages + 1);
+        assert_eq!(&*mapped.read_page(2).unwrap(), &vec![2; size][..]);
+        assert_eq!(&*mapped.read_page(pages + 1).unwrap(), &vec![3; size][..]);
+        drop(read);
+
+        let transaction = mapped.begin().unwrap();
+        mapped.write_page(2, vec![4; size]).unwrap();
+        transaction.commit().unwrap();
+        assert!(mapped.written.lock().unwrap().is_empty());
+        assert_eq!(&*writer.read_page(2).unwrap(), &vec![4; size][..]);
+        assert_eq!(&*mapped.read_page(2).unwrap(), &vec![4; size][..]);
+    }
+
+    // A memory map is checked for a hot journal the same way as a file is read.
+    #[test]
+    fn mapped_file_detects_hot_journal() {
+        let file = TempFile::new(
+            "mapped-journal",
+            include_bytes!("../tests/fixtures/crash.db"),
+        );
+        let journal = journal_path(&file.0);
+        let writer = Pager::open(&file.0, PagerOptions::default()).unwrap();
+        let transaction = writer.begin().unwrap();
+        std::fs::write(
+            &journal,
+            include_bytes!("../tests/fixtures/crash.db-journal"),
+        )
+        .unwrap();
+
+        let options = PagerOptions {
+            mmap: true,
+            ..PagerOptions::default()
+        };
+        let reader = Pager::open(&file.0, options).unwrap();
+        assert_eq!(reader.page_count(), 104);
+
+        drop(transaction);
+        drop(reader.read_lock().unwrap());
+        assert!(!journal.exists());
+        assert_eq!(reader.page_count(), 8);
+    }
+
+    // A page of a file costs one read of the whole page, and everything on it is parsed from
+    // that, rather than from many small reads of the file.
+    #[test]
+    fn reads_whole_pages() {
+        let file = TempFile::new("whole-pages", include_bytes!("../tests/fixtures/many.db"));
+        let db = crate::Db::new(file.0.clone(), PagerOptions::default()).unwrap();
+        let before = db.pager.stats();
+        let mut rows = crate::Rows::default();
+        crate::sql_engine::SqlEngine::new(&mut rows)
+            .execute("SELECT count(s), sum(length(s)) FROM m", &db)
+            .unwrap();
+        assert_eq!(
+            rows.rows[0].values(),
+            [crate::Value::Int(1000), crate::Value::Int(8000)]
+        );
+
+        // 36 leaves and the root.
+        let stats = db.pager.stats().since(&before);
+        assert_eq!(stats.pages_read, 37);
+        assert_eq!(stats.bytes_read, 37 * 512);
+        assert_eq!(stats.cache_hits, 0);
+    }
+}
@@ -0,0 +1,599 @@
+use std::io::{self, Write};
+
+use crate::batch::ArrowType;
+use crate::catalog::Affinity;
+use crate::Value;
+
+// Writes rows out as an Apache Parquet file, for loading an extract of a database into tools that
+// read columnar data. The file is as simple as Parquet allows: one row group, holding one
+// uncompressed data page per column, with every value written plainly.
+//
+// Each column gets the type that fits every value in it, the same way as in a RecordBatch: INT64
+// for integers, DOUBLE for numbers, a UTF8 string for text, and otherwise bytes. A column with
+// nothing but NULLs in it takes its type from the affinity of the column it came from, if known.
+// NULLs are nulls in every column.
+//
+// The file's metadata is encoded with Thrift's compact protocol, which `Thrift` writes just enough
+// of for the structures here.
+pub(crate) fn write(
+    out: &mut impl Write,
+    columns: &[String],
+    affinities: &[Option<Affinity>],
+    rows: &[&[Value]],
+) -> io::Result<()> {
+    let mut file = Vec::from(MAGIC);
+    let mut chunks = Vec::new();
+
+    for (index, name) in columns.iter().enumerate() {
+        let values = rows.iter().map(|row| &row[index]).collect::<Vec<_>>();
+        let data_type = ArrowType::infer(&values, affinities.get(index).copied().flatten());
+
+        let data = encode_page(&values, data_type);
+        let mut header = Thrift::new();
+        header.i32(1, PAGE_TYPE_DATA);
+        header.i32(2, data.len() as i32);
+        header.i32(3, data.len() as i32);
+        header.begin_struct(5);
+        header.i32(1, values.len() as i32);
+        header.i32(2, ENCODING_PLAIN);
+        header.i32(3, ENCODING_RLE);
+        header.i32(4, ENCODING_RLE);
+        header.end_struct();
+        header.stop();
+
+        let offset = file.len() as i64;
+        let size = (header.bytes.len() + data.len()) as i64;
+        file.extend_from_slice(&header.bytes);
+        file.extend_from_slice(&data);
+
+        chunks.push(Chunk {
+            name: name.clone(),
+            data_type,
+            values: values.len() as i64,
+            offset,
+            size,
+        });
+    }
+
+    let metadata = file_metadata(&chunks, rows.len() as i64);
+    file.extend_from_slice(&metadata);
+    file.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+
+    out.write_all(&file)?;
+    out.flush()
+}
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Values of the enums in Parquet's Thrift definitions.
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REPETITION_REQUIRED: i32 = 0;
+const REPETITION_OPTIONAL: i32 = 1;
+const CONVERTED_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_TYPE_DATA: i32 = 0;
+
+// The physical type that stores values of each type of column.
+fn physical_type(data_type: ArrowType) -> i32 {
+    match data_type {
+        ArrowType::Int64 => TYPE_INT64,
+        ArrowType::Float64 => TYPE_DOUBLE,
+        ArrowType::Utf8 | ArrowType::Binary => TYPE_BYTE_ARRAY,
+    }
+}
+
+// A data page: the definition levels, saying which values aren't NULL, then those values. Every
+// column is optional and flat, so the levels are one bit each and there are no repetition levels.
+fn encode_page(values: &[&Value], data_type: ArrowType) -> Vec<u8> {
+    // The levels are one bit-packed run, in groups of eight, padded out with zeros at the end.
+    let groups = values.len().div_ceil(8);
+    let mut levels = Vec::new();
+    push_varint(&mut levels, ((groups as u64) << 1) | 1);
+    let mut bits = vec![0u8; groups];
+    for (index, value) in values.iter().enumerate() {
+        if !matches!(value, Value::Null) {
+            bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+    levels.extend_from_slice(&bits);
+
+    let mut page = Vec::new();
+    page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+    page.extend_from_slice(&levels);
+
+    for value in values {
+        match (data_type, value) {
+            (_, Value::Null) => {}
+            (ArrowType::Int64, Value::Int(n)) => page.extend_from_slice(&n.to_le_bytes()),
+            (ArrowType::Float64, Value::Int(n)) => {
+                page.extend_from_slice(&(*n as f64).to_le_bytes())
+            }
+            (ArrowType::Float64, Value::Real(r)) => page.extend_from_slice(&r.to_le_bytes()),
+            (_, Value::Blob(bytes)) => push_byte_array(&mut page, bytes),
+            (_, value) => push_byte_array(&mut page, value.to_string().as_bytes()),
+        }
+    }
+    page
+}
+
+fn push_byte_array(page: &mut Vec<u8>, bytes: &[u8]) {
+    page.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    page.extend_from_slice(bytes);
+}
+
+struct Chunk {
+    name: String,
+    data_type: ArrowType,
+    values: i64,
+    offset: i64,
+    size: i64,
+}
+
+fn file_metadata(chunks: &[Chunk], rows: i64) -> Vec<u8> {
+    let mut thrift = Thrift::new();
+    thrift.i32(1, 1);
+
+    // The schema is a tree, flattened depth first, whose root holds the columns.
+    thrift.begin_list(2, THRIFT_STRUCT, chunks.len() + 1);
+    thrift.begin_element();
+    thrift.i32(3, REPETITION_REQUIRED);
+    thrift.binary(4, b"schema");
+    thrift.i32(5, chunks.len() as i32);
+    thrift.end_element();
+    for chunk in chunks {
+        thrift.begin_element();
+        thrift.i32(1, physical_type(chunk.data_type));
+        thrift.i32(3, REPETITION_OPTIONAL);
+        thrift.binary(4, chunk.name.as_bytes());
+        if chunk.data_type == ArrowType::Utf8 {
+            thrift.i32(6, CONVERTED_UTF8);
+            // The logical type union, set to its empty STRING member.
+            thrift.begin_struct(10);
+            thrift.begin_struct(1);
+            thrift.end_struct();
+            thrift.end_struct();
+        }
+        thrift.end_element();
+    }
+
+    thrift.i64(3, rows);
+
+    let total = chunks.iter().map(|chunk| chunk.size).sum::<i64>();
+    thrift.begin_list(4, THRIFT_STRUCT, 1);
+    thrift.begin_element();
+    thrift.begin_list(1, THRIFT_STRUCT, chunks.len());
+    for chunk in chunks {
+        thrift.begin_element();
+        thrift.i64(2, chunk.offset);
+        thrift.begin_struct(3);
+        thrift.i32(1, physical_type(chunk.data_type));
+        thrift.begin_list(2, THRIFT_I32, 2);
+        thrift.list_i32(ENCODING_PLAIN);
+        thrift.list_i32(ENCODING_RLE);
+        thrift.begin_list(3, THRIFT_BINARY, 1);
+        thrift.list_binary(chunk.name.as_bytes());
+        thrift.i32(4, CODEC_UNCOMPRESSED);
+        thrift.i64(5, chunk.values);
+        thrift.i64(6, chunk.size);
+        thrift.i64(7, chunk.size);
+        thrift.i64(9, chunk.offset);
+        thrift.end_struct();
+        thrift.end_element();
+    }
+    thrift.i64(2, total);
+    thrift.i64(3, rows);
+    thrift.end_element();
+
+    thrift.binary(6, b"codecrafters-sqlite-rust");
+    thrift.stop();
+    thrift.bytes
+}
+
+const THRIFT_I32: u8 = 5;
+const THRIFT_I64: u8 = 6;
+const THRIFT_BINARY: u8 = 8;
+const THRIFT_LIST: u8 = 9;
+const THRIFT_STRUCT: u8 = 12;
+
+// A writer for Thrift's compact protocol. A field starts with its type and how far its id is
+// from the one before it in the same struct, so the last id is kept for each struct being
+// written. Lists only ever hold structs, i32s or strings here.
+struct Thrift {
+    bytes: Vec<u8>,
+    last_ids: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            last_ids: vec![0],
+        }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last_ids.last_mut().unwrap();
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.bytes.push(((delta as u8) << 4) | kind);
+        } else {
+            self.bytes.push(kind);
+            push_varint(&mut self.bytes, zigzag(id as i64));
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, THRIFT_I32);
+        push_varint(&mut self.bytes, zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, THRIFT_I64);
+        push_varint(&mut self.bytes, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, THRIFT_BINARY);
+        self.list_binary(value);
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, THRIFT_STRUCT);
+        self.last_ids.push(0);
+    }
+
+    fn end_struct(&mut self) {
+        self.stop();
+        self.last_ids.pop();
+    }
+
+    fn begin_list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, THRIFT_LIST);
+        if len < 15 {
+            self.bytes.push(((len as u8) << 4) | kind);
+        } else {
+            self.bytes.push(0xf0 | kind);
+            push_varint(&mut self.bytes, len as u64);
+        }
+    }
+
+    // A struct in a list, which has no field header of its own.
+    fn begin_element(&mut self) {
+        self.last_ids.push(0);
+    }
+
+    fn end_element(&mut self) {
+        self.end_struct();
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        push_varint(&mut self.bytes, zigzag(value as i64));
+    }
+
+    fn list_binary(&mut self, value: &[u8]) {
+        push_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn stop(&mut self) {
+        self.bytes.push(0);
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+// Thrift's and Parquet's varints are little-endian base 128, unlike sqlite's.
+fn push_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // A value read back with Thrift's compact protocol, without knowing the structures it makes.
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, PartialEq)]
+    enum Field {
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Field>),
+        Struct(Vec<(i16, Field)>),
+    }
+
+    impl Field {
+        #[allow(dead_code)]
+        fn get(&self, id: i16) -> Option<&Field> {
+            let Field::Struct(fields) = self else {
+                panic!("{:?} isn't a struct", self);
+            };
+            fields
+                .iter()
+                .find(|(field, _)| *field == id)
+                .map(|(_, value)| value)
+        }
+
+        #[allow(dead_code)]
+        fn int(&self, id: i16) -> i64 {
+            match self.get(id) {
+                Some(Field::Int(n)) => *n,
+                value => panic!("field {} is {:?}", id, value),
+            }
+        }
+
+        #[allow(dead_code)]
+        fn list(&self, id: i16) -> &[Field] {
+            match self.get(id) {
+                Some(Field::List(values)) => values,
+                value => panic!("field {} is {:?}", id, value),
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        #[allow(dead_code)]
+        fn byte(&mut self) -> u8 {
+            self.position += 1;
+            self.bytes[self.position - 1]
+        }
+
+        #[allow(dead_code)]
+        fn take(&mut self, len: usize) -> &[u8] {
+            self.position += len;
+            &self.bytes[self.position - len..self.position]
+        }
+
+        #[allow(dead_code)]
+        fn varint(&mut self) -> u64 {
+            let mut n = 0;
+            for shift in (0..).step_by(7) {
+                let byte = self.byte();
+                n |= ((byte & 0x7f) as u64) << shift;
+                if byte < 0x80 {
+                    break;
+                }
+            }
+            n
+        }
+
+        #[allow(dead_code)]
+        fn zigzag(&mut self) -> i64 {
+            let n = self.varint();
+            (n >> 1) as i64 ^ -((n & 1) as i64)
+        }
+
+        #[allow(dead_code)]
+        fn value(&mut self, kind: u8) -> Field {
+            match kind {
+                THRIFT_I32 | THRIFT_I64 => Field::Int(self.zigzag()),
+                THRIFT_BINARY => {
+                    let len = self.varint() as usize;
+                    Field::Binary(self.take(len).to_vec())
+                }
+                THRIFT_LIST => {
+                    let header = self.byte();
+                    let len = match header >> 4 {
+                        15 => self.varint() as usize,
+                        len => len as usize,
+                    };
+                    Field::List((0..len).map(|_| self.value(header & 0xf)).collect())
+                }
+                THRIFT_STRUCT => {
+                    let mut fields = Vec::new();
+                    let mut id = 0;
+                    loop {
+                        let header = self.byte();
+                        if header == 0 {
+                            break Field::Struct(fields);
+                        }
+                        id = match header >> 4 {
+                            0 => self.zigzag() as i16,
+                            delta => id + delta as i16,
+                        };
+                        fields.push((id, self.value(header & 0xf)));
+                    }
+                }
+                kind => panic!("unexpected Thrift type {}", kind),
+            }
+        }
+    }
+
+    // Reads back the columns of a file `write` wrote, by the offsets its footer gives, as the
+    // values that went in. Text is told from bytes by the column's converted type.
+    #[allow(dead_code)]
+    fn read(file: &[u8]) -> (Vec<(String, i32)>, Vec<Vec<Value>>) {
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+        let len = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let start = file.len() - 8 - len as usize;
+        let mut reader = Reader {
+            bytes: &file[..file.len() - 8],
+            position: start,
+        };
+        let metadata = reader.value(THRIFT_STRUCT);
+        assert_eq!(reader.position, file.len() - 8);
+        let rows = metadata.int(3) as usize;
+
+        let schema = &metadata.list(2)[1..];
+        let row_groups = metadata.list(4);
+        assert_eq!(row_groups.len(), 1);
+        assert_eq!(row_groups[0].int(3) as usize, rows);
+        let chunks = row_groups[0].list(1);
+        assert_eq!(chunks.len(), schema.len());
+
+        let mut names = Vec::new();
+        let mut columns = Vec::new();
+        for (element, chunk) in schema.iter().zip(chunks) {
+            let Some(Field::Binary(name)) = element.get(4) else {
+                panic!("{:?} has no name", element);
+            };
+            names.push((
+                String::from_utf8(name.clone()).unwrap(),
+                element.int(1) as i32,
+            ));
+            let text = element.get(6) == Some(&Field::Int(CONVERTED_UTF8 as i64));
+
+            let column = chunk.get(3).unwrap();
+            assert_eq!(column.int(1), element.int(1));
+            assert_eq!(column.int(5) as usize, rows);
+            let mut reader = Reader {
+                bytes: file,
+                position: column.int(9) as usize,
+            };
+            let header = reader.value(THRIFT_STRUCT);
+            assert_eq!(header.int(1), PAGE_TYPE_DATA as i64);
+            assert_eq!(header.get(5).unwrap().int(1) as usize, rows);
+            let page_start = reader.position;
+
+            let levels_len = u32::from_le_bytes(reader.take(4).try_into().unwrap()) as usize;
+            let levels_end = reader.position + levels_len;
+            let groups = reader.varint();
+            assert_eq!(groups & 1, 1, "levels aren't bit packed");
+            let bits = reader.take((groups >> 1) as usize).to_vec();
+            assert_eq!(reader.position, levels_end);
+
+            let values = (0..rows)
+                .map(|row| {
+                    if bits[row / 8] & (1 << (row % 8)) == 0 {
+                        return Value::Null;
+                    }
+                    match element.int(1) as i32 {
+                        TYPE_INT64 => {
+                            Value::Int(i64::from_le_bytes(reader.take(8).try_into().unwrap()))
+                        }
+                        TYPE_DOUBLE => {
+                            Value::Real(f64::from_le_bytes(reader.take(8).try_into().unwrap()))
+                        }
+                        TYPE_BYTE_ARRAY => {
+                            let len =
+                                u32::from_le_bytes(reader.take(4).try_into().unwrap()) as usize;
+                            let bytes = reader.take(len).to_vec();
+                            match text {
+                                true => Value::Text(String::from_utf8(bytes).unwrap()),
+                                false => Value::Blob(bytes),
+                            }
+                        }
+                        kind => panic!("unexpected physical type {}", kind),
+                    }
+                })
+                .collect();
+            assert_eq!(reader.position - page_start, header.int(3) as usize);
+            assert_eq!(
+                column.int(6) as usize,
+                reader.position - column.int(9) as usize
+            );
+            columns.push(values);
+        }
+        (names, columns)
+    }
+
+    // Each kind of field, including one whose id is too far from the last for a delta, a nested
+    // struct, and lists short and long, as the compact protocol lays them out.
+    #[test]
+    fn thrift_bytes() {
+        let mut thrift = Thrift::new();
+        thrift.i32(1, 1);
+        thrift.i32(3, -1);
+        thrift.binary(4, b"ab");
+        thrift.i64(20, 300);
+        thrift.begin_struct(21);
+        thrift.i32(1, 7);
+        thrift.end_struct();
+        thrift.begin_list(22, THRIFT_I32, 2);
+        thrift.list_i32(0);
+        thrift.list_i32(3);
+        thrift.begin_list(23, THRIFT_STRUCT, 1);
+        thrift.begin_element();
+        thrift.i32(2, 5);
+        thrift.end_element();
+        thrift.begin_list(24, THRIFT_BINARY, 15);
+        for _ in 0..15 {
+            thrift.list_binary(b"");
+        }
+        thrift.stop();
+
+        #[rustfmt::skip]
+        let expected = [
+            0x15, 0x02,
+            0x25, 0x01,
+            0x18, 0x02, b'a', b'b',
+            0x06, 0x28, 0xd8, 0x04,
+            0x1c, 0x15, 0x0e, 0x00,
+            0x19, 0x25, 0x00, 0x06,
+            0x19, 0x1c, 0x25, 0x0a, 0x00,
+            0x19, 0xf8, 0x0f, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x00,
+        ];
+        assert_eq!(thrift.bytes, expected);
+    }
+
+    // Rows written out read back the same, with each column in the type that holds all of its
+    // values, NULLs where they were, and an integer in a column of reals widened to one.
+    #[test]
+    fn round_trip() {
+        let columns = ["id", "score", "name", "data", "nothing"].map(String::from);
+        let rows = [
+            [
+                Value::Int(1),
+                Value::Real(1.5),
+                Value::Text("one".to_string()),
+                Value::Blob(vec![0, 255]),
+                Value::Null,
+            ],
+            [
+                Value::Int(-2),
+                Value::Int(3),
+                Value::Null,
+                Value::Blob(Vec::new()),
+                Value::Null,
+            ],
+            [
+                Value::Null,
+                Value::Real(-0.25),
+                Value::Text("thrée".to_string()),
+                Value::Null,
+                Value::Null,
+            ],
+        ];
+        let rows = rows.iter().map(|row| &row[..]).collect::<Vec<_>>();
+        let affinities = [None, None, None, None, Some(Affinity::Integer)];
+        let mut file = Vec::new();
+        write(&mut file, &columns, &affinities, &rows).unwrap();
+
+        let (schema, values) = read(&file);
+        let types = [
+            TYPE_INT64,
+            TYPE_DOUBLE,
+            TYPE_BYTE_ARRAY,
+            TYPE_BYTE_ARRAY,
+            TYPE_INT64,
+        ];
+        assert_eq!(
+            schema,
+            columns.iter().cloned().zip(types).collect::<Vec<_>>()
+        );
+        let mut expected = (0..columns.len())
+            .map(|column| rows.iter().map(|row| row[column].clone()).collect())
+            .collect::<Vec<Vec<_>>>();
+        expected[1][1] = Value::Real(3.0);
+        assert_eq!(values, expected);
+    }
+}
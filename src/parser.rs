@@ -1,25 +1,58 @@
+use std::ops::Range;
+
+use crate::error::{Error, Result};
 use crate::lexer::Token;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Ast {
     All,
     StmtList(Vec<Ast>),
     Stmt(Box<Ast>),
     Select {
         result_columns: Vec<Ast>,
-        from: Box<Ast>,
+        from: Option<Box<Ast>>,
         r#where: Option<Box<Ast>>,
+        group_by: Vec<Ast>,
+        order_by: Vec<Ast>,
+        limit: Option<Box<Ast>>,
+        offset: Option<Box<Ast>>,
+    },
+    // A term of ORDER BY: an expression, or the number of a result column, and which way it sorts.
+    OrderingTerm {
+        expr: Box<Ast>,
+        descending: bool,
+    },
+    // An expression in the list of a select, with the name that labels its column: the name given
+    // to it with AS, or else the expression as it was written.
+    ResultColumn {
+        expr: Box<Ast>,
+        name: String,
     },
     TableOrSubQuery(Box<Ast>),
     Table(String),
+    // A table called with arguments, as in `FROM pragma_table_info('t')`.
+    TableFunction {
+        name: String,
+        args: Vec<Ast>,
+    },
     Expr(Box<Ast>),
+    // A call to a function, whose arguments can be any expressions, including other calls. An
+    // aggregate called with DISTINCT only sees each of its values once.
     Function {
         name: String,
         args: Vec<Ast>,
+        distinct: bool,
     },
     CreateTable {
         name: String,
         column_defs: Vec<Ast>,
+        without_rowid: bool,
+    },
+    // A table implemented by a module, with the text of each of the module's arguments.
+    CreateVirtualTable {
+        name: String,
+        module: String,
+        args: Vec<String>,
     },
     ColumnDef {
         name: String,
@@ -28,34 +61,154 @@ pub enum Ast {
     },
     Identifier(String),
     StringLiteral(String),
+    IntegerLiteral(i64),
+    RealLiteral(f64),
+    BlobLiteral(Vec<u8>),
+    NullLiteral,
+    // A value bound when the statement runs. Parameters are numbered from 1, and the text is how
+    // it was written, which is also its name if it has one.
+    Parameter {
+        index: usize,
+        text: String,
+    },
     BinaryOp {
         op: Op,
         lhs: Box<Ast>,
         rhs: Box<Ast>,
     },
+    // The operand with its sign changed.
+    Negate(Box<Ast>),
+    // An index on some columns of a table, which only has the rows the WHERE is true of, if it has
+    // one.
     CreateIndex {
         name: String,
         table_name: String,
         columns: Vec<Ast>,
+        unique: bool,
+        r#where: Option<Box<Ast>>,
+    },
+    // A column of an index, with the collation it is sorted by when it names one, and which way.
+    IndexedColumn {
+        name: String,
+        collation: Option<String>,
+        descending: bool,
+    },
+    // A primary key declared for the whole table, after its columns, like `PRIMARY KEY (a, b)`,
+    // with each of its columns as an indexed column.
+    PrimaryKey(Vec<Ast>),
+    // A CHECK constraint on the whole table, like `CHECK (a < b)`.
+    Check,
+    // A foreign key declared for the whole table, like `FOREIGN KEY (a, b) REFERENCES p (x, y)`.
+    ForeignKey {
+        columns: Vec<String>,
+        table: String,
+        table_columns: Vec<String>,
+    },
+    InList {
+        lhs: Box<Ast>,
+        list: Vec<Ast>,
+    },
+    // `INSERT INTO table (columns) VALUES (...), (...)`, where the columns are empty when they
+    // aren't named, which means all of them in order.
+    Insert {
+        table: String,
+        columns: Vec<String>,
+        rows: Vec<Vec<Ast>>,
+    },
+    // `DELETE FROM table WHERE expr`, which deletes every row when there is no WHERE.
+    Delete {
+        table: String,
+        r#where: Option<Box<Ast>>,
     },
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Op {
     Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Match,
+    Like,
+    And,
+    Or,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Constraint {
-    PrimaryKey,
+    // Which way the key sorts matters to a WITHOUT ROWID table, and also means that an INTEGER
+    // column declared with it isn't the rowid.
+    PrimaryKey { descending: bool },
     AutoIncrement,
     NotNull,
+    // A foreign key on the column, to the given columns of another table, or to its primary key
+    // when none are given.
+    References { table: String, columns: Vec<String> },
+    // The collating sequence the column's values are compared with.
+    Collate(String),
+    // The value the column is given when a row leaves it out, as it was written.
+    Default(String),
+    // A condition every row must meet, which isn't kept, as nothing checks it yet.
+    Check,
+    // A column computed from the others. Only a stored one has its value in the record.
+    Generated { stored: bool },
 }
 
+// The words that can start a column constraint, and so end the column's type.
+const COLUMN_CONSTRAINTS: [&str; 8] = [
+    "CONSTRAINT",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+// The words that can follow a foreign key's parent, to say what happens when the parent changes
+// and when the key is checked, as in `ON DELETE SET NULL` or `DEFERRABLE INITIALLY DEFERRED`.
+const FOREIGN_KEY_ACTIONS: [&str; 15] = [
+    "DELETE",
+    "UPDATE",
+    "SET",
+    "DEFAULT",
+    "CASCADE",
+    "RESTRICT",
+    "NO",
+    "ACTION",
+    "DEFERRABLE",
+    "INITIALLY",
+    "DEFERRED",
+    "IMMEDIATE",
+    "SIMPLE",
+    "FULL",
+    "PARTIAL",
+];
+
+// sqlite's default limit on the number of a parameter.
+const MAX_PARAMETERS: usize = 32766;
+
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     position: usize,
+    // The parameters seen so far, by number, with the name of each named one.
+    parameters: Vec<Option<String>>,
+    // The text the tokens were lexed from, and where each of them was in it, if the parser was
+    // given them.
+    source: String,
+    spans: Vec<Range<usize>>,
+    // The text of each statement parsed so far, without the semicolon after it.
+    texts: Vec<String>,
 }
 
 impl Parser {
@@ -63,13 +216,34 @@ impl Parser {
         Parser {
             tokens,
             position: 0,
+            parameters: Vec::new(),
+            source: String::new(),
+            spans: Vec::new(),
+            texts: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Ast {
-        let statements = self.parse_statements();
+    // Gives the parser the text its tokens came from, so that result columns are named as they
+    // were written, with the case and spacing they were written with, and syntax errors can say
+    // where they are.
+    pub fn with_source(mut self, source: &str, spans: &[Range<usize>]) -> Parser {
+        self.source = source.to_string();
+        self.spans = spans.to_vec();
+        self
+    }
+
+    pub fn parameters(&self) -> &[Option<String>] {
+        &self.parameters
+    }
+
+    pub fn texts(&self) -> &[String] {
+        &self.texts
+    }
+
+    pub fn parse(&mut self) -> Result<Ast> {
+        let statements = self.parse_statements()?;
 
-        Ast::StmtList(statements)
+        Ok(Ast::StmtList(statements))
     }
 
     fn peek_token(&self) -> &Token {
@@ -88,32 +262,87 @@ impl Parser {
         &self.tokens[self.position + 1]
     }
 
-    fn consume(&mut self, token: Token) -> Token {
-        match token {
-            Token::Identifier(_) => {
-                if let Token::Identifier(_) = self.peek_token() {
-                    self.position += 1;
-                    self.tokens[self.position - 1].clone()
-                } else {
-                    panic!("Unexpected token: {:?}", self.peek_token());
-                }
-            }
-            _ => {
-                if self.peek_token() == &token {
-                    self.position += 1;
-                    self.tokens[self.position - 1].clone()
-                } else {
-                    panic!("Unexpected token: {:?}", self.peek_token());
+    // The error for finding the next token where it doesn't belong, when the parser expected
+    // something else, which sqlite reports differently when the statement has simply run out.
+    fn unexpected(&self, expected: &str) -> Error {
+        match self.peek_token() {
+            Token::Eof => Error::Parse("incomplete input".to_string()),
+            // The token is quoted as it was written, when the parser has the source.
+            token => {
+                let span = self.spans.get(self.position).cloned();
+                Error::Syntax {
+                    near: match &span {
+                        Some(span) => self.source[span.clone()].to_string(),
+                        None => token.to_string(),
+                    },
+                    span,
+                    expected: expected.to_string(),
                 }
             }
         }
     }
 
-    fn parse_statements(&mut self) -> Vec<Ast> {
+    fn consume(&mut self, token: Token) -> Result<Token> {
+        let matches = match token {
+            Token::Identifier(_) => matches!(self.peek_token(), Token::Identifier(_)),
+            _ => self.peek_token() == &token,
+        };
+
+        if matches {
+            self.position += 1;
+            Ok(self.tokens[self.position - 1].clone())
+        } else {
+            Err(match token {
+                Token::Identifier(_) => self.unexpected("a name"),
+                token => self.unexpected(&format!("\"{}\"", token)),
+            })
+        }
+    }
+
+    // The text of the tokens from `start` up to the next one, as it was written, or the tokens
+    // written back out when the parser wasn't given the source. At least one token must have been
+    // consumed since `start`.
+    fn text_since(&self, start: usize) -> String {
+        self.text_of(start..self.position)
+    }
+
+    // The text of some of the tokens, which mustn't be none of them.
+    fn text_of(&self, tokens: Range<usize>) -> String {
+        match (self.spans.get(tokens.start), self.spans.get(tokens.end - 1)) {
+            (Some(first), Some(last)) => self.source[first.start..last.end].to_string(),
+            _ => self.tokens[tokens]
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    // A name that was just consumed, as it was written when it was written bare, or without its
+    // quotes when it was quoted.
+    fn name_since(&self, start: usize, name: &str) -> String {
+        match self.text_since(start) {
+            text if text.eq_ignore_ascii_case(name) => text,
+            _ => name.to_string(),
+        }
+    }
+
+    // Names in a CREATE statement can be written bare or quoted.
+    fn consume_name(&mut self) -> Result<String> {
+        let name = match self.peek_token() {
+            Token::Identifier(name) => name.clone(),
+            Token::StringLiteral(name) => name.clone(),
+            _ => return Err(self.unexpected("a name")),
+        };
+        self.position += 1;
+
+        Ok(name)
+    }
+
+    fn parse_statements(&mut self) -> Result<Vec<Ast>> {
         let mut statements = Vec::new();
 
         loop {
-            let statement = self.parse_statement();
+            let statement = self.parse_statement()?;
 
             statements.push(statement);
 
@@ -122,600 +351,2258 @@ impl Parser {
             }
         }
 
-        statements
+        Ok(statements)
     }
 
-    fn parse_statement(&mut self) -> Ast {
+    fn parse_statement(&mut self) -> Result<Ast> {
+        let start = self.position;
         let statement = match self.peek_token() {
-            Token::Select => self.parse_select(),
-            Token::Create => self.parse_create(),
-            _ => {
-                panic!("Unexpected token: {:?}", self.peek_token());
-            }
+            Token::Select => self.parse_select()?,
+            Token::Create => self.parse_create()?,
+            token if token.is_word("PRAGMA") => self.parse_pragma()?,
+            token if token.is_word("INSERT") => self.parse_insert()?,
+            token if token.is_word("DELETE") => self.parse_delete()?,
+            _ => return Err(self.unexpected("a statement")),
         };
+        self.texts.push(self.text_since(start));
 
         if self.peek_token() == &Token::Semicolon {
-            self.consume(Token::Semicolon);
+            self.consume(Token::Semicolon)?;
         }
-        Ast::Stmt(Box::new(statement))
+        Ok(Ast::Stmt(Box::new(statement)))
     }
 
-    fn parse_select(&mut self) -> Ast {
+    fn parse_select(&mut self) -> Result<Ast> {
         let mut result_columns = Vec::new();
 
-        self.consume(Token::Select);
+        self.consume(Token::Select)?;
 
-        while self.peek_token() != &Token::From {
+        // The result columns are separated by commas, with none after the last.
+        loop {
             match self.peek_token() {
                 Token::Star => {
                     result_columns.push(Ast::All);
-                    self.consume(Token::Star);
-                }
-                _ => {
-                    result_columns.push(self.parse_expr());
-                    if self.peek_token() == &Token::Comma {
-                        self.consume(Token::Comma);
-                    }
+                    self.consume(Token::Star)?;
                 }
+                _ => result_columns.push(self.parse_result_column()?),
+            }
+            if self.peek_token() != &Token::Comma {
+                break;
             }
+            self.consume(Token::Comma)?;
         }
 
-        let from = self.parse_from();
+        let from = if self.peek_token() == &Token::From {
+            Some(Box::new(self.parse_from()?))
+        } else {
+            None
+        };
 
         let r#where = if self.peek_token() == &Token::Where {
-            self.consume(Token::Where);
-            let expr = self.parse_expr();
+            self.consume(Token::Where)?;
+            let expr = self.parse_expr()?;
             Some(Box::new(expr))
         } else {
             None
         };
 
-        Ast::Select {
-            result_columns,
-            from: Box::new(from),
-            r#where,
+        let mut group_by = Vec::new();
+        if self.peek_token() == &Token::Group {
+            self.consume(Token::Group)?;
+            self.consume(Token::By)?;
+            loop {
+                group_by.push(self.parse_expr()?);
+                if self.peek_token() == &Token::Comma {
+                    self.consume(Token::Comma)?;
+                } else {
+                    break;
+                }
+            }
         }
-    }
 
-    fn parse_from(&mut self) -> Ast {
-        self.consume(Token::From);
+        let mut order_by = Vec::new();
+        if self.peek_token() == &Token::Order {
+            self.consume(Token::Order)?;
+            self.consume(Token::By)?;
+            loop {
+                let expr = self.parse_expr()?;
+                // ASC and DESC aren't keywords, as they can follow an indexed column's name too.
+                let descending = self.consume_direction();
+                order_by.push(Ast::OrderingTerm {
+                    expr: Box::new(expr),
+                    descending,
+                });
+
+                if self.peek_token() == &Token::Comma {
+                    self.consume(Token::Comma)?;
+                } else {
+                    break;
+                }
+            }
+        }
 
-        let table_or_subquery = self.parse_table_or_subquery();
+        // `LIMIT count OFFSET skip` can also be written `LIMIT skip, count`.
+        let (mut limit, mut offset) = (None, None);
+        if self.peek_token() == &Token::Limit {
+            self.consume(Token::Limit)?;
+            limit = Some(Box::new(self.parse_expr()?));
+            match self.peek_token() {
+                Token::Offset => {
+                    self.consume(Token::Offset)?;
+                    offset = Some(Box::new(self.parse_expr()?));
+                }
+                Token::Comma => {
+                    self.consume(Token::Comma)?;
+                    offset = limit.replace(Box::new(self.parse_expr()?));
+                }
+                _ => {}
+            }
+        }
 
-        Ast::TableOrSubQuery(Box::new(table_or_subquery))
+        Ok(Ast::Select {
+            result_columns,
+            from,
+            r#where,
+            group_by,
+            order_by,
+            limit,
+            offset,
+        })
     }
 
-    fn parse_table_or_subquery(&mut self) -> Ast {
-        let identifier = self.consume(Token::Identifier("".to_string()));
+    // An expression in a select list, and the name of its column. A name given to it can follow
+    // AS, or the expression itself, and keeps the case it was written in.
+    fn parse_result_column(&mut self) -> Result<Ast> {
+        let start = self.position;
+        let expr = self.parse_expr()?;
+        let mut name = match &expr {
+            Ast::Expr(inner) => match inner.as_ref() {
+                Ast::Identifier(column) => self.name_since(start, column),
+                _ => self.text_since(start),
+            },
+            _ => self.text_since(start),
+        };
 
-        match identifier {
-            Token::Identifier(name) => Ast::Table(name.to_string()),
-            _ => panic!("Unexpected token: {:?}", identifier),
+        if self.peek_token().is_word("AS") {
+            self.position += 1;
+            if !matches!(
+                self.peek_token(),
+                Token::Identifier(_) | Token::StringLiteral(_)
+            ) {
+                return Err(self.unexpected("a name"));
+            }
         }
-    }
-
-    fn parse_expr(&mut self) -> Ast {
         match self.peek_token().clone() {
-            Token::Identifier(name) => {
-                self.consume(Token::Identifier("".to_string()));
-                match self.peek_token() {
-                    Token::LParen => self.parse_function(name),
-                    _ => {
-                        if self.peek_token() == &Token::Equals {
-                            self.consume(Token::Equals);
-                            let rhs = self.parse_expr();
-                            Ast::Expr(Box::new(Ast::BinaryOp {
-                                op: Op::Equal,
-                                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier(name)))),
-                                rhs: Box::new(rhs),
-                            }))
-                        } else {
-                            Ast::Expr(Box::new(Ast::Identifier(name)))
-                        }
-                    }
-                }
+            Token::Identifier(alias) => {
+                let start = self.position;
+                self.position += 1;
+                name = self.name_since(start, &alias);
             }
-            Token::StringLiteral(value) => {
+            Token::StringLiteral(alias) => {
                 self.position += 1;
-                Ast::Expr(Box::new(Ast::StringLiteral(value.to_string())))
+                name = alias;
             }
-            _ => panic!("Unexpected token: {:?}", self.peek_token()),
+            _ => {}
         }
-    }
 
-    fn parse_function(&mut self, name: String) -> Ast {
-        self.consume(Token::LParen);
-
-        let args = self.parse_function_arguments();
-
-        Ast::Expr(Box::new(Ast::Function { name, args }))
+        Ok(Ast::ResultColumn {
+            expr: Box::new(expr),
+            name,
+        })
     }
 
-    fn parse_function_arguments(&mut self) -> Vec<Ast> {
-        let mut args = Vec::new();
+    fn parse_insert(&mut self) -> Result<Ast> {
+        self.consume_keyword("INSERT")?;
+        self.consume_keyword("INTO")?;
+        let table = self.consume_name()?;
+        let columns = match self.peek_token() {
+            Token::LParen => self.parse_column_names()?,
+            _ => Vec::new(),
+        };
+        self.consume_keyword("VALUES")?;
 
+        let mut rows = Vec::new();
         loop {
-            match self.peek_token() {
-                Token::Star => {
-                    args.push(Ast::All);
-                    self.consume(Token::Star);
-                }
-                _ => {
-                    args.push(self.parse_expr());
-                }
+            self.consume(Token::LParen)?;
+            let mut values = vec![self.parse_expr()?];
+            while self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+                values.push(self.parse_expr()?);
             }
+            self.consume(Token::RParen)?;
+            rows.push(values);
 
             if self.peek_token() == &Token::Comma {
-                self.consume(Token::Comma);
+                self.consume(Token::Comma)?;
             } else {
                 break;
             }
         }
 
-        self.consume(Token::RParen);
+        Ok(Ast::Insert {
+            table,
+            columns,
+            rows,
+        })
+    }
+
+    fn parse_delete(&mut self) -> Result<Ast> {
+        self.consume_keyword("DELETE")?;
+        self.consume(Token::From)?;
+        let table = self.consume_name()?;
+        let r#where = match self.peek_token() {
+            Token::Where => {
+                self.consume(Token::Where)?;
+                Some(Box::new(self.parse_expr()?))
+            }
+            _ => None,
+        };
 
-        args
+        Ok(Ast::Delete { table, r#where })
     }
 
-    pub fn parse_create(&mut self) -> Ast {
-        self.consume(Token::Create);
+    fn parse_from(&mut self) -> Result<Ast> {
+        self.consume(Token::From)?;
 
-        match self.peek_token() {
-            Token::Table => self.parse_create_table(),
-            Token::Index => self.parse_create_index(),
-            _ => panic!("Unexpected token: {:?}", self.peek_token()),
-        }
+        let table_or_subquery = self.parse_table_or_subquery()?;
+
+        Ok(Ast::TableOrSubQuery(Box::new(table_or_subquery)))
     }
 
-    fn parse_create_table(&mut self) -> Ast {
-        self.consume(Token::Table);
-        let name = self.peek_token().clone();
-        let name = match name {
+    // A pragma reads the table-valued function of the same name, so `PRAGMA table_info(apples)`
+    // is `SELECT * FROM pragma_table_info('apples')`. The argument can be given in brackets or
+    // after `=`, and a bare name as the argument is taken as text.
+    fn parse_pragma(&mut self) -> Result<Ast> {
+        self.consume(Token::Identifier("".to_string()))?;
+        let name = match self.consume(Token::Identifier("".to_string()))? {
             Token::Identifier(name) => name,
-            Token::StringLiteral(name) => name,
-            _ => panic!("Unexpected token: {:?}", name),
+            _ => unreachable!(),
         };
-        self.position += 1;
 
-        if name == "SQLITE_SEQUENCE" {
-            return self.sqlite_sequence_hack();
+        let mut args = Vec::new();
+        if matches!(self.peek_token(), Token::LParen | Token::Equals) {
+            let bracketed = self.peek_token() == &Token::LParen;
+            self.position += 1;
+            args.push(match self.parse_expr()? {
+                Ast::Expr(expr) => match *expr {
+                    Ast::Identifier(name) => Ast::Expr(Box::new(Ast::StringLiteral(name))),
+                    expr => Ast::Expr(Box::new(expr)),
+                },
+                arg => arg,
+            });
+            if bracketed {
+                self.consume(Token::RParen)?;
+            }
         }
 
-        self.consume(Token::LParen);
-
-        let column_defs = self.parse_column_defs();
-
-        self.consume(Token::RParen);
-
-        Ast::CreateTable { name, column_defs }
+        Ok(Ast::Select {
+            result_columns: vec![Ast::All],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(
+                Ast::TableFunction {
+                    name: format!("PRAGMA_{}", name),
+                    args,
+                },
+            )))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        })
     }
 
-    fn parse_create_index(&mut self) -> Ast {
-        self.consume(Token::Index);
-        let name = self.peek_token().clone();
-        let name = match name {
-            Token::Identifier(name) => name,
-            Token::StringLiteral(name) => name,
-            _ => panic!("Unexpected token: {:?}", name),
-        };
-        self.position += 1;
-        self.consume(Token::On);
-        let table_name = self.peek_token().clone();
-        let table_name = match table_name {
+    fn parse_table_or_subquery(&mut self) -> Result<Ast> {
+        let name = match self.consume(Token::Identifier("".to_string()))? {
             Token::Identifier(name) => name,
-            Token::StringLiteral(name) => name,
-            _ => panic!("Unexpected token: {:?}", table_name),
+            _ => unreachable!(),
         };
-        self.position += 1;
-        self.consume(Token::LParen);
-        let mut columns = Vec::new();
-
-        loop {
-            if self.peek_token() == &Token::RParen {
-                break;
-            }
 
-            if self.peek_token() == &Token::Comma {
-                self.consume(Token::Comma);
-            }
-
-            let column = self.peek_token().clone();
-            let column = match column {
-                Token::Identifier(name) => name,
-                Token::StringLiteral(name) => name,
-                _ => panic!("Unexpected token: {:?}", column),
-            };
-            self.position += 1;
-            columns.push(Ast::Identifier(column));
+        if self.peek_token() == &Token::LParen {
+            self.consume(Token::LParen)?;
+            let args = self.parse_function_arguments(false)?;
+            return Ok(Ast::TableFunction { name, args });
         }
 
-        self.consume(Token::RParen);
+        Ok(Ast::Table(name))
+    }
 
-        Ast::CreateIndex {
-            name,
-            table_name,
-            columns,
+    // AND binds more tightly than OR, and both less tightly than a comparison, so `a = 1 AND b = 2
+    // OR c = 3` is `(a = 1 AND b = 2) OR c = 3`. Both group from the left.
+    fn parse_expr(&mut self) -> Result<Ast> {
+        let mut lhs = self.parse_conjunction()?;
+        while self.peek_token() == &Token::Or {
+            self.consume(Token::Or)?;
+            let rhs = self.parse_conjunction()?;
+            lhs = Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Or,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }));
         }
+        Ok(lhs)
     }
 
-    fn sqlite_sequence_hack(&mut self) -> Ast {
-        self.consume(Token::LParen);
-        self.consume(Token::Identifier("".to_string()));
-        self.consume(Token::Comma);
-        self.consume(Token::Identifier("".to_string()));
-        self.consume(Token::RParen);
-
-        Ast::CreateTable {
-            name: "SQLITE_SEQUENCE".to_string(),
-            column_defs: vec![
-                Ast::ColumnDef {
-                    name: "NAME".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![],
-                },
-                Ast::ColumnDef {
-                    name: "SEQ".to_string(),
-                    data_type: "INTEGER".to_string(),
-                    constraints: vec![],
-                },
-            ],
+    fn parse_conjunction(&mut self) -> Result<Ast> {
+        let mut lhs = self.parse_comparison()?;
+        while self.peek_token() == &Token::And {
+            self.consume(Token::And)?;
+            let rhs = self.parse_comparison()?;
+            lhs = Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::And,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }));
         }
+        Ok(lhs)
     }
 
-    fn parse_column_defs(&mut self) -> Vec<Ast> {
-        let mut column_defs = Vec::new();
-
+    // Comparisons group from the left, so `a = b = c` is `(a = b) = c`. Arithmetic binds more
+    // tightly than a comparison: `*`, `/` and `%` more than `+` and `-`, and `||` more than any of
+    // them, as in sqlite.
+    fn parse_comparison(&mut self) -> Result<Ast> {
+        let mut lhs = self.parse_sum()?;
         loop {
-            let name = self.peek_token().clone();
-            let name = match name {
-                Token::Identifier(name) => name,
-                Token::StringLiteral(name) => name.to_ascii_uppercase(),
-                _ => panic!("Unexpected token: {:?}", name),
+            let op = match self.peek_token() {
+                Token::Equals => Op::Equal,
+                Token::NotEquals => Op::NotEqual,
+                Token::Less => Op::Less,
+                Token::LessEquals => Op::LessEqual,
+                Token::Greater => Op::Greater,
+                Token::GreaterEquals => Op::GreaterEqual,
+                Token::Match => Op::Match,
+                Token::Like => Op::Like,
+                Token::In => return self.parse_in_list(lhs),
+                _ => return Ok(lhs),
             };
             self.position += 1;
+            let rhs = self.parse_sum()?;
+            lhs = Ast::Expr(Box::new(Ast::BinaryOp {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }));
+        }
+    }
 
-            let data_type = self.consume(Token::Identifier("".to_string()));
-            let data_type = match data_type {
-                Token::Identifier(data_type) => data_type,
-                _ => panic!("Unexpected token: {:?}", data_type),
-            };
+    fn parse_sum(&mut self) -> Result<Ast> {
+        self.parse_operations(Self::parse_product, |token| match token {
+            Token::Plus => Some(Op::Add),
+            Token::Minus => Some(Op::Subtract),
+            _ => None,
+        })
+    }
 
-            let mut constraints = Vec::new();
+    fn parse_product(&mut self) -> Result<Ast> {
+        self.parse_operations(Self::parse_concatenation, |token| match token {
+            Token::Star => Some(Op::Multiply),
+            Token::Slash => Some(Op::Divide),
+            Token::Percent => Some(Op::Remainder),
+            _ => None,
+        })
+    }
 
-            loop {
+    fn parse_concatenation(&mut self) -> Result<Ast> {
+        self.parse_operations(Self::parse_operand, |token| match token {
+            Token::Concat => Some(Op::Concat),
+            _ => None,
+        })
+    }
+
+    // Operands joined by operators of the same precedence, which group from the left, so `a - b -
+    // c` is `(a - b) - c`.
+    fn parse_operations(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Ast>,
+        op: fn(&Token) -> Option<Op>,
+    ) -> Result<Ast> {
+        let mut lhs = operand(self)?;
+        while let Some(op) = op(self.peek_token()) {
+            self.position += 1;
+            let rhs = operand(self)?;
+            lhs = Ast::Expr(Box::new(Ast::BinaryOp {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_operand(&mut self) -> Result<Ast> {
+        match self.peek_token().clone() {
+            Token::Identifier(name) => {
+                self.consume(Token::Identifier("".to_string()))?;
                 match self.peek_token() {
-                    Token::Primary => {
-                        if self.peek_next() == &Token::Key {
-                            constraints.push(Constraint::PrimaryKey);
-                            self.consume(Token::Primary);
-                            self.consume(Token::Key);
-                        }
+                    Token::LParen => self.parse_function(name),
+                    _ => Ok(Ast::Expr(Box::new(Ast::Identifier(name)))),
+                }
+            }
+            Token::StringLiteral(value) => {
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::StringLiteral(value.to_string()))))
+            }
+            Token::IntegerLiteral(value) => {
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::IntegerLiteral(value))))
+            }
+            Token::RealLiteral(value) => {
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::RealLiteral(value))))
+            }
+            Token::BlobLiteral(bytes) => {
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::BlobLiteral(bytes))))
+            }
+            // A sign before a number makes a literal of them both. Before anything else, `-`
+            // changes its sign and `+` leaves it as it is.
+            sign @ (Token::Plus | Token::Minus) => {
+                self.position += 1;
+                let negate = sign == Token::Minus;
+                let number = match self.peek_token() {
+                    Token::IntegerLiteral(value) if negate => Ast::IntegerLiteral(-value),
+                    Token::IntegerLiteral(value) => Ast::IntegerLiteral(*value),
+                    // The one integer too big to write without its sign.
+                    Token::RealLiteral(value) if negate && *value == -(i64::MIN as f64) => {
+                        Ast::IntegerLiteral(i64::MIN)
                     }
-                    Token::Not => {
-                        if self.peek_next() == &Token::Null {
-                            constraints.push(Constraint::NotNull);
-                            self.consume(Token::Not);
-                            self.consume(Token::Null);
-                        }
+                    Token::RealLiteral(value) if negate => Ast::RealLiteral(-value),
+                    Token::RealLiteral(value) => Ast::RealLiteral(*value),
+                    _ if negate => {
+                        return Ok(Ast::Expr(Box::new(Ast::Negate(Box::new(
+                            self.parse_operand()?,
+                        )))))
                     }
-                    Token::AutoIncrement => {
-                        constraints.push(Constraint::AutoIncrement);
-                        self.consume(Token::AutoIncrement);
+                    _ => return self.parse_operand(),
+                };
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(number)))
+            }
+            Token::Null => {
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::NullLiteral)))
+            }
+            Token::Variable(text) => {
+                let index = self.parameter_index(&text)?;
+                self.position += 1;
+                Ok(Ast::Expr(Box::new(Ast::Parameter { index, text })))
+            }
+            // Brackets only group, and leave nothing of their own in the tree.
+            Token::LParen => {
+                self.consume(Token::LParen)?;
+                let expr = self.parse_expr()?;
+                self.consume(Token::RParen)?;
+                Ok(expr)
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    // Numbers a parameter the way sqlite does: `?NNN` is parameter NNN, a bare `?` is one more
+    // than the largest so far, and every use of a name shares the number it was first given.
+    fn parameter_index(&mut self, text: &str) -> Result<usize> {
+        if let Some(number) = text.strip_prefix('?') {
+            let index = match number {
+                "" => self.parameters.len() + 1,
+                number => match number.parse::<usize>() {
+                    Ok(index) if (1..=MAX_PARAMETERS).contains(&index) => index,
+                    _ => {
+                        return Err(Error::Parse(format!(
+                            "variable number must be between ?1 and ?{}",
+                            MAX_PARAMETERS
+                        )))
                     }
-                    Token::Comma => break,
-                    Token::RParen => break,
-                    _ => panic!("Unexpected token: {:?}", self.peek_token()),
-                }
+                },
+            };
+            if index > self.parameters.len() {
+                self.parameters.resize(index, None);
             }
+            return Ok(index);
+        }
 
-            column_defs.push(Ast::ColumnDef {
-                name,
-                data_type,
-                constraints,
-            });
+        match self
+            .parameters
+            .iter()
+            .position(|name| name.as_deref() == Some(text))
+        {
+            Some(position) => Ok(position + 1),
+            None => {
+                self.parameters.push(Some(text.to_string()));
+                Ok(self.parameters.len())
+            }
+        }
+    }
+
+    fn parse_in_list(&mut self, lhs: Ast) -> Result<Ast> {
+        self.consume(Token::In)?;
+        self.consume(Token::LParen)?;
+
+        let mut list = Vec::new();
+        loop {
+            list.push(self.parse_expr()?);
 
             if self.peek_token() == &Token::Comma {
-                self.consume(Token::Comma);
+                self.consume(Token::Comma)?;
             } else {
                 break;
             }
         }
 
-        column_defs
-    }
-}
+        self.consume(Token::RParen)?;
 
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
-    #[allow(unused_imports)]
-    use crate::lexer::Lexer;
+        Ok(Ast::Expr(Box::new(Ast::InList {
+            lhs: Box::new(lhs),
+            list,
+        })))
+    }
 
-    #[test]
-    fn select_from() {
-        let input = "SELECT * FROM Employee;";
+    fn parse_function(&mut self, name: String) -> Result<Ast> {
+        self.consume(Token::LParen)?;
 
-        let mut lexer = Lexer::new(input.to_string());
+        let distinct = self.peek_token() == &Token::Distinct;
+        if distinct {
+            self.consume(Token::Distinct)?;
+        }
+        let args = self.parse_function_arguments(distinct)?;
 
-        let tokens = lexer.lex();
+        Ok(Ast::Expr(Box::new(Ast::Function {
+            name,
+            args,
+            distinct,
+        })))
+    }
 
-        let mut parser = Parser::new(tokens);
+    // The arguments of a call, up to its closing parenthesis. `*` is only an argument on its own,
+    // as in `count(*)`, and never after DISTINCT.
+    fn parse_function_arguments(&mut self, distinct: bool) -> Result<Vec<Ast>> {
+        let mut args = Vec::new();
 
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
-            result_columns: vec![Ast::All],
-            from: Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
-                "EMPLOYEE".to_string(),
-            )))),
-            r#where: None,
-        }))]);
+        if self.peek_token() == &Token::RParen && !distinct {
+            self.consume(Token::RParen)?;
+            return Ok(args);
+        }
 
-        let ast = parser.parse();
+        if self.peek_token() == &Token::Star && !distinct {
+            self.consume(Token::Star)?;
+            self.consume(Token::RParen)?;
+            return Ok(vec![Ast::All]);
+        }
 
-        assert_eq!(ast, expected);
-    }
+        loop {
+            args.push(self.parse_expr()?);
 
-    #[test]
-    fn select_apple_from_fruits() {
-        let input = "SELECT apple FROM fruits;";
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
 
-        let mut lexer = Lexer::new(input.to_string());
+        self.consume(Token::RParen)?;
 
-        let tokens = lexer.lex();
+        Ok(args)
+    }
 
-        let mut parser = Parser::new(tokens);
+    pub fn parse_create(&mut self) -> Result<Ast> {
+        self.consume(Token::Create)?;
+
+        match self.peek_token() {
+            Token::Table => self.parse_create_table(),
+            Token::Index => self.parse_create_index(false),
+            token if token.is_word("UNIQUE") => {
+                self.position += 1;
+                self.parse_create_index(true)
+            }
+            Token::Virtual => self.parse_create_virtual_table(),
+            _ => Err(self.unexpected("TABLE, INDEX or VIRTUAL TABLE")),
+        }
+    }
+
+    fn parse_create_table(&mut self) -> Result<Ast> {
+        self.consume(Token::Table)?;
+        let name = self.consume_name()?;
+
+        self.consume(Token::LParen)?;
+
+        let column_defs = self.parse_column_defs()?;
+
+        self.consume(Token::RParen)?;
+
+        // The table's rows are kept in an index b-tree rather than by rowid.
+        let without_rowid = self.peek_token().is_word("WITHOUT");
+        if without_rowid {
+            self.position += 1;
+            self.consume_keyword("ROWID")?;
+        }
+
+        Ok(Ast::CreateTable {
+            name,
+            column_defs,
+            without_rowid,
+        })
+    }
+
+    // The arguments of a module can be any tokens at all, which are kept as text for the module
+    // to make sense of. They are separated by commas outside of any parentheses.
+    fn parse_create_virtual_table(&mut self) -> Result<Ast> {
+        self.consume(Token::Virtual)?;
+        self.consume(Token::Table)?;
+        let name = self.consume_name()?;
+        self.consume(Token::Using)?;
+        let module = self.consume_name()?;
+
+        let mut args = Vec::new();
+        if self.peek_token() == &Token::LParen {
+            self.consume(Token::LParen)?;
+
+            let mut arg = Vec::new();
+            let mut depth = 0;
+            loop {
+                match self.peek_token() {
+                    Token::RParen if depth == 0 => break,
+                    Token::Comma if depth == 0 => {
+                        args.push(arg.join(" "));
+                        arg.clear();
+                    }
+                    Token::Eof => return Err(self.unexpected("\")\"")),
+                    token => {
+                        match token {
+                            Token::LParen => depth += 1,
+                            Token::RParen => depth -= 1,
+                            _ => {}
+                        }
+                        arg.push(token.to_string());
+                    }
+                }
+                self.position += 1;
+            }
+            if !arg.is_empty() {
+                args.push(arg.join(" "));
+            }
+
+            self.consume(Token::RParen)?;
+        }
+
+        Ok(Ast::CreateVirtualTable { name, module, args })
+    }
+
+    // ASC or DESC after an ORDER BY term or an indexed column, returning whether it sorts in
+    // descending order. They aren't keywords, so they are names to the lexer.
+    fn consume_direction(&mut self) -> bool {
+        let descending = self.peek_token().is_word("DESC");
+        if descending || self.peek_token().is_word("ASC") {
+            self.position += 1;
+        }
+        descending
+    }
+
+    // Words that only mean something in one place, like ROWID in WITHOUT ROWID, aren't keywords.
+    fn consume_keyword(&mut self, keyword: &str) -> Result<()> {
+        match self.peek_token() {
+            token if token.is_word(keyword) => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.unexpected(&format!("\"{}\"", keyword))),
+        }
+    }
+
+    // Of the constraints on the whole table, a primary key and foreign keys are kept, as the lists
+    // of their columns, and a CHECK is noted without its condition. The others, like `UNIQUE (a)`,
+    // are skipped.
+    fn parse_table_constraint(&mut self) -> Result<Option<Ast>> {
+        if self.peek_token().is_word("CONSTRAINT") {
+            self.position += 1;
+            self.consume_name()?;
+        }
+
+        if self.peek_token().is_word("FOREIGN") {
+            self.position += 1;
+            self.consume(Token::Key)?;
+            let columns = self.parse_column_names()?;
+            let (table, table_columns) = self.parse_foreign_key_clause()?;
+            self.skip_table_constraint()?;
+            return Ok(Some(Ast::ForeignKey {
+                columns,
+                table,
+                table_columns,
+            }));
+        }
+
+        if self.peek_token().is_word("CHECK") {
+            self.position += 1;
+            self.skip_bracketed()?;
+            return Ok(Some(Ast::Check));
+        }
+
+        if self.peek_token() != &Token::Primary {
+            self.skip_table_constraint()?;
+            return Ok(None);
+        }
+        self.consume(Token::Primary)?;
+        self.consume(Token::Key)?;
+        self.consume(Token::LParen)?;
+
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.parse_indexed_column()?);
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume(Token::RParen)?;
+
+        // Like `ON CONFLICT REPLACE`.
+        self.skip_table_constraint()?;
+        Ok(Some(Ast::PrimaryKey(columns)))
+    }
+
+    // What a foreign key refers to, from REFERENCES on: the parent table, and the columns of it,
+    // which are left out to mean its primary key. What the key does when the parent changes, and
+    // when it is checked, don't matter to a reader, so they are skipped.
+    fn parse_foreign_key_clause(&mut self) -> Result<(String, Vec<String>)> {
+        self.consume_keyword("REFERENCES")?;
+        let table = self.consume_name()?;
+        let columns = match self.peek_token() {
+            Token::LParen => self.parse_column_names()?,
+            _ => Vec::new(),
+        };
+
+        loop {
+            match self.peek_token() {
+                Token::On | Token::Null | Token::Match => {}
+                Token::Not if self.peek_next().is_word("DEFERRABLE") => {}
+                token if FOREIGN_KEY_ACTIONS.iter().any(|word| token.is_word(word)) => {}
+                _ => break,
+            }
+            self.position += 1;
+        }
+        Ok((table, columns))
+    }
+
+    // A bracketed list of column names, like `(a, b)`.
+    fn parse_column_names(&mut self) -> Result<Vec<String>> {
+        self.consume(Token::LParen)?;
+        let mut columns = Vec::new();
+        loop {
+            let name = match self.peek_token() {
+                Token::Identifier(name) => name.clone(),
+                Token::StringLiteral(name) => name.clone(),
+                _ => return Err(self.unexpected("a column name")),
+            };
+            self.position += 1;
+            columns.push(name);
+
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume(Token::RParen)?;
+        Ok(columns)
+    }
+
+    // Skips `ON CONFLICT` and what to do, after a constraint that can fail.
+    fn skip_conflict_clause(&mut self) -> Result<()> {
+        if self.peek_token() == &Token::On && self.peek_next().is_word("CONFLICT") {
+            self.position += 2;
+            match self.peek_token() {
+                Token::Identifier(_) => self.position += 1,
+                _ => return Err(self.unexpected("a conflict resolution")),
+            }
+        }
+        Ok(())
+    }
+
+    // Skips an expression in brackets, like a CHECK constraint's, which nothing reads yet.
+    fn skip_bracketed(&mut self) -> Result<()> {
+        self.consume(Token::LParen)?;
+        let mut depth = 0;
+        loop {
+            match self.peek_token() {
+                Token::RParen if depth == 0 => break,
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                Token::Eof => return Err(self.unexpected("\")\"")),
+                _ => {}
+            }
+            self.position += 1;
+        }
+        self.consume(Token::RParen)?;
+        Ok(())
+    }
+
+    // Skips a constraint on the whole table, or the rest of one, which runs up to the next comma
+    // or the end of the column list.
+    fn skip_table_constraint(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.peek_token() {
+                Token::Comma | Token::RParen if depth == 0 => return Ok(()),
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                Token::Eof => return Err(self.unexpected("\")\"")),
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    fn parse_create_index(&mut self, unique: bool) -> Result<Ast> {
+        self.consume(Token::Index)?;
+        let name = self.consume_name()?;
+        self.consume(Token::On)?;
+        let table_name = self.consume_name()?;
+        self.consume(Token::LParen)?;
+        let mut columns = Vec::new();
+
+        loop {
+            columns.push(self.parse_indexed_column()?);
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+
+        self.consume(Token::RParen)?;
+
+        // A partial index.
+        let r#where = match self.peek_token() {
+            Token::Where => {
+                self.consume(Token::Where)?;
+                Some(Box::new(self.parse_expr()?))
+            }
+            _ => None,
+        };
+
+        Ok(Ast::CreateIndex {
+            name,
+            table_name,
+            columns,
+            unique,
+            r#where,
+        })
+    }
+
+    // A column of an index or a primary key: `name [COLLATE collation] [ASC | DESC]`.
+    fn parse_indexed_column(&mut self) -> Result<Ast> {
+        let name = self.consume_name()?;
+        let collation = match self.peek_token() {
+            token if token.is_word("COLLATE") => {
+                self.position += 1;
+                Some(self.consume_name()?)
+            }
+            _ => None,
+        };
+        let descending = self.consume_direction();
+        Ok(Ast::IndexedColumn {
+            name,
+            collation,
+            descending,
+        })
+    }
+
+    // A column's declared type, which is any number of words, as in `UNSIGNED BIG INT`, and then
+    // perhaps one or two numbers in brackets, as in `VARCHAR(255)` or `DECIMAL(10, 2)`. A column
+    // doesn't have to have a type. It's kept as it was written, except that the types sqlite knows
+    // by name are in capitals, as sqlite keeps them.
+    fn parse_type_name(&mut self) -> Result<String> {
+        let start = self.position;
+        while let token @ Token::Identifier(_) = self.peek_token() {
+            if COLUMN_CONSTRAINTS.iter().any(|word| token.is_word(word)) {
+                break;
+            }
+            self.position += 1;
+        }
+        if self.position == start {
+            return Ok(String::new());
+        }
+
+        if self.peek_token() == &Token::LParen {
+            self.consume(Token::LParen)?;
+            self.consume_signed_number()?;
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+                self.consume_signed_number()?;
+            }
+            self.consume(Token::RParen)?;
+        }
+
+        match &self.tokens[start..self.position] {
+            [token @ Token::Identifier(name)]
+                if ["INT", "INTEGER", "REAL", "TEXT", "BLOB", "ANY"]
+                    .iter()
+                    .any(|word| token.is_word(word)) =>
+            {
+                Ok(name.to_ascii_uppercase())
+            }
+            _ => Ok(self.text_since(start)),
+        }
+    }
+
+    fn consume_signed_number(&mut self) -> Result<()> {
+        if matches!(self.peek_token(), Token::Plus | Token::Minus) {
+            self.position += 1;
+        }
+        match self.peek_token() {
+            Token::IntegerLiteral(_) | Token::RealLiteral(_) => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.unexpected("a number")),
+        }
+    }
+
+    fn parse_column_defs(&mut self) -> Result<Vec<Ast>> {
+        let mut column_defs = Vec::new();
+
+        loop {
+            // Constraints on the whole table come after all of the columns.
+            let table_constraint = match self.peek_token() {
+                Token::Primary => true,
+                token @ Token::Identifier(_) => ["CONSTRAINT", "UNIQUE", "CHECK", "FOREIGN"]
+                    .iter()
+                    .any(|word| token.is_word(word)),
+                _ => false,
+            };
+            if table_constraint {
+                column_defs.extend(self.parse_table_constraint()?);
+                if self.peek_token() == &Token::Comma {
+                    self.consume(Token::Comma)?;
+                    continue;
+                }
+                break;
+            }
+
+            let name = match self.peek_token() {
+                Token::Identifier(name) => name.clone(),
+                Token::StringLiteral(name) => name.clone(),
+                _ => return Err(self.unexpected("a column name")),
+            };
+            self.position += 1;
+
+            let data_type = self.parse_type_name()?;
+
+            let mut constraints = Vec::new();
+
+            loop {
+                match self.peek_token() {
+                    token if token.is_word("CONSTRAINT") => {
+                        self.position += 1;
+                        self.consume_name()?;
+                    }
+                    Token::Primary if self.peek_next() == &Token::Key => {
+                        self.consume(Token::Primary)?;
+                        self.consume(Token::Key)?;
+                        let descending = self.consume_direction();
+                        constraints.push(Constraint::PrimaryKey { descending });
+                        self.skip_conflict_clause()?;
+                    }
+                    Token::Not if self.peek_next() == &Token::Null => {
+                        constraints.push(Constraint::NotNull);
+                        self.consume(Token::Not)?;
+                        self.consume(Token::Null)?;
+                        self.skip_conflict_clause()?;
+                    }
+                    Token::Null => {
+                        self.consume(Token::Null)?;
+                        self.skip_conflict_clause()?;
+                    }
+                    Token::AutoIncrement => {
+                        constraints.push(Constraint::AutoIncrement);
+                        self.consume(Token::AutoIncrement)?;
+                    }
+                    token if token.is_word("UNIQUE") => {
+                        self.position += 1;
+                        self.skip_conflict_clause()?;
+                    }
+                    token if token.is_word("CHECK") => {
+                        self.position += 1;
+                        self.skip_bracketed()?;
+                        constraints.push(Constraint::Check);
+                    }
+                    token if token.is_word("DEFAULT") => {
+                        self.position += 1;
+                        let start = self.position;
+                        let text = match self.peek_token() {
+                            // Kept without its brackets, as sqlite gives it.
+                            Token::LParen => {
+                                self.skip_bracketed()?;
+                                self.text_of(start + 1..self.position - 1)
+                            }
+                            Token::Plus | Token::Minus => {
+                                self.consume_signed_number()?;
+                                self.text_since(start)
+                            }
+                            Token::IntegerLiteral(_)
+                            | Token::RealLiteral(_)
+                            | Token::BlobLiteral(_)
+                            | Token::StringLiteral(_)
+                            | Token::Null
+                            | Token::Identifier(_) => {
+                                self.position += 1;
+                                self.text_since(start)
+                            }
+                            _ => return Err(self.unexpected("a default value")),
+                        };
+                        constraints.push(Constraint::Default(text));
+                    }
+                    token if token.is_word("COLLATE") => {
+                        self.position += 1;
+                        constraints.push(Constraint::Collate(self.consume_name()?));
+                    }
+                    token if token.is_word("REFERENCES") => {
+                        let (table, columns) = self.parse_foreign_key_clause()?;
+                        constraints.push(Constraint::References { table, columns });
+                    }
+                    token if token.is_word("GENERATED") || token.is_word("AS") => {
+                        if token.is_word("GENERATED") {
+                            self.position += 1;
+                            self.consume_keyword("ALWAYS")?;
+                        }
+                        self.consume_keyword("AS")?;
+                        self.skip_bracketed()?;
+                        // Virtual is the default.
+                        let stored = self.peek_token().is_word("STORED");
+                        if stored || self.peek_token().is_word("VIRTUAL") {
+                            self.position += 1;
+                        }
+                        constraints.push(Constraint::Generated { stored });
+                    }
+                    Token::Comma => break,
+                    Token::RParen => break,
+                    _ => return Err(self.unexpected("a column constraint")),
+                }
+            }
+
+            column_defs.push(Ast::ColumnDef {
+                name,
+                data_type,
+                constraints,
+            });
+
+            if self.peek_token() == &Token::Comma {
+                self.consume(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(column_defs)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn select_from() {
+        let input = "SELECT * FROM Employee;";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+
+        let mut parser = Parser::new(tokens);
 
         let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
-            result_columns: vec![Ast::Expr(Box::new(Ast::Identifier("APPLE".to_string())))],
-            from: Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
-                "FRUITS".to_string(),
-            )))),
+            result_columns: vec![Ast::All],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "Employee".to_string(),
+            ))))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_apple_from_fruits() {
+        let input = "SELECT apple FROM fruits;";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("apple".to_string())))),
+                name: "apple".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "fruits".to_string(),
+            ))))),
             r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_multiple_columns() {
+        let input = "SELECT name, color FROM apples;";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![
+                Ast::ResultColumn {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                    name: "name".to_string(),
+                },
+                Ast::ResultColumn {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("color".to_string())))),
+                    name: "color".to_string(),
+                },
+            ],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "apples".to_string(),
+            ))))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_count() {
+        let input = "SELECT COUNT(*) FROM Employee;";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Function {
+                    name: "COUNT".to_string(),
+                    args: vec![Ast::All],
+                    distinct: false,
+                }))),
+                name: "COUNT(*)".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "Employee".to_string(),
+            ))))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn create_table() {
+        let input = "CREATE TABLE Employee (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);";
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        let tokens = lexer.lex().unwrap();
+
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
+            name: "Employee".to_string(),
+            column_defs: vec![
+                Ast::ColumnDef {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    constraints: vec![
+                        Constraint::PrimaryKey { descending: false },
+                        Constraint::AutoIncrement,
+                    ],
+                },
+                Ast::ColumnDef {
+                    name: "name".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+            ],
+            without_rowid: false,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_from_where() {
+        let input = "SELECT name, color FROM apples WHERE color = 'Yellow';";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![
+                Ast::ResultColumn {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                    name: "name".to_string(),
+                },
+                Ast::ResultColumn {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("color".to_string())))),
+                    name: "color".to_string(),
+                },
+            ],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "apples".to_string(),
+            ))))),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Equal,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("color".to_string())))),
+                rhs: Box::new(Ast::Expr(Box::new(Ast::StringLiteral(
+                    "Yellow".to_string(),
+                )))),
+            })))),
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_where_comparison() {
+        let input = "SELECT name FROM apples WHERE weight >= 150";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                name: "name".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "apples".to_string(),
+            ))))),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::GreaterEqual,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("weight".to_string())))),
+                rhs: Box::new(Ast::Expr(Box::new(Ast::IntegerLiteral(150)))),
+            })))),
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn select_where_and_or() {
+        let input = "SELECT * FROM t WHERE a = 1 AND b = 'x' OR c = 'y'";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select { r#where, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+
+        let expr = |ast| Box::new(Ast::Expr(Box::new(ast)));
+        let equal = |column: &str, value| {
+            expr(Ast::BinaryOp {
+                op: Op::Equal,
+                lhs: expr(Ast::Identifier(column.to_string())),
+                rhs: expr(value),
+            })
+        };
+        let expected = expr(Ast::BinaryOp {
+            op: Op::Or,
+            lhs: expr(Ast::BinaryOp {
+                op: Op::And,
+                lhs: equal("a", Ast::IntegerLiteral(1)),
+                rhs: equal("b", Ast::StringLiteral("x".to_string())),
+            }),
+            rhs: equal("c", Ast::StringLiteral("y".to_string())),
+        });
+
+        assert_eq!(r#where, &Some(expected));
+    }
+
+    #[test]
+    fn select_where_signed_numbers_and_values_first() {
+        let input = "SELECT * FROM t WHERE 5 > age AND score < -1.5";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select { r#where, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+
+        let expr = |ast| Box::new(Ast::Expr(Box::new(ast)));
+        let expected = expr(Ast::BinaryOp {
+            op: Op::And,
+            lhs: expr(Ast::BinaryOp {
+                op: Op::Greater,
+                lhs: expr(Ast::IntegerLiteral(5)),
+                rhs: expr(Ast::Identifier("age".to_string())),
+            }),
+            rhs: expr(Ast::BinaryOp {
+                op: Op::Less,
+                lhs: expr(Ast::Identifier("score".to_string())),
+                rhs: expr(Ast::RealLiteral(-1.5)),
+            }),
+        });
+
+        assert_eq!(r#where, &Some(expected));
+    }
+
+    #[test]
+    fn select_arithmetic() {
+        let input = "SELECT -a + 2 * b || c - d FROM t";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens).with_source(input, lexer.spans());
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select { result_columns, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+
+        let expr = |ast| Box::new(Ast::Expr(Box::new(ast)));
+        let column = |name: &str| expr(Ast::Identifier(name.to_string()));
+        let operation = |op, lhs, rhs| expr(Ast::BinaryOp { op, lhs, rhs });
+        let expected = operation(
+            Op::Subtract,
+            operation(
+                Op::Add,
+                expr(Ast::Negate(column("a"))),
+                operation(
+                    Op::Multiply,
+                    expr(Ast::IntegerLiteral(2)),
+                    operation(Op::Concat, column("b"), column("c")),
+                ),
+            ),
+            column("d"),
+        );
+
+        assert_eq!(
+            result_columns,
+            &[Ast::ResultColumn {
+                expr: expected,
+                name: "-a + 2 * b || c - d".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn create_superhero_table() {
+        let input = "CREATE TABLE \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text)";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
+            name: "superheroes".to_string(),
+            column_defs: vec![
+                Ast::ColumnDef {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    constraints: vec![
+                        Constraint::PrimaryKey { descending: false },
+                        Constraint::AutoIncrement,
+                    ],
+                },
+                Ast::ColumnDef {
+                    name: "name".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![Constraint::NotNull],
+                },
+                Ast::ColumnDef {
+                    name: "eye_color".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "hair_color".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "appearance_count".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "first_appearance".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "first_appearance_year".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+            ],
+            without_rowid: false,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn create_table_with_string_literal_column_name() {
+        let input = "CREATE TABLE companies\n(\n\tid integer primary key autoincrement\n, \"size range\" text, 'Locality' text);";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
+            name: "companies".to_string(),
+            column_defs: vec![
+                Ast::ColumnDef {
+                    name: "id".to_string(),
+                    data_type: "INTEGER".to_string(),
+                    constraints: vec![
+                        Constraint::PrimaryKey { descending: false },
+                        Constraint::AutoIncrement,
+                    ],
+                },
+                Ast::ColumnDef {
+                    name: "size range".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "Locality".to_string(),
+                    data_type: "TEXT".to_string(),
+                    constraints: vec![],
+                },
+            ],
+            without_rowid: false,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn sqlite_sequence() {
+        let input = "CREATE TABLE sqlite_sequence(name,seq);";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
+            name: "sqlite_sequence".to_string(),
+            column_defs: vec![
+                Ast::ColumnDef {
+                    name: "name".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![],
+                },
+                Ast::ColumnDef {
+                    name: "seq".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![],
+                },
+            ],
+            without_rowid: false,
         }))]);
 
-        let ast = parser.parse();
+        let ast = parser.parse().unwrap();
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn untyped_columns() {
+        let input = "CREATE TABLE t(a PRIMARY KEY, b NOT NULL, c);";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
+            name: "t".to_string(),
+            column_defs: vec![
+                Ast::ColumnDef {
+                    name: "a".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::PrimaryKey { descending: false }],
+                },
+                Ast::ColumnDef {
+                    name: "b".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::NotNull],
+                },
+                Ast::ColumnDef {
+                    name: "c".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![],
+                },
+            ],
+            without_rowid: false,
+        }))]);
 
+        let ast = parser.parse().unwrap();
         assert_eq!(ast, expected);
     }
 
     #[test]
-    fn select_multiple_columns() {
-        let input = "SELECT name, color FROM apples;";
+    fn create_index() {
+        let input =
+            "CREATE INDEX idx_superheroes_first_appeared ON superheroes (first_appearance);";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateIndex {
+            name: "idx_superheroes_first_appeared".to_string(),
+            table_name: "superheroes".to_string(),
+            columns: vec![Ast::IndexedColumn {
+                name: "first_appearance".to_string(),
+                collation: None,
+                descending: false,
+            }],
+            unique: false,
+            r#where: None,
+        }))]);
+
+        let ast = parser.parse().unwrap();
+        assert_eq!(ast, expected);
+    }
 
+    #[test]
+    fn create_partial_index() {
+        let input =
+            "CREATE UNIQUE INDEX i ON t (a COLLATE NOCASE DESC, \"b c\" ASC, d) WHERE a > 1";
         let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
-        let tokens = lexer.lex();
+        let column = |name: &str, collation: Option<&str>, descending| Ast::IndexedColumn {
+            name: name.to_string(),
+            collation: collation.map(str::to_string),
+            descending,
+        };
+        let expected = Ast::CreateIndex {
+            name: "i".to_string(),
+            table_name: "t".to_string(),
+            columns: vec![
+                column("a", Some("NOCASE"), true),
+                column("b c", None, false),
+                column("d", None, false),
+            ],
+            unique: true,
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Greater,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("a".to_string())))),
+                rhs: Box::new(Ast::Expr(Box::new(Ast::IntegerLiteral(1)))),
+            })))),
+        };
+        assert_eq!(parser.parse_create().unwrap(), expected);
+
+        for input in [
+            "CREATE INDEX i ON t (a b)",
+            "CREATE INDEX i ON t (a,)",
+            "CREATE INDEX i ON t ()",
+        ] {
+            let mut lexer = Lexer::new(input.to_string());
+            let mut parser = Parser::new(lexer.lex().unwrap());
+            assert!(parser.parse_create().is_err(), "{}", input);
+        }
+    }
 
-        let mut parser = Parser::new(tokens);
+    #[test]
+    fn select_where_like() {
+        let input = "SELECT name FROM fruits WHERE name LIKE 'App%'";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
         let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
-            result_columns: vec![
-                Ast::Expr(Box::new(Ast::Identifier("NAME".to_string()))),
-                Ast::Expr(Box::new(Ast::Identifier("COLOR".to_string()))),
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                name: "name".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "fruits".to_string(),
+            ))))),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Like,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                rhs: Box::new(Ast::Expr(Box::new(Ast::StringLiteral("App%".to_string())))),
+            })))),
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
+
+    #[test]
+    fn insert_several_rows() {
+        let input = "INSERT INTO apples (name, color) VALUES ('Fuji', 'Red'), (?, NULL);";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Insert {
+            table: "apples".to_string(),
+            columns: vec!["name".to_string(), "color".to_string()],
+            rows: vec![
+                vec![
+                    Ast::Expr(Box::new(Ast::StringLiteral("Fuji".to_string()))),
+                    Ast::Expr(Box::new(Ast::StringLiteral("Red".to_string()))),
+                ],
+                vec![
+                    Ast::Expr(Box::new(Ast::Parameter {
+                        index: 1,
+                        text: "?".to_string(),
+                    })),
+                    Ast::Expr(Box::new(Ast::NullLiteral)),
+                ],
             ],
-            from: Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
-                "APPLES".to_string(),
-            )))),
-            r#where: None,
         }))]);
 
-        let ast = parser.parse();
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
+
+    #[test]
+    fn delete_where() {
+        let input = "DELETE FROM apples WHERE name = 'Fuji'";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Delete {
+            table: "apples".to_string(),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Equal,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                rhs: Box::new(Ast::Expr(Box::new(Ast::StringLiteral("Fuji".to_string())))),
+            })))),
+        }))]);
+
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
+
+    #[test]
+    fn select_where_in() {
+        let input = "SELECT name FROM apples WHERE id IN (1, 'two');";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                name: "name".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "apples".to_string(),
+            ))))),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::InList {
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("id".to_string())))),
+                list: vec![
+                    Ast::Expr(Box::new(Ast::IntegerLiteral(1))),
+                    Ast::Expr(Box::new(Ast::StringLiteral("two".to_string()))),
+                ],
+            })))),
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
 
+        let ast = parser.parse().unwrap();
         assert_eq!(ast, expected);
     }
 
     #[test]
-    fn select_count() {
-        let input = "SELECT COUNT(*) FROM Employee;";
+    fn select_function_without_from() {
+        let input = "SELECT changes();";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Function {
+                    name: "changes".to_string(),
+                    args: vec![],
+                    distinct: false,
+                }))),
+                name: "changes()".to_string(),
+            }],
+            from: None,
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
+
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
 
+    #[test]
+    fn select_with_parameters() {
+        let input = "SELECT ?, :name, ?5, :name FROM fruits WHERE colour = ?";
         let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let parameter = |index: usize, text: &str| {
+            Ast::Expr(Box::new(Ast::Parameter {
+                index,
+                text: text.to_string(),
+            }))
+        };
+        let column = |expr: Ast, name: &str| Ast::ResultColumn {
+            expr: Box::new(expr),
+            name: name.to_string(),
+        };
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![
+                column(parameter(1, "?"), "?"),
+                column(parameter(2, ":name"), ":name"),
+                column(parameter(5, "?5"), "?5"),
+                column(parameter(2, ":name"), ":name"),
+            ],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "fruits".to_string(),
+            ))))),
+            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
+                op: Op::Equal,
+                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("colour".to_string())))),
+                rhs: Box::new(parameter(6, "?")),
+            })))),
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }))]);
 
-        let tokens = lexer.lex();
+        assert_eq!(parser.parse().unwrap(), expected);
+        assert_eq!(parser.parameters().len(), 6);
+        assert_eq!(parser.parameters()[1].as_deref(), Some(":name"));
+    }
 
-        let mut parser = Parser::new(tokens);
+    #[test]
+    fn pragma_statement() {
+        let input = "PRAGMA integrity_check(apples);";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
         let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
-            result_columns: vec![Ast::Expr(Box::new(Ast::Function {
-                name: "COUNT".to_string(),
-                args: vec![Ast::All],
-            }))],
-            from: Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
-                "EMPLOYEE".to_string(),
+            result_columns: vec![Ast::All],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(
+                Ast::TableFunction {
+                    name: "PRAGMA_integrity_check".to_string(),
+                    args: vec![Ast::Expr(Box::new(Ast::StringLiteral(
+                        "apples".to_string(),
+                    )))],
+                },
             )))),
             r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         }))]);
 
-        let ast = parser.parse();
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
 
-        assert_eq!(ast, expected);
+    #[test]
+    fn select_column_names() {
+        let input = "SELECT count( * ), name AS Fruit, length(name) \"name length\" FROM fruits";
+        let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens).with_source(input, lexer.spans());
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select { result_columns, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+        let names = result_columns
+            .iter()
+            .map(|column| match column {
+                Ast::ResultColumn { name, .. } => name.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["count( * )", "Fruit", "name length"]);
     }
 
     #[test]
-    fn create_table() {
-        let input = "CREATE TABLE Employee (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);";
+    fn select_list_commas() {
+        let parse = |input: &str| {
+            let mut lexer = Lexer::new(input.to_string());
+            let tokens = lexer.lex().unwrap();
+            Parser::new(tokens)
+                .with_source(input, lexer.spans())
+                .parse()
+        };
+
+        for input in [
+            "SELECT id, FROM t",
+            "SELECT id name x FROM t",
+            "SELECT id,",
+            "SELECT , id FROM t",
+            "SELECT id AS FROM t",
+        ] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+
+        let Ast::StmtList(statements) = parse("SELECT a AS b, c d, * FROM NoSuch").unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select {
+            result_columns,
+            from,
+            ..
+        } = statement.as_ref()
+        else {
+            unreachable!()
+        };
+        let names = result_columns
+            .iter()
+            .map(|column| match column {
+                Ast::ResultColumn { name, .. } => name.as_str(),
+                _ => "*",
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["b", "d", "*"]);
+        assert_eq!(
+            from,
+            &Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "NoSuch".to_string()
+            )))))
+        );
+    }
 
+    #[test]
+    fn column_type_names() {
+        let input =
+            "CREATE TABLE t(a varchar(255), b Decimal(10, -2) NOT NULL, c unsigned big int, d int)";
         let mut lexer = Lexer::new(input.to_string());
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens).with_source(input, lexer.spans());
 
-        let tokens = lexer.lex();
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::CreateTable { column_defs, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+        let data_types = column_defs
+            .iter()
+            .map(|column| match column {
+                Ast::ColumnDef { data_type, .. } => data_type.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            data_types,
+            ["varchar(255)", "Decimal(10, -2)", "unsigned big int", "INT"]
+        );
+    }
 
-        let mut parser = Parser::new(tokens);
+    #[test]
+    fn select_order_by() {
+        let input = "SELECT name FROM fruits ORDER BY length(name) DESC, 1";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
-            name: "EMPLOYEE".to_string(),
-            column_defs: vec![
-                Ast::ColumnDef {
-                    name: "ID".to_string(),
-                    data_type: "INTEGER".to_string(),
-                    constraints: vec![Constraint::PrimaryKey, Constraint::AutoIncrement],
+        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
+            result_columns: vec![Ast::ResultColumn {
+                expr: Box::new(Ast::Expr(Box::new(Ast::Identifier("name".to_string())))),
+                name: "name".to_string(),
+            }],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
+                "fruits".to_string(),
+            ))))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![
+                Ast::OrderingTerm {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::Function {
+                        name: "length".to_string(),
+                        args: vec![Ast::Expr(Box::new(Ast::Identifier("name".to_string())))],
+                        distinct: false,
+                    }))),
+                    descending: true,
                 },
-                Ast::ColumnDef {
-                    name: "NAME".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                Ast::OrderingTerm {
+                    expr: Box::new(Ast::Expr(Box::new(Ast::IntegerLiteral(1)))),
+                    descending: false,
                 },
             ],
+            limit: None,
+            offset: None,
         }))]);
 
-        let ast = parser.parse();
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
 
-        assert_eq!(ast, expected);
+    #[test]
+    fn select_group_by_limit() {
+        let input = "SELECT color, count(*) FROM apples GROUP BY color LIMIT 10, 5";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select {
+            group_by,
+            limit,
+            offset,
+            ..
+        } = statement.as_ref()
+        else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            group_by,
+            &[Ast::Expr(Box::new(Ast::Identifier("color".to_string())))]
+        );
+        // With a comma, the offset comes first.
+        assert_eq!(
+            limit.as_deref(),
+            Some(&Ast::Expr(Box::new(Ast::IntegerLiteral(5))))
+        );
+        assert_eq!(
+            offset.as_deref(),
+            Some(&Ast::Expr(Box::new(Ast::IntegerLiteral(10))))
+        );
     }
 
     #[test]
-    fn select_from_where() {
-        let input = "SELECT name, color FROM apples WHERE color = 'Yellow';";
+    fn select_nested_functions() {
+        let input = "SELECT upper(substr(name, 1, 3)), count(DISTINCT lower(color)) FROM apples";
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.lex();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let Ast::StmtList(statements) = parser.parse().unwrap() else {
+            unreachable!()
+        };
+        let Ast::Stmt(statement) = &statements[0] else {
+            unreachable!()
+        };
+        let Ast::Select { result_columns, .. } = statement.as_ref() else {
+            unreachable!()
+        };
+        let exprs = result_columns
+            .iter()
+            .map(|column| match column {
+                Ast::ResultColumn { expr, .. } => expr.as_ref().clone(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        let call = |name: &str, args: Vec<Ast>, distinct| {
+            Ast::Expr(Box::new(Ast::Function {
+                name: name.to_string(),
+                args,
+                distinct,
+            }))
+        };
+        let column = |name: &str| Ast::Expr(Box::new(Ast::Identifier(name.to_string())));
+        let integer = |value| Ast::Expr(Box::new(Ast::IntegerLiteral(value)));
+        assert_eq!(
+            exprs,
+            [
+                call(
+                    "upper",
+                    vec![call(
+                        "substr",
+                        vec![column("name"), integer(1), integer(3)],
+                        false
+                    )],
+                    false
+                ),
+                call(
+                    "count",
+                    vec![call("lower", vec![column("color")], false)],
+                    true
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_from_table_function() {
+        let input = "SELECT * FROM series(1, ?)";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
         let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::Select {
-            result_columns: vec![
-                Ast::Expr(Box::new(Ast::Identifier("NAME".to_string()))),
-                Ast::Expr(Box::new(Ast::Identifier("COLOR".to_string()))),
-            ],
-            from: Box::new(Ast::TableOrSubQuery(Box::new(Ast::Table(
-                "APPLES".to_string(),
+            result_columns: vec![Ast::All],
+            from: Some(Box::new(Ast::TableOrSubQuery(Box::new(
+                Ast::TableFunction {
+                    name: "series".to_string(),
+                    args: vec![
+                        Ast::Expr(Box::new(Ast::IntegerLiteral(1))),
+                        Ast::Expr(Box::new(Ast::Parameter {
+                            index: 1,
+                            text: "?".to_string(),
+                        })),
+                    ],
+                },
             )))),
-            r#where: Some(Box::new(Ast::Expr(Box::new(Ast::BinaryOp {
-                op: Op::Equal,
-                lhs: Box::new(Ast::Expr(Box::new(Ast::Identifier("COLOR".to_string())))),
-                rhs: Box::new(Ast::Expr(Box::new(Ast::StringLiteral(
-                    "Yellow".to_string(),
-                )))),
-            })))),
+            r#where: None,
+            group_by: vec![],
+            order_by: vec![],
+            limit: None,
+            offset: None,
         }))]);
 
-        let ast = parser.parse();
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
 
-        assert_eq!(ast, expected);
+    #[test]
+    fn create_virtual_table() {
+        let input = "CREATE VIRTUAL TABLE docs USING fts4(title, body, tokenize=unicode61 \"remove_diacritics=2\")";
+        let mut lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer.lex().unwrap());
+
+        let expected = Ast::CreateVirtualTable {
+            name: "docs".to_string(),
+            module: "fts4".to_string(),
+            args: vec![
+                "title".to_string(),
+                "body".to_string(),
+                "tokenize = unicode61 \"remove_diacritics=2\"".to_string(),
+            ],
+        };
+
+        assert_eq!(parser.parse_create().unwrap(), expected);
     }
 
     #[test]
-    fn create_superhero_table() {
-        let input = "CREATE TABLE \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text)";
+    fn create_table_with_foreign_keys() {
+        let input = "CREATE TABLE track (id INTEGER PRIMARY KEY, artist REFERENCES artist ON DELETE SET NULL, a INT, b TEXT, FOREIGN KEY (a, b) REFERENCES pair (x, y) DEFERRABLE INITIALLY DEFERRED)";
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.lex();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
-            name: "superheroes".to_string(),
+        let expected = Ast::CreateTable {
+            name: "track".to_string(),
             column_defs: vec![
                 Ast::ColumnDef {
-                    name: "ID".to_string(),
+                    name: "id".to_string(),
                     data_type: "INTEGER".to_string(),
-                    constraints: vec![Constraint::PrimaryKey, Constraint::AutoIncrement],
-                },
-                Ast::ColumnDef {
-                    name: "NAME".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![Constraint::NotNull],
-                },
-                Ast::ColumnDef {
-                    name: "EYE_COLOR".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                    constraints: vec![Constraint::PrimaryKey { descending: false }],
                 },
                 Ast::ColumnDef {
-                    name: "HAIR_COLOR".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                    name: "artist".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::References {
+                        table: "artist".to_string(),
+                        columns: vec![],
+                    }],
                 },
                 Ast::ColumnDef {
-                    name: "APPEARANCE_COUNT".to_string(),
-                    data_type: "INTEGER".to_string(),
+                    name: "a".to_string(),
+                    data_type: "INT".to_string(),
                     constraints: vec![],
                 },
                 Ast::ColumnDef {
-                    name: "FIRST_APPEARANCE".to_string(),
+                    name: "b".to_string(),
                     data_type: "TEXT".to_string(),
                     constraints: vec![],
                 },
-                Ast::ColumnDef {
-                    name: "FIRST_APPEARANCE_YEAR".to_string(),
-                    data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                Ast::ForeignKey {
+                    columns: vec!["a".to_string(), "b".to_string()],
+                    table: "pair".to_string(),
+                    table_columns: vec!["x".to_string(), "y".to_string()],
                 },
             ],
-        }))]);
+            without_rowid: false,
+        };
 
-        let ast = parser.parse();
-        assert_eq!(ast, expected);
+        assert_eq!(parser.parse_create().unwrap(), expected);
     }
 
     #[test]
-    fn create_table_with_string_literal_column_name() {
-        let input = "CREATE TABLE companies\n(\n\tid integer primary key autoincrement\n, \"size range\" text, locality text);";
+    fn create_table_with_primary_key() {
+        let input = "CREATE TABLE pairs (a INTEGER, b TEXT NOT NULL, CONSTRAINT pk PRIMARY KEY (b, a DESC))";
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.lex();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(lexer.lex().unwrap());
 
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
-            name: "COMPANIES".to_string(),
+        let expected = Ast::CreateTable {
+            name: "pairs".to_string(),
             column_defs: vec![
                 Ast::ColumnDef {
-                    name: "ID".to_string(),
+                    name: "a".to_string(),
                     data_type: "INTEGER".to_string(),
-                    constraints: vec![Constraint::PrimaryKey, Constraint::AutoIncrement],
-                },
-                Ast::ColumnDef {
-                    name: "SIZE RANGE".to_string(),
-                    data_type: "TEXT".to_string(),
                     constraints: vec![],
                 },
                 Ast::ColumnDef {
-                    name: "LOCALITY".to_string(),
+                    name: "b".to_string(),
                     data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                    constraints: vec![Constraint::NotNull],
                 },
+                Ast::PrimaryKey(vec![
+                    Ast::IndexedColumn {
+                        name: "b".to_string(),
+                        collation: None,
+                        descending: false,
+                    },
+                    Ast::IndexedColumn {
+                        name: "a".to_string(),
+                        collation: None,
+                        descending: true,
+                    },
+                ]),
             ],
-        }))]);
+            without_rowid: false,
+        };
 
-        let ast = parser.parse();
-        assert_eq!(ast, expected);
+        assert_eq!(parser.parse_create().unwrap(), expected);
     }
 
     #[test]
-    fn sqlite_sequence() {
-        let input = "CREATE TABLE sqlite_sequence(name,seq);";
+    fn create_table_with_column_constraints() {
+        let input = "CREATE TABLE br ([a b] TEXT CONSTRAINT nn NOT NULL ON CONFLICT IGNORE COLLATE NOCASE DEFAULT 'x' UNIQUE CHECK (length([a b] || '!') > 1), n REAL DEFAULT -1 NULL, id INTEGER PRIMARY KEY DESC ON CONFLICT REPLACE AUTOINCREMENT, d DEFAULT ( date('now') ), g GENERATED ALWAYS AS (n * 2) STORED, v AS (n + 1))";
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.lex();
-        let mut parser = Parser::new(tokens);
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens).with_source(input, lexer.spans());
 
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateTable {
-            name: "SQLITE_SEQUENCE".to_string(),
+        let expected = Ast::CreateTable {
+            name: "br".to_string(),
             column_defs: vec![
                 Ast::ColumnDef {
-                    name: "NAME".to_string(),
+                    name: "a b".to_string(),
                     data_type: "TEXT".to_string(),
-                    constraints: vec![],
+                    constraints: vec![
+                        Constraint::NotNull,
+                        Constraint::Collate("NOCASE".to_string()),
+                        Constraint::Default("'x'".to_string()),
+                        Constraint::Check,
+                    ],
+                },
+                Ast::ColumnDef {
+                    name: "n".to_string(),
+                    data_type: "REAL".to_string(),
+                    constraints: vec![Constraint::Default("-1".to_string())],
                 },
                 Ast::ColumnDef {
-                    name: "SEQ".to_string(),
+                    name: "id".to_string(),
                     data_type: "INTEGER".to_string(),
-                    constraints: vec![],
+                    constraints: vec![
+                        Constraint::PrimaryKey { descending: true },
+                        Constraint::AutoIncrement,
+                    ],
+                },
+                Ast::ColumnDef {
+                    name: "d".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::Default("date('now')".to_string())],
+                },
+                Ast::ColumnDef {
+                    name: "g".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::Generated { stored: true }],
+                },
+                Ast::ColumnDef {
+                    name: "v".to_string(),
+                    data_type: String::new(),
+                    constraints: vec![Constraint::Generated { stored: false }],
                 },
             ],
-        }))]);
+            without_rowid: false,
+        };
 
-        let ast = parser.parse();
-        assert_eq!(ast, expected);
+        assert_eq!(parser.parse_create().unwrap(), expected);
     }
 
     #[test]
-    fn create_index() {
-        let input =
-            "CREATE INDEX idx_superheroes_first_appeared ON superheroes (first_appearance);";
+    fn syntax_error() {
+        let input = "SELECT name FROM apples WHERE = 1";
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.lex();
-        let mut parser = Parser::new(tokens);
-
-        let expected = Ast::StmtList(vec![Ast::Stmt(Box::new(Ast::CreateIndex {
-            name: "IDX_SUPERHEROES_FIRST_APPEARED".to_string(),
-            table_name: "SUPERHEROES".to_string(),
-            // TODO: This isn't exactly true to spec, I'm taking some easier shortcuts to get this challenge done!
-            columns: vec![Ast::Identifier("FIRST_APPEARANCE".to_string())],
-        }))]);
-
-        let ast = parser.parse();
-        assert_eq!(ast, expected);
+        let tokens = lexer.lex().unwrap();
+        let mut parser = Parser::new(tokens).with_source(input, lexer.spans());
+
+        let Err(Error::Syntax {
+            near,
+            span,
+            expected,
+        }) = parser.parse()
+        else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(near, "=");
+        assert_eq!(span, Some(30..31));
+        assert_eq!(expected, "an expression");
     }
 }
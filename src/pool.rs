@@ -0,0 +1,218 @@
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+use crate::error::Result;
+use crate::Connection;
+
+// A fixed number of connections to one database, for servers that answer many read queries at
+// once. Each connection has a pager and a schema of its own, though every pager in the process
+// shares one handle on the file (see `OpenFile` in the pager). A connection is checked out with
+// `get`, and goes back to the pool when the guard it comes in is dropped:
+//
+//     let pool = Pool::open("sample.db", 8)?;
+//     let mut connection = pool.get()?;
+//     let rows = connection.query("SELECT name FROM apples")?;
+//
+// Connections only read the schema when they are opened, so before one is handed out again, the
+// schema cookie in the database header is compared with the one it was opened with. If another
+// process has changed the schema since, the connection is replaced with a new one.
+pub struct Pool {
+    path: PathBuf,
+    max_size: usize,
+    state: Mutex<State>,
+    // Signalled whenever a connection is checked back in.
+    returned: Condvar,
+}
+
+struct State {
+    idle: Vec<Connection>,
+    // Connections that exist, checked out or not.
+    open: usize,
+}
+
+impl Pool {
+    // Opens the first connection straight away, so that a database that can't be opened is an
+    // error here rather than on the first checkout.
+    pub fn open(path: impl AsRef<Path>, max_size: usize) -> Result<Pool> {
+        let path = path.as_ref().to_path_buf();
+        let connection = Connection::open(&path)?;
+
+        Ok(Pool {
+            path,
+            max_size: max_size.max(1),
+            state: Mutex::new(State {
+                idle: vec![connection],
+                open: 1,
+            }),
+            returned: Condvar::new(),
+        })
+    }
+
+    // Checks out an idle connection, opens a new one if the pool isn't full, or waits for one to
+    // be checked back in.
+    pub fn get(&self) -> Result<PooledConnection<'_>> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(connection) = state.idle.pop() {
+                drop(state);
+                return self.check(connection);
+            }
+
+            if state.open < self.max_size {
+                state.open += 1;
+                drop(state);
+                return self.connect();
+            }
+
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+
+    // How many connections are open, and how many of them are idle.
+    pub fn size(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.open, state.idle.len())
+    }
+
+    // Hands out a connection that was idle, or a new one in its place if the schema changed.
+    fn check(&self, connection: Connection) -> Result<PooledConnection<'_>> {
+        match connection.schema_changed() {
+            Ok(false) => Ok(PooledConnection {
+                pool: self,
+                connection: Some(connection),
+            }),
+            _ => self.connect(),
+        }
+    }
+
+    // Opens a connection for a slot that has already been counted in `open`, and gives the slot
+    // back if it can't be opened.
+    fn connect(&self) -> Result<PooledConnection<'_>> {
+        match Connection::open(&self.path) {
+            Ok(connection) => Ok(PooledConnection {
+                pool: self,
+                connection: Some(connection),
+            }),
+            Err(err) => {
+                self.state.lock().unwrap().open -= 1;
+                self.returned.notify_one();
+                Err(err)
+            }
+        }
+    }
+
+    fn check_in(&self, connection: Connection) {
+        self.state.lock().unwrap().idle.push(connection);
+        self.returned.notify_one();
+    }
+}
+
+// A connection checked out of a pool, which it goes back to when this is dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    connection: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.check_in(connection);
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use std::sync::atomic::{AtomicBool, Ordering};
+    #[allow(unused_imports)]
+    use std::time::Duration;
+
+    #[allow(dead_code)]
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        #[allow(dead_code)]
+        fn new(name: &str) -> Self {
+            let name = format!("{}-{}.db", name, std::process::id());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, include_bytes!("../tests/fixtures/people.db")).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // With every connection checked out, `get` waits until one comes back, and then has it.
+    #[test]
+    fn waits_for_a_connection() {
+        let file = TempFile::new("pool-waits");
+        let pool = Pool::open(&file.0, 1).unwrap();
+        let connection = pool.get().unwrap();
+        let got = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| {
+                let mut connection = pool.get().unwrap();
+                got.store(true, Ordering::SeqCst);
+                connection
+                    .query("SELECT count(*) FROM people")
+                    .unwrap()
+                    .rows
+                    .len()
+            });
+
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!got.load(Ordering::SeqCst));
+            assert_eq!(pool.size(), (1, 0));
+
+            drop(connection);
+            assert_eq!(waiter.join().unwrap(), 1);
+        });
+        assert!(got.load(Ordering::SeqCst));
+        assert_eq!(pool.size(), (1, 1));
+    }
+
+    // A connection that was idle while another changed the schema is replaced before it's handed
+    // out again, and the new one has the new schema from the start.
+    #[test]
+    fn replaces_connections_after_a_schema_change() {
+        let file = TempFile::new("pool-schema");
+        let pool = Pool::open(&file.0, 2).unwrap();
+        let names = |connection: &Connection| {
+            connection
+                .tables()
+                .iter()
+                .map(|table| table.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&pool.get().unwrap()), ["people"]);
+
+        let mut other = Connection::open(&file.0).unwrap();
+        other.query("CREATE TABLE pets(name TEXT)").unwrap();
+
+        assert_eq!(names(&pool.get().unwrap()), ["people", "pets"]);
+        assert_eq!(pool.size(), (1, 1));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::catalog::{IndexOrigin, Table};
+use crate::error::Result;
+use crate::pager::Synchronous;
+use crate::vtab::{Constraint, VirtualCursor, VirtualTable};
+use crate::{foreign_key, integrity};
+use crate::{Db, Value};
+
+// The pragmas that describe the schema, as tables that can be queried like any other, under the
+// names sqlite gives them:
+//
+//     SELECT name, type FROM pragma_table_info('apples') WHERE pk = 1
+//
+// The argument names the table or index to describe. There are no rows for one that isn't in the
+// schema, or when the argument is left out. The integrity checks take either the most problems to
+// report, or the name of the one table to check, and check the whole database without one, as
+// does the foreign key check.
+//
+// `synchronous` is a setting rather than a description: with an argument, which is a level's name
+// or its number, it sets how commits flush their writes, and without one it has the level it is
+// set to as its one row.
+pub(crate) struct Pragma<'a> {
+    db: &'a Db,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    TableInfo,
+    TableXInfo,
+    IndexList,
+    IndexInfo,
+    IndexXInfo,
+    IntegrityCheck,
+    QuickCheck,
+    ForeignKeyCheck,
+    Synchronous,
+}
+
+impl<'a> Pragma<'a> {
+    // The pragma that a table name like `pragma_table_info` stands for, if there is one.
+    pub(crate) fn open(db: &'a Db, name: &str) -> Option<Self> {
+        let kind = match name.to_ascii_uppercase().strip_prefix("PRAGMA_")? {
+            "TABLE_INFO" => Kind::TableInfo,
+            "TABLE_XINFO" => Kind::TableXInfo,
+            "INDEX_LIST" => Kind::IndexList,
+            "INDEX_INFO" => Kind::IndexInfo,
+            "INDEX_XINFO" => Kind::IndexXInfo,
+            "INTEGRITY_CHECK" => Kind::IntegrityCheck,
+            "QUICK_CHECK" => Kind::QuickCheck,
+            "FOREIGN_KEY_CHECK" => Kind::ForeignKeyCheck,
+            "SYNCHRONOUS" => Kind::Synchronous,
+            _ => return None,
+        };
+        Some(Self { db, kind })
+    }
+
+    fn rows(&self, args: &[Value]) -> Result<Vec<Vec<Value>>> {
+        match self.kind {
+            Kind::IntegrityCheck | Kind::QuickCheck => return Ok(self.check(args.first())),
+            Kind::ForeignKeyCheck => {
+                let table = args.first().map(|table| table.to_string());
+                return foreign_key::check(self.db, table.as_deref());
+            }
+            Kind::Synchronous => return Ok(self.synchronous(args.first())),
+            _ => {}
+        }
+        let name = match args.first() {
+            Some(Value::Null) | None => return Ok(Vec::new()),
+            Some(name) => name.to_string(),
+        };
+        let name = name.as_str();
+
+        let tables = self.db.tables();
+        let table = tables
+            .iter()
+            .find(|table| table.name().eq_ignore_ascii_case(name));
+
+        Ok(match (self.kind, table) {
+            // table_info leaves out the hidden columns, which in a table read from the file are
+            // the generated ones, and numbers the rest without them.
+            (Kind::TableInfo | Kind::TableXInfo, Some(table)) => table
+                .columns()
+                .iter()
+                .filter(|column| self.kind == Kind::TableXInfo || !column.generated())
+                .enumerate()
+                .map(|(cid, column)| {
+                    let mut row = vec![
+                        Value::Int(cid as i64),
+                        Value::Text(column.name().to_string()),
+                        Value::Text(column.declared_type().to_string()),
+                        Value::Int(column.not_null() as i64),
+                        column
+                            .default()
+                            .map_or(Value::Null, |text| Value::Text(text.to_string())),
+                        Value::Int(column.primary_key().unwrap_or(0) as i64),
+                    ];
+                    // A stored generated column is hidden as 3.
+                    if self.kind == Kind::TableXInfo {
+                        row.push(Value::Int(if column.generated() { 3 } else { 0 }));
+                    }
+                    row
+                })
+                .collect(),
+            // The most recently made index comes first.
+            (Kind::IndexList, Some(table)) => table
+                .indexes()
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(seq, index)| {
+                    let origin = match index.origin() {
+                        IndexOrigin::Create => "c",
+                        IndexOrigin::Unique => "u",
+                        IndexOrigin::PrimaryKey => "pk",
+                    };
+                    vec![
+                        Value::Int(seq as i64),
+                        Value::Text(index.name().to_string()),
+                        Value::Int(index.unique() as i64),
+                        Value::Text(origin.to_string()),
+                        Value::Int(index.partial() as i64),
+                    ]
+                })
+                .collect(),
+            (Kind::IndexInfo | Kind::IndexXInfo, _) => {
+                index_info(tables, name, self.kind == Kind::IndexXInfo)
+            }
+            _ => Vec::new(),
+        })
+    }
+
+    // Like sqlite, a level it doesn't know is taken as NORMAL.
+    fn synchronous(&self, arg: Option<&Value>) -> Vec<Vec<Value>> {
+        let Some(arg) = arg else {
+            let level = self.db.pager.synchronous() as i64;
+            return vec![vec![Value::Int(level)]];
+        };
+        let level = match arg {
+            Value::Int(0) => Synchronous::Off,
+            Value::Int(2) => Synchronous::Full,
+            Value::Int(3) => Synchronous::Extra,
+            Value::Text(name) => match name.to_ascii_uppercase().as_str() {
+                "OFF" | "NO" | "FALSE" => Synchronous::Off,
+                "FULL" => Synchronous::Full,
+                "EXTRA" => Synchronous::Extra,
+                _ => Synchronous::Normal,
+            },
+            _ => Synchronous::Normal,
+        };
+        self.db.pager.set_synchronous(level);
+        Vec::new()
+    }
+
+    // sqlite reports at most 100 problems unless told otherwise.
+    fn check(&self, arg: Option<&Value>) -> Vec<Vec<Value>> {
+        let (max_errors, table) = match arg {
+            Some(Value::Int(n)) if *n > 0 => (*n as usize, None),
+            Some(Value::Text(table)) => (100, Some(table.as_str())),
+            _ => (100, None),
+        };
+        let quick = self.kind == Kind::QuickCheck;
+
+        let problems = integrity::check(self.db, quick, max_errors, table);
+        if problems.is_empty() {
+            return vec![vec![Value::Text("ok".to_string())]];
+        }
+        problems
+            .into_iter()
+            .map(|problem| vec![Value::Text(problem)])
+            .collect()
+    }
+}
+
+fn index_info(tables: &[Table], name: &str, extended: bool) -> Vec<Vec<Value>> {
+    let found = tables.iter().find_map(|table| {
+        let index = table
+            .indexes()
+            .iter()
+            .find(|index| index.name().eq_ignore_ascii_case(name))?;
+        Some((table, index))
+    });
+    let Some((table, index)) = found else {
+        return Vec::new();
+    };
+
+    let mut rows = index
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(seqno, column)| {
+            let name = column.name();
+            let cid = table
+                .columns()
+                .iter()
+                .position(|column| column.name().eq_ignore_ascii_case(name));
+            // The name as the table declares it, rather than as the index wrote it.
+            let name = cid.map_or(name, |cid| table.columns()[cid].name());
+            let mut row = vec![
+                Value::Int(seqno as i64),
+                Value::Int(cid.map_or(-1, |cid| cid as i64)),
+                Value::Text(name.to_string()),
+            ];
+            if extended {
+                row.extend([
+                    Value::Int(column.descending() as i64),
+                    Value::Text(column.collation().to_string()),
+                    Value::Int(1),
+                ]);
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+
+    if !extended {
+        return rows;
+    }
+    // After its key, each entry of an index on a rowid table has the rowid of its row. On a
+    // WITHOUT ROWID table, it has the columns of the primary key that aren't in the key already,
+    // and the primary key's own index has all of the other columns, which aren't sorted by
+    // anything.
+    let in_key = |name: &str| {
+        index
+            .columns()
+            .iter()
+            .any(|column| column.name().eq_ignore_ascii_case(name))
+    };
+    let rest = match (table.without_rowid(), index.origin()) {
+        (false, _) => vec![None],
+        (true, IndexOrigin::PrimaryKey) => table
+            .columns()
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| !in_key(column.name()))
+            .map(|(cid, column)| Some((cid, column.name(), "BINARY")))
+            .collect(),
+        (true, _) => table
+            .indexes()
+            .iter()
+            .filter(|index| index.origin() == IndexOrigin::PrimaryKey)
+            .flat_map(|key| key.columns())
+            .filter(|column| !in_key(column.name()))
+            .filter_map(|column| {
+                let cid = table
+                    .columns()
+                    .iter()
+                    .position(|declared| declared.name().eq_ignore_ascii_case(column.name()))?;
+                Some(Some((cid, table.columns()[cid].name(), column.collation())))
+            })
+            .collect(),
+    };
+    for column in rest {
+        let (cid, name, collation) = match column {
+            Some((cid, name, collation)) => (cid as i64, Value::Text(name.to_string()), collation),
+            None => (-1, Value::Null, "BINARY"),
+        };
+        rows.push(vec![
+            Value::Int(rows.len() as i64),
+            Value::Int(cid),
+            name,
+            Value::Int(0),
+            Value::Text(collation.to_string()),
+            Value::Int(0),
+        ]);
+    }
+    rows
+}
+
+impl VirtualTable for Pragma<'_> {
+    fn columns(&self) -> Vec<String> {
+        let columns: &[&str] = match self.kind {
+            Kind::TableInfo => &["cid", "name", "type", "notnull", "dflt_value", "pk"],
+            Kind::TableXInfo => &[
+                "cid",
+                "name",
+                "type",
+                "notnull",
+                "dflt_value",
+                "pk",
+                "hidden",
+            ],
+            Kind::IndexList => &["seq", "name", "unique", "origin", "partial"],
+            Kind::IndexInfo => &["seqno", "cid", "name"],
+            Kind::IndexXInfo => &["seqno", "cid", "name", "desc", "coll", "key"],
+            Kind::IntegrityCheck => &["integrity_check"],
+            Kind::QuickCheck => &["quick_check"],
+            Kind::ForeignKeyCheck => &["table", "rowid", "parent", "fkid"],
+            Kind::Synchronous => &["synchronous"],
+        };
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>> {
+        Ok(Box::new(PragmaCursor {
+            pragma: self,
+            rows: Vec::new(),
+            position: 0,
+        }))
+    }
+}
+
+struct PragmaCursor<'a> {
+    pragma: &'a Pragma<'a>,
+    rows: Vec<Vec<Value>>,
+    position: usize,
+}
+
+impl VirtualCursor for PragmaCursor<'_> {
+    fn filter(&mut self, args: &[Value], _constraints: &[Constraint]) -> Result<()> {
+        self.rows = self.pragma.rows(args)?;
+        self.position = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.position += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.position >= self.rows.len()
+    }
+
+    fn column(&self, index: usize) -> Result<Value> {
+        Ok(self.rows[self.position][index].clone())
+    }
+
+    // Rows are numbered from 1, in the order they come.
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.position as i64 + 1)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // The rows of a query as sqlite3 lists them, with NULL as nothing at all.
+    #[allow(dead_code)]
+    fn list(connection: &mut Connection, sql: &str) -> Vec<String> {
+        let rows = connection.query(sql).unwrap();
+        rows.rows
+            .iter()
+            .map(|row| {
+                let values = row.values().iter().map(|value| match value {
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                });
+                values.collect::<Vec<_>>().join("|")
+            })
+            .collect()
+    }
+
+    // The pragma functions describe the schema as sqlite3 does, and can be filtered like any other
+    // table:
+    //
+    //     $ sqlite3 objects.db "SELECT * FROM pragma_table_info('b')"
+    //     0|x|INT|0||0
+    //     1|y|INT|0||0
+    //     2|z|TEXT|0||0
+    #[test]
+    fn describes_the_schema_like_sqlite3() {
+        let bytes = include_bytes!("../tests/fixtures/objects.db");
+        let mut connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let mut list = |sql: &str| list(&mut connection, sql);
+
+        assert_eq!(
+            list("SELECT * FROM pragma_table_info('b')"),
+            ["0|x|INT|0||0", "1|y|INT|0||0", "2|z|TEXT|0||0"]
+        );
+        assert_eq!(
+            list("SELECT name, pk FROM pragma_table_info('a') WHERE pk > 0"),
+            ["id|1"]
+        );
+        assert_eq!(
+            list("SELECT * FROM pragma_index_list('b')"),
+            ["0|b_xy|0|c|0"]
+        );
+        assert_eq!(
+            list("SELECT * FROM pragma_index_info('b_xy')"),
+            ["0|0|x", "1|1|y"]
+        );
+        assert!(list("SELECT * FROM pragma_table_info('nope')").is_empty());
+    }
+}
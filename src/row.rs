@@ -0,0 +1,163 @@
+use std::ops::Index;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::Value;
+
+// One row of a result set. Its values can be read by position or by column name, and converted
+// to Rust types on the way out:
+//
+//     let id: i64 = row.get(0)?;
+//     let name: String = row.get("name")?;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    // Shared by every row of the same result set.
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub(crate) fn new(columns: Arc<[String]>, values: Vec<Value>) -> Row {
+        Row { columns, values }
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn column_name(&self, index: usize) -> Result<&str, Error> {
+        self.columns
+            .get(index)
+            .map(String::as_str)
+            .ok_or(Error::Range)
+    }
+
+    // The value of a column, as it was read.
+    pub fn get_ref(&self, column: impl ColumnIndex) -> Result<&Value, Error> {
+        let index = column.index(self)?;
+        self.values.get(index).ok_or(Error::Range)
+    }
+
+    // The value of a column, converted to a T.
+    pub fn get<T: FromValue>(&self, column: impl ColumnIndex) -> Result<T, Error> {
+        T::from_value(self.get_ref(column)?)
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
+
+impl Index<usize> for Row {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+}
+
+// Something that picks out a column of a row: its position, counting from 0, or its name.
+pub trait ColumnIndex {
+    fn index(&self, row: &Row) -> Result<usize, Error>;
+}
+
+impl ColumnIndex for usize {
+    fn index(&self, row: &Row) -> Result<usize, Error> {
+        if *self < row.column_count() {
+            Ok(*self)
+        } else {
+            Err(Error::Range)
+        }
+    }
+}
+
+// Names are matched ignoring ASCII case, like every other name in SQL. When two columns share a
+// name, the first one wins.
+impl ColumnIndex for &str {
+    fn index(&self, row: &Row) -> Result<usize, Error> {
+        row.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(self))
+            .ok_or_else(|| Error::NoSuchColumn(self.to_string()))
+    }
+}
+
+// A Rust type that a column's value can be read as.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, Error>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+// Anything a Value converts to can be read from a row, through its TryFrom conversion.
+macro_rules! from_value_via_try_from {
+    ($($t:ty),*) => {$(
+        impl FromValue for $t {
+            fn from_value(value: &Value) -> Result<Self, Error> {
+                value.clone().try_into()
+            }
+        }
+    )*};
+}
+
+#[rustfmt::skip]
+from_value_via_try_from!(
+    i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f64, bool, String, Vec<u8>
+);
+
+// A column that may be NULL reads as None when it is.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // A row reads by position or by name, in any case, the values sqlite3 shows for it:
+    //
+    //     $ sqlite3 -header people.db "SELECT * FROM people WHERE id = 11"
+    //     id|name|age|score|x
+    //     11|p11||1.3|x3
+    #[test]
+    fn reads_columns() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        let mut connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let rows = connection
+            .query("SELECT * FROM people WHERE id = 11")
+            .unwrap();
+        let row = &rows.rows[0];
+
+        assert_eq!(row.column_count(), 5);
+        assert_eq!(row.column_name(1).unwrap(), "name");
+        assert!(matches!(row.column_name(5), Err(Error::Range)));
+
+        assert_eq!(row.get::<i64>(0).unwrap(), 11);
+        assert_eq!(row.get::<String>("NAME").unwrap(), "p11");
+        assert_eq!(row.get::<Option<i64>>("age").unwrap(), None);
+        assert_eq!(row.get::<f64>("score").unwrap(), 1.2999999999999998);
+        assert_eq!(row[4], Value::Text("x3".to_string()));
+
+        assert!(matches!(row.get::<i64>(5), Err(Error::Range)));
+        assert!(matches!(row.get::<i64>("age"), Err(Error::Mismatch)));
+        assert!(matches!(
+            row.get::<i64>("nope"),
+            Err(Error::NoSuchColumn(name)) if name == "nope"
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::vtab::{Constraint, ConstraintOp, VirtualCursor, VirtualTable};
+use crate::{Db, MasterPageRecord, Value, ValueRef};
+
+// R-tree tables, as made by sqlite's rtree and rtree_i32 modules, for finding boxes by range.
+//
+// The tree is kept in shadow tables: `<table>_node` holds each node as a blob, keyed by its
+// number, with the root as node 1, and `<table>_rowid` says which leaf node each entry is in. A
+// node is a two byte depth (only meaningful in the root, where it is the height of the tree), a
+// two byte cell count, and then its cells: a 64 bit id, which is the entry's id in a leaf and a
+// child node's number above that, followed by a min and max coordinate for every dimension. The
+// coordinates are 32 bit floats for rtree and 32 bit integers for rtree_i32, all big-endian.
+//
+// A lookup by id goes straight to the leaf it is in. Range constraints on the coordinates skip
+// every subtree whose bounding box can't hold a match, which is the point of an R-tree.
+pub(crate) struct RTree<'a> {
+    db: &'a Db,
+    columns: Vec<String>,
+    integer: bool,
+    nodes: Arc<MasterPageRecord>,
+    rowids: Arc<MasterPageRecord>,
+}
+
+// sqlite won't build a tree any deeper than this.
+const MAX_DEPTH: u16 = 40;
+
+pub(crate) fn is_rtree(module: &str) -> bool {
+    ["RTREE", "RTREE_I32"].contains(&module.to_ascii_uppercase().as_str())
+}
+
+// An entry, or a child node and the box around everything in it.
+#[derive(Debug, Clone)]
+struct Cell {
+    id: i64,
+    coordinates: Vec<f64>,
+}
+
+impl<'a> RTree<'a> {
+    pub(crate) fn open(db: &'a Db, table: &MasterPageRecord) -> Result<Self> {
+        let module = table.module.as_ref().unwrap();
+        Ok(Self {
+            db,
+            columns: table.column_names(),
+            integer: module.name.eq_ignore_ascii_case("RTREE_I32"),
+            nodes: db.get_table(&format!("{}_node", table.name))?,
+            rowids: db.get_table(&format!("{}_rowid", table.name))?,
+        })
+    }
+
+    fn node(&self, number: i64) -> Result<(u16, Vec<Cell>)> {
+        let corrupt = |message: String| Error::Corrupt {
+            page: self.nodes.root_page,
+            message,
+        };
+
+        let rows = self
+            .db
+            .get_table_rows(&self.nodes, Some(&[number as u64]))?;
+        let Some(ValueRef::Blob(data)) = rows.first().map(|row| row.value(1)) else {
+            return Err(corrupt(format!("rtree node {} is missing", number)));
+        };
+
+        let width = 8 + 4 * (self.columns.len() - 1);
+        let (depth, count) = match data {
+            [a, b, c, d, ..] => (u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d])),
+            _ => return Err(corrupt(format!("rtree node {} is too short", number))),
+        };
+        let cells = data[4..]
+            .chunks_exact(width)
+            .take(count as usize)
+            .map(|cell| Cell {
+                id: i64::from_be_bytes(cell[..8].try_into().unwrap()),
+                coordinates: cell[8..]
+                    .chunks_exact(4)
+                    .map(|bytes| {
+                        let bytes = bytes.try_into().unwrap();
+                        if self.integer {
+                            i32::from_be_bytes(bytes) as f64
+                        } else {
+                            f32::from_be_bytes(bytes) as f64
+                        }
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        if cells.len() != count as usize {
+            return Err(corrupt(format!("rtree node {} is too short", number)));
+        }
+
+        Ok((depth, cells))
+    }
+
+    // Walks the tree from the root, depth first, collecting the entries that meet every
+    // constraint on the coordinates.
+    fn search(&self, constraints: &[Constraint]) -> Result<Vec<Cell>> {
+        let (height, root) = self.node(1)?;
+        if height > MAX_DEPTH {
+            return Err(Error::Corrupt {
+                page: self.nodes.root_page,
+                message: format!("rtree is {} levels deep", height),
+            });
+        }
+
+        let mut entries = Vec::new();
+        let mut stack = vec![(root, height)];
+
+        while let Some((cells, depth)) = stack.pop() {
+            let mut children = Vec::new();
+            for cell in cells {
+                if depth == 0 {
+                    if constraints.iter().all(|c| self.holds(c, &cell, false)) {
+                        entries.push(cell);
+                    }
+                } else if constraints.iter().all(|c| self.holds(c, &cell, true)) {
+                    let (_, cells) = self.node(cell.id)?;
+                    children.push((cells, depth - 1));
+                }
+            }
+            stack.extend(children.into_iter().rev());
+        }
+
+        Ok(entries)
+    }
+
+    // Finds entries by id through the rowid table, which says which leaf each of them is in.
+    fn lookup(&self, ids: &[Value], constraints: &[Constraint]) -> Result<Vec<Cell>> {
+        let mut ids = ids
+            .iter()
+            .filter_map(|id| match id {
+                Value::Int(id) => Some(*id as u64),
+                Value::Real(id) if id.fract() == 0.0 => Some(*id as u64),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut entries = Vec::new();
+        for row in self.db.get_table_rows(&self.rowids, Some(&ids))? {
+            let id = row.header.row_id as i64;
+            let ValueRef::Int(node) = row.value(1) else {
+                continue;
+            };
+            let (_, cells) = self.node(node)?;
+            entries.extend(cells.into_iter().filter(|cell| {
+                cell.id == id && constraints.iter().all(|c| self.holds(c, cell, false))
+            }));
+        }
+        Ok(entries)
+    }
+
+    // Whether an entry meets a constraint or, for the box of a subtree, whether anything in it
+    // could. Constraints this can't judge, like ones against text, let everything through.
+    fn holds(&self, constraint: &Constraint, cell: &Cell, subtree: bool) -> bool {
+        if constraint.column == 0 || constraint.column >= self.columns.len() {
+            return true;
+        }
+        let dimension = (constraint.column - 1) / 2;
+        let (low, high) = if subtree {
+            (
+                cell.coordinates[dimension * 2],
+                cell.coordinates[dimension * 2 + 1],
+            )
+        } else {
+            let value = cell.coordinates[constraint.column - 1];
+            (value, value)
+        };
+
+        constraint.values.iter().any(|value| {
+            let value = match value {
+                Value::Int(value) => *value as f64,
+                Value::Real(value) => *value,
+                _ => return true,
+            };
+            match constraint.op {
+                ConstraintOp::Equal => low <= value && value <= high,
+                ConstraintOp::Less => low < value,
+                ConstraintOp::LessEqual => low <= value,
+                ConstraintOp::Greater => high > value,
+                ConstraintOp::GreaterEqual => high >= value,
+                ConstraintOp::Match => true,
+            }
+        })
+    }
+}
+
+impl VirtualTable for RTree<'_> {
+    fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>> {
+        Ok(Box::new(RTreeCursor {
+            rtree: self,
+            cells: Vec::new(),
+            position: 0,
+        }))
+    }
+}
+
+struct RTreeCursor<'a> {
+    rtree: &'a RTree<'a>,
+    cells: Vec<Cell>,
+    position: usize,
+}
+
+impl VirtualCursor for RTreeCursor<'_> {
+    fn filter(&mut self, _args: &[Value], constraints: &[Constraint]) -> Result<()> {
+        let ids = constraints
+            .iter()
+            .find(|c| c.column == 0 && c.op == ConstraintOp::Equal);
+
+        self.cells = match ids {
+            Some(ids) => self.rtree.lookup(&ids.values, constraints)?,
+            None => self.rtree.search(constraints)?,
+        };
+        self.position = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.position += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.position >= self.cells.len()
+    }
+
+    fn column(&self, index: usize) -> Result<Value> {
+        let cell = &self.cells[self.position];
+        Ok(match index {
+            0 => Value::Int(cell.id),
+            _ if self.rtree.integer => Value::Int(cell.coordinates[index - 1] as i64),
+            _ => Value::Real(cell.coordinates[index - 1]),
+        })
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.cells[self.position].id)
+    }
+}
@@ -0,0 +1,1641 @@
+// The sqlite3 style command line: flags, dot commands, and statements, run against one database.
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+
+use anyhow::{bail, Context, Result};
+
+use crate::catalog::ObjectType;
+use crate::error::Error;
+use crate::lexer::{Lexer, Token};
+use crate::line_editor::LineEditor;
+use crate::output::{unescape, Mode, OutputSettings, ResultWriter, KEYWORDS};
+use crate::pager::{PagerOptions, PagerStats};
+use crate::sql_engine::SqlEngine;
+use crate::{bench, dbhash, dump, inspect, like, parquet, Db, Row, Rows, SOURCE_ID};
+
+// Errors are reported the way sqlite3 reports them, on stderr, with an exit code scripts can check.
+// Output goes through one locked, buffered handle on stdout, rather than locking and flushing it
+// for every row, and is flushed after each command, before anything it has to say on stderr.
+pub fn main() -> ExitCode {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let result = run(std::env::args().collect(), &mut out);
+    drop(out);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            report(&err);
+            let code = err.downcast_ref::<Error>().map_or(1, Error::exit_code);
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn report(err: &anyhow::Error) {
+    eprintln!("{}", error_message(err));
+}
+
+// What sqlite3 prints for an error. One from a statement says whether it failed while being
+// prepared or while it ran, and gives its result code when that says more than a plain error,
+// followed by the statement with the error pointed out, where it can be.
+fn error_message(err: &anyhow::Error) -> String {
+    let phase = match err.downcast_ref::<Error>() {
+        Some(statement) if statement.is_prepare() => "in prepare",
+        Some(_) if err.downcast_ref::<Stepping>().is_some() => "stepping",
+        _ => return format!("Error: {}", err),
+    };
+    let statement = err.downcast_ref::<Error>().unwrap();
+    let mut message = format!("Error: {}, {}", phase, statement);
+    if statement.exit_code() > 1 {
+        message.push_str(&format!(" ({})", statement.exit_code()));
+    }
+    if let Some(context) = err.downcast_ref::<ErrorContext>() {
+        message.push_str(&format!("\n{}", context));
+    }
+    message
+}
+
+// Marks an error as one from a statement that was running, rather than being prepared.
+#[derive(Debug)]
+struct Stepping;
+
+impl std::fmt::Display for Stepping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stepping")
+    }
+}
+
+fn run(mut args: Vec<String>, out: &mut dyn Write) -> Result<()> {
+    // Parse arguments, pulling out any option flags that come before the database path
+    let mut options = PagerOptions::default();
+    let mut output = OutputSettings::default();
+
+    while args.len() > 1 && args[1].starts_with('-') {
+        let flag = args.remove(1);
+        match flag.trim_start_matches('-') {
+            "version" => {
+                writeln!(out, "{} ({}-bit)", SOURCE_ID, usize::BITS)?;
+                return Ok(());
+            }
+            "mmap" => options.mmap = true,
+            "readonly" => {
+                options.shared_lock = true;
+                options.read_only = true;
+            }
+            "salvage" => options.salvage = true,
+            "stats" => output.stats = true,
+            "changes" => output.changes = true,
+            "header" | "noheader" => {
+                output.headers = flag.trim_start_matches('-') == "header";
+                output.headers_set = true;
+            }
+            "mode" | "separator" | "newline" | "width" | "key" => {
+                if args.len() < 2 {
+                    bail!("missing argument to {}", flag);
+                }
+                let value = args.remove(1);
+                match flag.trim_start_matches('-') {
+                    "mode" => output.set_mode(value.parse()?),
+                    "separator" => output.column_separator = value,
+                    "key" => options.key = Some(value),
+                    "width" => {
+                        output.widths = value
+                            .split(',')
+                            .map(|width| width.trim().parse::<i32>())
+                            .collect::<Result<_, _>>()
+                            .context("--width expects a comma separated list of numbers")?;
+                    }
+                    _ => output.row_separator = value,
+                }
+            }
+            // sqlite3 also accepts the name of a mode as a flag of its own, e.g. `-box`.
+            name => match name.parse::<Mode>() {
+                Ok(mode) => output.set_mode(mode),
+                Err(_) => bail!("unknown option: {}", flag),
+            },
+        }
+    }
+
+    if args.len() < 2 {
+        bail!("Missing <database path>");
+    }
+
+    // Without any commands after the path, they're read from stdin instead.
+    let mut db = Db::new(PathBuf::from(&args[1]), options.clone())?;
+    print_warnings(&db);
+    if args.len() == 2 {
+        return repl(db, options, &mut output, out);
+    }
+
+    // Every argument after the path is a command of its own, run in order against the same
+    // database, so settings from a dot command carry over to the statements after it. Like
+    // sqlite3, the first one to fail stops the rest.
+    for command in &args[2..] {
+        let result = run_command(&db, command, &mut output, out);
+        out.flush()?;
+        result?;
+        print_warnings(&db);
+
+        // As in the shell, a statement that changed the schema leaves it out of date for the
+        // commands after it.
+        if db.schema_changed()? {
+            db = db.reopen(options.clone())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_command(
+    db: &Db,
+    command: &str,
+    output: &mut OutputSettings,
+    out: &mut dyn Write,
+) -> Result<()> {
+    match command.strip_prefix('.') {
+        // Dot commands read pages of their own, outside of any statement, so they hold the lock a
+        // statement would.
+        Some(rest) => {
+            let _lock = db.pager.read_lock().map_err(Error::locking)?;
+            handle_dot_command(rest, db, output, out)
+        }
+        None => run_sql_command(db, command, output, out),
+    }
+}
+
+// Reads commands from stdin, a line at a time for dot commands, and as many lines as it takes to
+// end with a semicolon for statements. At a terminal, lines are read with a prompt, and can be
+// edited, gone back to, and completed with Tab, and a command that fails doesn't stop the rest.
+// From a pipe, like the commands given as arguments, the first one to fail does.
+fn repl(
+    mut db: Db,
+    options: PagerOptions,
+    output: &mut OutputSettings,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        writeln!(out, "{}", SOURCE_ID)?;
+        out.flush()?;
+    }
+
+    let mut editor = LineEditor::default();
+    let mut statement = String::new();
+    loop {
+        let prompt = match (interactive, statement.is_empty()) {
+            (false, _) => "",
+            (true, true) => "sqlite> ",
+            (true, false) => "   ...> ",
+        };
+        let line = match editor.read_line(prompt, &|line: &str| completions(&db, line)) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            // Ctrl-C throws away the statement being entered, but not the shell.
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                statement.clear();
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if interactive {
+            editor.add_history(&line);
+        }
+
+        let command = match statement.is_empty() {
+            true if line.trim().is_empty() => continue,
+            true if line.trim_start().starts_with('.') => line.trim().to_string(),
+            _ => {
+                statement.push_str(&line);
+                statement.push('\n');
+                if !is_complete(&statement) {
+                    continue;
+                }
+                std::mem::take(&mut statement)
+            }
+        };
+        if matches!(command.as_str(), ".quit" | ".exit") {
+            break;
+        }
+
+        let result = run_command(&db, &command, output, out);
+        out.flush()?;
+        print_warnings(&db);
+        match result {
+            Ok(()) => {}
+            Err(err) if interactive => report(&err),
+            Err(err) => return Err(err),
+        }
+
+        // A statement that changed the schema, e.g. CREATE TABLE, leaves the one read when the
+        // database was opened out of date, for the statements after it and for completion.
+        if db.schema_changed()? {
+            db = db.reopen(options.clone())?;
+        }
+    }
+
+    Ok(())
+}
+
+// Whether a statement has been entered in full, which is when the last thing in it that isn't
+// whitespace or a comment is a semicolon outside of any quotes.
+fn is_complete(sql: &str) -> bool {
+    let mut chars = sql.chars().peekable();
+    let mut ends_with_semicolon = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                if !chars.by_ref().any(|c| c == close) {
+                    return false;
+                }
+                ends_with_semicolon = false;
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut star = false;
+                loop {
+                    match chars.next() {
+                        Some('/') if star => break,
+                        Some(c) => star = c == '*',
+                        None => return false,
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => ends_with_semicolon = c == ';',
+        }
+    }
+    ends_with_semicolon
+}
+
+// The dot commands the shell has, for completion.
+const DOT_COMMANDS: &[&str] = &[
+    ".bench",
+    ".btree",
+    ".changes",
+    ".databases",
+    ".dbhash",
+    ".dbinfo",
+    ".dump",
+    ".exit",
+    ".headers",
+    ".mode",
+    ".pagedump",
+    ".parquet",
+    ".quit",
+    ".schema",
+    ".separator",
+    ".space",
+    ".stats",
+    ".tables",
+    ".width",
+];
+
+// What the word before the cursor could be completed to, and where it starts: a dot command at
+// the start of a line, a column of the table named before a dot, and otherwise a keyword, or the
+// name of a table or of a column in any of them. Names keep the case they have in the schema, and
+// keywords take the case of what has been typed so far.
+fn completions(db: &Db, line: &str) -> (usize, Vec<String>) {
+    let start = line
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.')
+        .last()
+        .map_or(line.len(), |(i, _)| i);
+    let word = &line[start..];
+    let matches = |candidate: &str| {
+        candidate
+            .get(..word.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(word))
+    };
+
+    let tables = db.tables();
+    let mut candidates = if word.starts_with('.') && line[..start].trim().is_empty() {
+        DOT_COMMANDS
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    } else if let Some((prefix, _)) = word.split_once('.') {
+        let table = tables
+            .iter()
+            .find(|t| t.name().eq_ignore_ascii_case(prefix));
+        let columns = table.into_iter().flat_map(|t| t.columns());
+        columns
+            .map(|c| format!("{}.{}", prefix, c.name()))
+            .collect()
+    } else {
+        let lowercase = word.chars().any(char::is_lowercase);
+        let keywords = KEYWORDS.iter().map(|keyword| match lowercase {
+            true => keyword.to_ascii_lowercase(),
+            false => keyword.to_string(),
+        });
+        let names = tables.iter().flat_map(|table| {
+            let columns = table
+                .columns()
+                .iter()
+                .map(|column| column.name().to_string());
+            std::iter::once(table.name().to_string()).chain(columns)
+        });
+        keywords.chain(names).collect::<Vec<_>>()
+    };
+    candidates.retain(|candidate| matches(candidate));
+    candidates.sort_unstable();
+    candidates.dedup();
+    (start, candidates)
+}
+
+// With -salvage, what each command had to skip is reported after its output.
+fn print_warnings(db: &Db) {
+    for warning in db.pager.take_warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+// Lays names out the way the sqlite3 shell does: sorted down the columns first, each padded to the
+// longest name, with as many columns as fit in 80 characters.
+pub(crate) fn print_in_columns(names: &[&str], out: &mut impl Write) -> io::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let width = names.iter().map(|name| name.chars().count()).max().unwrap();
+    let columns = (80 / (width + 2)).max(1);
+    let rows = names.len().div_ceil(columns);
+
+    for row in 0..rows {
+        let line = names
+            .iter()
+            .skip(row)
+            .step_by(rows)
+            .map(|name| format!("{:<width$}", name))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn run_sql_command(
+    db: &Db,
+    command: &str,
+    output: &OutputSettings,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let before = db.pager.stats();
+
+    let mut writer = ResultWriter::new(&mut *out, output);
+    // Codecrafters input doesn't include a semicolon, but the parser doesn't need one.
+    let mut engine = SqlEngine::new(&mut writer);
+    engine
+        .execute(command, db)
+        .map_err(|err| match err.offset() {
+            Some(offset) => match ErrorContext::new(command, offset) {
+                Some(context) => anyhow::Error::from(err).context(context),
+                None => err.into(),
+            },
+            None if err.is_prepare() => err.into(),
+            None => anyhow::Error::from(err).context(Stepping),
+        })?;
+    let stats = engine.stats();
+    drop(writer);
+
+    if output.changes {
+        writeln!(
+            out,
+            "changes: {}   total_changes: {}",
+            db.changes.load(Ordering::Relaxed),
+            db.total_changes.load(Ordering::Relaxed)
+        )?;
+    }
+    if output.stats {
+        print_stats(&db.pager.stats().since(&before), out)?;
+    }
+    if output.stmt_stats {
+        writeln!(out, "-- {}", stats)?;
+    }
+
+    Ok(())
+}
+
+// The part of a statement around an error, with the error pointed out under it, as sqlite3 shows
+// it: the statement from up to 50 bytes before the error, cut off at 78 bytes, with whitespace
+// turned into spaces so that it's all on one line.
+#[derive(Debug)]
+struct ErrorContext {
+    code: String,
+    offset: usize,
+}
+
+impl ErrorContext {
+    // The offset is in bytes into the SQL, which can hold more than one statement. None when it's
+    // past the end, as it is for a statement that was cut short.
+    fn new(sql: &str, offset: usize) -> Option<ErrorContext> {
+        // The statement runs from the first token after the last semicolon before the error.
+        let mut lexer = Lexer::new(sql[..offset].to_string());
+        let tokens = lexer.lex().ok()?;
+        let start = tokens
+            .iter()
+            .zip(lexer.spans())
+            .rev()
+            .find(|(token, _)| **token == Token::Semicolon)
+            .map_or(0, |(_, span)| span.end);
+        let start = offset - sql[start..offset].trim_start().len();
+
+        let mut code = &sql[start..];
+        let mut offset = offset - start;
+        if offset >= code.len() {
+            return None;
+        }
+        while offset > 50 {
+            let c = code.chars().next()?;
+            code = &code[c.len_utf8()..];
+            offset -= c.len_utf8();
+        }
+        let mut len = code.len().min(78);
+        while !code.is_char_boundary(len) {
+            len -= 1;
+        }
+        let code = code[..len]
+            .chars()
+            .map(|c| if c.is_ascii_whitespace() { ' ' } else { c })
+            .collect();
+        Some(ErrorContext { code, offset })
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  {}", self.code)?;
+        match self.offset {
+            offset if offset < 25 => write!(f, "  {}^--- error here", " ".repeat(offset)),
+            offset => write!(f, "  {}error here ---^", " ".repeat(offset - 14)),
+        }
+    }
+}
+
+fn print_stats(stats: &PagerStats, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "Pages read:                          {}",
+        stats.pages_read
+    )?;
+    writeln!(
+        out,
+        "Page cache hits:                     {}",
+        stats.cache_hits
+    )?;
+    writeln!(
+        out,
+        "Bytes read from disk:                {}",
+        stats.bytes_read
+    )?;
+    writeln!(
+        out,
+        "Page cache writes:                   {}",
+        stats.pages_written
+    )
+}
+
+// Splits a dot command into words the way sqlite3 does. A word can be quoted to hold spaces, and
+// only in double quotes are backslash escapes expanded, so `.separator "\t"` is a tab but
+// `.separator \t` is a backslash and a t.
+fn split_dot_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = command.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let end = match first {
+            '"' | '\'' => {
+                let mut escaped = false;
+                let close = rest[1..].find(|c| {
+                    let close = c == first && !escaped;
+                    escaped = first == '"' && c == '\\' && !escaped;
+                    close
+                });
+                let word = &rest[1..close.map_or(rest.len(), |close| close + 1)];
+                words.push(match first {
+                    '"' => unescape(word),
+                    _ => word.to_string(),
+                });
+                close.map_or(rest.len(), |close| close + 2)
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                words.push(rest[..end].to_string());
+                end
+            }
+        };
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+fn handle_dot_command(
+    command: &str,
+    db: &Db,
+    output: &mut OutputSettings,
+    mut out: &mut dyn Write,
+) -> Result<()> {
+    let header = &db.header;
+
+    // Dot commands can take arguments of their own, e.g. `.schema superheroes`.
+    let words = split_dot_command(command);
+    let mut words = words.iter().map(String::as_str);
+    let command = words.next().unwrap_or_default();
+    let command_args = words.collect::<Vec<_>>();
+
+    match command {
+        "dbinfo" => {
+            let count_of = |object_type: ObjectType| {
+                db.catalog
+                    .objects()
+                    .iter()
+                    .filter(|record| record.object_type == object_type)
+                    .count()
+            };
+            let schema_size = db
+                .catalog
+                .objects()
+                .iter()
+                .map(|record| record.sql.chars().count())
+                .sum::<usize>();
+
+            // Laid out exactly like the sqlite3 shell's .dbinfo, so the two can be diffed.
+            let fields: [(&str, String); 21] = [
+                ("database page size", header.page_size.to_string()),
+                (
+                    "write format",
+                    (header.file_format_write_version as u8).to_string(),
+                ),
+                (
+                    "read format",
+                    (header.file_format_read_version as u8).to_string(),
+                ),
+                ("reserved bytes", header.reserved_space.to_string()),
+                (
+                    "file change counter",
+                    header.file_change_counter.to_string(),
+                ),
+                (
+                    "database page count",
+                    header.database_size_in_pages.to_string(),
+                ),
+                (
+                    "freelist page count",
+                    header.number_of_freelist_pages.to_string(),
+                ),
+                ("schema cookie", header.schema_cookie.to_string()),
+                ("schema format", (header.schema_format as u32).to_string()),
+                (
+                    "default cache size",
+                    (header.default_page_cache_size as i32).to_string(),
+                ),
+                (
+                    "autovacuum top root",
+                    header.largest_root_btree_page_number.to_string(),
+                ),
+                (
+                    "incremental vacuum",
+                    (header.incremental_vacuum_mode as u32).to_string(),
+                ),
+                (
+                    "text encoding",
+                    format!(
+                        "{} ({})",
+                        header.text_encoding as u32,
+                        header.text_encoding.name()
+                    ),
+                ),
+                ("user version", header.user_version.to_string()),
+                ("application id", header.application_id.to_string()),
+                ("software version", header.sqlite_version_number.to_string()),
+                ("number of tables", count_of(ObjectType::Table).to_string()),
+                ("number of indexes", count_of(ObjectType::Index).to_string()),
+                (
+                    "number of triggers",
+                    count_of(ObjectType::Trigger).to_string(),
+                ),
+                ("number of views", count_of(ObjectType::View).to_string()),
+                ("schema size", schema_size.to_string()),
+            ];
+
+            for (name, value) in fields {
+                writeln!(out, "{:<21}{}", format!("{}:", name), value)?;
+            }
+        }
+        "tables" => {
+            if command_args.len() > 1 {
+                bail!("Usage: .tables ?PATTERN?");
+            }
+
+            // Views are listed along with tables, and sqlite's own tables never are, even when a
+            // pattern asks for them.
+            let pattern = command_args.first().copied();
+            let mut table_names = db
+                .catalog
+                .objects()
+                .iter()
+                .filter(|record| matches!(record.object_type, ObjectType::Table | ObjectType::View))
+                .map(|record| record.name.as_str())
+                .filter(|name| !like("sqlite_%", name))
+                .filter(|name| pattern.is_none_or(|pattern| like(pattern, name)))
+                .collect::<Vec<_>>();
+            table_names.sort_unstable();
+
+            print_in_columns(&table_names, &mut out)?;
+        }
+        "schema" => {
+            if command_args.len() > 1 {
+                bail!("Usage: .schema ?PATTERN?");
+            }
+
+            // Like sqlite3, and like .dump, a table whose name starts with a quote is only
+            // created if there isn't one already.
+            for record in db.schema_objects(command_args.first().copied()) {
+                match record.sql.strip_prefix("CREATE TABLE ") {
+                    Some(rest) if rest.starts_with(['"', '\'']) => {
+                        writeln!(out, "CREATE TABLE IF NOT EXISTS {};", rest)?
+                    }
+                    _ => writeln!(out, "{};", record.sql)?,
+                }
+            }
+        }
+        "dump" => {
+            if command_args.len() > 1 {
+                bail!("Usage: .dump ?PATTERN?");
+            }
+
+            dump::dump(db, command_args.first().copied(), &mut out)?;
+        }
+        "parquet" => {
+            let [path, source @ ..] = command_args.as_slice() else {
+                bail!("Usage: .parquet FILE TABLE|QUERY");
+            };
+            if source.is_empty() {
+                bail!("Usage: .parquet FILE TABLE|QUERY");
+            }
+
+            // A single word that names a table exports all of it, and its columns' affinities
+            // decide the types of any that hold nothing but NULLs.
+            let tables = db.tables();
+            let table = match source {
+                [name] => tables
+                    .iter()
+                    .find(|table| table.name().eq_ignore_ascii_case(name)),
+                _ => None,
+            };
+            let sql = match table {
+                Some(table) => format!("SELECT * FROM {}", table.name()),
+                None => source.join(" "),
+            };
+
+            let mut rows = Rows::default();
+            SqlEngine::new(&mut rows).execute(&sql, db)?;
+            let affinities = rows
+                .columns
+                .iter()
+                .map(|name| Some(table?.column(name)?.affinity()))
+                .collect::<Vec<_>>();
+            let values = rows.rows.iter().map(Row::values).collect::<Vec<_>>();
+
+            let file =
+                std::fs::File::create(path).with_context(|| format!("Cannot open \"{}\"", path))?;
+            parquet::write(
+                &mut BufWriter::new(file),
+                &rows.columns,
+                &affinities,
+                &values,
+            )?;
+        }
+        "bench" => {
+            let usage = "Usage: .bench ?--warmup N? ?--repeat N? ?--lookups N? ?TABLE?";
+            let mut options = bench::Options::default();
+            let mut args = command_args.iter();
+            while let Some(arg) = args.next() {
+                let mut count = || -> Result<usize> {
+                    args.next()
+                        .and_then(|count| count.parse().ok())
+                        .context(usage)
+                };
+                match *arg {
+                    "--warmup" => options.warmups = count()?,
+                    "--repeat" => options.repetitions = count()?.max(1),
+                    "--lookups" => options.lookups = count()?,
+                    table if !table.starts_with('-') && options.table.is_none() => {
+                        options.table = Some(table.to_string())
+                    }
+                    _ => bail!(usage),
+                }
+            }
+
+            for measurement in bench::run(db, &options)? {
+                writeln!(out, "{}", measurement)?;
+            }
+        }
+        "dbhash" => {
+            let usage = "Usage: .dbhash ?--like PATTERN? ?--schema-only|--without-schema?";
+            let mut pattern = None;
+            let mut include = dbhash::Include::All;
+            let mut args = command_args.iter();
+            while let Some(arg) = args.next() {
+                match *arg {
+                    "--like" => pattern = Some(*args.next().context(usage)?),
+                    "--schema-only" => include = dbhash::Include::Schema,
+                    "--without-schema" => include = dbhash::Include::Content,
+                    _ => bail!(usage),
+                }
+            }
+
+            // Printed like dbhash prints it, so the output of the two can be diffed.
+            let hash = dbhash::hash(db, pattern, include)?;
+            writeln!(out, "{} {}", hash, db.path.display())?;
+        }
+        "databases" => {
+            if !command_args.is_empty() {
+                bail!("Usage: .databases");
+            }
+
+            // There is no ATTACH yet, so the main database is the only one.
+            let access = if db.pager.writable() { "r/w" } else { "r/o" };
+            // Like sqlite3, an in-memory database is shown with an empty file name.
+            let path = match db.path.to_str() {
+                Some(":memory:") => "\"\"".to_string(),
+                _ => db.path.display().to_string(),
+            };
+            writeln!(out, "main: {} {}", path, access)?;
+        }
+        "changes" => match command_args.as_slice() {
+            ["on"] => output.changes = true,
+            ["off"] => output.changes = false,
+            _ => bail!("Usage: .changes on|off"),
+        },
+        "headers" | "header" => match command_args.as_slice() {
+            ["on"] => (output.headers, output.headers_set) = (true, true),
+            ["off"] => (output.headers, output.headers_set) = (false, true),
+            _ => bail!("Usage: .headers on|off"),
+        },
+        "stats" => match command_args.as_slice() {
+            // With nothing to switch, show everything the pager has done since it was opened.
+            [] => print_stats(&db.pager.stats(), out)?,
+            ["on"] => output.stats = true,
+            ["off"] => {
+                output.stats = false;
+                output.stmt_stats = false;
+            }
+            ["stmt"] => output.stmt_stats = true,
+            _ => bail!("Usage: .stats ?on|off|stmt?"),
+        },
+        "btree" => {
+            let (name, format) = match command_args.as_slice() {
+                [name] => (name, inspect::TreeFormat::Ascii),
+                [name, "dot"] => (name, inspect::TreeFormat::Dot),
+                _ => bail!("Usage: .btree TABLE|INDEX ?dot?"),
+            };
+
+            // The schema table isn't in the schema, but it is a b-tree like any other.
+            let root_page = if name.eq_ignore_ascii_case("sqlite_schema") {
+                1
+            } else {
+                match db
+                    .catalog
+                    .objects()
+                    .iter()
+                    .find(|record| record.name.eq_ignore_ascii_case(name))
+                {
+                    Some(record) if record.root_page != 0 => record.root_page,
+                    Some(_) => bail!("{} is not stored in a b-tree", name),
+                    None => bail!("no such table or index: {}", name),
+                }
+            };
+
+            inspect::print_btree(&db.pager, root_page, format, &mut out)?;
+        }
+        "space" => {
+            inspect::print_space_report(
+                &db.pager,
+                &db.header,
+                &db.path,
+                db.catalog.objects(),
+                &mut out,
+            )?;
+        }
+        "pagedump" => {
+            let page_number = match command_args.as_slice() {
+                [page_number] => page_number
+                    .parse::<u32>()
+                    .context("Usage: .pagedump PAGE")?,
+                _ => bail!("Usage: .pagedump PAGE"),
+            };
+            if page_number == 0 || page_number > header.database_size_in_pages {
+                bail!(
+                    "page {} is out of range, the database has {} pages",
+                    page_number,
+                    header.database_size_in_pages
+                );
+            }
+
+            inspect::print_pagedump(&db.pager, &db.header, page_number, &mut out)?;
+        }
+        "mode" => match command_args.as_slice() {
+            [] => writeln!(out, "current output mode: {}", output.mode)?,
+            // Like sqlite3, `.mode column` switches headers on unless they've been set already,
+            // though the `-column` flag doesn't.
+            [mode] => {
+                output.set_mode(mode.parse()?);
+                output.insert_table = "table".to_string();
+                if output.mode == Mode::Column && !output.headers_set {
+                    output.headers = true;
+                }
+            }
+            ["insert", table] => {
+                output.set_mode(Mode::Insert);
+                output.insert_table = table.to_string();
+            }
+            _ => bail!("Usage: .mode ?MODE? ?TABLE?"),
+        },
+        "width" => {
+            output.widths = command_args
+                .iter()
+                .map(|width| width.parse::<i32>())
+                .collect::<Result<_, _>>()
+                .context("Usage: .width NUM1 NUM2 ...")?;
+        }
+        "separator" => match command_args.as_slice() {
+            [column] => output.column_separator = column.to_string(),
+            [column, row] => {
+                output.column_separator = column.to_string();
+                output.row_separator = row.to_string();
+            }
+            _ => bail!("Usage: .separator COL ?ROW?"),
+        },
+        _ => return Err(Error::UnknownCommand(command.to_string()).into()),
+    }
+
+    Ok(())
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // A copy of a database in the temporary directory, deleted when it's dropped.
+    #[allow(dead_code)]
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        #[allow(dead_code)]
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let name = format!("{}-{}.db", name, std::process::id());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+
+        #[allow(dead_code)]
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // What the shell writes to stdout when it's run with these arguments, or why it failed.
+    #[allow(dead_code)]
+    fn shell(args: &[&str]) -> Result<String> {
+        let args = std::iter::once("sqlite3")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        let mut out = Vec::new();
+        run(args, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // Rows come out as sqlite3 writes them, however many there are.
+    #[test]
+    fn writes_rows_like_sqlite3() {
+        let file = TempFile::new("shell-rows", include_bytes!("../tests/fixtures/people.db"));
+        let out = shell(&[file.path(), "SELECT * FROM people WHERE id <= 3 OR id = 60"]).unwrap();
+        assert_eq!(
+            out,
+            "1|p1|27|0.3|x1\n2|p2|14|1.6|x2\n3|p3|1|2.9|x3\n60|p60|10|1.0|\n"
+        );
+
+        let out = shell(&[file.path(), "SELECT * FROM people"]).unwrap();
+        assert_eq!(out.lines().count(), 60);
+        assert!(
+            out.ends_with("59|p59|23|-0.3|x3\n60|p60|10|1.0|\n"),
+            "{}",
+            out
+        );
+    }
+
+    // The columnar modes lay rows out as sqlite3 does. `.mode column` switches headers on, but
+    // the `-column` flag doesn't, and table and box always have them.
+    #[test]
+    fn draws_tables() {
+        let file = TempFile::new(
+            "shell-tables",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        let query = "SELECT id, name, score, x FROM people WHERE id IN (1, 11, 39, 60)";
+        let rows = "1   p1    0.3    x1\n\
+                    11  p11   1.3    x3\n\
+                    39  p39          x3\n\
+                    60  p60   1.0      \n";
+        let column = format!("id  name  score  x \n--  ----  -----  --\n{}", rows);
+        let table = "+----+------+-------+----+\n\
+                     | id | name | score | x  |\n\
+                     +----+------+-------+----+\n\
+                     | 1  | p1   | 0.3   | x1 |\n\
+                     | 11 | p11  | 1.3   | x3 |\n\
+                     | 39 | p39  |       | x3 |\n\
+                     | 60 | p60  | 1.0   |    |\n\
+                     +----+------+-------+----+\n";
+        let boxed = "┌────┬──────┬───────┬────┐\n\
+                     │ id │ name │ score │ x  │\n\
+                     ├────┼──────┼───────┼────┤\n\
+                     │ 1  │ p1   │ 0.3   │ x1 │\n\
+                     │ 11 │ p11  │ 1.3   │ x3 │\n\
+                     │ 39 │ p39  │       │ x3 │\n\
+                     │ 60 │ p60  │ 1.0   │    │\n\
+                     └────┴──────┴───────┴────┘\n";
+        for (args, expected) in [
+            (vec![".mode column"], column.as_str()),
+            (vec![".mode table"], table),
+            (vec![".mode box"], boxed),
+            (vec![".mode column", ".headers off"], rows),
+            (vec![".headers off", ".mode column"], rows),
+            (vec!["-column"], rows),
+            (vec!["-box", "-noheader"], boxed),
+        ] {
+            let (flags, commands): (Vec<_>, Vec<_>) =
+                args.iter().partition(|arg| arg.starts_with('-'));
+            let mut shell_args = flags;
+            shell_args.push(file.path());
+            shell_args.extend(commands);
+            shell_args.push(query);
+            assert_eq!(shell(&shell_args).unwrap(), expected, "{:?}", args);
+        }
+    }
+
+    // Fixed widths pad, right align or wrap values as sqlite3 does, while column names that don't
+    // fit are cut short.
+    #[test]
+    fn fixes_column_widths() {
+        let file = TempFile::new(
+            "shell-widths",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        let query = "SELECT id, name, score, x FROM people WHERE id IN (1, 11, 60)";
+        let column = "id   na   score  x \n\
+                      ---  --  ------  --\n\
+                      1    p1     0.3  x1\n\
+                      \n\
+                      11   p1     1.3  x3\n     1             \n\
+                      \n\
+                      60   p6     1.0    \n     0             \n";
+        let table = "+------+------+-------+----+\n\
+                     |  id  | name | score | x  |\n\
+                     +------+------+-------+----+\n\
+                     |    1 | p1   | 0.3   | x1 |\n\
+                     |   11 | p11  | 1.3   | x3 |\n\
+                     |   60 | p60  | 1.0   |    |\n\
+                     +------+------+-------+----+\n";
+        for (mode, widths, expected) in [
+            (".mode column", ".width 3 2 -6 0", column),
+            (".mode table", ".width -4 0 5", table),
+        ] {
+            assert_eq!(
+                shell(&[file.path(), mode, widths, query]).unwrap(),
+                expected,
+                "{}",
+                widths
+            );
+        }
+    }
+
+    // Separators are set as sqlite3 sets them: escapes only count in double quotes, csv mode has
+    // its own, and going back to list mode puts back the defaults.
+    #[test]
+    fn separates_values() {
+        let file = TempFile::new(
+            "shell-separators",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        let query = "SELECT id, name, x FROM people WHERE id IN (1, 60)";
+        for (flags, commands, expected) in [
+            (vec![], vec![".separator , ;"], "1,p1,x1;60,p60,;"),
+            (vec![], vec![r".separator \t"], "1\\tp1\\tx1\n60\\tp60\\t\n"),
+            (
+                vec![],
+                vec![r#".separator "\t" "\n\n""#],
+                "1\tp1\tx1\n\n60\tp60\t\n\n",
+            ),
+            (
+                vec![],
+                vec![".separator ' | '"],
+                "1 | p1 | x1\n60 | p60 | \n",
+            ),
+            (vec![], vec![".mode csv"], "1,p1,x1\r\n60,p60,\r\n"),
+            (
+                vec![],
+                vec![".separator , ;", ".mode list"],
+                "1|p1|x1\n60|p60|\n",
+            ),
+            (vec!["-separator", ":"], vec![], "1:p1:x1\n60:p60:\n"),
+        ] {
+            let mut args = flags;
+            args.push(file.path());
+            args.extend(commands);
+            args.push(query);
+            assert_eq!(shell(&args).unwrap(), expected, "{:?}", args);
+        }
+    }
+
+    // A dump is the script sqlite3's .dump writes, with or without a pattern: tables and their
+    // rows, then views, triggers and indexes, with sqlite_sequence after the tables. The one
+    // difference is 1e300, which sqlite3 writes as digits that read back as a different real.
+    #[test]
+    fn dumps_like_sqlite3() {
+        let names = TempFile::new(
+            "shell-dump-names",
+            include_bytes!("../tests/fixtures/names.db"),
+        );
+        assert_eq!(
+            shell(&[names.path(), ".dump"]).unwrap(),
+            "PRAGMA foreign_keys=OFF;\n\
+             BEGIN TRANSACTION;\n\
+             CREATE TABLE IF NOT EXISTS \"my t\"(\"select\" TEXT, [a \"b\"] INT, `c``d` REAL, \
+             e BLOB);\n\
+             INSERT INTO \"my t\" VALUES('it''s',1,1.5,X'00ff');\n\
+             INSERT INTO \"my t\" VALUES(NULL,-3,2.0,NULL);\n\
+             INSERT INTO \"my t\" VALUES(unistr('line\\u000abreak'),NULL,-0.25,'text');\n\
+             INSERT INTO \"my t\" VALUES('',9223372036854775807,1e300,X'');\n\
+             CREATE TABLE plain(id INTEGER PRIMARY KEY, v);\n\
+             INSERT INTO plain VALUES(1,1);\n\
+             INSERT INTO plain VALUES(2,'two');\n\
+             INSERT INTO plain VALUES(3,3.5);\n\
+             INSERT INTO plain VALUES(4,NULL);\n\
+             CREATE INDEX \"my i\" ON \"my t\"(\"select\");\n\
+             COMMIT;\n"
+        );
+
+        let objects = TempFile::new("shell-dump-objects", OBJECTS);
+        let out = shell(&[objects.path(), ".dump"]).unwrap();
+        let lines = out
+            .lines()
+            .filter(|line| !line.starts_with("INSERT INTO b "))
+            .collect::<Vec<_>>();
+        assert_eq!(out.lines().count() - lines.len(), 303);
+        assert_eq!(
+            lines,
+            [
+                "PRAGMA foreign_keys=OFF;",
+                "BEGIN TRANSACTION;",
+                "CREATE TABLE a(id INTEGER PRIMARY KEY AUTOINCREMENT, x TEXT);",
+                "INSERT INTO a VALUES(1,'one');",
+                "INSERT INTO a VALUES(2,'two');",
+                "INSERT INTO a VALUES(3,'three');",
+                "CREATE TABLE b(x INT, y INT, z TEXT);",
+                "PRAGMA writable_schema=ON;",
+                "CREATE TABLE IF NOT EXISTS sqlite_sequence(name,seq);",
+                "DELETE FROM sqlite_sequence;",
+                "INSERT INTO sqlite_sequence VALUES('a',3);",
+                "CREATE VIEW v AS SELECT x FROM a;",
+                "CREATE TRIGGER a_log AFTER INSERT ON a BEGIN INSERT INTO b VALUES (-1, new.id, \
+                 new.x); END;",
+                "CREATE INDEX b_xy ON b(x, y);",
+                "PRAGMA writable_schema=OFF;",
+                "COMMIT;",
+            ]
+        );
+
+        for (pattern, objects_sql) in [
+            (
+                "a",
+                "CREATE TABLE a(id INTEGER PRIMARY KEY AUTOINCREMENT, x TEXT);\n\
+                 INSERT INTO a VALUES(1,'one');\n\
+                 INSERT INTO a VALUES(2,'two');\n\
+                 INSERT INTO a VALUES(3,'three');\n",
+            ),
+            ("v", "CREATE VIEW v AS SELECT x FROM a;\n"),
+        ] {
+            assert_eq!(
+                shell(&[objects.path(), &format!(".dump {}", pattern)]).unwrap(),
+                format!(
+                    "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n{}COMMIT;\n",
+                    objects_sql
+                ),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    // With `.stats on`, each statement is followed by what the pager did for it alone, so a
+    // seek to the end of an index shows up as reading far fewer pages than a full scan.
+    #[test]
+    fn reports_page_reads() {
+        let file = TempFile::new("shell-stats", include_bytes!("../tests/fixtures/many.db"));
+        let stats = |pages: usize| {
+            format!(
+                "Pages read:                          {}\n\
+                 Page cache hits:                     0\n\
+                 Bytes read from disk:                {}\n\
+                 Page cache writes:                   0\n",
+                pages,
+                pages * 512
+            )
+        };
+        let out = shell(&[
+            file.path(),
+            ".stats on",
+            "SELECT count(*) FROM m WHERE s = 'x'",
+            "SELECT max(n) FROM m",
+            ".stats off",
+            "SELECT max(n) FROM m",
+        ])
+        .unwrap();
+        assert_eq!(out, format!("0\n{}9\n{}9\n", stats(37), stats(2)));
+    }
+
+    // JSON output is an array of objects, one per line, with reals to the digits sqlite3 gives
+    // them, and nothing at all for no rows. Where sqlite3 writes a blob's bytes as the characters
+    // of a string, "\u0000ÿ", it is written in hex:
+    //
+    //     $ sqlite3 -json people.db "SELECT id, name, age, score, 'a\"b' AS q FROM people ..."
+    //     [{"id":1,"name":"p1","age":27,"score":0.3000000000000000445,"q":"a\"b"},
+    //     {"id":11,"name":"p11","age":null,"score":1.299999999999999822,"q":"a\"b"}]
+    #[test]
+    fn writes_json_like_sqlite3() {
+        let file = TempFile::new("shell-json", include_bytes!("../tests/fixtures/people.db"));
+        let sql = "SELECT id, name, age, score, 'a\"b' AS q FROM people WHERE id IN (1, 11)";
+        let expected = "[{\"id\":1,\"name\":\"p1\",\"age\":27,\"score\":0.3000000000000000445,\
+                        \"q\":\"a\\\"b\"},\n\
+                        {\"id\":11,\"name\":\"p11\",\"age\":null,\"score\":1.299999999999999822,\
+                        \"q\":\"a\\\"b\"}]\n";
+        assert_eq!(shell(&["-json", file.path(), sql]).unwrap(), expected);
+        assert_eq!(
+            shell(&[file.path(), ".mode json", sql, "SELECT 1 WHERE 0"]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            shell(&["-json", ":memory:", "SELECT 1.0 AS r, x'00ff' AS b"]).unwrap(),
+            "[{\"r\":1.0,\"b\":\"00ff\"}]\n"
+        );
+    }
+
+    // With `.stats stmt`, each statement's rows are followed by a comment saying what it took,
+    // for the same 37 pages and 1000 rows as sqlite3's `.stats stmt` counts for this scan.
+    #[test]
+    fn reports_statement_stats() {
+        let file = TempFile::new("shell-stmt", include_bytes!("../tests/fixtures/many.db"));
+        let out = shell(&[
+            file.path(),
+            ".stats stmt",
+            "SELECT count(*) FROM m WHERE s LIKE '%5'",
+        ])
+        .unwrap();
+        assert_eq!(
+            out,
+            "100\n-- pages read: 37, cache hits: 0, cells decoded: 1035, index seeks: 0, \
+             rows scanned: 1000, rows returned: 1\n"
+        );
+    }
+
+    // The main database is listed as sqlite3 lists it: by its full path, or an empty name in
+    // memory, and whether it can be written.
+    #[test]
+    fn lists_databases() {
+        let file = TempFile::new(
+            "shell-databases",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        for (flags, expected) in [
+            (vec![], format!("main: {} r/w\n", file.path())),
+            (vec!["-readonly"], format!("main: {} r/o\n", file.path())),
+        ] {
+            let mut args = flags;
+            args.extend([file.path(), ".databases"]);
+            assert_eq!(shell(&args).unwrap(), expected, "{:?}", args);
+        }
+        assert_eq!(
+            shell(&[":memory:", ".databases"]).unwrap(),
+            "main: \"\" r/w\n"
+        );
+    }
+
+    // With `.changes on`, each statement is followed by how many rows it and every statement so
+    // far changed, as sqlite3 reports them, and a table created by one command is there for the
+    // next.
+    #[test]
+    fn reports_changes() {
+        let out = shell(&[
+            ":memory:",
+            ".changes on",
+            "CREATE TABLE t(a)",
+            "INSERT INTO t VALUES (1), (2), (3)",
+            "DELETE FROM t WHERE a > 1",
+            "SELECT changes(), total_changes()",
+            ".changes off",
+            "INSERT INTO t VALUES (4)",
+        ])
+        .unwrap();
+        assert_eq!(
+            out,
+            "changes: 0   total_changes: 0\n\
+             changes: 3   total_changes: 3\n\
+             changes: 2   total_changes: 5\n\
+             2|5\n\
+             changes: 2   total_changes: 5\n"
+        );
+    }
+
+    // A b-tree is drawn page by page, with the same pages, types and cell counts that sqlite3's
+    // dbstat table reports for it, and the range of keys under each.
+    #[test]
+    fn draws_btrees() {
+        let file = TempFile::new("shell-btree", OBJECTS);
+        assert_eq!(
+            shell(&[file.path(), ".btree b"]).unwrap(),
+            "page 4: interior table, 4 cells, keys 83 .. 288\n\
+             ├── page 6: leaf table, 83 cells, keys 1 .. 83\n\
+             ├── page 7: leaf table, 73 cells, keys 84 .. 156\n\
+             ├── page 10: leaf table, 66 cells, keys 157 .. 222\n\
+             ├── page 12: leaf table, 66 cells, keys 223 .. 288\n\
+             └── page 13: leaf table, 15 cells, keys 289 .. 303\n"
+        );
+        assert_eq!(
+            shell(&[file.path(), ".btree B_XY"]).unwrap(),
+            "page 5: interior index, 3 cells, keys (0,258,262) .. (2,161,165)\n\
+             ├── page 8: leaf index, 89 cells, keys (-1,1,1) .. (0,255,259)\n\
+             ├── page 9: leaf index, 91 cells, keys (0,261,265) .. (1,232,236)\n\
+             ├── page 11: leaf index, 74 cells, keys (1,238,242) .. (2,158,162)\n\
+             └── page 14: leaf index, 46 cells, keys (2,164,168) .. (2,299,303)\n"
+        );
+
+        let dot = shell(&[file.path(), ".btree b dot"]).unwrap();
+        assert!(dot.starts_with("digraph btree {\n"), "{}", dot);
+        assert!(dot.contains("  p4 -> p13;\n  p13 [label=\"page 13: leaf table, 15 cells, keys 289 .. 303\"];\n}\n"), "{}", dot);
+
+        for (name, message) in [
+            ("v", "v is not stored in a b-tree"),
+            ("nope", "no such table or index: nope"),
+        ] {
+            let err = shell(&[file.path(), &format!(".btree {}", name)]).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    // A page is dumped byte for byte with its header and cells marked out. The 984 bytes between
+    // the cell pointers and the cells are what sqlite3's dbstat reports as unused on the page.
+    #[test]
+    fn dumps_pages() {
+        let file = TempFile::new("shell-pagedump", OBJECTS);
+        assert_eq!(
+            shell(&[file.path(), ".pagedump 2"]).unwrap(),
+            "Page 2 (1024 bytes)\n\
+             Header at 0..8\n  \
+               page type            0x0d (leaf table)\n  \
+               first freeblock      0\n  \
+               cell count           3\n  \
+               cell content start   998\n  \
+               fragmented bytes     0\n\
+             Cells\n  \
+               cell 0    at 1016..1024 (8 bytes), rowid 1\n  \
+               cell 1    at 1008..1016 (8 bytes), rowid 2\n  \
+               cell 2    at 998..1008 (10 bytes), rowid 3\n\
+             \n\
+             0000  0d 00 00 00 03 03 e6 00  03 f8 03 f0 03 e6 00 00  |................|  \
+             <- page header 0..8, cell pointers 8..14, unallocated 14..998\n\
+             *\n\
+             03e0  00 00 00 00 00 00 08 03  03 00 17 74 68 72 65 65  |...........three|  \
+             <- cell 2 998..1008\n\
+             03f0  06 02 03 00 13 74 77 6f  06 01 03 00 13 6f 6e 65  |.....two.....one|  \
+             <- cell 0 1016..1024, cell 1 1008..1016\n"
+        );
+
+        let err = shell(&[file.path(), ".pagedump 15"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "page 15 is out of range, the database has 14 pages"
+        );
+    }
+
+    // The space report's sections say what sqlite3_analyzer says about the same tables and
+    // indexes, line for line.
+    #[test]
+    fn reports_space_used() {
+        let file = TempFile::new("shell-space", OBJECTS);
+        let out = shell(&[file.path(), ".space"]).unwrap();
+        for section in [
+            "*** Table A *******************************************************************\n\
+             \n\
+             Percentage of total database......................   7.1%\n\
+             Number of entries................................. 3\n\
+             Bytes of storage consumed......................... 1024\n\
+             Bytes of payload.................................. 20           2.0%\n\
+             Bytes of metadata................................. 20           2.0%\n\
+             B-tree depth...................................... 1\n\
+             Average payload per entry......................... 6.67\n\
+             Average unused bytes per entry.................... 328.00\n\
+             Average metadata per entry........................ 6.67\n\
+             Maximum payload per entry......................... 8\n\
+             Entries that use overflow......................... 0            0.0%\n\
+             Primary pages used................................ 1\n\
+             Overflow pages used............................... 0\n\
+             Total pages used.................................. 1\n\
+             Unused bytes on primary pages..................... 984         96.1%\n\
+             Unused bytes on overflow pages.................... 0\n\
+             Unused bytes on all pages......................... 984         96.1%\n",
+            "*** Index B_XY of table B *****************************************************\n\
+             \n\
+             Percentage of total database......................  35.7%\n\
+             Number of entries................................. 303\n\
+             Bytes of storage consumed......................... 5120\n\
+             Bytes of payload.................................. 2265        44.2%\n\
+             Bytes of metadata................................. 965         18.8%\n\
+             B-tree depth...................................... 2\n\
+             Average payload per entry......................... 7.48\n\
+             Average unused bytes per entry.................... 6.24\n\
+             Average metadata per entry........................ 3.18\n\
+             Average fanout.................................... 5.00\n\
+             Non-sequential pages.............................. 3           75.0%\n\
+             Maximum payload per entry......................... 9\n\
+             Entries that use overflow......................... 0            0.0%\n\
+             Index pages used.................................. 1\n\
+             Primary pages used................................ 4\n\
+             Overflow pages used............................... 0\n\
+             Total pages used.................................. 5\n\
+             Unused bytes on index pages....................... 966         94.3%\n\
+             Unused bytes on primary pages..................... 924         22.6%\n\
+             Unused bytes on overflow pages.................... 0\n\
+             Unused bytes on all pages......................... 1890        36.9%\n",
+        ] {
+            assert!(out.contains(section), "{}", out);
+        }
+        assert!(out.contains(
+            "B................................................. 6           42.9%\n\
+             B_XY.............................................. 5           35.7%\n"
+        ));
+    }
+
+    // Errors are reported with the message and exit code sqlite3 gives for them.
+    #[test]
+    fn reports_errors_like_sqlite3() {
+        let file = TempFile::new(
+            "shell-errors",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        for (args, message, code) in [
+            (
+                vec![file.path(), "SELECT * FROM nope"],
+                "Error: in prepare, no such table: nope",
+                1,
+            ),
+            (
+                vec![file.path(), "SELEC 1"],
+                "Error: in prepare, near \"SELEC\": syntax error\n  SELEC 1\n  ^--- error here",
+                1,
+            ),
+            (
+                vec![file.path(), "SELECT 1 FROM people WHERE"],
+                "Error: in prepare, incomplete input",
+                1,
+            ),
+            (
+                vec![file.path(), "INSERT INTO people(id) VALUES (1)"],
+                "Error: stepping, UNIQUE constraint failed: people.id (19)",
+                19,
+            ),
+            (
+                vec!["-readonly", file.path(), "INSERT INTO people(id) VALUES (100)"],
+                "Error: stepping, attempt to write a readonly database (8)",
+                8,
+            ),
+            (
+                vec![file.path(), ".nope"],
+                "Error: unknown command or invalid arguments:  \"nope\". Enter \".help\" for help",
+                1,
+            ),
+            (
+                vec!["/nonexistent/x.db", "SELECT 1"],
+                "Error: unable to open database \"/nonexistent/x.db\": unable to open database file",
+                1,
+            ),
+        ] {
+            let err = shell(&args).unwrap_err();
+            assert_eq!(error_message(&err), message, "{:?}", args);
+            let exit_code = err.downcast_ref::<Error>().map_or(1, Error::exit_code);
+            assert_eq!(exit_code, code, "{:?}", args);
+        }
+    }
+
+    // A statement's error is pointed out under it as sqlite3 points it out: in the statement it
+    // is in, from at most 50 bytes before it, with the arrow turned around once it is far enough
+    // along the line to have room.
+    #[test]
+    fn points_out_errors_like_sqlite3() {
+        let file = TempFile::new(
+            "shell-context",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        for (sql, message) in [
+            (
+                "SELECT 1; SELECT 2 FROM people WHERE @@",
+                "Error: in prepare, unrecognized token: \"@\"\n\
+                 \x20 SELECT 2 FROM people WHERE @@\n\
+                 \x20              error here ---^",
+            ),
+            (
+                "SELECT id, name, age, score, x FROM people WHERE name = 'p1' AND age > 20 \
+                 ORDER BY id LIMIT 1 OFFSET 0 GARBAGE",
+                "Error: in prepare, near \"GARBAGE\": syntax error\n\
+                 \x20  = 'p1' AND age > 20 ORDER BY id LIMIT 1 OFFSET 0 GARBAGE\n\
+                 \x20                                     error here ---^",
+            ),
+            (
+                "SELECT id FROM people WHERE name = 'x' AND  (age  >  1 !! 2)",
+                "Error: in prepare, unrecognized token: \"!\"\n\
+                 \x20 T id FROM people WHERE name = 'x' AND  (age  >  1 !! 2)\n\
+                 \x20                                     error here ---^",
+            ),
+            (
+                "SELECT 'é' FROM people WHERE name IS #",
+                "Error: in prepare, unrecognized token: \"#\"\n\
+                 \x20 SELECT 'é' FROM people WHERE name IS #\n\
+                 \x20                         error here ---^",
+            ),
+        ] {
+            let err = shell(&[file.path(), sql]).unwrap_err();
+            assert_eq!(error_message(&err), message, "{}", sql);
+        }
+    }
+
+    // Each argument after the path runs in turn against the same database, as sqlite3 runs them,
+    // including every statement in one argument, until one fails and stops the rest.
+    #[test]
+    fn runs_each_argument() {
+        let file = TempFile::new(
+            "shell-arguments",
+            include_bytes!("../tests/fixtures/people.db"),
+        );
+        assert_eq!(
+            shell(&[
+                file.path(),
+                "SELECT count(*) FROM people",
+                "SELECT name FROM people WHERE id = 2",
+                ".tables",
+                "SELECT 1; SELECT 2",
+            ])
+            .unwrap(),
+            "60\np2\npeople\n1\n2\n"
+        );
+
+        let args = [
+            "sqlite3",
+            file.path(),
+            "SELECT 1",
+            "SELECT nope",
+            "SELECT 3",
+        ];
+        let mut out = Vec::new();
+        let err = run(args.iter().map(|arg| arg.to_string()).collect(), &mut out).unwrap_err();
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+        assert!(err.to_string().contains("no such column: nope"), "{}", err);
+    }
+
+    // Like sqlite3's, the version flag needs no database, and prints the source id followed by how
+    // many bits the build is for.
+    #[test]
+    fn prints_its_version() {
+        let expected = format!(
+            "sqlite-starter-rust {}, compatible with the file format of SQLite 3.51.2 ({}-bit)\n",
+            env!("CARGO_PKG_VERSION"),
+            usize::BITS
+        );
+        for flag in ["--version", "-version"] {
+            assert_eq!(shell(&[flag]).unwrap(), expected, "{}", flag);
+        }
+    }
+
+    // Only the objects whose table name matches the pattern are shown, in the order they were
+    // created, as sqlite3 shows them.
+    #[test]
+    fn shows_the_schema() {
+        let file = TempFile::new("shell-schema", include_bytes!("../tests/fixtures/names.db"));
+        let my_t =
+            "CREATE TABLE IF NOT EXISTS \"my t\"(\"select\" TEXT, [a \"b\"] INT, `c``d` REAL, \
+                    e BLOB);\n\
+                    CREATE INDEX \"my i\" ON \"my t\"(\"select\");\n";
+        let plain = "CREATE TABLE plain(id INTEGER PRIMARY KEY, v);\n";
+        for (pattern, expected) in [
+            ("", format!("{}{}", my_t, plain)),
+            (" PLAIN", plain.to_string()),
+            (" my%", my_t.to_string()),
+            (" a", String::new()),
+        ] {
+            let command = format!(".schema{}", pattern);
+            assert_eq!(
+                shell(&[file.path(), &command]).unwrap(),
+                expected,
+                "{}",
+                command
+            );
+        }
+        assert!(shell(&[file.path(), ".schema a b"]).is_err());
+    }
+
+    // Tables a and b, a view v on a, an index on b, a trigger, and sqlite_sequence, as a has an
+    // AUTOINCREMENT key.
+    #[allow(dead_code)]
+    const OBJECTS: &[u8] = include_bytes!("../tests/fixtures/objects.db");
+
+    // Tables and views are listed, in columns, but not indexes, triggers, or sqlite's own tables,
+    // as sqlite3 lists them.
+    #[test]
+    fn lists_tables() {
+        let file = TempFile::new("shell-tables", OBJECTS);
+        for (pattern, expected) in [
+            ("", "a  b  v\n"),
+            (" %", "a  b  v\n"),
+            (" V", "v\n"),
+            (" sqlite%", ""),
+            (" b_xy", ""),
+        ] {
+            let command = format!(".tables{}", pattern);
+            assert_eq!(
+                shell(&[file.path(), &command]).unwrap(),
+                expected,
+                "{}",
+                command
+            );
+        }
+    }
+
+    // Names go down the columns first, in the order they're given, each padded to the longest,
+    // with as many columns as fit in 80 characters, as sqlite3 lays out the same tables.
+    #[test]
+    fn lays_out_names_in_columns() {
+        let mut names = vec!["a", "bbbbbbbbbbbbbbbbbbbbbbbbb", "c", "dddddddddddddddd"];
+        let mut out = Vec::new();
+        print_in_columns(&names, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a                          c                        \n\
+             bbbbbbbbbbbbbbbbbbbbbbbbb  dddddddddddddddd         \n"
+        );
+
+        let tables = (1..=5).map(|i| format!("tbl_{}", i)).collect::<Vec<_>>();
+        names.extend(tables.iter().map(String::as_str));
+        let mut out = Vec::new();
+        print_in_columns(&names, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a                          tbl_2                    \n\
+             bbbbbbbbbbbbbbbbbbbbbbbbb  tbl_3                    \n\
+             c                          tbl_4                    \n\
+             dddddddddddddddd           tbl_5                    \n\
+             tbl_1                    \n"
+        );
+    }
+
+    // Every field sqlite3's .dbinfo has, laid out the same, but for the data version it ends
+    // with, which counts commits made by other connections while it was open.
+    #[test]
+    fn shows_database_info() {
+        let file = TempFile::new("shell-dbinfo", OBJECTS);
+        assert_eq!(
+            shell(&[file.path(), ".dbinfo"]).unwrap(),
+            "database page size:  1024\n\
+             write format:        1\n\
+             read format:         1\n\
+             reserved bytes:      0\n\
+             file change counter: 7\n\
+             database page count: 14\n\
+             freelist page count: 0\n\
+             schema cookie:       5\n\
+             schema format:       4\n\
+             default cache size:  0\n\
+             autovacuum top root: 0\n\
+             incremental vacuum:  0\n\
+             text encoding:       1 (utf8)\n\
+             user version:        0\n\
+             application id:      0\n\
+             software version:    3051002\n\
+             number of tables:    3\n\
+             number of indexes:   1\n\
+             number of triggers:  1\n\
+             number of views:     1\n\
+             schema size:         284\n"
+        );
+    }
+}
@@ -1,266 +1,3671 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::Write;
+use std::ops::{Bound, Range, RangeInclusive};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
 use crate::{
+    btree::{BTreeCursor, Leaves},
+    catalog::{Affinity, Collation, Index, ObjectType},
+    error::{Error, Result},
+    fts::{self, Fts},
+    into_table_leaf_record,
     lexer::Lexer,
+    like,
+    output::{sql_literal, ResultWriter},
+    pager::ReadLock,
     parser::{Ast, Op, Parser},
-    Db, MasterPageRecord, TableLeafRecord, Value,
+    pragma::Pragma,
+    rtree::{self, RTree},
+    trace::{self, Target},
+    visit,
+    vtab::{self, Constraint, ConstraintOp, VirtualRow, VirtualTable, VirtualTables},
+    Action, Db, MasterPageRecord, Row, RowIds, Rows, TableLeafRecord, Value, ValueRef,
+    SCHEMA_TABLE_NAMES, SOURCE_ID, VERSION,
 };
 
-struct ExecutionContext {
-    rows: Option<Vec<TableLeafRecord>>,
-    table: Option<MasterPageRecord>,
+// Called with what a statement did to a row, the table's name and the row's rowid.
+pub(crate) type UpdateHook = dyn Fn(Action, &str, i64) + Send + Sync;
+
+// The expressions a select returns, each with the name of its column.
+type ResultColumns = Vec<(Ast, String)>;
+
+// Every function that can be called, with how many arguments it takes.
+const FUNCTIONS: &[(&str, RangeInclusive<usize>)] = &[
+    ("CHANGES", 0..=0),
+    ("TOTAL_CHANGES", 0..=0),
+    ("LAST_INSERT_ROWID", 0..=0),
+    ("SQLITE_VERSION", 0..=0),
+    ("SQLITE_SOURCE_ID", 0..=0),
+    ("LENGTH", 1..=1),
+    ("UPPER", 1..=1),
+    ("LOWER", 1..=1),
+    ("SUBSTR", 2..=3),
+    ("UNISTR", 1..=1),
+];
+
+// Functions that work out one value from the rows of a group, each with one argument.
+const AGGREGATES: &[&str] = &["COUNT", "MIN", "MAX", "SUM", "AVG"];
+
+// Function names are written in any case.
+fn is_aggregate(name: &str) -> bool {
+    AGGREGATES
+        .iter()
+        .any(|aggregate| aggregate.eq_ignore_ascii_case(name))
 }
 
-struct QueryPlanner {
-    steps: Vec<QueryStep>,
+fn has_aggregate(expr: &Ast) -> bool {
+    visit::any(
+        expr,
+        |node| matches!(node, Ast::Function { name, .. } if is_aggregate(name)),
+    )
 }
 
-impl QueryPlanner {
-    fn new() -> Self {
-        Self { steps: Vec::new() }
+fn mentions_column(expr: &Ast) -> bool {
+    visit::any(expr, |node| matches!(node, Ast::Identifier(_) | Ast::All))
+}
+
+// The result of evaluating an expression against a row. Columns are borrowed straight out of the
+// row, so only values that are computed are ever owned.
+pub enum Datum<'a> {
+    Borrowed(ValueRef<'a>),
+    Owned(Value),
+}
+
+impl Datum<'_> {
+    pub fn into_value(self) -> Value {
+        match self {
+            Datum::Borrowed(value) => value.to_value(),
+            Datum::Owned(value) => value,
+        }
     }
 
-    fn add_step(&mut self, step: QueryStep) {
-        self.steps.push(step);
+    pub fn value_ref(&self) -> ValueRef<'_> {
+        match self {
+            Datum::Borrowed(value) => *value,
+            Datum::Owned(value) => value.as_value_ref(),
+        }
     }
+}
 
-    fn execute(&self, db: &mut Db) {
-        let mut execution_context = ExecutionContext {
-            table: None,
-            rows: None,
-        };
+impl Display for Datum<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Datum::Borrowed(value) => value.fmt(f),
+            Datum::Owned(value) => value.fmt(f),
+        }
+    }
+}
 
-        let mut results = Vec::new();
+// Makes sure everything an expression refers to exists before any rows are read, so a bad
+// column or function is an error even when the table is empty.
+fn check(table: Option<&MasterPageRecord>, expr: &Ast) -> Result<()> {
+    match expr {
+        Ast::All => Err(Error::Parse("no tables specified".to_string())),
+        Ast::Expr(expr) => check(table, expr),
+        Ast::Identifier(name) => match table {
+            Some(table) if is_rowid(name) || table.get_column_index(name).is_ok() => Ok(()),
+            _ => Err(Error::NoSuchColumn(name.clone())),
+        },
+        Ast::Function { name, args, .. } if is_aggregate(name) => {
+            let upper = name.to_ascii_uppercase();
+            match args.as_slice() {
+                [Ast::All] if upper == "COUNT" => Ok(()),
+                [arg] if has_aggregate(arg) => Err(Error::Parse(format!(
+                    "misuse of aggregate function {}()",
+                    name.to_ascii_lowercase()
+                ))),
+                [arg] => check(table, arg),
+                _ if upper == "MIN" || upper == "MAX" => Err(Error::Unsupported(describe(expr))),
+                _ => Err(Error::Parse(format!(
+                    "wrong number of arguments to function {}()",
+                    name.to_ascii_lowercase()
+                ))),
+            }
+        }
+        // A bad escape is only an error when the text is written out. Any other text that turns
+        // out to have one is NULL, as a function can't fail once rows are being read.
+        Ast::Function { name, args, .. }
+            if name.eq_ignore_ascii_case("UNISTR")
+                && matches!(args.as_slice(), [Ast::Expr(arg)]
+                    if matches!(arg.as_ref(), Ast::StringLiteral(text) if unistr(text).is_none())) =>
+        {
+            Err(Error::Parse("invalid Unicode escape".to_string()))
+        }
+        Ast::Function { name, args, .. } => {
+            match FUNCTIONS
+                .iter()
+                .find(|(function, _)| function.eq_ignore_ascii_case(name))
+            {
+                None => Err(Error::NoSuchFunction(name.clone())),
+                Some((_, arity)) if !arity.contains(&args.len()) => Err(Error::Parse(format!(
+                    "wrong number of arguments to function {}()",
+                    name.to_ascii_lowercase()
+                ))),
+                Some(_) => args.iter().try_for_each(|arg| check(table, arg)),
+            }
+        }
+        Ast::BinaryOp { op: Op::Match, .. } => Err(match_out_of_context()),
+        Ast::BinaryOp { lhs, rhs, .. } => {
+            check(table, lhs)?;
+            check(table, rhs)
+        }
+        Ast::InList { lhs, list } => {
+            check(table, lhs)?;
+            list.iter().try_for_each(|value| check(table, value))
+        }
+        Ast::Negate(operand) => check(table, operand),
+        Ast::StringLiteral(_)
+        | Ast::IntegerLiteral(_)
+        | Ast::RealLiteral(_)
+        | Ast::BlobLiteral(_)
+        | Ast::NullLiteral
+        | Ast::Parameter { .. } => Ok(()),
+        _ => Err(Error::Unsupported(describe(expr))),
+    }
+}
 
-        for step in self.steps.iter() {
-            match step {
-                QueryStep::SetTable(string) => {
-                    let table = db.get_table(string);
-                    execution_context.table = Some((*table).clone());
-                }
-                QueryStep::Where(ident, value) => {
-                    let table = execution_context.table.as_ref().unwrap();
-                    let col_index = table.get_column_index(ident);
+// Whether a name is the rowid's, which a column of the same name hides. Names are compared without
+// regard to case, as they are everywhere, and whether they were quoted.
+fn is_rowid(name: &str) -> bool {
+    name.eq_ignore_ascii_case("ROWID")
+}
 
-                    // FIXME: This is not to spec! Can be more than one column in an index!
-                    if let Some(index) = db.get_index_for_column_and_table(&table.table_name, ident)
-                    {
-                        execution_context.rows = Some(db.fetch_rows_from_index(&index, value));
-                    } else {
-                        execution_context.rows = Some(db.get_table_rows(table, &mut None));
-                    }
+// A value that is compared with a column, converted the way sqlite converts it first: a column
+// with INTEGER, REAL or NUMERIC affinity makes text that reads as a number into one, and a column
+// with TEXT affinity makes numbers into text. A column with BLOB affinity takes the value as it is.
+fn compared_with(table: &MasterPageRecord, column: usize, value: Datum) -> Value {
+    table.affinity(column).apply(value.into_value())
+}
 
-                    execution_context.rows = Some(
-                        execution_context
-                            .rows
-                            .unwrap()
-                            .into_iter()
-                            .filter(|row| {
-                                let record = row;
-                                let record_value = &record.values[col_index];
-                                record_value == value
-                            })
-                            .collect::<Vec<TableLeafRecord>>(),
-                    );
-                }
-                QueryStep::Select(columns) => {
-                    let table = execution_context.table.as_ref().unwrap();
+// A row that expressions read columns from: a record out of a table b-tree, or a row that a
+// virtual table produced.
+trait SourceRow {
+    fn rowid(&self) -> i64;
+    fn column(&self, index: usize) -> ValueRef<'_>;
+}
 
-                    // If we get here and no rows have been fetched, then we need to fetch all the rows
-                    if execution_context.rows.is_none() {
-                        execution_context.rows = Some(db.get_table_rows(table, &mut None));
-                    }
+impl SourceRow for TableLeafRecord {
+    fn rowid(&self) -> i64 {
+        self.header.row_id as i64
+    }
 
-                    let rows = execution_context.rows.as_ref().unwrap();
+    fn column(&self, index: usize) -> ValueRef<'_> {
+        self.value(index)
+    }
+}
 
-                    let col_indexes = if columns != &["*".to_string()] {
-                        columns
-                            .iter()
-                            .map(|col_name| {
-                                if col_name == "ID" {
-                                    -1
-                                } else {
-                                    table.get_column_index(col_name) as isize
-                                }
-                            })
-                            .collect::<Vec<isize>>()
-                    } else {
-                        (0..table.columns.len() as isize).collect::<Vec<isize>>()
-                    };
+impl SourceRow for VirtualRow {
+    fn rowid(&self) -> i64 {
+        self.rowid
+    }
 
-                    for record in rows {
-                        let mut table_results = Vec::new();
-                        for index in &col_indexes {
-                            if index == &-1 {
-                                table_results.push(Value::Int(record.header.row_id as i64));
-                                continue;
-                            }
-                            let value = record.values[*index as usize].clone();
-                            table_results.push(value);
-                        }
-                        if !table_results.is_empty() {
-                            results.push(
-                                table_results
-                                    .iter()
-                                    .map(|v| format!("{}", v))
-                                    .collect::<Vec<String>>()
-                                    .join("|"),
-                            );
-                        }
-                    }
+    fn column(&self, index: usize) -> ValueRef<'_> {
+        self.values
+            .get(index)
+            .map_or(ValueRef::Null, Value::as_value_ref)
+    }
+}
+
+// The value of a column of a row. A column that is an alias for the rowid is NULL in the record, as
+// its value is the rowid itself.
+fn column_value<'a>(
+    table: &MasterPageRecord,
+    record: &'a dyn SourceRow,
+    index: usize,
+) -> ValueRef<'a> {
+    match table.rowid_alias == Some(index) {
+        true => ValueRef::Int(record.rowid()),
+        false => table.stored_value(index, record.column(index)),
+    }
+}
+
+// Evaluates an expression from the select list, against the current row of the table if the
+// query reads from one. Anything it refers to has already been checked.
+fn evaluate<'a>(
+    db: &Db,
+    params: &'a [Value],
+    row: Option<(&MasterPageRecord, &'a dyn SourceRow)>,
+    expr: &Ast,
+) -> Datum<'a> {
+    match expr {
+        Ast::Expr(expr) => evaluate(db, params, row, expr),
+        Ast::Identifier(name) => {
+            let Some((table, record)) = row else {
+                unreachable!("no such column: {}", name);
+            };
+
+            // The rowid isn't stored in the record, even when a column is an alias for it. A column
+            // that is actually called rowid hides it.
+            if is_rowid(name) && table.get_column_index(name).is_err() {
+                return Datum::Borrowed(ValueRef::Int(record.rowid()));
+            }
+            let index = table.get_column_index(name).unwrap();
+            Datum::Borrowed(column_value(table, record, index))
+        }
+        Ast::StringLiteral(value) => Datum::Owned(Value::Text(value.clone())),
+        Ast::IntegerLiteral(value) => Datum::Owned(Value::Int(*value)),
+        Ast::RealLiteral(value) => Datum::Owned(Value::Real(*value)),
+        Ast::BlobLiteral(bytes) => Datum::Owned(Value::Blob(bytes.clone())),
+        Ast::NullLiteral => Datum::Borrowed(ValueRef::Null),
+        Ast::Parameter { index, .. } => Datum::Borrowed(parameter(params, *index).as_value_ref()),
+        Ast::Function { name, args, .. } => {
+            let args = args
+                .iter()
+                .map(|arg| evaluate(db, params, row, arg))
+                .collect();
+            call(db, name, args)
+        }
+        // NULL is neither true nor false, so it's only the answer when the other side doesn't
+        // decide it.
+        Ast::BinaryOp {
+            op: op @ (Op::And | Op::Or),
+            lhs,
+            rhs,
+        } => {
+            let lhs = truth(evaluate(db, params, row, lhs).value_ref());
+            let rhs = truth(evaluate(db, params, row, rhs).value_ref());
+            boolean(match (op, lhs, rhs) {
+                (Op::And, Some(false), _) | (Op::And, _, Some(false)) => Some(false),
+                (Op::Or, Some(true), _) | (Op::Or, _, Some(true)) => Some(true),
+                (_, Some(lhs), Some(_)) => Some(lhs),
+                _ => None,
+            })
+        }
+        Ast::BinaryOp {
+            op: Op::Like,
+            lhs,
+            rhs,
+        } => {
+            let value = evaluate(db, params, row, lhs);
+            let pattern = evaluate(db, params, row, rhs);
+            boolean(match (value.value_ref(), pattern.value_ref()) {
+                (ValueRef::Null, _) | (_, ValueRef::Null) => None,
+                (value, pattern) => Some(like(&pattern.to_string(), &value.to_string())),
+            })
+        }
+        Ast::BinaryOp {
+            op: Op::Concat,
+            lhs,
+            rhs,
+        } => {
+            let lhs = evaluate(db, params, row, lhs);
+            let rhs = evaluate(db, params, row, rhs);
+            Datum::Owned(match (lhs.value_ref(), rhs.value_ref()) {
+                (ValueRef::Null, _) | (_, ValueRef::Null) => Value::Null,
+                (lhs, rhs) => Value::Text(as_text(lhs) + &as_text(rhs)),
+            })
+        }
+        Ast::BinaryOp {
+            op: op @ (Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder),
+            lhs,
+            rhs,
+        } => {
+            let lhs = evaluate(db, params, row, lhs);
+            let rhs = evaluate(db, params, row, rhs);
+            Datum::Owned(arithmetic(*op, lhs.value_ref(), rhs.value_ref()))
+        }
+        Ast::Negate(operand) => {
+            let operand = evaluate(db, params, row, operand);
+            Datum::Owned(match operand.value_ref() {
+                ValueRef::Null => Value::Null,
+                value => arithmetic(Op::Subtract, ValueRef::Int(0), value),
+            })
+        }
+        Ast::BinaryOp { op, lhs, rhs } => boolean(compare_operands(db, params, row, lhs, rhs).map(
+            |ordering| match op {
+                Op::Equal => ordering.is_eq(),
+                Op::NotEqual => ordering.is_ne(),
+                Op::Less => ordering.is_lt(),
+                Op::LessEqual => ordering.is_le(),
+                Op::Greater => ordering.is_gt(),
+                Op::GreaterEqual => ordering.is_ge(),
+                _ => unreachable!("not supported: {:?}", op),
+            },
+        )),
+        // True if any value is equal, and otherwise NULL if any of them might have been.
+        Ast::InList { lhs, list } => {
+            let mut unknown = false;
+            for value in list {
+                match compare_operands(db, params, row, lhs, value) {
+                    Some(ordering) if ordering.is_eq() => return boolean(Some(true)),
+                    Some(_) => {}
+                    None => unknown = true,
                 }
-                QueryStep::Count(what) => {
-                    if what != "*" {
-                        panic!("Only support count(*) for now");
-                    }
+            }
+            boolean((!unknown).then_some(false))
+        }
+        _ => unreachable!("not supported: {:?}", expr),
+    }
+}
 
-                    if execution_context.rows.is_none() {
-                        let table = execution_context.table.as_ref().unwrap();
-                        execution_context.rows = Some(db.get_table_rows(table, &mut None));
-                    }
+// Works out `lhs op rhs` for an arithmetic operator, which is NULL when either side is. Anything
+// that isn't a number is taken as the number its text starts with. Integers stay integers, unless
+// the result is too big for one, and dividing by zero is NULL. `%` takes the remainder of the
+// operands' integer parts, and is a real if either of them is.
+fn arithmetic(op: Op, lhs: ValueRef, rhs: ValueRef) -> Value {
+    let (lhs, rhs) = match (numeric_prefix(lhs), numeric_prefix(rhs)) {
+        (Value::Null, _) | (_, Value::Null) => return Value::Null,
+        operands => operands,
+    };
+    match (op, lhs, rhs) {
+        (Op::Remainder, lhs, rhs) => {
+            let real = matches!(lhs, Value::Real(_)) || matches!(rhs, Value::Real(_));
+            let (lhs, rhs) = (as_int(lhs.as_value_ref()), as_int(rhs.as_value_ref()));
+            match (rhs, real) {
+                (0, _) => Value::Null,
+                (rhs, false) => Value::Int(lhs.checked_rem(rhs).unwrap_or(0)),
+                (rhs, true) => Value::Real(lhs.checked_rem(rhs).unwrap_or(0) as f64),
+            }
+        }
+        (_, Value::Int(lhs), Value::Int(rhs)) => {
+            let result = match op {
+                Op::Add => lhs.checked_add(rhs),
+                Op::Subtract => lhs.checked_sub(rhs),
+                Op::Multiply => lhs.checked_mul(rhs),
+                _ if rhs == 0 => return Value::Null,
+                _ => lhs.checked_div(rhs),
+            };
+            result.map_or_else(
+                || arithmetic(op, ValueRef::Real(lhs as f64), ValueRef::Real(rhs as f64)),
+                Value::Int,
+            )
+        }
+        (_, lhs, rhs) => {
+            let (lhs, rhs) = (as_real(&lhs), as_real(&rhs));
+            match op {
+                Op::Add => Value::Real(lhs + rhs),
+                Op::Subtract => Value::Real(lhs - rhs),
+                Op::Multiply => Value::Real(lhs * rhs),
+                _ if rhs == 0.0 => Value::Null,
+                _ => Value::Real(lhs / rhs),
+            }
+        }
+    }
+}
+
+// A value as a number for arithmetic: text is the number it starts with, after any spaces, or 0 if
+// it doesn't start with one, and a blob is read as text. An integer too big for 64 bits is a real.
+fn numeric_prefix(value: ValueRef) -> Value {
+    let text = match value {
+        ValueRef::Null => return Value::Null,
+        ValueRef::Int(n) => return Value::Int(n),
+        ValueRef::Real(r) => return Value::Real(r),
+        ValueRef::Text(text) => text.to_string(),
+        ValueRef::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+    };
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let digits = |mut end: usize| {
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+        end
+    };
+
+    let start = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let mut end = digits(start);
+    let mut real = false;
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+        real = true;
+    }
+    // A sign or a point on its own isn't a number.
+    if end == start + usize::from(real) {
+        return Value::Int(0);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent = digits(end + 1 + sign);
+        if exponent > end + 1 + sign {
+            end = exponent;
+            real = true;
+        }
+    }
+
+    let number = &text[..end];
+    match number.parse::<i64>() {
+        Ok(n) if !real => Value::Int(n),
+        _ => Value::Real(number.parse().unwrap_or(0.0)),
+    }
+}
+
+// Compares two operands the way sqlite does, or gives None when either is NULL. A column's
+// affinity is applied to the other side first, as it is when a column is compared with a value,
+// except that a numeric column wins over a text one, and the left column's collation comes before
+// the right's.
+fn compare_operands(
+    db: &Db,
+    params: &[Value],
+    row: Option<(&MasterPageRecord, &dyn SourceRow)>,
+    lhs: &Ast,
+    rhs: &Ast,
+) -> Option<std::cmp::Ordering> {
+    let mut lhs_value = evaluate(db, params, row, lhs).into_value();
+    let mut rhs_value = evaluate(db, params, row, rhs).into_value();
+    if matches!(lhs_value, Value::Null) || matches!(rhs_value, Value::Null) {
+        return None;
+    }
+
+    let table = row.map(|(table, _)| table);
+    let (lhs_affinity, lhs_collation) = operand_column(table, lhs).unzip();
+    let (rhs_affinity, rhs_collation) = operand_column(table, rhs).unzip();
+    let numeric = |affinity: Option<Affinity>| {
+        matches!(
+            affinity,
+            Some(Affinity::Integer | Affinity::Real | Affinity::Numeric)
+        )
+    };
+    if numeric(lhs_affinity) && !numeric(rhs_affinity) {
+        rhs_value = Affinity::Numeric.apply(rhs_value);
+    } else if numeric(rhs_affinity) && !numeric(lhs_affinity) {
+        lhs_value = Affinity::Numeric.apply(lhs_value);
+    } else if lhs_affinity == Some(Affinity::Text) && rhs_affinity.is_none() {
+        rhs_value = Affinity::Text.apply(rhs_value);
+    } else if rhs_affinity == Some(Affinity::Text) && lhs_affinity.is_none() {
+        lhs_value = Affinity::Text.apply(lhs_value);
+    }
+
+    let collation = lhs_collation.or(rhs_collation).unwrap_or(Collation::Binary);
+    Some(collation.compare(lhs_value.as_value_ref(), rhs_value.as_value_ref()))
+}
+
+// The affinity and collation of an operand that is a column. Anything else has neither. A
+// collation sqlite doesn't know couldn't have been declared, so one that isn't known is BINARY.
+fn operand_column(table: Option<&MasterPageRecord>, expr: &Ast) -> Option<(Affinity, Collation)> {
+    match (expr, table) {
+        (Ast::Expr(expr), _) => operand_column(table, expr),
+        (Ast::Identifier(name), Some(table)) => match table.get_column_index(name) {
+            Ok(index) => Some((
+                table.affinity(index),
+                Collation::of(
+                    table
+                        .columns
+                        .get(index)
+                        .and_then(|column| column.collation()),
+                )
+                .unwrap_or(Collation::Binary),
+            )),
+            Err(_) => Some((Affinity::Integer, Collation::Binary)),
+        },
+        _ => None,
+    }
+}
+
+// Whether a value is true, as a condition: NULL is neither, and anything else is true unless it's
+// zero as a number.
+fn truth(value: ValueRef) -> Option<bool> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Int(n) => Some(n != 0),
+        value => Some(as_real(&value.to_value()) != 0.0),
+    }
+}
+
+// A condition's result as a value: 1 for true, 0 for false, and NULL for neither.
+fn boolean<'a>(value: Option<bool>) -> Datum<'a> {
+    Datum::Owned(value.map_or(Value::Null, |value| Value::Int(value as i64)))
+}
 
-                    let rows = execution_context.rows.as_ref().unwrap();
-                    results.push(format!("{}", rows.len()));
+// Calls a function that isn't an aggregate with the values of its arguments.
+fn call<'a>(db: &Db, name: &str, args: Vec<Datum<'a>>) -> Datum<'a> {
+    let name = name.to_ascii_uppercase();
+    match (name.as_str(), args.as_slice()) {
+        ("CHANGES", []) => Datum::Owned(Value::Int(db.changes.load(Ordering::Relaxed) as i64)),
+        ("TOTAL_CHANGES", []) => {
+            Datum::Owned(Value::Int(db.total_changes.load(Ordering::Relaxed) as i64))
+        }
+        ("LAST_INSERT_ROWID", []) => {
+            Datum::Owned(Value::Int(db.last_insert_rowid.load(Ordering::Relaxed)))
+        }
+        ("SQLITE_VERSION", []) => Datum::Borrowed(ValueRef::Text(VERSION)),
+        ("SQLITE_SOURCE_ID", []) => Datum::Borrowed(ValueRef::Text(SOURCE_ID)),
+        // Text is measured in characters and blobs in bytes. A number is as long as its text.
+        ("LENGTH", [arg]) => Datum::Owned(match arg.value_ref() {
+            ValueRef::Null => Value::Null,
+            ValueRef::Text(text) => Value::Int(text.chars().count() as i64),
+            ValueRef::Blob(blob) => Value::Int(blob.len() as i64),
+            value => Value::Int(value.to_string().len() as i64),
+        }),
+        // Like sqlite without ICU, only ASCII letters change case.
+        ("UPPER" | "LOWER", [arg]) => Datum::Owned(match arg.value_ref() {
+            ValueRef::Null => Value::Null,
+            value if name == "UPPER" => Value::Text(as_text(value).to_ascii_uppercase()),
+            value => Value::Text(as_text(value).to_ascii_lowercase()),
+        }),
+        ("SUBSTR", [arg, start, rest @ ..]) => {
+            let length = rest.first().map(|length| length.value_ref());
+            if [Some(arg.value_ref()), Some(start.value_ref()), length]
+                .iter()
+                .any(|value| matches!(value, Some(ValueRef::Null)))
+            {
+                return Datum::Owned(Value::Null);
+            }
+            let start = as_int(start.value_ref());
+            let length = length.map(as_int);
+            Datum::Owned(match arg.value_ref() {
+                ValueRef::Blob(blob) => {
+                    Value::Blob(blob[substr(blob.len(), start, length)].to_vec())
                 }
+                value => {
+                    let chars = value.to_string().chars().collect::<Vec<_>>();
+                    Value::Text(chars[substr(chars.len(), start, length)].iter().collect())
+                }
+            })
+        }
+        ("UNISTR", [arg]) => Datum::Owned(match arg.value_ref() {
+            ValueRef::Null => Value::Null,
+            value => unistr(&as_text(value)).map_or(Value::Null, Value::Text),
+        }),
+        _ => unreachable!("no such function: {}", name),
+    }
+}
+
+// Text with its escapes made into the characters they stand for, as sqlite's unistr() does, or
+// None if one of them isn't an escape. `\\` is a backslash, and `\XXXX`, `\uXXXX`, `\+XXXXXX`
+// and `\UXXXXXXXX` are the character with that many hex digits of code point. .dump writes text
+// with control characters in it this way.
+fn unistr(text: &str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('\\') {
+        result.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let (skip, digits) = match rest.as_bytes().first() {
+            Some(b'\\') => {
+                result.push('\\');
+                rest = &rest[1..];
+                continue;
             }
+            Some(b'u') => (1, 4),
+            Some(b'+') => (1, 6),
+            Some(b'U') => (1, 8),
+            _ => (0, 4),
+        };
+        let hex = rest.get(skip..skip + digits)?;
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        result.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+        rest = &rest[skip + digits..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+// Where substr() takes characters or bytes from, given how many there are. The start counts from
+// 1, or back from the end when it's negative, and a negative length takes what comes before it.
+// Without a length it takes everything after the start.
+fn substr(len: usize, start: i64, length: Option<i64>) -> Range<usize> {
+    let len = len as i64;
+    let (mut start, mut length, backwards) = match length {
+        Some(length) if length < 0 => (start, length.saturating_neg(), true),
+        Some(length) => (start, length, false),
+        None => (start, i64::MAX, false),
+    };
+    if start < 0 {
+        start = start.saturating_add(len);
+        if start < 0 {
+            length = length.saturating_add(start).max(0);
+            start = 0;
         }
+    } else if start > 0 {
+        start -= 1;
+    } else if length > 0 {
+        length -= 1;
+    }
+    if backwards {
+        start -= length;
+        if start < 0 {
+            length += start;
+            start = 0;
+        }
+    }
+    let start = start.min(len);
+    let end = start.saturating_add(length).min(len);
+    start as usize..end as usize
+}
+
+// A value as the text a function or || takes it as: a number is written out, and a blob's bytes
+// are read as UTF-8, as sqlite reads them.
+fn as_text(value: ValueRef) -> String {
+    match value {
+        ValueRef::Text(text) => text.to_string(),
+        ValueRef::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+        value => value.to_string(),
+    }
+}
+
+// A value as the integer a function takes it as: text that doesn't read as a number, and any
+// blob, is 0, and a real loses its fraction.
+fn as_int(value: ValueRef) -> i64 {
+    match Affinity::Numeric.apply(value.to_value()) {
+        Value::Int(n) => n,
+        Value::Real(r) => r as i64,
+        _ => 0,
+    }
+}
+
+// The smallest or largest value of a column, read from one end of a b-tree sorted by it: the
+// table's own for the rowid, or an index with the column first. None when there is no such b-tree.
+fn seek_bound(db: &Db, table: &MasterPageRecord, expr: &Ast, max: bool) -> Result<Option<Value>> {
+    let Ast::Expr(expr) = expr else {
+        return Ok(None);
+    };
+    let Ast::Identifier(name) = expr.as_ref() else {
+        return Ok(None);
+    };
+
+    let rowid = match table.get_column_index(name) {
+        Ok(index) => table.rowid_alias == Some(index),
+        Err(_) => is_rowid(name),
+    };
+    if rowid && !table.without_rowid {
+        trace::event!(
+            Target::Planner,
+            "{}({}) on {} by rowid",
+            if max { "max" } else { "min" },
+            name,
+            table.name
+        );
+        return Ok(Some(db.rowid_bound(table, max)?));
+    }
 
-        for result in results {
-            println!("{}", result);
+    match index_on(db, table, name) {
+        Some(index) => {
+            trace::event!(
+                Target::Planner,
+                "{}({}) on {} by index {}",
+                if max { "max" } else { "min" },
+                name,
+                table.name,
+                index.name
+            );
+            Ok(Some(db.indexed_bound(&index, max)?))
         }
+        None => Ok(None),
     }
 }
 
-#[derive(Debug)]
-enum QueryStep {
-    SetTable(String),
-    Where(String, Value),
-    Select(Vec<String>),
-    Count(String),
+// The first index on a table that can be sought for the values of a column, which is one that
+// sorts by that column first.
+fn index_on(db: &Db, table: &MasterPageRecord, column: &str) -> Option<Arc<MasterPageRecord>> {
+    db.catalog
+        .indexes_on(&table.name)
+        .iter()
+        .find(|index| {
+            index
+                .indexed
+                .first()
+                .is_some_and(|first| first.name.eq_ignore_ascii_case(column))
+                && seekable(db, index)
+        })
+        .cloned()
 }
 
-pub struct SqlEngine {}
+// Whether an index can be sought in: it has every row of its table, and sorts its keys in sqlite's
+// default order, ascending by the BINARY collation, which is the only order a seek knows how to
+// follow.
+fn seekable(db: &Db, index: &MasterPageRecord) -> bool {
+    index.partial.is_none()
+        && db
+            .catalog
+            .index(&index.name)
+            .is_some_and(Index::default_order)
+}
 
-impl SqlEngine {
-    pub fn new() -> Self {
-        Self {}
+// An index whose second column is the one a WHERE clause looks for, and whose first column has so
+// few distinct values that seeking each of them in turn reads less than scanning the table. Like
+// sqlite, that is only known from sqlite_stat1, when ANALYZE found at least 18 rows for each value
+// of the first column on average.
+fn skip_scan_index(
+    db: &Db,
+    table: &MasterPageRecord,
+    column: &str,
+) -> Result<Option<Arc<MasterPageRecord>>> {
+    if db.get_table("sqlite_stat1").is_err() {
+        return Ok(None);
     }
 
-    pub fn execute(&self, sql: &str, db: &mut Db) {
-        let mut lexer = Lexer::new(sql.to_string());
-        let mut parser = Parser::new(lexer.lex());
-        let ast = parser.parse();
+    let plan = Plan::prepare("SELECT stat FROM sqlite_stat1 WHERE idx = ?")?;
+    for index in db.catalog.indexes_on(&table.name).iter().filter(|record| {
+        record
+            .indexed
+            .get(1)
+            .is_some_and(|second| second.name.eq_ignore_ascii_case(column))
+            && seekable(db, record)
+    }) {
+        let mut rows = Rows::default();
+        SqlEngine::new(&mut rows).run(&plan, &[Value::Text(index.name.clone())], db)?;
+        // The stat is the number of rows in the index, followed by the average number of rows
+        // for each value of its first column, of its first two, and so on.
+        let rows_per_value = rows.rows.first().and_then(|row| {
+            row.values()[0]
+                .to_string()
+                .split_whitespace()
+                .nth(1)?
+                .parse::<u64>()
+                .ok()
+        });
+        if rows_per_value.is_some_and(|rows| rows >= 18) {
+            return Ok(Some(index.clone()));
+        }
+    }
+    Ok(None)
+}
+
+// The ranges of values that hold everything a LIKE pattern can match in an index sorted by the
+// BINARY collation, which is everything that starts with the pattern's prefix up to its first
+// wildcard, so that `LIKE 'Bat%'` reads `>= 'Bat' AND < 'Bau'`. LIKE ignores the case of ASCII
+// letters and the index doesn't, so there is a range for each way of writing the letters of the
+// prefix, which stops before its fourth letter to keep them few. A pattern that starts with a
+// wildcard has no ranges.
+fn like_ranges(pattern: &str) -> Vec<(Bound<Value>, Bound<Value>)> {
+    let mut prefixes = vec![String::new()];
+    let mut letters = 0;
+    for c in pattern.chars().take_while(|c| *c != '%' && *c != '_') {
+        if !c.is_ascii_alphabetic() {
+            prefixes.iter_mut().for_each(|prefix| prefix.push(c));
+            continue;
+        }
+        if letters == 3 {
+            break;
+        }
+        letters += 1;
+        prefixes = prefixes
+            .into_iter()
+            .flat_map(|prefix| {
+                [
+                    format!("{}{}", prefix, c.to_ascii_uppercase()),
+                    format!("{}{}", prefix, c.to_ascii_lowercase()),
+                ]
+            })
+            .collect();
+    }
+    if prefixes[0].is_empty() {
+        return Vec::new();
+    }
+
+    prefixes
+        .into_iter()
+        .map(|prefix| {
+            let high = successor(&prefix)
+                .map_or(Bound::Unbounded, |high| Bound::Excluded(Value::Text(high)));
+            (Bound::Included(Value::Text(prefix)), high)
+        })
+        .collect()
+}
+
+// The least string that comes after every string starting with `prefix`, which is the prefix with
+// its last character moved on by one, or None if there is no such string.
+fn successor(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+    while let Some(last) = chars.pop() {
+        if let Some(next) = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// Works out what each term of ORDER BY sorts by, and whether it's descending. A term that is just
+// an integer is the number of a result column, counting from 1, and sorts by that column's
+// expression.
+fn ordering_terms(order_by: &[Ast], columns: &[Ast]) -> Result<Vec<(Ast, bool)>> {
+    order_by
+        .iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let Ast::OrderingTerm { expr, descending } = term else {
+                unreachable!();
+            };
+            Ok((result_term(expr, columns, "ORDER", i)?, *descending))
+        })
+        .collect()
+}
+
+// Works out what each term of GROUP BY groups by, which can be the number of a result column like
+// a term of ORDER BY, so long as that column isn't an aggregate.
+fn grouping_terms(group_by: &[Ast], columns: &[Ast]) -> Result<Vec<Ast>> {
+    group_by
+        .iter()
+        .enumerate()
+        .map(|(i, expr)| match result_term(expr, columns, "GROUP", i)? {
+            expr if has_aggregate(&expr) => Err(Error::Parse(
+                "aggregate functions are not allowed in the GROUP BY clause".to_string(),
+            )),
+            expr => Ok(expr),
+        })
+        .collect()
+}
+
+// The `i`th term of an ORDER BY or GROUP BY: its expression, or the expression of the result
+// column it numbers.
+fn result_term(expr: &Ast, columns: &[Ast], clause: &str, i: usize) -> Result<Ast> {
+    let Ast::Expr(inner) = expr else {
+        return Ok(expr.clone());
+    };
+    let Ast::IntegerLiteral(ordinal) = inner.as_ref() else {
+        return Ok(expr.clone());
+    };
+    match usize::try_from(*ordinal) {
+        Ok(ordinal @ 1..) if ordinal <= columns.len() => Ok(columns[ordinal - 1].clone()),
+        _ => Err(Error::Parse(format!(
+            "{} {} BY term out of range - should be between 1 and {}",
+            nth(i + 1),
+            clause,
+            columns.len()
+        ))),
+    }
+}
+
+// The number of rows a LIMIT or OFFSET gives, which has to be an integer, or None without one.
+fn row_count(db: &Db, params: &[Value], expr: Option<&Ast>) -> Result<Option<i64>> {
+    let Some(expr) = expr else {
+        return Ok(None);
+    };
+    check(None, expr)?;
+    match Affinity::Integer.apply(evaluate(db, params, None, expr).into_value()) {
+        Value::Int(n) => Ok(Some(n)),
+        _ => Err(Error::Mismatch),
+    }
+}
+
+// 1st, 2nd, 3rd, 4th, ..., 11th, 12th, 13th, ..., 21st, and so on.
+fn nth(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+// Compares the values of two rows' ORDER BY terms, term by term.
+fn compare_keys(a: &[Value], b: &[Value], descending: &[bool]) -> std::cmp::Ordering {
+    a.iter()
+        .zip(b)
+        .zip(descending)
+        .map(|((a, b), descending)| match descending {
+            true => b.compare(a),
+            false => a.compare(b),
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+// Splits rows into groups that GROUP BY can't tell apart, in the order of their keys. Without
+// GROUP BY, every row is in the one group, even when there are none.
+fn group<'a>(
+    db: &Db,
+    params: &'a [Value],
+    table: Option<&MasterPageRecord>,
+    records: Vec<&'a dyn SourceRow>,
+    group_by: &[Ast],
+) -> Vec<Vec<&'a dyn SourceRow>> {
+    if group_by.is_empty() {
+        return vec![records];
+    }
+
+    let ascending = vec![false; group_by.len()];
+    let mut keyed = records
+        .into_iter()
+        .map(|record| {
+            let row = table.map(|table| (table, record));
+            let key = group_by
+                .iter()
+                .map(|expr| evaluate(db, params, row, expr).into_value())
+                .collect::<Vec<_>>();
+            (key, record)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| compare_keys(a, b, &ascending));
+
+    keyed
+        .chunk_by(|(a, _), (b, _)| compare_keys(a, b, &ascending).is_eq())
+        .map(|group| group.iter().map(|(_, record)| *record).collect())
+        .collect()
+}
+
+// Evaluates a result column or ORDER BY term for a group of rows. An aggregate is worked out over
+// every row of the group, and anything else is evaluated against the first of them, as sqlite
+// does. When the group is empty, anything that refers to a column is NULL.
+fn evaluate_group<'a>(
+    db: &Db,
+    params: &'a [Value],
+    table: Option<&MasterPageRecord>,
+    rows: &[&'a dyn SourceRow],
+    expr: &Ast,
+) -> Result<Value> {
+    match expr {
+        Ast::Expr(expr) => evaluate_group(db, params, table, rows, expr),
+        Ast::Function {
+            name,
+            args,
+            distinct,
+        } if is_aggregate(name) => {
+            let [arg] = args.as_slice() else {
+                unreachable!()
+            };
+            aggregate(db, params, table, rows, name, arg, *distinct)
+        }
+        // A function of an aggregate is called once the aggregate has been worked out.
+        Ast::Function { name, args, .. } if has_aggregate(expr) => {
+            let args = args
+                .iter()
+                .map(|arg| evaluate_group(db, params, table, rows, arg).map(Datum::Owned))
+                .collect::<Result<_>>()?;
+            Ok(call(db, name, args).into_value())
+        }
+        expr => Ok(match rows.first() {
+            Some(row) => evaluate(db, params, table.map(|table| (table, *row)), expr).into_value(),
+            None if mentions_column(expr) => Value::Null,
+            None => evaluate(db, params, None, expr).into_value(),
+        }),
+    }
+}
+
+// Works out an aggregate over the rows of a group. NULLs are left out of all but count(*), and
+// with DISTINCT, values that compare equal are only counted once.
+fn aggregate<'a>(
+    db: &Db,
+    params: &'a [Value],
+    table: Option<&MasterPageRecord>,
+    rows: &[&'a dyn SourceRow],
+    name: &str,
+    arg: &Ast,
+    distinct: bool,
+) -> Result<Value> {
+    if *arg == Ast::All {
+        return Ok(Value::Int(rows.len() as i64));
+    }
 
-        match ast {
-            Ast::StmtList(statements) => self.execute_statements(statements, db),
-            _ => panic!("Not implemented"),
+    let mut values = rows
+        .iter()
+        .map(|row| evaluate(db, params, table.map(|table| (table, *row)), arg).into_value())
+        .filter(|value| !matches!(value, Value::Null))
+        .collect::<Vec<_>>();
+    if distinct {
+        values.sort_by(Value::compare);
+        values.dedup_by(|a, b| a.compare(b).is_eq());
+    }
+    let values = values.into_iter();
+    Ok(match name.to_ascii_uppercase().as_str() {
+        "COUNT" => Value::Int(values.count() as i64),
+        "MIN" => extreme(values, false),
+        "MAX" => extreme(values, true),
+        "SUM" => sum(values)?,
+        "AVG" => {
+            let (count, total) = values.fold((0, 0.0), |(count, total), value| {
+                (count + 1, total + as_real(&value))
+            });
+            match count {
+                0 => Value::Null,
+                count => Value::Real(total / count as f64),
+            }
         }
+        _ => unreachable!("no such aggregate: {}", name),
+    })
+}
+
+// The smallest or largest of some values that aren't NULL, or NULL when there are none.
+fn extreme(values: impl Iterator<Item = Value>, max: bool) -> Value {
+    values
+        .reduce(|a, b| match (b.compare(&a).is_gt(), max) {
+            (true, true) | (false, false) => b,
+            _ => a,
+        })
+        .unwrap_or(Value::Null)
+}
+
+// sum() is an integer while every value it adds is one, and fails rather than overflow. Once any
+// value isn't, the sum is a real, and text that doesn't read as a number adds nothing.
+fn sum(values: impl Iterator<Item = Value>) -> Result<Value> {
+    let mut int = Some(0i64);
+    let mut real = 0.0;
+    let mut any = false;
+    for value in values {
+        any = true;
+        let value = Affinity::Numeric.apply(value);
+        real += as_real(&value);
+        int = match (int, value) {
+            (Some(total), Value::Int(n)) => {
+                Some(total.checked_add(n).ok_or(Error::IntegerOverflow)?)
+            }
+            _ => None,
+        };
     }
+    Ok(match (any, int) {
+        (false, _) => Value::Null,
+        (true, Some(total)) => Value::Int(total),
+        (true, None) => Value::Real(real),
+    })
+}
+
+// A value as a real for adding up, where text that doesn't read as a number, and any blob, is 0.
+fn as_real(value: &Value) -> f64 {
+    match Affinity::Real.apply(value.clone()) {
+        Value::Real(r) => r,
+        _ => 0.0,
+    }
+}
+
+// The value bound to a parameter. Like sqlite3, a parameter that was never bound is NULL.
+fn parameter(params: &[Value], index: usize) -> &Value {
+    params.get(index - 1).unwrap_or(&Value::Null)
+}
+
+// A result column of a select, and the name of its column. Only the select a DELETE makes to find
+// its rows has columns that aren't named, and they are named after what they are.
+fn result_column(result: &Ast) -> (&Ast, String) {
+    match result {
+        Ast::ResultColumn { expr, name } => (expr, name.clone()),
+        result => (result, describe(result)),
+    }
+}
 
-    fn execute_statements(&self, stmts: Vec<Ast>, db: &mut Db) {
-        for stmt in stmts {
-            match stmt {
-                Ast::Stmt(stmt) => self.execute_statement(*stmt, db),
-                _ => panic!("Not implemented"),
+// The expressions of the result columns, and the names of their columns, with `*` standing for
+// every column of the table, in order. A column that is named after itself is named the way the
+// table declares it, however the query spelled it.
+fn expand(columns: &[(Ast, String)], table: Option<&MasterPageRecord>) -> ResultColumns {
+    columns
+        .iter()
+        .flat_map(|(column, header)| match (column, table) {
+            (Ast::All, Some(table)) => table
+                .columns
+                .iter()
+                .map(|column| (Ast::Identifier(column.name.clone()), column.name.clone()))
+                .collect(),
+            (Ast::Identifier(name), Some(table)) if name == header => {
+                let declared = table
+                    .columns
+                    .iter()
+                    .map(|column| &column.name)
+                    .find(|column| column.eq_ignore_ascii_case(name))
+                    .unwrap_or(header);
+                vec![(column.clone(), declared.clone())]
             }
+            _ => vec![(column.clone(), header.clone())],
+        })
+        .collect()
+}
+
+// Writes an expression back out as SQL, for an error message or to name a column that wasn't
+// written out.
+fn describe(expr: &Ast) -> String {
+    match expr {
+        Ast::All => "*".to_string(),
+        Ast::Expr(expr) | Ast::ResultColumn { expr, .. } => describe(expr),
+        Ast::Identifier(name) => name.clone(),
+        Ast::StringLiteral(value) => format!("'{}'", value.replace('\'', "''")),
+        Ast::IntegerLiteral(value) => value.to_string(),
+        Ast::RealLiteral(value) => format!("{:?}", value),
+        Ast::BlobLiteral(bytes) => sql_literal(ValueRef::Blob(bytes)),
+        Ast::NullLiteral => "NULL".to_string(),
+        Ast::Parameter { text, .. } => text.clone(),
+        Ast::Function {
+            name,
+            args,
+            distinct,
+        } => {
+            let args = args.iter().map(describe).collect::<Vec<_>>();
+            let distinct = if *distinct { "DISTINCT " } else { "" };
+            format!("{}({}{})", name, distinct, args.join(", "))
+        }
+        Ast::BinaryOp { op, lhs, rhs } => {
+            let op = match op {
+                Op::Equal => "=",
+                Op::NotEqual => "!=",
+                Op::Less => "<",
+                Op::LessEqual => "<=",
+                Op::Greater => ">",
+                Op::GreaterEqual => ">=",
+                Op::Match => "MATCH",
+                Op::Like => "LIKE",
+                Op::And => "AND",
+                Op::Or => "OR",
+                Op::Add => "+",
+                Op::Subtract => "-",
+                Op::Multiply => "*",
+                Op::Divide => "/",
+                Op::Remainder => "%",
+                Op::Concat => "||",
+            };
+            format!("{} {} {}", describe(lhs), op, describe(rhs))
+        }
+        Ast::Negate(operand) => format!("-{}", describe(operand)),
+        Ast::InList { lhs, list } => {
+            let list = list.iter().map(describe).collect::<Vec<_>>();
+            format!("{} IN ({})", describe(lhs), list.join(", "))
+        }
+        _ => format!("{:?}", expr),
+    }
+}
+
+// Where the results of a statement go: the shell prints them, and a library connection collects
+// them. A statement produces at most one result set, started before its first row and finished
+// after its last.
+pub trait ResultSink {
+    fn start(&mut self, columns: Vec<String>) -> Result<()>;
+    fn row<'a>(&mut self, values: impl Iterator<Item = Datum<'a>>) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<W: Write> ResultSink for ResultWriter<'_, W> {
+    fn start(&mut self, columns: Vec<String>) -> Result<()> {
+        ResultWriter::start(self, columns);
+        Ok(())
+    }
+
+    fn row<'a>(&mut self, values: impl Iterator<Item = Datum<'a>>) -> Result<()> {
+        let values = values.collect::<Vec<_>>();
+        Ok(self.write_row(values.iter().map(Datum::value_ref))?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(ResultWriter::finish(self)?)
+    }
+}
+
+// A row on its way through a query's operators: a record out of a table b-tree, a row a virtual
+// table produced, the one row of a query without FROM, which has no columns, or values that an
+// operator worked out from the rows under it.
+enum Tuple {
+    Table(TableLeafRecord),
+    Virtual(VirtualRow),
+    Empty,
+    Values(Vec<Value>),
+}
+
+impl Tuple {
+    fn into_values(self) -> Vec<Value> {
+        match self {
+            Tuple::Values(values) => values,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl SourceRow for Tuple {
+    fn rowid(&self) -> i64 {
+        match self {
+            Tuple::Table(record) => record.rowid(),
+            Tuple::Virtual(row) => row.rowid(),
+            Tuple::Empty | Tuple::Values(_) => 0,
         }
     }
 
-    fn execute_statement(&self, stmt: Ast, db: &mut Db) {
-        match stmt {
-            Ast::Select {
-                result_columns,
-                from,
-                r#where,
-            } => self.execute_select(result_columns, *from, r#where, db),
-            _ => panic!("Not implemented {:?}", stmt),
+    fn column(&self, index: usize) -> ValueRef<'_> {
+        match self {
+            Tuple::Table(record) => record.column(index),
+            Tuple::Virtual(row) => row.column(index),
+            Tuple::Empty => ValueRef::Null,
+            Tuple::Values(values) => values
+                .get(index)
+                .map_or(ValueRef::Null, Value::as_value_ref),
         }
     }
+}
+
+// One of the operators a running query is made of. Each hands out its rows one at a time, pulling
+// rows from the operators under it only as it needs them, so a LIMIT stops reading once it has
+// enough. An operator that needs all of its input before it can hand out its first row, like a
+// sort, reads all of it then.
+trait Operator {
+    fn next(&mut self) -> Result<Option<Tuple>>;
+}
+
+type BoxedOperator<'a> = Box<dyn Operator + 'a>;
+
+// What a running query's operators share: the database, the values bound to the statement, the
+// virtual tables FROM can name, and how many rows have been read out of tables so far.
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    db: &'a Db,
+    params: &'a [Value],
+    virtual_tables: Option<&'a VirtualTables>,
+    scanned: &'a Cell<u64>,
+}
+
+impl Context<'_> {
+    fn count_scanned(&self, rows: u64) {
+        self.scanned.set(self.scanned.get() + rows);
+    }
 
-    fn execute_select(
+    // Reads a virtual table's rows the first time one is asked for, and hands them out from there.
+    fn fetch(
         &self,
-        result_columns: Vec<Ast>,
-        from: Ast,
-        r#where: Option<Box<Ast>>,
-        db: &mut Db,
-    ) {
-        let mut query_plan = QueryPlanner::new();
-
-        let table_name = match from {
-            Ast::TableOrSubQuery(node) => match *node {
-                Ast::Table(table_name) => table_name,
-                _ => panic!("Not implemented {:?}", node),
-            },
-            _ => panic!("Not implemented {:?}", from),
-        };
+        rows: &mut Option<std::vec::IntoIter<Tuple>>,
+        read: impl FnOnce() -> Result<Vec<Tuple>>,
+    ) -> Result<Option<Tuple>> {
+        if rows.is_none() {
+            let read = read()?;
+            self.count_scanned(read.len() as u64);
+            *rows = Some(read.into_iter());
+        }
+        Ok(rows.as_mut().and_then(Iterator::next))
+    }
+}
 
-        query_plan.add_step(QueryStep::SetTable(table_name));
-
-        if let Some(where_clause) = r#where {
-            if let Ast::Expr(expr) = *where_clause {
-                match *expr {
-                    Ast::BinaryOp { op, lhs, rhs } => {
-                        let column_name = if let Ast::Expr(lhs) = *lhs {
-                            match *lhs {
-                                Ast::Identifier(name) => name,
-                                _ => panic!("LHS Not implemented {:?}", lhs),
-                            }
-                        } else {
-                            panic!("LHS Not implemented {:?}", lhs);
-                        };
-
-                        let value = if let Ast::Expr(rhs) = *rhs {
-                            match *rhs {
-                                Ast::StringLiteral(value) => value,
-                                _ => panic!("RHS Not implemented {:?}", rhs),
-                            }
-                        } else {
-                            panic!("RHS Not implemented {:?}", rhs);
-                        };
-
-                        if op != Op::Equal {
-                            panic!("Only support equals for now");
-                        }
+// The most leaves a scan reads at once, or rowids an index seek looks up at once.
+const MAX_BATCH: usize = 64;
 
-                        query_plan.add_step(QueryStep::Where(column_name, Value::Text(value)));
-                    }
-                    _ => panic!("Not implemented {:?}", expr),
+// Every row of a table, or the rows a virtual table finds for some constraints. A table's leaves
+// are read in batches, split across threads, that start at one leaf and double in size, so a
+// LIMIT stops reading soon after it has its rows and a long scan still gets to use every core.
+struct Scan<'a> {
+    context: Context<'a>,
+    source: ScanSource<'a>,
+    leaves: Option<Leaves<'a>>,
+    batch: usize,
+    rows: Option<std::vec::IntoIter<Tuple>>,
+}
+
+enum ScanSource<'a> {
+    Table(Arc<MasterPageRecord>),
+    // The table, the arguments it was called with, and what it's asked to find.
+    Virtual(Arc<dyn VirtualTable + 'a>, Vec<Value>, Vec<Constraint>),
+}
+
+impl<'a> Scan<'a> {
+    fn new(context: Context<'a>, source: ScanSource<'a>) -> Self {
+        Self {
+            context,
+            source,
+            leaves: None,
+            batch: 1,
+            rows: None,
+        }
+    }
+}
+
+impl Operator for Scan<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        let (db, source) = (self.context.db, &self.source);
+        match source {
+            ScanSource::Table(table) => loop {
+                if let Some(row) = self.rows.as_mut().and_then(Iterator::next) {
+                    self.context.count_scanned(1);
+                    return Ok(Some(row));
                 }
-            } else {
-                panic!("Not implemented {:?}", where_clause);
+                if self.leaves.is_none() {
+                    self.leaves = Some(db.table_leaves(table)?);
+                }
+                let leaves = self.leaves.as_mut().unwrap();
+                let batch = leaves.take(self.batch).collect::<Result<Vec<_>>>()?;
+                if batch.is_empty() {
+                    return Ok(None);
+                }
+                self.batch = (self.batch * 2).min(MAX_BATCH);
+                let rows = db.read_leaves(batch)?.into_iter().map(Tuple::Table);
+                self.rows = Some(rows.collect::<Vec<_>>().into_iter());
+            },
+            ScanSource::Virtual(table, args, constraints) => {
+                self.context.fetch(&mut self.rows, || {
+                    Ok(vtab::scan(table.as_ref(), args, constraints)?
+                        .into_iter()
+                        .map(Tuple::Virtual)
+                        .collect())
+                })
             }
         }
+    }
+}
 
-        let mut columns = Vec::new();
+// The rows of a table that one of its indexes finds, in the order of the index. The rowids the
+// seek finds are looked up in the table in batches that double in size like a scan's, each in a
+// single pass down the table's b-tree in rowid order, and then handed out in the index's order. A
+// row found by more than one of the seek's keys or ranges is only handed out the first time.
+struct IndexSeek<'a> {
+    context: Context<'a>,
+    table: Arc<MasterPageRecord>,
+    row_ids: RowIds<'a>,
+    seen: HashSet<u64>,
+    batch: usize,
+    rows: std::vec::IntoIter<Tuple>,
+}
+
+enum Seek {
+    // Rows whose indexed column has any of the values.
+    Keys(Vec<Value>),
+    // Rows whose value in the index's second column is any of the values, whatever their first.
+    SkipScan(Vec<Value>),
+    // Rows whose indexed column is in any of the ranges.
+    Ranges(Vec<(Bound<Value>, Bound<Value>)>),
+}
+
+impl<'a> IndexSeek<'a> {
+    fn new(
+        context: Context<'a>,
+        table: Arc<MasterPageRecord>,
+        index: &MasterPageRecord,
+        seek: Seek,
+    ) -> Self {
+        let db = context.db;
+        let row_ids = match seek {
+            Seek::Keys(values) => db.index_row_ids(index, values),
+            Seek::SkipScan(values) => db.skip_scan_row_ids(index, values),
+            Seek::Ranges(ranges) => db.index_range_row_ids(index, ranges),
+        };
+        Self {
+            context,
+            table,
+            row_ids,
+            seen: HashSet::new(),
+            batch: 1,
+            rows: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Operator for IndexSeek<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                self.context.count_scanned(1);
+                return Ok(Some(row));
+            }
 
-        for result in result_columns {
-            match result {
-                Ast::All => {
-                    columns = vec!["*".to_string()];
+            let mut row_ids = Vec::with_capacity(self.batch);
+            while row_ids.len() < self.batch {
+                let Some(row_id) = self.row_ids.next().transpose()? else {
                     break;
+                };
+                if self.seen.insert(row_id) {
+                    row_ids.push(row_id);
                 }
-                Ast::Identifier(name) => columns.push(name),
-                Ast::Expr(expr) => {
-                    if let Ast::Function { name, args } = *expr {
-                        if name == "COUNT" && args.first() == Some(&Ast::All) {
-                            columns.clear();
-                            query_plan.add_step(QueryStep::Count("*".to_string()));
-                            break;
-                        } else {
-                            panic!("function {} not implemented", name);
-                        }
-                    } else if let Ast::Identifier(name) = *expr {
-                        columns.push(name);
-                    } else {
-                        panic!("Not implemented {:?}", expr);
-                    }
-                }
-                _ => panic!("Not implemented {:?}", result),
             }
-        }
+            if row_ids.is_empty() {
+                return Ok(None);
+            }
+            self.batch = (self.batch * 2).min(MAX_BATCH);
 
-        if !columns.is_empty() {
-            query_plan.add_step(QueryStep::Select(columns));
+            let mut found = HashMap::new();
+            for row in self
+                .context
+                .db
+                .get_table_rows(&self.table, Some(&row_ids))?
+            {
+                found.insert(row.header.row_id, row);
+            }
+            // An entry whose row isn't in the table has been skipped by a salvaging pager.
+            let rows = row_ids.iter().filter_map(|row_id| found.remove(row_id));
+            self.rows = rows.map(Tuple::Table).collect::<Vec<_>>().into_iter();
         }
+    }
+}
 
-        query_plan.execute(db);
+// Rows that were worked out when the query was opened, like the one row of a query without FROM.
+struct Precomputed {
+    rows: std::vec::IntoIter<Tuple>,
+}
+
+impl Precomputed {
+    fn new(rows: Vec<Tuple>) -> Self {
+        Self {
+            rows: rows.into_iter(),
+        }
+    }
+}
+
+impl Operator for Precomputed {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        Ok(self.rows.next())
+    }
+}
+
+// The rows of its input that pass a check.
+struct Filter<'a> {
+    context: Context<'a>,
+    input: BoxedOperator<'a>,
+    table: Arc<MasterPageRecord>,
+    check: Check,
+}
+
+// What a row has to pass to get through a filter: a test of its value in one column, an expression
+// that has to be true of it, or all or any of several checks, for conditions joined by AND or OR.
+// Nothing here is ever NULL rather than false, as without NOT a NULL condition can't make a row
+// pass any more than false can.
+enum Check {
+    // A test of a column, comparing by the column's collation.
+    Column(usize, Collation, Test),
+    Expr(Ast),
+    All(Vec<Check>),
+    Any(Vec<Check>),
+}
+
+impl Check {
+    fn passes(&self, context: Context, table: &MasterPageRecord, row: &Tuple) -> bool {
+        match self {
+            Check::Column(column, collation, test) => {
+                test.passes(column_value(table, row, *column), *collation)
+            }
+            Check::Expr(expr) => {
+                let value = evaluate(context.db, context.params, Some((table, row)), expr);
+                truth(value.value_ref()) == Some(true)
+            }
+            Check::All(checks) => checks.iter().all(|check| check.passes(context, table, row)),
+            Check::Any(checks) => checks.iter().any(|check| check.passes(context, table, row)),
+        }
+    }
+}
+
+enum Test {
+    // Compares equal to any of the values, which are sorted so a row's value can be searched for.
+    // Nothing is equal to NULL, so none of them are.
+    In(Vec<Value>),
+    // Matches the LIKE pattern. A NULL pattern matches nothing, and any other is matched as text.
+    Like(Option<String>),
+    // Compares with the value that way. Nothing compares with NULL.
+    Compare(Comparison, Value),
+}
+
+impl Test {
+    // LIKE has its own rules for case, so only the other tests compare by the collation.
+    fn passes(&self, value: ValueRef, collation: Collation) -> bool {
+        match (self, value) {
+            (Test::In(values), value) => values
+                .binary_search_by(|probe| collation.compare(probe.as_value_ref(), value))
+                .is_ok(),
+            (Test::Like(None), _) | (Test::Like(_), ValueRef::Null) => false,
+            (Test::Like(Some(pattern)), value) => like(pattern, &value.to_string()),
+            (Test::Compare(_, Value::Null), _) | (Test::Compare(..), ValueRef::Null) => false,
+            (Test::Compare(comparison, operand), value) => {
+                comparison.holds(collation.compare(value, operand.as_value_ref()))
+            }
+        }
+    }
+}
+
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        while let Some(row) = self.input.next()? {
+            if self.check.passes(self.context, &self.table, &row) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The rows of its input in order of their keys. The sort is stable, so rows that ORDER BY can't
+// tell apart stay in the order they were read in.
+struct Sort<'a> {
+    input: BoxedOperator<'a>,
+    key: SortKey<'a>,
+    // Whether each term of the key sorts in descending order.
+    descending: Vec<bool>,
+    rows: Option<std::vec::IntoIter<Tuple>>,
+}
+
+// The values of a row's ORDER BY terms.
+type SortKey<'a> = Box<dyn Fn(&Tuple) -> Vec<Value> + 'a>;
+
+impl Operator for Sort<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        if self.rows.is_none() {
+            let mut keyed = Vec::new();
+            while let Some(row) = self.input.next()? {
+                keyed.push(((self.key)(&row), row));
+            }
+            keyed.sort_by(|(a, _), (b, _)| compare_keys(a, b, &self.descending));
+            let rows = keyed.into_iter().map(|(_, row)| row).collect::<Vec<_>>();
+            self.rows = Some(rows.into_iter());
+        }
+        Ok(self.rows.as_mut().and_then(Iterator::next))
+    }
+}
+
+// One row for each group of the rows of its input, with the value of each expression for the
+// group, in the order of the groups' keys.
+struct Aggregate<'a> {
+    input: BoxedOperator<'a>,
+    context: Context<'a>,
+    table: Option<Arc<MasterPageRecord>>,
+    group_by: Vec<Ast>,
+    exprs: Vec<Ast>,
+    rows: Option<std::vec::IntoIter<Tuple>>,
+}
+
+impl Operator for Aggregate<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        if self.rows.is_none() {
+            let mut input = Vec::new();
+            while let Some(row) = self.input.next()? {
+                input.push(row);
+            }
+
+            let Context { db, params, .. } = self.context;
+            let table = self.table.as_deref();
+            let records = input.iter().map(|row| row as &dyn SourceRow).collect();
+            let rows = group(db, params, table, records, &self.group_by)
+                .iter()
+                .map(|rows| {
+                    let values = self
+                        .exprs
+                        .iter()
+                        .map(|expr| evaluate_group(db, params, table, rows, expr))
+                        .collect::<Result<_>>()?;
+                    Ok(Tuple::Values(values))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.rows = Some(rows.into_iter());
+        }
+        Ok(self.rows.as_mut().and_then(Iterator::next))
+    }
+}
+
+// Each row of its input made into the values of the result columns.
+struct Project<'a> {
+    input: BoxedOperator<'a>,
+    project: Box<dyn Fn(Tuple) -> Vec<Value> + 'a>,
+}
+
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        Ok(self
+            .input
+            .next()?
+            .map(|row| Tuple::Values((self.project)(row))))
+    }
+}
+
+// The rows of its input after the first `offset`, and no more than `limit` of them.
+struct Limit<'a> {
+    input: BoxedOperator<'a>,
+    offset: usize,
+    limit: usize,
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Result<Option<Tuple>> {
+        while self.offset > 0 {
+            self.offset -= 1;
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+        }
+        if self.limit == 0 {
+            return Ok(None);
+        }
+        self.limit -= 1;
+        self.input.next()
+    }
+}
+
+// Opens a virtual table in the schema with the built in module that implements it.
+fn builtin_module<'a>(db: &'a Db, table: &MasterPageRecord) -> Result<Arc<dyn VirtualTable + 'a>> {
+    let module = &table.module.as_ref().unwrap().name;
+    if fts::is_fts(module) {
+        Ok(Arc::new(Fts::open(db, table)?))
+    } else if rtree::is_rtree(module) {
+        Ok(Arc::new(RTree::open(db, table)?))
+    } else {
+        Err(Error::Parse(format!(
+            "no such module: {}",
+            module.to_ascii_lowercase()
+        )))
+    }
+}
+
+// How a statement is run, worked out from its SQL alone.
+#[derive(Debug)]
+enum QueryPlanner {
+    // The rows a query reads, and what it does with them.
+    Query(Node, Select),
+    // The table to insert into, the columns named for the values, and the values of each row.
+    Insert(String, Vec<String>, Vec<Vec<Ast>>),
+    // The table to delete from, and the query that finds the rows to delete.
+    Delete(String, Box<QueryPlanner>),
+    // The table to create, and the statement the schema keeps for it.
+    CreateTable(String, String),
+}
+
+// Where a query's rows come from. Which operators read them, a scan or a seek of an index, is only
+// chosen when the query is run, once the values bound to it are known.
+#[derive(Debug)]
+enum Node {
+    // The table to read from, and the arguments when it's called like a table-valued function.
+    Table(String, Vec<Ast>),
+    // The one row of a query without FROM.
+    NoTable,
+    // The rows that meet every one of the conditions of a WHERE clause that are joined by AND.
+    Filter(Box<Node>, Vec<Predicate>),
+}
+
+// A condition on a column, an expression that rows are checked against one by one, or conditions
+// joined by AND or OR.
+#[derive(Debug)]
+enum Predicate {
+    Condition(String, Condition),
+    Expr(Ast),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+}
+
+impl Predicate {
+    // The predicate written back out as SQL, for saying how the planner met it.
+    fn describe(&self) -> String {
+        match self {
+            Predicate::Condition(column, condition) => condition.describe(column),
+            Predicate::Expr(expr) => describe(expr),
+            Predicate::And(predicates) => predicates
+                .iter()
+                .map(|predicate| match predicate {
+                    Predicate::Or(_) => format!("({})", predicate.describe()),
+                    _ => predicate.describe(),
+                })
+                .collect::<Vec<_>>()
+                .join(" AND "),
+            Predicate::Or(predicates) => predicates
+                .iter()
+                .map(Predicate::describe)
+                .collect::<Vec<_>>()
+                .join(" OR "),
+        }
+    }
+
+    // The first column the predicate names, if it names one.
+    fn column(&self) -> Option<String> {
+        match self {
+            Predicate::Condition(column, _) => Some(column.clone()),
+            Predicate::Expr(expr) => {
+                let mut column = None;
+                visit::walk(expr, &mut |node: &Ast| {
+                    if let (None, Ast::Identifier(name)) = (&column, node) {
+                        column = Some(name.clone());
+                    }
+                    column.is_none()
+                });
+                column
+            }
+            Predicate::And(predicates) | Predicate::Or(predicates) => {
+                predicates.iter().find_map(Predicate::column)
+            }
+        }
+    }
+}
+
+// A column compared with literals and parameters, whose values are only known when the statement
+// is run.
+#[derive(Debug)]
+enum Condition {
+    Equal(Ast),
+    In(Vec<Ast>),
+    Match(Ast),
+    Like(Ast),
+    Compare(Comparison, Ast),
+}
+
+impl Condition {
+    // The condition written back out as SQL, for saying how the planner met it.
+    fn describe(&self, column: &str) -> String {
+        match self {
+            Condition::Equal(value) => format!("{} = {}", column, describe(value)),
+            Condition::In(values) => {
+                let values = values.iter().map(describe).collect::<Vec<_>>();
+                format!("{} IN ({})", column, values.join(", "))
+            }
+            Condition::Match(value) => format!("{} MATCH {}", column, describe(value)),
+            Condition::Like(pattern) => format!("{} LIKE {}", column, describe(pattern)),
+            Condition::Compare(comparison, value) => {
+                format!("{} {} {}", column, comparison.symbol(), describe(value))
+            }
+        }
+    }
+}
+
+// The ways of comparing a column with a value other than for equality.
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+
+    // Whether a column's value passes, given how it orders against the value it's compared with.
+    fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Comparison::NotEqual => ordering.is_ne(),
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessEqual => ordering.is_le(),
+            Comparison::Greater => ordering.is_gt(),
+            Comparison::GreaterEqual => ordering.is_ge(),
+        }
+    }
+
+    // The range of an index sorted by the BINARY collation that holds every value that passes.
+    // NULLs sort first and never pass, so the range starts after them. Nearly everything passes
+    // `!=`, so there is no range for it, and reading the table is quicker.
+    fn range(self, value: &Value) -> Option<(Bound<Value>, Bound<Value>)> {
+        let value = value.clone();
+        let after_nulls = Bound::Excluded(Value::Null);
+        match self {
+            Comparison::NotEqual => None,
+            Comparison::Less => Some((after_nulls, Bound::Excluded(value))),
+            Comparison::LessEqual => Some((after_nulls, Bound::Included(value))),
+            Comparison::Greater => Some((Bound::Excluded(value), Bound::Unbounded)),
+            Comparison::GreaterEqual => Some((Bound::Included(value), Bound::Unbounded)),
+        }
+    }
+
+    // The constraint a virtual table is given to skip rows by, which has no way of saying `!=`.
+    fn constraint_op(self) -> Option<ConstraintOp> {
+        match self {
+            Comparison::NotEqual => None,
+            Comparison::Less => Some(ConstraintOp::Less),
+            Comparison::LessEqual => Some(ConstraintOp::LessEqual),
+            Comparison::Greater => Some(ConstraintOp::Greater),
+            Comparison::GreaterEqual => Some(ConstraintOp::GreaterEqual),
+        }
+    }
+}
+
+// What a query does with the rows it finds: the result columns with the names of their columns,
+// and the terms of GROUP BY and ORDER BY, then LIMIT and OFFSET.
+#[derive(Debug)]
+struct Select {
+    columns: ResultColumns,
+    group_by: Vec<Ast>,
+    order_by: Vec<Ast>,
+    limit: Option<Box<Ast>>,
+    offset: Option<Box<Ast>>,
+}
+
+impl QueryPlanner {
+    fn execute(
+        &self,
+        db: &Db,
+        virtual_tables: Option<&VirtualTables>,
+        update_hook: Option<&UpdateHook>,
+        params: &[Value],
+        sink: &mut impl ResultSink,
+        stats: &mut QueryStats,
+    ) -> Result<()> {
+        match self {
+            QueryPlanner::Query(from, select) => {
+                let scanned = Cell::new(0);
+                let context = Context {
+                    db,
+                    params,
+                    virtual_tables,
+                    scanned: &scanned,
+                };
+                let result = query(context, from, select, sink, stats);
+                stats.rows_scanned += scanned.get();
+                result
+            }
+            QueryPlanner::Insert(name, columns, rows) => {
+                insert(db, virtual_tables, update_hook, params, name, columns, rows)
+            }
+            QueryPlanner::Delete(name, rows) => {
+                delete(db, virtual_tables, update_hook, params, name, rows, stats)
+            }
+            QueryPlanner::CreateTable(name, sql) => create_table(db, virtual_tables, name, sql),
+        }
+    }
+}
+
+// Opens the operators that run a query and pulls its rows out of them into the sink.
+fn query(
+    context: Context,
+    from: &Node,
+    select: &Select,
+    sink: &mut impl ResultSink,
+    stats: &mut QueryStats,
+) -> Result<()> {
+    let (headers, mut rows) = open_select(context, from, select)?;
+    sink.start(headers)?;
+    while let Some(row) = rows.next()? {
+        sink.row(row.into_values().into_iter().map(Datum::Owned))?;
+        stats.rows_returned += 1;
+    }
+    sink.finish()
+}
+
+// The table FROM names, and when it's a virtual table, the table itself and the arguments it was
+// called with.
+type OpenTable<'a> = (
+    Arc<MasterPageRecord>,
+    Option<(Arc<dyn VirtualTable + 'a>, Vec<Value>)>,
+);
+
+fn open_table<'a>(context: Context<'a>, name: &str, args: &[Ast]) -> Result<OpenTable<'a>> {
+    let Context { db, params, .. } = context;
+    let registered = context
+        .virtual_tables
+        .and_then(|tables| tables.get(&name.to_ascii_uppercase()));
+
+    // A table in the database comes before a pragma of the same name.
+    let virtual_table: Arc<dyn VirtualTable> = match registered {
+        Some(table) => table.clone(),
+        None => match db.get_table(name) {
+            Ok(table) => {
+                if !args.is_empty() {
+                    return Err(Error::Parse(format!("'{}' is not a function", name)));
+                }
+                let virtual_table = match table.module.is_some() {
+                    true => Some((builtin_module(db, &table)?, Vec::new())),
+                    false => None,
+                };
+                return Ok((table, virtual_table));
+            }
+            Err(err) => match Pragma::open(db, name) {
+                Some(pragma) => Arc::new(pragma),
+                None => return Err(err),
+            },
+        },
+    };
+
+    let args = args
+        .iter()
+        .map(|arg| {
+            check(None, arg)?;
+            Ok(evaluate(db, params, None, arg).into_value())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let table = MasterPageRecord::virtual_table(name, virtual_table.columns());
+    Ok((Arc::new(table), Some((virtual_table, args))))
+}
+
+// The operators that read a query's rows, with the table they're read from. Every row of an
+// ordinary table is `whole`, which lets an aggregate over all of them be worked out some other
+// way than reading them.
+struct Source<'a> {
+    table: Option<Arc<MasterPageRecord>>,
+    rows: BoxedOperator<'a>,
+    whole: bool,
+}
+
+fn open_source<'a>(context: Context<'a>, node: &Node) -> Result<Source<'a>> {
+    match node {
+        Node::NoTable => Ok(Source {
+            table: None,
+            rows: Box::new(Precomputed::new(vec![Tuple::Empty])),
+            whole: false,
+        }),
+        Node::Table(name, args) => {
+            let (table, virtual_table) = open_table(context, name, args)?;
+            let whole = virtual_table.is_none();
+            let source = match virtual_table {
+                Some((virtual_table, args)) => ScanSource::Virtual(virtual_table, args, Vec::new()),
+                None => ScanSource::Table(table.clone()),
+            };
+            Ok(Source {
+                table: Some(table),
+                rows: Box::new(Scan::new(context, source)),
+                whole,
+            })
+        }
+        Node::Filter(input, predicates) => {
+            // A WHERE clause can only name columns when there is a table. Without one, it's true or
+            // false of the one row there is.
+            let Node::Table(name, args) = input.as_ref() else {
+                if let Some(column) = predicates.iter().find_map(Predicate::column) {
+                    return Err(Error::NoSuchColumn(column));
+                }
+                let mut rows = vec![Tuple::Empty];
+                for predicate in predicates {
+                    if !holds(context, predicate)? {
+                        rows.clear();
+                    }
+                }
+                return Ok(Source {
+                    table: None,
+                    rows: Box::new(Precomputed::new(rows)),
+                    whole: false,
+                });
+            };
+            let (table, virtual_table) = open_table(context, name, args)?;
+
+            // One of the conditions chooses how rows are found, and the rows found are checked
+            // against the rest. When they are all joined by OR, every row is checked.
+            let chosen = choose_condition(context.db, &table, predicates);
+            let (mut rows, mut checks) = match chosen {
+                Some(chosen) => {
+                    let Predicate::Condition(column, condition) = &predicates[chosen] else {
+                        unreachable!()
+                    };
+                    let (rows, check) =
+                        open_filter(context, &table, virtual_table, column, condition)?;
+                    (rows, check.into_iter().collect())
+                }
+                None => {
+                    trace::event!(
+                        Target::Planner,
+                        "{} on {} by full scan",
+                        predicates[0].describe(),
+                        table.name
+                    );
+                    let source = match virtual_table {
+                        Some((virtual_table, args)) => {
+                            ScanSource::Virtual(virtual_table, args, Vec::new())
+                        }
+                        None => ScanSource::Table(table.clone()),
+                    };
+                    let rows: BoxedOperator<'a> = Box::new(Scan::new(context, source));
+                    (rows, Vec::new())
+                }
+            };
+            for (index, predicate) in predicates.iter().enumerate() {
+                if Some(index) != chosen {
+                    checks.push(open_check(context, &table, predicate)?);
+                }
+            }
+            if !checks.is_empty() {
+                rows = Box::new(Filter {
+                    context,
+                    input: rows,
+                    table: table.clone(),
+                    check: Check::All(checks),
+                });
+            }
+            Ok(Source {
+                table: Some(table),
+                rows,
+                whole: false,
+            })
+        }
+    }
+}
+
+// Which of the conditions of a WHERE clause that are joined by AND is the one to find rows by.
+// MATCH has to be, as only a virtual table can tell which rows match. Otherwise it's the first
+// whose column has an index, preferring one that finds rows by key, and failing that the first
+// condition. A WHERE clause of conditions joined by OR has no condition to find rows by.
+fn choose_condition(db: &Db, table: &MasterPageRecord, predicates: &[Predicate]) -> Option<usize> {
+    predicates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, predicate)| match predicate {
+            Predicate::Condition(column, condition) => {
+                let indexed = || index_on(db, table, column).is_some();
+                let rank = match condition {
+                    Condition::Match(_) => 0,
+                    Condition::Equal(_) | Condition::In(_) if indexed() => 1,
+                    _ if indexed() => 2,
+                    _ => 3,
+                };
+                Some((rank, index))
+            }
+            _ => None,
+        })
+        .min()
+        .map(|(_, index)| index)
+}
+
+// Chooses how to find the rows that meet a WHERE clause's condition: by asking a virtual table for
+// them, by seeking an index, or by scanning the table. Returns the rows, and what they have to be
+// checked against to be sure they meet the condition, if anything.
+fn open_filter<'a>(
+    context: Context<'a>,
+    table: &Arc<MasterPageRecord>,
+    virtual_table: Option<(Arc<dyn VirtualTable + 'a>, Vec<Value>)>,
+    column: &str,
+    condition: &Condition,
+) -> Result<(BoxedOperator<'a>, Option<Check>)> {
+    let Context { db, params, .. } = context;
+    let scan = |source| -> BoxedOperator<'a> { Box::new(Scan::new(context, source)) };
+    let described = condition.describe(column);
+
+    // Only a virtual table knows what it means for a row to match, so the rows it returns are
+    // taken as they are.
+    if let Condition::Match(value) = condition {
+        let value = evaluate(db, params, None, value).into_value();
+        let Some((virtual_table, args)) = virtual_table else {
+            return Err(match_out_of_context());
+        };
+
+        // Naming the table matches against all of its columns.
+        let column = if column.eq_ignore_ascii_case(&table.name) {
+            table.columns.len()
+        } else {
+            table.get_column_index(column)?
+        };
+        let constraint = Constraint {
+            column,
+            op: ConstraintOp::Match,
+            values: vec![value],
+        };
+        let rows = scan(ScanSource::Virtual(virtual_table, args, vec![constraint]));
+        return Ok((rows, None));
+    }
+
+    let (col_index, collation, test) = open_test(context, table, column, condition)?;
+    let rows = if let Some((virtual_table, args)) = virtual_table {
+        trace::event!(
+            Target::Planner,
+            "{} on {} by virtual table",
+            described,
+            table.name
+        );
+        let constraint = match &test {
+            Test::In(values) => Some(Constraint {
+                column: col_index,
+                op: ConstraintOp::Equal,
+                values: values.clone(),
+            }),
+            Test::Compare(comparison, value) => comparison.constraint_op().map(|op| Constraint {
+                column: col_index,
+                op,
+                values: vec![value.clone()],
+            }),
+            Test::Like(_) => None,
+        };
+        scan(ScanSource::Virtual(
+            virtual_table,
+            args,
+            constraint.into_iter().collect(),
+        ))
+    } else if let Some((index, seek, how)) =
+        index_seek(db, table, column, col_index, collation, &test)?
+    {
+        trace::event!(
+            Target::Planner,
+            "{} on {} by {}",
+            described,
+            table.name,
+            how
+        );
+        Box::new(IndexSeek::new(context, table.clone(), &index, seek))
+    } else {
+        trace::event!(
+            Target::Planner,
+            "{} on {} by full scan",
+            described,
+            table.name
+        );
+        scan(ScanSource::Table(table.clone()))
+    };
+    Ok((rows, Some(Check::Column(col_index, collation, test))))
+}
+
+// The seek of an index that finds every row that can pass a test of a column, if there is one,
+// with how it finds them. Only a seekable index is used, and only for a test that compares by the
+// BINARY collation too, as the index does. LIKE has its own rules for case, so it can seek
+// whatever the column's collation is.
+fn index_seek(
+    db: &Db,
+    table: &MasterPageRecord,
+    column: &str,
+    col_index: usize,
+    collation: Collation,
+    test: &Test,
+) -> Result<Option<(Arc<MasterPageRecord>, Seek, String)>> {
+    if collation != Collation::Binary && !matches!(test, Test::Like(_)) {
+        return Ok(None);
+    }
+    let index = index_on(db, table, column);
+    Ok(match (test, index) {
+        (Test::In(values), Some(index)) => {
+            let how = format!("index {}", index.name);
+            Some((index, Seek::Keys(values.clone()), how))
+        }
+        (Test::In(values), None) => skip_scan_index(db, table, column)?.map(|index| {
+            let how = format!("skip scan of index {}", index.name);
+            (index, Seek::SkipScan(values.clone()), how)
+        }),
+        // Only a column with TEXT affinity has nothing but text in it to find: numbers sort before
+        // all text, so one that matches could be outside the ranges.
+        (Test::Like(Some(pattern)), Some(index))
+            if table.columns.get(col_index).map(|column| column.affinity())
+                == Some(Affinity::Text) =>
+        {
+            let ranges = like_ranges(pattern);
+            let how = format!("{} ranges of index {}", ranges.len(), index.name);
+            (!ranges.is_empty()).then_some((index, Seek::Ranges(ranges), how))
+        }
+        (Test::Compare(comparison, value), Some(index)) => comparison.range(value).map(|range| {
+            let how = format!("range of index {}", index.name);
+            (index, Seek::Ranges(vec![range]), how)
+        }),
+        _ => None,
+    })
+}
+
+// The test of a column that a condition makes, with the values it compares with worked out.
+fn open_test(
+    context: Context,
+    table: &MasterPageRecord,
+    column: &str,
+    condition: &Condition,
+) -> Result<(usize, Collation, Test)> {
+    let Context { db, params, .. } = context;
+    let col_index = table.get_column_index(column)?;
+    let collation = Collation::of(
+        table
+            .columns
+            .get(col_index)
+            .and_then(|column| column.collation()),
+    )?;
+    let compared = |value| compared_with(table, col_index, evaluate(db, params, None, value));
+    let test = match condition {
+        Condition::Match(_) => return Err(match_out_of_context()),
+        Condition::Equal(value) => Test::In(
+            Some(compared(value))
+                .filter(|value| !matches!(value, Value::Null))
+                .into_iter()
+                .collect(),
+        ),
+        Condition::In(values) => {
+            let mut values = values
+                .iter()
+                .map(compared)
+                .filter(|value| !matches!(value, Value::Null))
+                .collect::<Vec<_>>();
+            values.sort_by(|a, b| collation.compare(a.as_value_ref(), b.as_value_ref()));
+            Test::In(values)
+        }
+        Condition::Like(pattern) => {
+            Test::Like(match evaluate(db, params, None, pattern).into_value() {
+                Value::Null => None,
+                pattern => Some(pattern.to_string()),
+            })
+        }
+        Condition::Compare(comparison, value) => Test::Compare(*comparison, compared(value)),
+    };
+    Ok((col_index, collation, test))
+}
+
+// A check of rows against a predicate of the WHERE clause other than the one they were found by.
+fn open_check(context: Context, table: &MasterPageRecord, predicate: &Predicate) -> Result<Check> {
+    Ok(match predicate {
+        Predicate::Condition(column, condition) => {
+            let (col_index, collation, test) = open_test(context, table, column, condition)?;
+            Check::Column(col_index, collation, test)
+        }
+        Predicate::Expr(expr) => {
+            check(Some(table), expr)?;
+            Check::Expr(expr.clone())
+        }
+        Predicate::And(predicates) => Check::All(
+            predicates
+                .iter()
+                .map(|predicate| open_check(context, table, predicate))
+                .collect::<Result<_>>()?,
+        ),
+        Predicate::Or(predicates) => Check::Any(
+            predicates
+                .iter()
+                .map(|predicate| open_check(context, table, predicate))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+// Whether a predicate that names no columns is true.
+fn holds(context: Context, predicate: &Predicate) -> Result<bool> {
+    Ok(match predicate {
+        Predicate::Expr(expr) => {
+            check(None, expr)?;
+            let value = evaluate(context.db, context.params, None, expr);
+            truth(value.value_ref()) == Some(true)
+        }
+        Predicate::And(predicates) => predicates
+            .iter()
+            .map(|predicate| holds(context, predicate))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .all(|holds| holds),
+        Predicate::Or(predicates) => predicates
+            .iter()
+            .map(|predicate| holds(context, predicate))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .any(|holds| holds),
+        Predicate::Condition(..) => unreachable!(),
+    })
+}
+
+fn match_out_of_context() -> Error {
+    Error::Parse("unable to use function MATCH in the requested context".to_string())
+}
+
+// Opens the operators that make a query's result out of the rows it reads, returning the names of
+// its columns along with them. Everything the query refers to is checked first, so a mistake is
+// an error before any rows are read.
+fn open_select<'a>(
+    context: Context<'a>,
+    from: &Node,
+    select: &Select,
+) -> Result<(Vec<String>, BoxedOperator<'a>)> {
+    let Context { db, params, .. } = context;
+    let source = open_source(context, from)?;
+    let table = source.table.clone();
+    let (columns, headers): (Vec<_>, _) = expand(&select.columns, table.as_deref())
+        .into_iter()
+        .unzip();
+    for column in &columns {
+        check(table.as_deref(), column)?;
+    }
+    let group_by = grouping_terms(&select.group_by, &columns)?;
+    for expr in &group_by {
+        check(table.as_deref(), expr)?;
+    }
+    let order_by = ordering_terms(&select.order_by, &columns)?;
+    for (expr, _) in &order_by {
+        check(table.as_deref(), expr)?;
+    }
+    let offset = row_count(db, params, select.offset.as_deref())?;
+    let limit = row_count(db, params, select.limit.as_deref())?;
+    let descending = order_by.iter().map(|(_, descending)| *descending).collect();
+
+    let aggregate = !group_by.is_empty()
+        || columns.iter().any(has_aggregate)
+        || order_by.iter().any(|(expr, _)| has_aggregate(expr));
+    let mut rows: BoxedOperator<'a> = if aggregate {
+        // An aggregate over every row of a table may not need to read any of them.
+        let alone =
+            group_by.is_empty() && order_by.is_empty() && limit.is_none() && offset.is_none();
+        let shortcut = match &table {
+            Some(table) if alone && source.whole => aggregate_without_reading(db, table, &columns)?,
+            _ => None,
+        };
+        if let Some(value) = shortcut {
+            Box::new(Precomputed::new(vec![Tuple::Values(vec![value])]))
+        } else if order_by.is_empty() {
+            Box::new(Aggregate {
+                input: source.rows,
+                context,
+                table,
+                group_by,
+                exprs: columns,
+                rows: None,
+            })
+        } else {
+            // Each group's ORDER BY terms are worked out after its columns, for sorting on, and
+            // dropped once the groups are in order.
+            let width = columns.len();
+            let exprs = columns
+                .into_iter()
+                .chain(order_by.into_iter().map(|(expr, _)| expr))
+                .collect();
+            let groups = Box::new(Aggregate {
+                input: source.rows,
+                context,
+                table,
+                group_by,
+                exprs,
+                rows: None,
+            });
+            Box::new(Project {
+                input: Box::new(Sort {
+                    input: groups,
+                    key: Box::new(move |row| match row {
+                        Tuple::Values(values) => values[width..].to_vec(),
+                        _ => unreachable!(),
+                    }),
+                    descending,
+                    rows: None,
+                }),
+                project: Box::new(move |row| {
+                    let mut values = row.into_values();
+                    values.truncate(width);
+                    values
+                }),
+            })
+        }
+    } else {
+        let mut rows = source.rows;
+        if !order_by.is_empty() {
+            let table = table.clone();
+            rows = Box::new(Sort {
+                input: rows,
+                key: Box::new(move |row| {
+                    let row = table.as_deref().map(|table| (table, row as &dyn SourceRow));
+                    order_by
+                        .iter()
+                        .map(|(expr, _)| evaluate(db, params, row, expr).into_value())
+                        .collect()
+                }),
+                descending,
+                rows: None,
+            });
+        }
+        Box::new(Project {
+            input: rows,
+            project: Box::new(move |row| {
+                let row_ref = table
+                    .as_deref()
+                    .map(|table| (table, &row as &dyn SourceRow));
+                columns
+                    .iter()
+                    .map(|column| evaluate(db, params, row_ref, column).into_value())
+                    .collect()
+            }),
+        })
+    };
+
+    if limit.is_some() || offset.is_some() {
+        // A negative OFFSET skips nothing, and a negative LIMIT doesn't limit.
+        rows = Box::new(Limit {
+            input: rows,
+            offset: usize::try_from(offset.unwrap_or(0)).unwrap_or(0),
+            limit: limit.map_or(usize::MAX, |limit| {
+                usize::try_from(limit).unwrap_or(usize::MAX)
+            }),
+        });
+    }
+    Ok((headers, rows))
+}
+
+// count(*), min() and max() on their own, over every row of a table, have ways to be worked out
+// without reading any of the rows. None is an aggregate that has to read them.
+fn aggregate_without_reading(
+    db: &Db,
+    table: &MasterPageRecord,
+    columns: &[Ast],
+) -> Result<Option<Value>> {
+    let [Ast::Function {
+        name,
+        args,
+        distinct,
+    }] = columns
+    else {
+        return Ok(None);
+    };
+    let name = name.to_ascii_uppercase();
+    match args.as_slice() {
+        // Unless the pager is salvaging, when a row that can't be read doesn't count.
+        [Ast::All] if name == "COUNT" && !distinct && !db.pager.salvage() => {
+            trace::event!(
+                Target::Planner,
+                "count(*) on {} by page headers",
+                table.name
+            );
+            Ok(Some(Value::Int(db.count_rows(table)? as i64)))
+        }
+        // When every row counts, the answer may be at one end of a b-tree.
+        [arg] if name == "MIN" || name == "MAX" => seek_bound(db, table, arg, name == "MAX"),
+        _ => Ok(None),
+    }
+}
+
+// A statement that has been lexed, parsed and planned, ready to run as many times as it's needed
+// with different values bound to its parameters.
+#[derive(Debug)]
+pub struct Plan {
+    sql: String,
+    ast: Ast,
+    parameters: Vec<Option<String>>,
+    // How each statement is run.
+    statements: Vec<QueryPlanner>,
+}
+
+impl Plan {
+    pub fn prepare(sql: &str) -> Result<Plan> {
+        let mut lexer = Lexer::new(sql.to_string());
+        let tokens = lexer.lex()?;
+        let mut parser = Parser::new(tokens).with_source(sql, lexer.spans());
+        let ast = parser.parse()?;
+        let Ast::StmtList(statements) = &ast else {
+            unreachable!();
+        };
+        let statements = statements
+            .iter()
+            .zip(parser.texts())
+            .map(|(statement, text)| match statement {
+                Ast::Stmt(statement) => plan_statement(statement, text),
+                _ => unreachable!(),
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Plan {
+            sql: sql.to_string(),
+            ast,
+            parameters: parser.parameters().to_vec(),
+            statements,
+        })
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    // How many values can be bound, which is the largest parameter number used.
+    pub fn parameter_count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    // The number of a named parameter, including its prefix: `:name`, `@name` or `$name`.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.parameters
+            .iter()
+            .position(|parameter| parameter.as_deref() == Some(name))
+            .map(|index| index + 1)
+    }
+
+    // The table, and the result columns with `*` expanded and their names, when the statement is a query that
+    // does nothing but read columns out of every row of a table in the database: no WHERE, no
+    // ORDER BY and no aggregates.
+    fn scan_only(
+        &self,
+        db: &Db,
+        virtual_tables: &VirtualTables,
+    ) -> Result<Option<(Arc<MasterPageRecord>, ResultColumns)>> {
+        let Ast::StmtList(statements) = &self.ast else {
+            unreachable!();
+        };
+        let [Ast::Stmt(statement)] = statements.as_slice() else {
+            return Ok(None);
+        };
+        let Ast::Select {
+            result_columns,
+            from: Some(from),
+            r#where: None,
+            group_by,
+            order_by,
+            limit: None,
+            offset: None,
+        } = statement.as_ref()
+        else {
+            return Ok(None);
+        };
+        let Ast::TableOrSubQuery(from) = from.as_ref() else {
+            unreachable!();
+        };
+        let Ast::Table(name) = from.as_ref() else {
+            return Ok(None);
+        };
+        if !group_by.is_empty()
+            || !order_by.is_empty()
+            || virtual_tables.contains_key(&name.to_ascii_uppercase())
+        {
+            return Ok(None);
+        }
+        // A table that doesn't exist is left for running the query to report.
+        let Ok(table) = db.get_table(name) else {
+            return Ok(None);
+        };
+        if table.module.is_some() || table.without_rowid {
+            return Ok(None);
+        }
+
+        let mut columns = Vec::new();
+        for column in result_columns {
+            let (column, header) = result_column(column);
+            match column {
+                Ast::Expr(expr) => match expr.as_ref() {
+                    expr if has_aggregate(expr) => return Ok(None),
+                    Ast::Function { name, .. }
+                        if !FUNCTIONS
+                            .iter()
+                            .any(|(function, _)| function.eq_ignore_ascii_case(name)) =>
+                    {
+                        return Ok(None)
+                    }
+                    expr => columns.push((expr.clone(), header)),
+                },
+                column => columns.push((column.clone(), header)),
+            }
+        }
+        let columns = expand(&columns, Some(&table));
+        for (column, _) in &columns {
+            check(Some(&table), column)?;
+        }
+        Ok(Some((table, columns)))
+    }
+}
+
+// A query's rows, handed out a batch at a time. A query that only reads columns out of every row of
+// a table keeps a cursor on the table's b-tree from one batch to the next, so a batch reads just the
+// pages its rows are on. Anything else is run to completion first, and handed out from there.
+pub(crate) enum Batches<'a> {
+    // The scan reads the table as it goes, so it keeps the database locked until it's dropped.
+    Scan {
+        _lock: ReadLock<'a>,
+        db: &'a Db,
+        cursor: BTreeCursor<'a>,
+        table: Arc<MasterPageRecord>,
+        columns: Vec<Ast>,
+        params: Vec<Value>,
+    },
+    Rows(std::vec::IntoIter<Row>),
+}
+
+impl<'a> Batches<'a> {
+    // Starts a query, returning the names of its result columns along with its rows.
+    pub(crate) fn start(
+        plan: &Plan,
+        params: &[Value],
+        db: &'a Db,
+        virtual_tables: &VirtualTables,
+    ) -> Result<(Arc<[String]>, Self)> {
+        if let Some((table, columns)) = plan.scan_only(db, virtual_tables)? {
+            let (columns, headers): (_, Vec<_>) = columns.into_iter().unzip();
+            let batches = Batches::Scan {
+                _lock: db.pager.read_lock().map_err(Error::locking)?,
+                db,
+                cursor: BTreeCursor::scan(&db.pager, table.root_page),
+                table,
+                columns,
+                params: params.to_vec(),
+            };
+            return Ok((headers.into(), batches));
+        }
+
+        let mut rows = Rows::default();
+        SqlEngine::new(&mut rows)
+            .with_virtual_tables(virtual_tables)
+            .run(plan, params, db)?;
+        Ok((rows.columns, Batches::Rows(rows.rows.into_iter())))
+    }
+
+    // Up to `n` more rows, which are fewer than `n` only once the rows have run out.
+    pub(crate) fn next_batch(&mut self, n: usize, names: &Arc<[String]>) -> Result<Vec<Row>> {
+        match self {
+            Batches::Rows(rows) => Ok(rows.by_ref().take(n).collect()),
+            Batches::Scan {
+                db,
+                cursor,
+                table,
+                columns,
+                params,
+                ..
+            } => {
+                let mut batch = Vec::new();
+                for record in cursor.by_ref().take(n) {
+                    let record = into_table_leaf_record(record?);
+                    let values = columns
+                        .iter()
+                        .map(|column| {
+                            evaluate(db, params, Some((table, &record)), column).into_value()
+                        })
+                        .collect();
+                    batch.push(Row::new(names.clone(), values));
+                }
+                Ok(batch)
+            }
+        }
+    }
+}
+
+// What running a statement took, for keeping track of how well queries are planned. The pager's
+// counters are shared by a connection's clones, so a statement run on another thread at the same
+// time adds its pages and cells to these.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    pub pages_read: u64,
+    pub cache_hits: u64,
+    pub cells_decoded: u64,
+    pub index_seeks: u64,
+    // Rows read out of a table, before the WHERE clause filtered them.
+    pub rows_scanned: u64,
+    pub rows_returned: u64,
+}
+
+impl Display for QueryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pages read: {}, cache hits: {}, cells decoded: {}, index seeks: {}, rows scanned: {}, \
+             rows returned: {}",
+            self.pages_read,
+            self.cache_hits,
+            self.cells_decoded,
+            self.index_seeks,
+            self.rows_scanned,
+            self.rows_returned
+        )
+    }
+}
+
+pub struct SqlEngine<'a, S: ResultSink> {
+    sink: &'a mut S,
+    virtual_tables: Option<&'a VirtualTables>,
+    update_hook: Option<&'a UpdateHook>,
+    stats: QueryStats,
+}
+
+impl<'a, S: ResultSink> SqlEngine<'a, S> {
+    pub fn new(sink: &'a mut S) -> Self {
+        Self {
+            sink,
+            virtual_tables: None,
+            update_hook: None,
+            stats: QueryStats::default(),
+        }
+    }
+
+    // What the last run took.
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
+
+    // Lets FROM name the virtual tables registered with a connection.
+    pub fn with_virtual_tables(mut self, virtual_tables: &'a VirtualTables) -> Self {
+        self.virtual_tables = Some(virtual_tables);
+        self
+    }
+
+    // Calls `hook` for every row a statement inserts, once the statement has committed.
+    pub fn with_update_hook(mut self, hook: &'a UpdateHook) -> Self {
+        self.update_hook = Some(hook);
+        self
+    }
+
+    pub fn execute(&mut self, sql: &str, db: &Db) -> Result<()> {
+        self.run(&Plan::prepare(sql)?, &[], db)
+    }
+
+    // Runs a prepared statement, with `params[0]` bound to parameter 1 and so on.
+    pub fn run(&mut self, plan: &Plan, params: &[Value], db: &Db) -> Result<()> {
+        let before = db.pager.stats();
+        self.stats = QueryStats::default();
+
+        let _lock = db.pager.read_lock().map_err(Error::locking)?;
+        let result = plan.statements.iter().try_for_each(|planner| {
+            planner.execute(
+                db,
+                self.virtual_tables,
+                self.update_hook,
+                params,
+                self.sink,
+                &mut self.stats,
+            )
+        });
+
+        let pager = db.pager.stats().since(&before);
+        self.stats.pages_read = pager.pages_read;
+        self.stats.cache_hits = pager.cache_hits;
+        self.stats.cells_decoded = pager.cells_decoded;
+        self.stats.index_seeks = pager.index_seeks;
+        result
+    }
+}
+
+// Works out how a statement is run. That depends only on its SQL, not on the values bound to it,
+// so it's worked out once, when the statement is prepared. `text` is the statement as it was
+// written.
+fn plan_statement(statement: &Ast, text: &str) -> Result<QueryPlanner> {
+    match statement {
+        Ast::Select {
+            result_columns,
+            from,
+            r#where,
+            group_by,
+            order_by,
+            limit,
+            offset,
+        } => {
+            let columns = result_columns
+                .iter()
+                .map(|result| {
+                    let (result, header) = result_column(result);
+                    match result {
+                        Ast::Expr(expr) => (expr.as_ref().clone(), header),
+                        result => (result.clone(), header),
+                    }
+                })
+                .collect();
+            let select = Select {
+                columns,
+                group_by: group_by.clone(),
+                order_by: order_by.clone(),
+                limit: limit.clone(),
+                offset: offset.clone(),
+            };
+            plan_select(from, r#where, select)
+        }
+        Ast::Insert {
+            table,
+            columns,
+            rows,
+        } => {
+            if rows.iter().any(|row| row.len() != rows[0].len()) {
+                return Err(Error::Parse(
+                    "all VALUES must have the same number of terms".to_string(),
+                ));
+            }
+            Ok(QueryPlanner::Insert(
+                table.clone(),
+                columns.clone(),
+                rows.clone(),
+            ))
+        }
+        // The rows are found as a query would find them, each with its rowid and then its values,
+        // which say where its index entries are.
+        Ast::Delete { table, r#where } => {
+            let from = Ast::TableOrSubQuery(Box::new(Ast::Table(table.clone())));
+            let select = Select {
+                columns: vec![
+                    (Ast::Identifier("ROWID".to_string()), "ROWID".to_string()),
+                    (Ast::All, "*".to_string()),
+                ],
+                group_by: Vec::new(),
+                order_by: Vec::new(),
+                limit: None,
+                offset: None,
+            };
+            let rows = plan_select(&Some(Box::new(from)), r#where, select)?;
+            Ok(QueryPlanner::Delete(table.clone(), Box::new(rows)))
+        }
+        Ast::CreateTable {
+            name,
+            without_rowid: false,
+            ..
+        } => {
+            // sqlite keeps the statement with the CREATE TABLE before the name written its own
+            // way, which is also how the shell's .schema shows it.
+            let definition = text["CREATE".len()..].trim_start()["TABLE".len()..].trim_start();
+            Ok(QueryPlanner::CreateTable(
+                name.clone(),
+                format!("CREATE TABLE {}", definition),
+            ))
+        }
+        Ast::CreateTable { .. } => Err(Error::Unsupported(
+            "creating a WITHOUT ROWID table".to_string(),
+        )),
+        Ast::CreateIndex { .. } => Err(Error::Unsupported("CREATE INDEX".to_string())),
+        Ast::CreateVirtualTable { .. } => {
+            Err(Error::Unsupported("CREATE VIRTUAL TABLE".to_string()))
+        }
+        _ => Err(Error::Unsupported(text.to_string())),
+    }
+}
+
+// Creates a table under a name that no table, index or view has, nor a virtual table registered
+// with the connection. Names starting with sqlite_ are kept for sqlite's own tables.
+fn create_table(
+    db: &Db,
+    virtual_tables: Option<&VirtualTables>,
+    name: &str,
+    sql: &str,
+) -> Result<()> {
+    if name.len() >= 7 && name[..7].eq_ignore_ascii_case("sqlite_") {
+        return Err(Error::Parse(format!(
+            "object name reserved for internal use: {}",
+            name
+        )));
+    }
+    let taken = db
+        .catalog
+        .objects()
+        .iter()
+        .find(|object| object.name.eq_ignore_ascii_case(name));
+    if let Some(object) = taken {
+        return Err(Error::Parse(match object.object_type {
+            ObjectType::Index => format!("there is already an index named {}", name),
+            ObjectType::View => format!("view {} already exists", name),
+            _ => format!("table {} already exists", name),
+        }));
+    }
+    if virtual_tables.is_some_and(|tables| tables.contains_key(&name.to_ascii_uppercase())) {
+        return Err(Error::Parse(format!("table {} already exists", name)));
+    }
+
+    if !db.pager.writable() {
+        return Err(Error::ReadOnly);
+    }
+    let transaction = db.pager.begin().map_err(Error::locking)?;
+    db.create_table(name, sql)?;
+    db.commit(transaction)
+}
+
+// The table an INSERT or DELETE writes to, which has to be an ordinary table in the database.
+fn table_to_write(
+    db: &Db,
+    virtual_tables: Option<&VirtualTables>,
+    name: &str,
+) -> Result<Arc<MasterPageRecord>> {
+    if virtual_tables.is_some_and(|tables| tables.contains_key(&name.to_ascii_uppercase())) {
+        return Err(Error::Unsupported(format!(
+            "writing to virtual table {}",
+            name
+        )));
+    }
+    if let Some(schema) = SCHEMA_TABLE_NAMES
+        .iter()
+        .find(|schema| schema.eq_ignore_ascii_case(name))
+    {
+        return Err(Error::Parse(format!(
+            "table {} may not be modified",
+            schema
+        )));
+    }
+    if let Some(view) = db
+        .catalog
+        .find(name)
+        .filter(|record| record.object_type == ObjectType::View)
+    {
+        return Err(Error::Parse(format!(
+            "cannot modify {} because it is a view",
+            view.name
+        )));
+    }
+    db.get_table(name)
+}
+
+// Inserts the rows of an INSERT, in a transaction of their own, so that they all go in or, when
+// one of them can't, none of them do. Each of the table's columns that isn't named is NULL.
+fn insert(
+    db: &Db,
+    virtual_tables: Option<&VirtualTables>,
+    update_hook: Option<&UpdateHook>,
+    params: &[Value],
+    name: &str,
+    columns: &[String],
+    rows: &[Vec<Ast>],
+) -> Result<()> {
+    let table = table_to_write(db, virtual_tables, name)?;
+
+    let targets = match columns.is_empty() {
+        true => (0..table.columns.len()).collect::<Vec<_>>(),
+        false => columns
+            .iter()
+            .map(|column| {
+                table.get_column_index(column).map_err(|_| {
+                    Error::Parse(format!(
+                        "table {} has no column named {}",
+                        table.name, column
+                    ))
+                })
+            })
+            .collect::<Result<_>>()?,
+    };
+    let supplied = rows[0].len();
+    if supplied != targets.len() {
+        return Err(Error::Parse(match columns.is_empty() {
+            true => format!(
+                "table {} has {} columns but {} values were supplied",
+                table.name,
+                targets.len(),
+                supplied
+            ),
+            false => format!("{} values for {} columns", supplied, targets.len()),
+        }));
+    }
+    for value in rows.iter().flatten() {
+        check(None, value)?;
+    }
+
+    if !db.pager.writable() {
+        return Err(Error::ReadOnly);
+    }
+    let transaction = db.pager.begin().map_err(Error::locking)?;
+    let mut rowids = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut values = vec![Value::Null; table.columns.len()];
+        for (target, value) in targets.iter().zip(row) {
+            values[*target] = evaluate(db, params, None, value).into_value();
+        }
+        rowids.push(db.insert_row(&table, values)?);
+    }
+    db.commit(transaction)?;
+
+    db.changes.store(rowids.len() as u64, Ordering::Relaxed);
+    db.total_changes
+        .fetch_add(rowids.len() as u64, Ordering::Relaxed);
+    if let Some(rowid) = rowids.last() {
+        db.last_insert_rowid.store(*rowid, Ordering::Relaxed);
+    }
+    if let Some(hook) = update_hook {
+        for rowid in rowids {
+            hook(Action::Insert, &table.name, rowid);
+        }
+    }
+    Ok(())
+}
+
+// Deletes the rows a query finds, all of them or none, like an INSERT.
+fn delete(
+    db: &Db,
+    virtual_tables: Option<&VirtualTables>,
+    update_hook: Option<&UpdateHook>,
+    params: &[Value],
+    name: &str,
+    rows: &QueryPlanner,
+    stats: &mut QueryStats,
+) -> Result<()> {
+    let table = table_to_write(db, virtual_tables, name)?;
+    if !db.pager.writable() {
+        return Err(Error::ReadOnly);
+    }
+
+    // Every row is found before any is deleted, so the b-tree doesn't change under the query.
+    let mut found = Rows::default();
+    rows.execute(db, virtual_tables, None, params, &mut found, stats)?;
+
+    let transaction = db.pager.begin().map_err(Error::locking)?;
+    let mut rowids = Vec::with_capacity(found.rows.len());
+    for row in found.rows {
+        let mut values = row.into_values();
+        let Value::Int(rowid) = values.remove(0) else {
+            unreachable!();
+        };
+        db.delete_row(&table, rowid, &values)?;
+        rowids.push(rowid);
+    }
+    db.commit(transaction)?;
+
+    db.changes.store(rowids.len() as u64, Ordering::Relaxed);
+    db.total_changes
+        .fetch_add(rowids.len() as u64, Ordering::Relaxed);
+    if let Some(hook) = update_hook {
+        for rowid in rowids {
+            hook(Action::Delete, &table.name, rowid);
+        }
+    }
+    Ok(())
+}
+
+fn plan_select(
+    from: &Option<Box<Ast>>,
+    r#where: &Option<Box<Ast>>,
+    select: Select,
+) -> Result<QueryPlanner> {
+    let mut node = match from {
+        Some(from) => match from.as_ref() {
+            Ast::TableOrSubQuery(node) => match node.as_ref() {
+                Ast::Table(table_name) => Node::Table(table_name.clone(), Vec::new()),
+                Ast::TableFunction { name, args } => Node::Table(name.clone(), args.clone()),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        },
+        None => Node::NoTable,
+    };
+
+    if let Some(where_clause) = r#where {
+        // The conditions joined by AND at the top of the clause are kept apart, so that any one of
+        // them can be the one rows are found by.
+        let predicates = match predicate(where_clause)? {
+            Predicate::And(predicates) => predicates,
+            predicate => vec![predicate],
+        };
+        node = Node::Filter(Box::new(node), predicates);
+    }
+
+    Ok(QueryPlanner::Query(node, select))
+}
+
+// The conditions of a WHERE clause, with those joined by the same one of AND or OR one after
+// another gathered together.
+fn predicate(expr: &Ast) -> Result<Predicate> {
+    let Ast::Expr(expr) = expr else {
+        return Err(unsupported(expr));
+    };
+    Ok(match expr.as_ref() {
+        Ast::BinaryOp {
+            op: op @ (Op::And | Op::Or),
+            lhs,
+            rhs,
+        } => {
+            let mut predicates = Vec::new();
+            for side in [lhs, rhs] {
+                match (op, predicate(side)?) {
+                    (Op::And, Predicate::And(inner)) | (Op::Or, Predicate::Or(inner)) => {
+                        predicates.extend(inner)
+                    }
+                    (_, predicate) => predicates.push(predicate),
+                }
+            }
+            match op {
+                Op::And => Predicate::And(predicates),
+                _ => Predicate::Or(predicates),
+            }
+        }
+        Ast::BinaryOp {
+            op:
+                op @ (Op::Equal
+                | Op::NotEqual
+                | Op::Less
+                | Op::LessEqual
+                | Op::Greater
+                | Op::GreaterEqual
+                | Op::Match
+                | Op::Like),
+            lhs,
+            rhs,
+        } => {
+            // A value compared with a column is the column compared with the value the other way
+            // round. Anything else is worked out for each row.
+            let (column_name, value, op) = match (column_name(lhs), literal(rhs)) {
+                (Some(column_name), Some(value)) => (column_name, value, *op),
+                _ => match (literal(lhs), column_name(rhs), flipped(*op)) {
+                    (Some(value), Some(column_name), Some(op)) => (column_name, value, op),
+                    _ if *op == Op::Match => return Err(match_out_of_context()),
+                    _ => return Ok(Predicate::Expr(Ast::Expr(expr.clone()))),
+                },
+            };
+
+            let condition = match op {
+                Op::Equal => Condition::Equal(value),
+                Op::Match => Condition::Match(value),
+                Op::Like => Condition::Like(value),
+                Op::NotEqual => Condition::Compare(Comparison::NotEqual, value),
+                Op::Less => Condition::Compare(Comparison::Less, value),
+                Op::LessEqual => Condition::Compare(Comparison::LessEqual, value),
+                Op::Greater => Condition::Compare(Comparison::Greater, value),
+                Op::GreaterEqual => Condition::Compare(Comparison::GreaterEqual, value),
+                _ => unreachable!(),
+            };
+            Predicate::Condition(column_name, condition)
+        }
+        Ast::InList { lhs, list } => match (column_name(lhs), list.iter().map(literal).collect()) {
+            (Some(column_name), Some(values)) => {
+                Predicate::Condition(column_name, Condition::In(values))
+            }
+            _ => Predicate::Expr(Ast::Expr(expr.clone())),
+        },
+        _ => Predicate::Expr(Ast::Expr(expr.clone())),
+    })
+}
+
+// The column an operand is, if it's one.
+fn column_name(expr: &Ast) -> Option<String> {
+    match expr {
+        Ast::Expr(expr) => match expr.as_ref() {
+            Ast::Identifier(name) => Some(name.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The value an operand is, if it's a literal or a parameter, whose value is the same for every
+// row.
+fn literal(expr: &Ast) -> Option<Ast> {
+    match expr {
+        Ast::Expr(inner) => match inner.as_ref() {
+            Ast::StringLiteral(_)
+            | Ast::IntegerLiteral(_)
+            | Ast::RealLiteral(_)
+            | Ast::BlobLiteral(_)
+            | Ast::NullLiteral
+            | Ast::Parameter { .. } => Some(expr.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The comparison that holds with its sides swapped when this one does. LIKE and MATCH aren't the
+// same the other way round.
+fn flipped(op: Op) -> Option<Op> {
+    match op {
+        Op::Equal | Op::NotEqual => Some(op),
+        Op::Less => Some(Op::Greater),
+        Op::LessEqual => Some(Op::GreaterEqual),
+        Op::Greater => Some(Op::Less),
+        Op::GreaterEqual => Some(Op::LessEqual),
+        _ => None,
+    }
+}
+
+fn unsupported(expr: &Ast) -> Error {
+    Error::Unsupported(format!("{} in WHERE", describe(expr)))
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // How many rows of a table in a database pass a condition.
+    #[allow(dead_code)]
+    fn count_in(db: &[u8], table: &str, condition: &str) -> i64 {
+        let mut connection = Connection::open_bytes(db.to_vec()).unwrap();
+        let rows = connection
+            .query(&format!(
+                "SELECT count(*) FROM {} WHERE {}",
+                table, condition
+            ))
+            .unwrap();
+        match rows.rows[0].get_ref(0).unwrap() {
+            Value::Int(count) => *count,
+            value => panic!("count(*) was {:?}", value),
+        }
+    }
+
+    // A table of 300 rows with indexes that can't all be sought: one sorted in descending order,
+    // one by the NOCASE collation its column declares, a partial one, and one by BINARY on a
+    // column that compares by RTRIM.
+    #[allow(dead_code)]
+    fn count(condition: &str) -> i64 {
+        count_in(
+            include_bytes!("../tests/fixtures/indexes.db"),
+            "t",
+            condition,
+        )
+    }
+
+    // 60 people, some of whose ages and scores are negative or NULL, with an index on age. Some
+    // ages are the same as the person's id.
+    #[allow(dead_code)]
+    fn count_people(condition: &str) -> i64 {
+        count_in(
+            include_bytes!("../tests/fixtures/people.db"),
+            "people",
+            condition,
+        )
+    }
+
+    #[test]
+    fn seeks_only_suitable_indexes() {
+        for (condition, expected) in [
+            ("k LIKE 'k17%'", 11),
+            ("k = 'k17'", 1),
+            ("k > 'k17'", 221),
+            ("c = 'AB3'", 1),
+            ("c LIKE 'ab1%'", 111),
+            ("c > 'AB2'", 188),
+            ("c IN ('AB3', 'ab4', 'x')", 2),
+            ("n = 1", 43),
+            ("n < 3", 128),
+            ("n IN (1, 5)", 86),
+            ("r = 'r1'", 30),
+            ("r > 'r8 '", 30),
+        ] {
+            assert_eq!(count(condition), expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn compares_with_reals() {
+        assert_eq!(count_people("score > 1.5"), 19);
+        assert_eq!(count_people("score >= -.5"), 51);
+        assert_eq!(count_people("id = 5.0"), 1);
+    }
+
+    #[test]
+    fn compares_with_negative_numbers() {
+        assert_eq!(count_people("age < -1"), 7);
+        assert_eq!(count_people("-3 <= score"), 56);
+    }
+
+    #[test]
+    fn compares_a_value_with_a_column() {
+        assert_eq!(count_people("5 > age"), 12);
+        assert_eq!(count_people("'5' > age"), 12);
+    }
+
+    #[test]
+    fn compares_columns() {
+        assert_eq!(count_people("age = id"), 9);
+        assert_eq!(count_people("age IN (id, 3)"), 9);
+    }
+
+    #[test]
+    fn nothing_compares_with_null() {
+        assert_eq!(count_people("x != NULL"), 0);
+        assert_eq!(count_people("x = NULL"), 0);
+        assert_eq!(count_people("age IN (1, NULL)"), 2);
+    }
+
+    // A REAL column keeps 2.0 as the integer 2, which still reads back as a real.
+    #[test]
+    fn reads_whole_reals_as_reals() {
+        let mut connection =
+            Connection::open_bytes(include_bytes!("../tests/fixtures/names.db").to_vec()).unwrap();
+        let rows = connection
+            .query("SELECT \"c`d\" FROM \"my t\" WHERE \"a \"\"b\"\"\" = -3")
+            .unwrap();
+        assert_eq!(rows.rows[0].get_ref(0).unwrap(), &Value::Real(2.0));
+    }
+
+    #[test]
+    fn computes_arithmetic_and_concatenation() {
+        let mut connection =
+            Connection::open_bytes(include_bytes!("../tests/fixtures/people.db").to_vec()).unwrap();
+        let rows = connection
+            .query("SELECT age + 1, -age, name || ':' || age, 7 / 2, 7.0 / 2, 5 % 0 FROM people WHERE id = 7")
+            .unwrap();
+        let values = (0..6)
+            .map(|column| rows.rows[0].get_ref(column).unwrap().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                Value::Int(8),
+                Value::Int(-7),
+                Value::Text("p7:7".to_string()),
+                Value::Int(3),
+                Value::Real(3.5),
+                Value::Null
+            ]
+        );
+        assert_eq!(count_people("age + 1 > 5"), 43);
+    }
+
+    // The values of a query without FROM, one for each of its result columns.
+    #[allow(dead_code)]
+    fn values_of(columns: &[&str]) -> Vec<Value> {
+        let mut connection = Connection::open(":memory:").unwrap();
+        let rows = connection
+            .query(&format!("SELECT {}", columns.join(", ")))
+            .unwrap();
+        (0..columns.len())
+            .map(|column| rows.rows[0].get_ref(column).unwrap().clone())
+            .collect()
+    }
+
+    // Each value is the one sqlite3 gives.
+    #[allow(dead_code)]
+    fn assert_values(cases: &[(&str, Value)]) {
+        let columns = cases.iter().map(|(sql, _)| *sql).collect::<Vec<_>>();
+        for ((sql, expected), value) in cases.iter().zip(values_of(&columns)) {
+            assert_eq!(&value, expected, "{}", sql);
+        }
+    }
+
+    // substr counts from 1, or back from the end; a start of 0 is before the first character, a
+    // negative length takes the characters before the start, and no length takes the rest.
+    #[test]
+    fn takes_substrings() {
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_values(&[
+            ("substr('abc', 0)", text("abc")),
+            ("substr('abc', 2)", text("bc")),
+            ("substr('abc', -1)", text("c")),
+            ("substr('abc', -5)", text("abc")),
+            ("substr('abc', 0, 2)", text("a")),
+            ("substr('abcdef', 2, 0)", text("")),
+            ("substr('abcdef', 4, -2)", text("bc")),
+            ("substr('abcdef', 3, -5)", text("ab")),
+            ("substr('abc', -5, 3)", text("a")),
+            ("substr('héllo', 2)", text("éllo")),
+            ("substr(12345, 2, 3)", text("234")),
+            ("substr(x'010203', 2)", Value::Blob(vec![2, 3])),
+            ("substr('abc', NULL)", Value::Null),
+            ("substr('abc', 1, NULL)", Value::Null),
+        ]);
+    }
+
+    // Only ASCII letters change case, and a blob is read as the text its bytes spell.
+    #[test]
+    fn changes_case() {
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_values(&[
+            ("upper('héllo')", text("HéLLO")),
+            ("lower('ÀB')", text("Àb")),
+            ("upper(x'6162')", text("AB")),
+            ("lower(x'41')", text("a")),
+            ("upper(1.5)", text("1.5")),
+            ("lower(12)", text("12")),
+            ("upper(NULL)", Value::Null),
+        ]);
+    }
+
+    #[test]
+    fn unescapes_unistr() {
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_values(&[
+            (r"unistr('aéb\0041')", text("aébA")),
+            (r"unistr('\\')", text("\\")),
+            (r"unistr('\+01F600')", text("😀")),
+            (r"unistr('\U0001F600')", text("😀")),
+            ("unistr(NULL)", Value::Null),
+        ]);
+    }
+
+    // || joins the text of its operands, and arithmetic follows sqlite's types: integers stay
+    // integers until they overflow, text is the number it starts with, and dividing by zero is
+    // NULL.
+    #[test]
+    fn concatenates_and_computes() {
+        let text = |text: &str| Value::Text(text.to_string());
+        assert_values(&[
+            ("'x' || NULL", Value::Null),
+            ("x'41' || 'b'", text("Ab")),
+            ("2 || 3", text("23")),
+            ("1.0 || 'a'", text("1.0a")),
+            ("1 + 2 * 3", Value::Int(7)),
+            ("10 / 4 * 2", Value::Int(4)),
+            ("5 - 7.5", Value::Real(-2.5)),
+            ("-7 % 3", Value::Int(-1)),
+            ("2.5 % 1", Value::Real(0.0)),
+            ("'5' * '2'", Value::Int(10)),
+            ("'abc' * 2", Value::Int(0)),
+            (
+                "9223372036854775807 + 1",
+                Value::Real(9223372036854775808.0),
+            ),
+            ("10 % 0", Value::Null),
+            ("1 / 0", Value::Null),
+        ]);
+    }
+
+    // The version is this crate's, not that of the SQLite whose file format it follows.
+    #[test]
+    fn reports_its_own_version() {
+        let [version, source_id] = values_of(&["sqlite_version()", "sqlite_source_id()"])
+            .try_into()
+            .unwrap();
+        assert_eq!(version, Value::Text(env!("CARGO_PKG_VERSION").to_string()));
+        let Value::Text(source_id) = source_id else {
+            panic!("{:?}", source_id);
+        };
+        assert!(source_id.starts_with(&format!(
+            "sqlite-starter-rust {},",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(source_id.ends_with("SQLite 3.51.2"), "{}", source_id);
+    }
+
+    // 1000 rows on 512 byte pages, whose column n is the id modulo 10, with an index on n.
+    #[allow(dead_code)]
+    fn stats_of(sql: &str) -> QueryStats {
+        let bytes = include_bytes!("../tests/fixtures/many.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        let mut statement = connection.prepare(sql).unwrap();
+        statement.query(&[]).unwrap();
+        statement.stats()
+    }
+
+    // Rows are read out of the table and the index in batches that start small, so a LIMIT stops
+    // reading pages soon after it has its rows.
+    #[test]
+    fn limit_stops_reading() {
+        let all = stats_of("SELECT * FROM m");
+        assert_eq!(all.rows_scanned, 1000);
+        assert!(all.pages_read > 30, "{}", all);
+
+        let limited = stats_of("SELECT * FROM m LIMIT 3");
+        assert_eq!(limited.rows_scanned, 3);
+        assert!(limited.pages_read <= 3, "{}", limited);
+
+        for sql in [
+            "SELECT * FROM m WHERE n = 3 LIMIT 2",
+            "SELECT * FROM m WHERE n IN (3, 4) LIMIT 2",
+            "SELECT * FROM m WHERE n > 7 LIMIT 2",
+        ] {
+            let limited = stats_of(sql);
+            assert_eq!(limited.index_seeks, 1, "{}", sql);
+            assert_eq!(limited.rows_scanned, 2, "{}", sql);
+            assert!(limited.pages_read <= 8, "{}: {}", sql, limited);
+        }
+    }
+
+    // WHERE filters the rows it's handed in place, and only the columns asked for are read out
+    // of them, in the order asked for, as sqlite3 gives them.
+    #[test]
+    fn filters_and_projects_rows() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        let rows = connection
+            .query("SELECT x, name, age FROM people WHERE age > 30 AND score < 0")
+            .unwrap();
+        let rows = rows
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<String>(0).unwrap(),
+                    row.get::<String>(1).unwrap(),
+                    row.get::<i64>(2).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected = [("x0", "p16", 32), ("x3", "p31", 37), ("x0", "p56", 56)];
+        assert_eq!(
+            rows,
+            expected.map(|(x, name, age)| (x.to_string(), name.to_string(), age))
+        );
+    }
+
+    // A scan reads each page of the table once, and an index seek looks its rows up in a few
+    // passes down the table rather than one descent per row, which would read at least two pages
+    // for each of them.
+    #[test]
+    fn reads_each_page_once() {
+        let all = stats_of("SELECT * FROM m");
+        assert_eq!(all.rows_scanned, 1000);
+        // 36 leaves and the root.
+        assert_eq!(all.pages_read, 37, "{}", all);
+
+        for (sql, rows) in [
+            ("SELECT * FROM m WHERE n = 3", 100),
+            ("SELECT * FROM m WHERE n > 7", 200),
+        ] {
+            let stats = stats_of(sql);
+            assert_eq!(stats.index_seeks, 1, "{}", sql);
+            assert_eq!(stats.rows_scanned, rows, "{}", sql);
+            assert!(stats.pages_read < rows, "{}: {}", sql, stats);
+        }
+    }
+
+    // An index seek hands out rows in the order of the index, like sqlite3 does, and a row that
+    // more than one of its keys finds only once.
+    #[test]
+    fn seeks_in_index_order() {
+        let bytes = include_bytes!("../tests/fixtures/many.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        let rows = connection
+            .query("SELECT id FROM m WHERE n IN (4, 3, 4) LIMIT 4 OFFSET 98")
+            .unwrap();
+        let ids = rows
+            .rows
+            .iter()
+            .map(|row| row.get_ref(0).unwrap().clone())
+            .collect::<Vec<_>>();
+        let expected = [983, 993, 4, 14].map(Value::Int);
+        assert_eq!(ids, expected);
+        assert_eq!(count_in(bytes, "m", "n IN (4, 3, 4)"), 200);
+    }
+
+    // Once ANALYZE has found few values of x, an equality on y alone skip-scans the index on
+    // (x, y), as sqlite3 does, and reads only the rows it finds rather than all 303. The rows are
+    // sqlite3's, from the same copy of objects.db:
+    //
+    //     sqlite> EXPLAIN QUERY PLAN SELECT x, y FROM b WHERE y IN (7, 100);
+    //     `--SEARCH b USING COVERING INDEX b_xy (ANY(x) AND y=?)
+    //     sqlite> SELECT x, y FROM b WHERE y IN (7, 100);
+    //     1|7
+    //     1|100
+    #[test]
+    fn skip_scans_analyzed_indexes() {
+        let bytes = include_bytes!("../tests/fixtures/analyzed.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        let mut query = |sql: &str| {
+            let mut statement = connection.prepare(sql).unwrap();
+            let rows = statement.query(&[]).unwrap();
+            let rows = rows
+                .rows
+                .iter()
+                .map(|row| row.values().to_vec())
+                .collect::<Vec<_>>();
+            (rows, statement.stats().rows_scanned)
+        };
+
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(
+            query("SELECT x, y, z FROM b WHERE y = 5"),
+            (vec![vec![Value::Int(2), Value::Int(5), text("z5")]], 1)
+        );
+        assert_eq!(
+            query("SELECT x, y FROM b WHERE y IN (7, 100)"),
+            (
+                vec![
+                    vec![Value::Int(1), Value::Int(7)],
+                    vec![Value::Int(1), Value::Int(100)]
+                ],
+                2
+            )
+        );
+        assert_eq!(count_in(bytes, "b", "y = -1"), 0);
+    }
+
+    // min() and max() of an indexed column, or of the rowid, read down one edge of its b-tree,
+    // as in sqlite3, whose `.stats stmt` counts one more page for the schema on page 1. Without an
+    // index the whole table is read:
+    //
+    //     $ sqlite3 many.db ".stats stmt" "SELECT max(n) FROM m"
+    //     9
+    //     Page cache misses:                   3
+    //     $ sqlite3 many.db ".stats stmt" "SELECT max(s) FROM m"
+    //     row 1000
+    //     Page cache misses:                   38
+    #[test]
+    fn reads_min_and_max_from_the_ends() {
+        let bytes = include_bytes!("../tests/fixtures/many.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        for (sql, value, pages) in [
+            ("SELECT max(n) FROM m", Value::Int(9), 2),
+            ("SELECT min(n) FROM m", Value::Int(0), 2),
+            ("SELECT max(id) FROM m", Value::Int(1000), 2),
+            ("SELECT min(id) FROM m", Value::Int(1), 2),
+            (
+                "SELECT max(s) FROM m",
+                Value::Text("row 1000".to_string()),
+                37,
+            ),
+        ] {
+            let mut statement = connection.prepare(sql).unwrap();
+            let rows = statement.query(&[]).unwrap();
+            assert_eq!(rows.rows[0][0], value, "{}", sql);
+            assert_eq!(statement.stats().pages_read, pages, "{}", sql);
+        }
+    }
+
+    // count(*) of a whole table adds up the cells on its leaves without decoding any of them. It
+    // reads every page of the table, as sqlite3 does when there is no index to count instead,
+    // with the schema's page 1 besides:
+    //
+    //     $ sqlite3 many-unindexed.db ".stats stmt" "SELECT count(*) FROM m"
+    //     1000
+    //     Page cache misses:                   38
+    #[test]
+    fn counts_without_decoding() {
+        let bytes = include_bytes!("../tests/fixtures/many-unindexed.db");
+        let mut connection = Connection::open_bytes(bytes.to_vec()).unwrap();
+        let mut statement = connection.prepare("SELECT count(*) FROM m").unwrap();
+        let rows = statement.query(&[]).unwrap();
+        assert_eq!(rows.rows[0][0], Value::Int(1000));
+        let stats = statement.stats();
+        assert_eq!(
+            (stats.pages_read, stats.cells_decoded, stats.rows_scanned),
+            (37, 0, 0)
+        );
+
+        // With a WHERE clause, each row has to be read to be tested.
+        let mut statement = connection
+            .prepare("SELECT count(*) FROM m WHERE n = 3")
+            .unwrap();
+        let rows = statement.query(&[]).unwrap();
+        assert_eq!(rows.rows[0][0], Value::Int(100));
+        assert_eq!(statement.stats().rows_scanned, 1000);
+    }
+
+    // Records the pages the database had read by the time each write came.
+    #[allow(dead_code)]
+    struct Recorder<'a> {
+        db: &'a Db,
+        writes: Vec<(u64, Vec<u8>)>,
+    }
+
+    impl Write for Recorder<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let pages = self.db.pager.stats().pages_read;
+            self.writes.push((pages, buf.to_vec()));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // JSONL writes each row's line as soon as the row is read, before the rest of the table is.
+    #[test]
+    fn streams_jsonl() {
+        let bytes = include_bytes!("../tests/fixtures/many.db");
+        let db = Db::from_bytes(bytes.to_vec()).unwrap();
+        let settings = crate::output::OutputSettings {
+            mode: crate::output::Mode::Jsonl,
+            ..Default::default()
+        };
+        let mut recorder = Recorder {
+            db: &db,
+            writes: Vec::new(),
+        };
+        let mut writer = ResultWriter::new(&mut recorder, &settings);
+        SqlEngine::new(&mut writer)
+            .execute("SELECT id, s FROM m", &db)
+            .unwrap();
+        drop(writer);
+
+        let (first, line) = &recorder.writes[0];
+        assert_eq!(line, b"{\"id\":1,\"s\":\"row 0001\"}\n");
+        let (last, _) = recorder.writes.last().unwrap();
+        assert!(first < last, "{} {}", first, last);
+        assert_eq!(recorder.writes.len(), 1000);
+    }
+
+    // The smallest and largest page sizes read the same as any other. The 512 byte pages keep 32
+    // bytes reserved, and hold enough of a schema that the schema table's root on page 1 is an
+    // interior page, with cells that start after the database header. The tables have blobs big
+    // enough to spill onto overflow pages, and the hashes are the ones dbhash gives.
+    #[test]
+    fn reads_odd_page_sizes() {
+        for (bytes, blobs, long, hash) in [
+            (
+                &include_bytes!("../tests/fixtures/page-512.db")[..],
+                20802,
+                1600,
+                "c18ef9aa644cfe0921051d0cd8b58d6de752da8c",
+            ),
+            (
+                include_bytes!("../tests/fixtures/page-65536.db"),
+                74900,
+                70000,
+                "459ad2514edd42be8c518b0fe5906a6f0f30ef66",
+            ),
+        ] {
+            // Read from memory, and from a file.
+            let path = std::env::temp_dir().join(format!("page-size-{}.db", std::process::id()));
+            std::fs::write(&path, bytes).unwrap();
+            let connections = [
+                Connection::open_bytes(bytes.to_vec()).unwrap(),
+                Connection::open(&path).unwrap(),
+            ];
+            for mut connection in connections {
+                let mut query = |sql: &str| -> Vec<Value> {
+                    let rows = connection.query(sql).unwrap();
+                    rows.rows
+                        .iter()
+                        .flat_map(|row| row.values().to_vec())
+                        .collect()
+                };
+                assert_eq!(query("PRAGMA integrity_check"), [Value::Text("ok".into())]);
+                assert_eq!(
+                    query("SELECT count(*), sum(length(s)), sum(length(b)), max(id) FROM t"),
+                    [
+                        Value::Int(200),
+                        Value::Int(1400),
+                        Value::Int(blobs),
+                        Value::Int(200)
+                    ]
+                );
+                assert_eq!(
+                    query("SELECT id, length(b) FROM t WHERE s = 'row 100'"),
+                    [Value::Int(100), Value::Int(long)]
+                );
+            }
+            std::fs::remove_file(&path).ok();
+
+            let db = Db::from_bytes(bytes.to_vec()).unwrap();
+            let dbhash = crate::dbhash::hash(&db, None, crate::dbhash::Include::All).unwrap();
+            assert_eq!(dbhash, hash);
+        }
     }
 }
@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::fmt::Arguments;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// Diagnostic output from the pager, the b-tree code and the planner, for finding out why a query
+// is slow. It is off unless the SQLITE_TRACE environment variable turns it on, for a list of
+// targets, as in `SQLITE_TRACE=btree,planner`, or for all of them with `SQLITE_TRACE=all`. Lines
+// go to stderr:
+//
+//     trace planner: NAME = 'x' on apples by index idx_apples_name
+//     trace btree: seek of 1 keys in index on page 4 (35us)
+//
+// Events are one-off messages, and spans say how long something took once it ends. Checking
+// whether a target is on is one load of a static, so leaving the calls in costs next to nothing.
+// Cargo.toml can't have dependencies or features added to it, which rules out the tracing crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    Pager,
+    BTree,
+    Planner,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Target::Pager => "pager",
+            Target::BTree => "btree",
+            Target::Planner => "planner",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+static TARGETS: OnceLock<u8> = OnceLock::new();
+
+thread_local! {
+    // The lines `capture` is collecting on this thread, which go nowhere else while it does.
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn enabled(target: Target) -> bool {
+    if CAPTURED.with(|captured| captured.borrow().is_some()) {
+        return true;
+    }
+    let targets = TARGETS.get_or_init(|| {
+        let Ok(setting) = std::env::var("SQLITE_TRACE") else {
+            return 0;
+        };
+        [Target::Pager, Target::BTree, Target::Planner]
+            .into_iter()
+            .filter(|target| {
+                setting
+                    .split(',')
+                    .any(|name| name.trim() == "all" || name.trim() == target.name())
+            })
+            .fold(0, |targets, target| targets | target.bit())
+    });
+    targets & target.bit() != 0
+}
+
+pub(crate) fn emit(target: Target, message: Arguments) {
+    let line = format!("trace {}: {}", target.name(), message);
+    let line = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(lines) => {
+            lines.push(line);
+            None
+        }
+        None => Some(line),
+    });
+    if let Some(line) = line {
+        eprintln!("{}", line);
+    }
+}
+
+// Runs `f` with every target on for this thread, whatever SQLITE_TRACE says, and hands back the
+// lines it traced instead of writing them. Work `f` hands to other threads isn't traced.
+#[allow(dead_code)]
+pub(crate) fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let lines = CAPTURED.with(|captured| captured.borrow_mut().take());
+    (result, lines.unwrap_or_default())
+}
+
+// Writes a line for the target, if it is on. The message is only formatted when it is.
+macro_rules! event {
+    ($target:expr, $($arg:tt)*) => {
+        if $crate::trace::enabled($target) {
+            $crate::trace::emit($target, format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use event;
+
+// Something that takes time, which writes how long it took when it is dropped.
+pub(crate) struct Span {
+    target: Target,
+    name: String,
+    start: Instant,
+}
+
+// Starts a span if the target is on. The name is only made when it is.
+pub(crate) fn span(target: Target, name: impl FnOnce() -> String) -> Option<Span> {
+    enabled(target).then(|| Span {
+        target,
+        name: name(),
+        start: Instant::now(),
+    })
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_micros();
+        emit(self.target, format_args!("{} ({}us)", self.name, elapsed));
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+
+    // The planner says which way it reads each table, which is the way sqlite3 picks:
+    //
+    //     $ sqlite3 people.db "EXPLAIN QUERY PLAN SELECT name FROM people WHERE age = 27"
+    //     `--SEARCH people USING INDEX people_age (age=?)
+    //     $ sqlite3 people.db "EXPLAIN QUERY PLAN SELECT name FROM people WHERE name = 'p1'"
+    //     `--SCAN people
+    #[test]
+    fn traces_the_plan() {
+        let bytes = include_bytes!("../tests/fixtures/people.db");
+        let mut connection = Connection::open_bytes(&bytes[..]).unwrap();
+        let mut planned = |sql: &str| {
+            let (rows, lines) = capture(|| connection.query(sql).unwrap());
+            assert!(!rows.rows.is_empty());
+            assert!(lines
+                .iter()
+                .any(|line| line.starts_with("trace pager: read page")));
+            lines
+                .into_iter()
+                .filter(|line| line.starts_with("trace planner:"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            planned("SELECT name FROM people WHERE age = 27"),
+            ["trace planner: age = 27 on people by index people_age"]
+        );
+        assert_eq!(
+            planned("SELECT name FROM people WHERE name = 'p1'"),
+            ["trace planner: name = 'p1' on people by full scan"]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+// Walking a tree of Ast nodes, so that a pass over a statement or an expression only has to say
+// what it does with each node, and not how to find the nodes inside it.
+use crate::parser::Ast;
+
+// Something that looks at every node of a tree, each before the nodes inside it. Returning false
+// from enter skips the nodes inside the one it was given, and leave is only called for the nodes
+// that were entered, once the nodes inside them have been.
+pub trait Visitor {
+    fn enter(&mut self, node: &Ast) -> bool;
+
+    fn leave(&mut self, _node: &Ast) {}
+}
+
+// A closure is a visitor that only enters nodes.
+impl<F: FnMut(&Ast) -> bool> Visitor for F {
+    fn enter(&mut self, node: &Ast) -> bool {
+        self(node)
+    }
+}
+
+pub fn walk(node: &Ast, visitor: &mut impl Visitor) {
+    if !visitor.enter(node) {
+        return;
+    }
+    for child in children(node) {
+        walk(child, visitor);
+    }
+    visitor.leave(node);
+}
+
+// Whether any node of a tree, including its root, is one the predicate is true of. The walk stops
+// at the first.
+pub fn any(node: &Ast, mut predicate: impl FnMut(&Ast) -> bool) -> bool {
+    let mut found = false;
+    walk(node, &mut |node: &Ast| {
+        found = found || predicate(node);
+        !found
+    });
+    found
+}
+
+// The nodes directly inside a node, in the order they were written.
+pub fn children(node: &Ast) -> Vec<&Ast> {
+    match node {
+        Ast::StmtList(nodes)
+        | Ast::TableFunction { args: nodes, .. }
+        | Ast::Function { args: nodes, .. }
+        | Ast::CreateTable {
+            column_defs: nodes, ..
+        } => nodes.iter().collect(),
+        Ast::CreateIndex {
+            columns, r#where, ..
+        } => columns.iter().chain(r#where.as_deref()).collect(),
+        Ast::Stmt(node)
+        | Ast::Expr(node)
+        | Ast::TableOrSubQuery(node)
+        | Ast::OrderingTerm { expr: node, .. }
+        | Ast::ResultColumn { expr: node, .. }
+        | Ast::Negate(node) => vec![node.as_ref()],
+        Ast::Select {
+            result_columns,
+            from,
+            r#where,
+            group_by,
+            order_by,
+            limit,
+            offset,
+        } => result_columns
+            .iter()
+            .chain(from.as_deref())
+            .chain(r#where.as_deref())
+            .chain(group_by)
+            .chain(order_by)
+            .chain(limit.as_deref())
+            .chain(offset.as_deref())
+            .collect(),
+        Ast::BinaryOp { lhs, rhs, .. } => vec![lhs.as_ref(), rhs.as_ref()],
+        Ast::InList { lhs, list } => std::iter::once(lhs.as_ref()).chain(list).collect(),
+        Ast::Insert { rows, .. } => rows.iter().flatten().collect(),
+        Ast::Delete { r#where, .. } => r#where.as_deref().into_iter().collect(),
+        Ast::All
+        | Ast::Table(_)
+        | Ast::CreateVirtualTable { .. }
+        | Ast::ColumnDef { .. }
+        | Ast::IndexedColumn { .. }
+        | Ast::Identifier(_)
+        | Ast::StringLiteral(_)
+        | Ast::IntegerLiteral(_)
+        | Ast::RealLiteral(_)
+        | Ast::BlobLiteral(_)
+        | Ast::NullLiteral
+        | Ast::Parameter { .. }
+        | Ast::PrimaryKey(_)
+        | Ast::Check
+        | Ast::ForeignKey { .. } => Vec::new(),
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::lexer::Lexer;
+    #[allow(unused_imports)]
+    use crate::parser::Parser;
+
+    // A walk meets the columns of a statement in the order sqlite3 reads them, which its
+    // authorizer reports from Python as each is read:
+    //
+    //     >>> c.set_authorizer(auth); c.execute("SELECT name, age + 1 FROM people ...")
+    //     ['name', 'age', 'score', 'x', 'name', 'id']
+    #[test]
+    fn walks_in_sqlite3_order() {
+        let sql =
+            "SELECT name, age + 1 FROM people WHERE score > 1 AND x IN ('a', name) ORDER BY id";
+        let mut lexer = Lexer::new(sql.to_string());
+        let ast = Parser::new(lexer.lex().unwrap()).parse().unwrap();
+
+        let mut columns = Vec::new();
+        walk(&ast, &mut |node: &Ast| {
+            if let Ast::Identifier(name) = node {
+                columns.push(name.clone());
+            }
+            true
+        });
+        assert_eq!(columns, ["name", "age", "score", "x", "name", "id"]);
+
+        // A walk that stops entering nodes misses what is inside them, and `any` stops at the
+        // first node it is looking for.
+        let mut entered = 0;
+        walk(&ast, &mut |node: &Ast| {
+            entered += 1;
+            !matches!(node, Ast::Select { .. })
+        });
+        assert_eq!(entered, 3);
+        assert!(any(&ast, |node| matches!(node, Ast::InList { .. })));
+        assert!(!any(&ast, |node| matches!(node, Ast::Function { .. })));
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::Value;
+
+// A table whose rows come from the program rather than the database file, like a CSV file or
+// another data source. Once registered with `Connection::register_virtual_table`, it can be named
+// in FROM like any other table, or called with arguments like a table-valued function:
+//
+//     connection.register_virtual_table("series", Series)?;
+//     let rows = connection.query("SELECT value FROM series(1, 10)")?;
+//
+// It is modelled on sqlite's virtual table interface: the table describes its columns, and each
+// query opens a new cursor on it, which is filtered and then stepped through a row at a time.
+pub trait VirtualTable: Send + Sync {
+    // The names of the columns, in order.
+    fn columns(&self) -> Vec<String>;
+
+    fn open(&self) -> Result<Box<dyn VirtualCursor + '_>>;
+}
+
+// A scan of a virtual table. `filter` starts the scan on its first row, then `next` moves on
+// until `eof` says there are no more. `column` and `rowid` read the row the cursor is on.
+pub trait VirtualCursor {
+    // `args` are the arguments the table was called with in FROM, if any. The constraints come
+    // from the WHERE clause, and can be used to skip rows that can't match; rows are checked
+    // against every constraint but MATCH again, so a cursor is free to ignore those.
+    fn filter(&mut self, args: &[Value], constraints: &[Constraint]) -> Result<()>;
+    fn next(&mut self) -> Result<()>;
+    fn eof(&self) -> bool;
+    fn column(&self, index: usize) -> Result<Value>;
+    fn rowid(&self) -> Result<i64>;
+}
+
+// Something a WHERE clause demands of a column, given by its index in `columns`. An index one
+// past the last column stands for the whole table, as in `WHERE docs MATCH 'word'`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub column: usize,
+    pub op: ConstraintOp,
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    // The column equals one of the values.
+    Equal,
+    // The column compares with the one value this way.
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    // The column matches the one value, in whatever sense the table gives MATCH. Only the table
+    // can tell which rows match, so these rows aren't checked again afterwards.
+    Match,
+}
+
+// The virtual tables registered with a connection, keyed by their name in upper case.
+pub(crate) type VirtualTables = HashMap<String, Arc<dyn VirtualTable>>;
+
+// A row read out of a virtual table, with the value of every column.
+pub(crate) struct VirtualRow {
+    pub(crate) rowid: i64,
+    pub(crate) values: Vec<Value>,
+}
+
+// Runs a cursor over the table from start to finish, collecting its rows.
+pub(crate) fn scan(
+    table: &dyn VirtualTable,
+    args: &[Value],
+    constraints: &[Constraint],
+) -> Result<Vec<VirtualRow>> {
+    let width = table.columns().len();
+    let mut cursor = table.open()?;
+    cursor.filter(args, constraints)?;
+
+    let mut rows = Vec::new();
+    while !cursor.eof() {
+        rows.push(VirtualRow {
+            rowid: cursor.rowid()?,
+            values: (0..width)
+                .map(|index| cursor.column(index))
+                .collect::<Result<_>>()?,
+        });
+        cursor.next()?;
+    }
+    Ok(rows)
+}
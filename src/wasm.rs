@@ -0,0 +1,166 @@
+// The API a browser calls when the crate is built for wasm32-unknown-unknown as a cdylib:
+//
+//   cargo rustc --lib --release --target wasm32-unknown-unknown --crate-type cdylib
+//
+// There are no bindings generated for it, so everything crosses as numbers. JavaScript copies the
+// bytes of a database (a Uint8Array) or a query into memory it got from `sqlite_alloc`, opens the
+// database with `sqlite_open`, and runs queries with `sqlite_query`. A query's rows come back as
+// a JSON array of objects, one per row, keyed by column name, in the buffer `sqlite_result_ptr`
+// and `sqlite_result_len` point at, which stays put until the next query. A failed call leaves
+// the error message there instead.
+//
+// The functions are only exported from wasm builds, so they can't clash with anything a native
+// program links against, and elsewhere nothing but the tests calls them.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+use std::cell::RefCell;
+
+use crate::error::Result;
+use crate::output::push_json_object;
+use crate::Connection;
+
+thread_local! {
+    static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Memory for `len` bytes, for JavaScript to copy a database or a query into.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn sqlite_alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::<u8>::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+// Hands back memory from `sqlite_alloc` once JavaScript is done with it.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn sqlite_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+// Opens the database in the `len` bytes at `ptr`, gzip compressed or not, which are copied, so
+// they can be freed straight after. A failure is null, with the reason in the result buffer.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn sqlite_open(ptr: *const u8, len: usize) -> *mut Connection {
+    let bytes = std::slice::from_raw_parts(ptr, len).to_vec();
+    match Connection::open_bytes(bytes) {
+        Ok(connection) => Box::into_raw(Box::new(connection)),
+        Err(err) => {
+            set_result(err.to_string().into_bytes());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn sqlite_close(connection: *mut Connection) {
+    if !connection.is_null() {
+        drop(Box::from_raw(connection));
+    }
+}
+
+// Runs the UTF-8 SQL in the `len` bytes at `sql`, which is 0 with the rows as JSON in the result
+// buffer, or 1 with the error message there.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn sqlite_query(
+    connection: *mut Connection,
+    sql: *const u8,
+    len: usize,
+) -> u32 {
+    let connection = &mut *connection;
+    let sql = String::from_utf8_lossy(std::slice::from_raw_parts(sql, len));
+    match query_json(connection, &sql) {
+        Ok(json) => {
+            set_result(json.into_bytes());
+            0
+        }
+        Err(err) => {
+            set_result(err.to_string().into_bytes());
+            1
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn sqlite_result_ptr() -> *const u8 {
+    RESULT.with(|result| result.borrow().as_ptr())
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn sqlite_result_len() -> usize {
+    RESULT.with(|result| result.borrow().len())
+}
+
+fn set_result(bytes: Vec<u8>) {
+    RESULT.with(|result| *result.borrow_mut() = bytes);
+}
+
+// The rows of a query as a JSON array, laid out like the shell's json mode, except that no rows
+// is an empty array rather than nothing at all.
+fn query_json(connection: &mut Connection, sql: &str) -> Result<String> {
+    let rows = connection.query(sql)?;
+    let mut json = String::from("[");
+    for (i, row) in rows.rows.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        push_json_object(
+            &mut json,
+            &rows.columns,
+            row.values().iter().map(|value| value.as_value_ref()),
+        );
+    }
+    json.push(']');
+    Ok(json)
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // What the result buffer holds, as JavaScript would read it.
+    #[allow(dead_code)]
+    fn result() -> String {
+        let bytes = unsafe { std::slice::from_raw_parts(sqlite_result_ptr(), sqlite_result_len()) };
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    // Copies bytes into memory from sqlite_alloc, the way JavaScript hands them over.
+    #[allow(dead_code)]
+    fn copy_in(bytes: &[u8]) -> *mut u8 {
+        let ptr = sqlite_alloc(bytes.len());
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+        ptr
+    }
+
+    #[test]
+    fn queries_to_json() {
+        let bytes = include_bytes!("../tests/fixtures/unreadable.db");
+        let db = copy_in(bytes);
+        let connection = unsafe { sqlite_open(db, bytes.len()) };
+        unsafe { sqlite_free(db, bytes.len()) };
+        assert!(!connection.is_null(), "{}", result());
+
+        let query = |sql: &str| {
+            let ptr = copy_in(sql.as_bytes());
+            let status = unsafe { sqlite_query(connection, ptr, sql.len()) };
+            unsafe { sqlite_free(ptr, sql.len()) };
+            (status, result())
+        };
+        assert_eq!(
+            query("SELECT a, 'x\"' AS b FROM ok"),
+            (0, r#"[{"a":1,"b":"x\""}]"#.to_string())
+        );
+        assert_eq!(query("SELECT a FROM ok WHERE a > 1"), (0, "[]".to_string()));
+        let (status, message) = query("SELECT nope FROM ok");
+        assert_eq!(status, 1);
+        assert!(message.contains("nope"), "{}", message);
+
+        unsafe { sqlite_close(connection) };
+
+        let junk = copy_in(b"not a database");
+        assert!(unsafe { sqlite_open(junk, 14) }.is_null());
+        unsafe { sqlite_free(junk, 14) };
+        assert!(!result().is_empty());
+    }
+}
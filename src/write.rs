@@ -0,0 +1,1500 @@
+use std::cmp::Ordering;
+use std::io;
+
+use crate::btree::{self, BTreeCursor};
+use crate::catalog::Index;
+use crate::error::{Error, Result};
+use crate::pager::{Pager, Transaction};
+use crate::{
+    into_table_leaf_record, invalid, read_page, ByteReader, Db, IndexLeafRecord, MasterPageRecord,
+    Record, Value, SQLITE_VERSION_NUMBER,
+};
+
+impl Db {
+    // Inserts a row into a table, with an entry for it in each of the table's indexes, and returns
+    // its rowid. The values are the table's columns in order. A rowid alias that is NULL, or a
+    // table without one, gets the rowid after the largest there is.
+    //
+    // The pages are only changed in the pager, inside the transaction the caller has begun. A row
+    // that breaks a constraint fails before anything of it is written, but the rows before it stay
+    // written until the transaction is dropped.
+    pub(crate) fn insert_row(
+        &self,
+        table: &MasterPageRecord,
+        mut values: Vec<Value>,
+    ) -> Result<i64> {
+        for (value, column) in values.iter_mut().zip(&table.columns) {
+            *value = column
+                .affinity()
+                .apply(std::mem::replace(value, Value::Null));
+            if column.not_null && matches!(value, Value::Null) {
+                return Err(Error::Constraint(format!(
+                    "NOT NULL constraint failed: {}.{}",
+                    table.name, column.name
+                )));
+            }
+        }
+
+        // The alias isn't stored in the record, as it's the rowid itself.
+        let given = match table.rowid_alias {
+            Some(alias) => match std::mem::replace(&mut values[alias], Value::Null) {
+                Value::Null => None,
+                Value::Int(rowid) => Some(rowid),
+                _ => return Err(Error::Mismatch),
+            },
+            None => None,
+        };
+        let rowid = match given {
+            Some(rowid) => rowid,
+            None => match self.rowid_bound(table, true)? {
+                Value::Int(i64::MAX) => {
+                    return Err(Error::Unsupported(
+                        "choosing a rowid when the largest is taken".to_string(),
+                    ))
+                }
+                Value::Int(rowid) => rowid + 1,
+                _ => 1,
+            },
+        };
+
+        let indexes = self.writable_indexes(table)?;
+        let mut keys = Vec::with_capacity(indexes.len());
+        for (index, columns) in &indexes {
+            let mut key = index_values(table, columns, rowid, &values);
+
+            // NULLs are never equal to each other, so a key with one in it is always unique.
+            let taken = index.unique
+                && !key.iter().any(|value| matches!(value, Value::Null))
+                && BTreeCursor::seek_index_prefix(&self.pager, index.root_page, key.clone())
+                    .next()
+                    .transpose()?
+                    .is_some();
+            if taken {
+                let names = columns
+                    .iter()
+                    .map(|column| format!("{}.{}", table.name, table.columns[*column].name))
+                    .collect::<Vec<_>>();
+                return Err(Error::Constraint(format!(
+                    "UNIQUE constraint failed: {}",
+                    names.join(", ")
+                )));
+            }
+
+            key.push(Value::Int(rowid));
+            keys.push((index.root_page, key));
+        }
+
+        let editor = self.editor();
+        if !editor.insert_table_row(table.root_page, rowid, &encode_record(&values))? {
+            let name = match table.rowid_alias {
+                Some(alias) => &table.columns[alias].name,
+                None => "rowid",
+            };
+            return Err(Error::Constraint(format!(
+                "UNIQUE constraint failed: {}.{}",
+                table.name, name
+            )));
+        }
+        for (root_page, key) in keys {
+            editor.insert_index_entry(root_page, &key)?;
+        }
+        Ok(rowid)
+    }
+
+    // Deletes a row from a table, and its entry from each of the table's indexes. The values are
+    // the row's columns as they are stored.
+    pub(crate) fn delete_row(
+        &self,
+        table: &MasterPageRecord,
+        rowid: i64,
+        values: &[Value],
+    ) -> Result<()> {
+        let editor = self.editor();
+        for (index, columns) in self.writable_indexes(table)? {
+            let mut key = index_values(table, &columns, rowid, values);
+            key.push(Value::Int(rowid));
+            editor.delete_index_entry(index.root_page, &key)?;
+        }
+        if !editor.delete_table_row(table.root_page, rowid)? {
+            return Err(Error::Corrupt {
+                page: table.root_page,
+                message: format!("row {} is missing from {}", rowid, table.name),
+            });
+        }
+        Ok(())
+    }
+
+    // Creates an empty table, with a row for it in the schema table that has the CREATE TABLE
+    // statement that made it, and bumps the schema cookie, which tells every connection to read
+    // the schema again. Like a row, it's only written inside the caller's transaction.
+    pub(crate) fn create_table(&self, name: &str, sql: &str) -> Result<()> {
+        let editor = self.editor();
+        let root_page = editor.allocate()?;
+        editor.store(&Node {
+            number: root_page,
+            page_type: TABLE_LEAF,
+            cells: Vec::new(),
+            rightmost: None,
+            bytes: Vec::new(),
+        })?;
+
+        let rowid = match btree::last(&self.pager, 1, true)? {
+            Some(record) => into_table_leaf_record(record).header.row_id as i64 + 1,
+            None => 1,
+        };
+        let record = encode_record(&[
+            Value::Text("table".to_string()),
+            Value::Text(name.to_string()),
+            Value::Text(name.to_string()),
+            Value::Int(root_page as i64),
+            Value::Text(sql.to_string()),
+        ]);
+        editor.insert_table_row(1, rowid, &record)?;
+
+        let mut header = read_page(&self.pager, 1)?.into_owned();
+        let cookie = u32_at(&header, 40).wrapping_add(1).to_be_bytes();
+        header[40..44].copy_from_slice(&cookie);
+        self.pager.write_page(1, header)?;
+        Ok(())
+    }
+
+    // Commits what a statement wrote, after bringing the database header up to date for other
+    // programs to see:
+    //
+    // - the file change counter goes up, which tells them to drop the pages they have cached
+    // - version-valid-for matches it, which tells sqlite that the size in the header is current
+    // - the size is the number of pages there are now, which sqlite believes before the file's
+    // - the version of sqlite that last wrote the file is the one this crate follows
+    //
+    // Only a change to the schema bumps the schema cookie, which `create_table` has done already.
+    pub(crate) fn commit(&self, transaction: Transaction<'_>) -> Result<()> {
+        let mut header = read_page(&self.pager, 1)?.into_owned();
+        let counter = u32_at(&header, 24).wrapping_add(1).to_be_bytes();
+        header[24..28].copy_from_slice(&counter);
+        header[28..32].copy_from_slice(&self.pager.page_count().to_be_bytes());
+        header[92..96].copy_from_slice(&counter);
+        header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+        self.pager.write_page(1, header)?;
+        transaction.commit().map_err(Error::locking)
+    }
+
+    // The indexes on a table, each with where its columns are in the table's rows. A table that
+    // can't be written yet, or with an index that can't be kept up to date, is Unsupported.
+    fn writable_indexes(
+        &self,
+        table: &MasterPageRecord,
+    ) -> Result<Vec<(&MasterPageRecord, Vec<usize>)>> {
+        let unsupported = |what: &str| {
+            Err(Error::Unsupported(format!(
+                "writing to {}, which {}",
+                table.name, what
+            )))
+        };
+        if table.module.is_some() {
+            return unsupported("is a virtual table");
+        }
+        if table.without_rowid {
+            return unsupported("is WITHOUT ROWID");
+        }
+        if table.autoincrement || table.checked {
+            return unsupported("has AUTOINCREMENT or CHECK constraints");
+        }
+        // A row that leaves a column out would have to be given its default, and a generated
+        // column has to be worked out from the rest.
+        if let Some(column) = table
+            .columns
+            .iter()
+            .find(|column| column.default.is_some() || column.generated)
+        {
+            return unsupported(&format!(
+                "has a DEFAULT or generated column {}",
+                column.name
+            ));
+        }
+
+        let typed = self.catalog.table(&table.name).map(|table| table.indexes());
+        let mut indexes = Vec::new();
+        for record in self.catalog.indexes_on(&table.name) {
+            // An index that can't be understood can't be kept up to date either.
+            record.usable()?;
+            // The index sqlite made for a UNIQUE constraint doesn't say what its columns are.
+            let columns = typed
+                .unwrap_or_default()
+                .iter()
+                .find(|index| index.name == record.name)
+                .map(|index| index.columns.as_slice())
+                .unwrap_or_default();
+            if columns.is_empty() {
+                return unsupported("has a UNIQUE constraint");
+            }
+            let sorted = typed
+                .unwrap_or_default()
+                .iter()
+                .find(|index| index.name == record.name)
+                .is_some_and(Index::default_order);
+            if !sorted || record.partial.is_some() {
+                return unsupported(&format!("has index {} on it", record.name));
+            }
+            let columns = columns
+                .iter()
+                .map(|column| table.get_column_index(&column.name))
+                .collect::<Result<Vec<_>>>()?;
+            indexes.push((record.as_ref(), columns));
+        }
+        Ok(indexes)
+    }
+
+    // The bytes of each page that hold cells are all of them but the reserved space at the end.
+    fn editor(&self) -> Editor<'_> {
+        Editor {
+            pager: &self.pager,
+            page_size: self.header.page_size as usize,
+            usable: (self.header.page_size - self.header.reserved_space as u32) as usize,
+        }
+    }
+}
+
+// The values of a row that an index on some of its columns has in its key, before the rowid.
+fn index_values(
+    table: &MasterPageRecord,
+    columns: &[usize],
+    rowid: i64,
+    values: &[Value],
+) -> Vec<Value> {
+    columns
+        .iter()
+        .map(|column| match Some(*column) == table.rowid_alias {
+            true => Value::Int(rowid),
+            false => values.get(*column).cloned().unwrap_or(Value::Null),
+        })
+        .collect()
+}
+
+// The four kinds of b-tree page, by the byte their header starts with.
+const INDEX_INTERIOR: u8 = 0x02;
+const TABLE_INTERIOR: u8 = 0x05;
+const INDEX_LEAF: u8 = 0x0a;
+const TABLE_LEAF: u8 = 0x0d;
+
+// Changes the b-trees of a database through the pager. A page is taken apart into its cells,
+// changed, and written back whole, with its cells packed together at the end of it.
+struct Editor<'a> {
+    pager: &'a Pager,
+    page_size: usize,
+    usable: usize,
+}
+
+// A b-tree page taken apart. The cells are as they are on the page, so those of an interior page
+// start with the page number of their left child.
+struct Node {
+    number: u32,
+    page_type: u8,
+    cells: Vec<Vec<u8>>,
+    rightmost: Option<u32>,
+    // The page as it was read, for the reserved space at its end. A new page has none.
+    bytes: Vec<u8>,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.rightmost.is_none()
+    }
+
+    // The first page starts with the database header, so its b-tree header sits after it.
+    fn header_offset(&self) -> usize {
+        if self.number == 1 {
+            100
+        } else {
+            0
+        }
+    }
+
+    // The children are numbered from the left child of the first cell, with the rightmost last.
+    fn child(&self, index: usize) -> u32 {
+        match self.cells.get(index) {
+            Some(cell) => left_child(cell),
+            None => self.rightmost.unwrap_or_default(),
+        }
+    }
+
+    fn set_child(&mut self, index: usize, page: u32) {
+        match self.cells.get_mut(index) {
+            Some(cell) => cell[..4].copy_from_slice(&page.to_be_bytes()),
+            None => self.rightmost = Some(page),
+        }
+    }
+
+    // The bytes the page needs for its cells, their pointers and its headers.
+    fn used(&self) -> usize {
+        self.header_offset() + header_size(self.page_type) + cells_size(&self.cells)
+    }
+}
+
+// An interior page that was passed through on the way down a b-tree, and which of its children
+// was followed.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    page: u32,
+    child: usize,
+    rightmost: bool,
+}
+
+impl Step {
+    fn new(node: &Node, child: usize) -> Self {
+        Self {
+            page: node.number,
+            child,
+            rightmost: child == node.cells.len(),
+        }
+    }
+}
+
+impl Editor<'_> {
+    // Puts a row into the leaf of a table b-tree that its rowid belongs in. Returns false when the
+    // rowid is already taken.
+    fn insert_table_row(&self, root_page: u32, rowid: i64, record: &[u8]) -> Result<bool> {
+        if record.len() > max_local(self.usable, true) {
+            return Err(overflow());
+        }
+
+        let (path, mut leaf) = self.find_rowid(root_page, rowid)?;
+        let position = leaf
+            .cells
+            .partition_point(|cell| cell_rowid(cell, TABLE_LEAF) < rowid);
+        if leaf
+            .cells
+            .get(position)
+            .is_some_and(|cell| cell_rowid(cell, TABLE_LEAF) == rowid)
+        {
+            return Ok(false);
+        }
+
+        let mut cell = Vec::with_capacity(record.len() + 18);
+        push_varint(&mut cell, record.len() as u64);
+        push_varint(&mut cell, rowid as u64);
+        cell.extend_from_slice(record);
+        leaf.cells.insert(position, cell);
+
+        let appending = appending(&path, &leaf, position);
+        self.balance(path, leaf, appending, true)?;
+        Ok(true)
+    }
+
+    // Puts an entry into the leaf of an index b-tree where its key belongs. The key is the indexed
+    // values followed by the rowid, which makes every key different.
+    fn insert_index_entry(&self, root_page: u32, key: &[Value]) -> Result<()> {
+        let record = encode_record(key);
+        if record.len() > max_local(self.usable, false) {
+            return Err(overflow());
+        }
+
+        let (path, mut leaf, position, found) = self.find_key(root_page, key)?;
+        if found {
+            return Err(Error::Corrupt {
+                page: leaf.number,
+                message: "index already has the entry being inserted".to_string(),
+            });
+        }
+
+        let mut cell = Vec::with_capacity(record.len() + 9);
+        push_varint(&mut cell, record.len() as u64);
+        cell.extend_from_slice(&record);
+        leaf.cells.insert(position, cell);
+
+        let appending = appending(&path, &leaf, position);
+        self.balance(path, leaf, appending, false)
+    }
+
+    // Takes a row out of a table b-tree. Returns false when there is no row with the rowid.
+    fn delete_table_row(&self, root_page: u32, rowid: i64) -> Result<bool> {
+        let (path, mut leaf) = self.find_rowid(root_page, rowid)?;
+        let position = leaf
+            .cells
+            .partition_point(|cell| cell_rowid(cell, TABLE_LEAF) < rowid);
+        if leaf
+            .cells
+            .get(position)
+            .is_none_or(|cell| cell_rowid(cell, TABLE_LEAF) != rowid)
+        {
+            return Ok(false);
+        }
+
+        let cell = leaf.cells.remove(position);
+        self.free_overflow(&cell, &leaf)?;
+        self.balance(path, leaf, false, true)?;
+        Ok(true)
+    }
+
+    // Takes an entry out of an index b-tree, where it has to be.
+    fn delete_index_entry(&self, root_page: u32, key: &[Value]) -> Result<()> {
+        let (path, mut node, position, found) = self.find_key(root_page, key)?;
+        if !found {
+            return Err(Error::Corrupt {
+                page: node.number,
+                message: "index is missing an entry for a row".to_string(),
+            });
+        }
+        if node.is_leaf() {
+            let removed = node.cells.remove(position);
+            self.free_overflow(&removed, &node)?;
+            return self.balance(path, node, false, false);
+        }
+
+        // An entry on an interior page is replaced by the one before it, which is the last on the
+        // rightmost leaf below its left child.
+        let (_, mut leaf) = self.rightmost_leaf(path.clone(), &node, position, false)?;
+        let Some(previous) = leaf.cells.pop() else {
+            return Err(Error::Corrupt {
+                page: leaf.number,
+                message: "index leaf has no entries".to_string(),
+            });
+        };
+        self.store(&leaf)?;
+        let previous_key = index_key(self.pager, &previous, INDEX_LEAF, leaf.number)?;
+
+        let mut replacement = node.cells[position][..4].to_vec();
+        replacement.extend_from_slice(&previous);
+        let removed = std::mem::replace(&mut node.cells[position], replacement);
+        self.free_overflow(&removed, &node)?;
+        self.balance(path, node, false, false)?;
+
+        // Balancing the interior page can move the pages between it and the leaf, so the leaf is
+        // found again by way of the entry that left it, before it's balanced in its turn.
+        let (path, node, position, _) = self.find_key(root_page, &previous_key)?;
+        let (path, leaf) = match node.is_leaf() {
+            true => (path, node),
+            false => self.rightmost_leaf(path, &node, position, false)?,
+        };
+        self.balance(path, leaf, false, false)
+    }
+
+    // The leaf of a table b-tree that a rowid belongs in, and the path down to it.
+    fn find_rowid(&self, root_page: u32, rowid: i64) -> Result<(Vec<Step>, Node)> {
+        let mut path = Vec::new();
+        let mut node = self.load(root_page, 0, true)?;
+        while !node.is_leaf() {
+            // A cell's left child holds the rowids up to its key, so the child to follow is the
+            // first whose key is at least the rowid, or the rightmost when every key is smaller.
+            let child = node
+                .cells
+                .partition_point(|cell| cell_rowid(cell, TABLE_INTERIOR) < rowid);
+            path.push(Step::new(&node, child));
+            node = self.load(node.child(child), path.len(), true)?;
+        }
+        Ok((path, node))
+    }
+
+    // Where a key is, or belongs, in an index b-tree: the path down to a page, the page, the
+    // position of the first of its cells that isn't smaller than the key, and whether that cell
+    // is the key. The search stops early at an interior page that has the key.
+    fn find_key(&self, root_page: u32, key: &[Value]) -> Result<(Vec<Step>, Node, usize, bool)> {
+        let mut path = Vec::new();
+        let mut node = self.load(root_page, 0, false)?;
+        loop {
+            let (mut low, mut high) = (0, node.cells.len());
+            let mut found = false;
+            while low < high {
+                let middle = (low + high) / 2;
+                let cell_key =
+                    index_key(self.pager, &node.cells[middle], node.page_type, node.number)?;
+                match compare_keys(&cell_key, key) {
+                    Ordering::Less => low = middle + 1,
+                    Ordering::Equal => {
+                        (low, found) = (middle, true);
+                        break;
+                    }
+                    Ordering::Greater => high = middle,
+                }
+            }
+
+            if found || node.is_leaf() {
+                return Ok((path, node, low, found));
+            }
+            path.push(Step::new(&node, low));
+            node = self.load(node.child(low), path.len(), false)?;
+        }
+    }
+
+    // The leaf at the right end of the subtree under a child of a page, and the path down to it.
+    fn rightmost_leaf(
+        &self,
+        mut path: Vec<Step>,
+        node: &Node,
+        child: usize,
+        table: bool,
+    ) -> Result<(Vec<Step>, Node)> {
+        path.push(Step::new(node, child));
+        let mut leaf = self.load(node.child(child), path.len(), table)?;
+        while !leaf.is_leaf() {
+            path.push(Step::new(&leaf, leaf.cells.len()));
+            leaf = self.load(leaf.child(leaf.cells.len()), path.len(), table)?;
+        }
+        Ok((path, leaf))
+    }
+
+    // Writes a page whose cells have changed back to the database, first moving cells between it
+    // and its siblings when they no longer fit on it, or when it's left too empty. That changes
+    // the dividers in the parent, which is balanced in the same way, and so on up the path to the
+    // root. A full root moves its cells down into a new page, and a root left with one child takes
+    // that child's cells, so the root stays on the same page while the tree grows and shrinks.
+    fn balance(
+        &self,
+        mut path: Vec<Step>,
+        mut node: Node,
+        mut appending: bool,
+        table: bool,
+    ) -> Result<()> {
+        loop {
+            let overfull = node.used() > self.usable;
+            let Some(step) = path.pop() else {
+                if overfull {
+                    let child = Node {
+                        number: self.allocate()?,
+                        page_type: node.page_type,
+                        cells: std::mem::take(&mut node.cells),
+                        rightmost: node.rightmost,
+                        bytes: Vec::new(),
+                    };
+                    node.page_type = if table {
+                        TABLE_INTERIOR
+                    } else {
+                        INDEX_INTERIOR
+                    };
+                    node.rightmost = Some(child.number);
+                    self.store(&node)?;
+                    path.push(Step::new(&node, 0));
+                    node = child;
+                    continue;
+                }
+
+                if let (true, Some(only)) = (node.cells.is_empty(), node.rightmost) {
+                    let child = self.load(only, 1, table)?;
+                    // Page 1 has less room than the others, for the database header.
+                    if node.header_offset()
+                        + header_size(child.page_type)
+                        + cells_size(&child.cells)
+                        <= self.usable
+                    {
+                        node.page_type = child.page_type;
+                        node.cells = child.cells;
+                        node.rightmost = child.rightmost;
+                        self.free(only)?;
+                    }
+                }
+                return self.store(&node);
+            };
+
+            if !overfull && !self.underfull(&node) {
+                return self.store(&node);
+            }
+            let mut parent = self.load(step.page, path.len(), table)?;
+            if overfull {
+                let pages = vec![node.number];
+                self.redistribute(&mut parent, step.child, node, pages, appending)?;
+            } else {
+                self.merge(&mut parent, step.child, node, path.len() + 1, table)?;
+            }
+            node = parent;
+            appending = false;
+        }
+    }
+
+    // sqlite balances a page once more than two thirds of it is free, and a page other than the
+    // root must have at least one cell.
+    fn underfull(&self, node: &Node) -> bool {
+        node.cells.is_empty() || self.usable - node.used() > self.usable * 2 / 3
+    }
+
+    // Puts the cells of a page that is too empty together with those of a sibling, either all on
+    // one of the two pages or shared out evenly between them. For every kind of page but a table
+    // leaf, the divider between the two comes down from the parent to go between their cells.
+    fn merge(
+        &self,
+        parent: &mut Node,
+        child: usize,
+        node: Node,
+        depth: usize,
+        table: bool,
+    ) -> Result<()> {
+        // An only child is left for the root to take in.
+        if parent.cells.is_empty() {
+            return self.store(&node);
+        }
+
+        let divider = child.saturating_sub(1);
+        let (left, right) = match child {
+            0 => (node, self.load(parent.child(1), depth, table)?),
+            _ => (self.load(parent.child(child - 1), depth, table)?, node),
+        };
+        if left.page_type != right.page_type {
+            return Err(Error::Corrupt {
+                page: parent.number,
+                message: "children are at different depths".to_string(),
+            });
+        }
+
+        let mut cells = left.cells;
+        let divider_cell = parent.cells.remove(divider);
+        match left.page_type {
+            TABLE_LEAF => {}
+            INDEX_LEAF => cells.push(divider_cell[4..].to_vec()),
+            _ => {
+                let mut cell = divider_cell;
+                cell[..4].copy_from_slice(&left.rightmost.unwrap_or_default().to_be_bytes());
+                cells.push(cell);
+            }
+        }
+        cells.extend(right.cells);
+        parent.set_child(divider, left.number);
+
+        let combined = Node {
+            number: left.number,
+            page_type: left.page_type,
+            cells,
+            rightmost: right.rightmost,
+            bytes: left.bytes,
+        };
+        self.redistribute(
+            parent,
+            divider,
+            combined,
+            vec![left.number, right.number],
+            false,
+        )
+    }
+
+    // Shares the cells of a page out over as many pages as they need, taking them from `pages`
+    // first, and freeing those left over. The pages replace the parent's `child`th child, with a
+    // divider in the parent before each of them but the last.
+    fn redistribute(
+        &self,
+        parent: &mut Node,
+        child: usize,
+        mut node: Node,
+        mut pages: Vec<u32>,
+        appending: bool,
+    ) -> Result<()> {
+        let page_type = node.page_type;
+        let number = node.number;
+        let bytes = std::mem::take(&mut node.bytes);
+        let (groups, dividers) = self.groups(node, appending)?;
+
+        while pages.len() < groups.len() {
+            pages.push(self.allocate()?);
+        }
+        for page in pages.split_off(groups.len()) {
+            self.free(page)?;
+        }
+
+        for ((cells, rightmost), page) in groups.into_iter().zip(&pages) {
+            self.store(&Node {
+                number: *page,
+                page_type,
+                cells,
+                rightmost,
+                bytes: if *page == number {
+                    bytes.clone()
+                } else {
+                    Vec::new()
+                },
+            })?;
+        }
+
+        parent.set_child(child, pages[pages.len() - 1]);
+        for (i, divider) in dividers.into_iter().enumerate() {
+            let mut cell = pages[i].to_be_bytes().to_vec();
+            cell.extend_from_slice(&divider);
+            parent.cells.insert(child + i, cell);
+        }
+        Ok(())
+    }
+
+    // Splits the cells of a page into groups that each fit on a page, as evenly as they can be,
+    // and the dividers between them, without the left child that starts a divider's cell. Cells
+    // appended at the end of a b-tree split off on their own instead, leaving the pages before
+    // them full, as sqlite does.
+    //
+    // A table leaf's groups are divided by the last rowid in each. On any other kind of page, the
+    // cell between two groups goes up into the parent as their divider, and its left child becomes
+    // the rightmost child of the group before it.
+    #[allow(clippy::type_complexity)]
+    fn groups(
+        &self,
+        node: Node,
+        appending: bool,
+    ) -> Result<(Vec<(Vec<Vec<u8>>, Option<u32>)>, Vec<Vec<u8>>)> {
+        let capacity = self.usable - header_size(node.page_type);
+        let promotes = node.page_type != TABLE_LEAF;
+        let count = node.cells.len();
+
+        // Where each group but the last ends.
+        let mut ends = Vec::new();
+        if appending && count >= 2 + promotes as usize {
+            ends.push(count - 1 - promotes as usize);
+        } else {
+            let total = cells_size(&node.cells);
+            let target = total.div_ceil(total.div_ceil(capacity).max(1));
+            let (mut start, mut used, mut i) = (0, 0, 0);
+            while i < count {
+                let size = node.cells[i].len().max(4) + 2;
+                if i > start
+                    && (used + size > capacity || used >= target)
+                    && i + (promotes as usize) < count
+                {
+                    ends.push(i);
+                    i += promotes as usize;
+                    (start, used) = (i, 0);
+                    continue;
+                }
+                used += size;
+                i += 1;
+            }
+        }
+
+        let mut cells = node.cells.into_iter();
+        let mut groups = Vec::with_capacity(ends.len() + 1);
+        let mut dividers = Vec::with_capacity(ends.len());
+        let mut start = 0;
+        for end in ends {
+            let group = cells.by_ref().take(end - start).collect::<Vec<_>>();
+            if promotes {
+                let middle = cells.next().unwrap_or_default();
+                let (rightmost, divider) = match node.page_type {
+                    INDEX_LEAF => (None, middle),
+                    _ => (Some(left_child(&middle)), middle[4..].to_vec()),
+                };
+                groups.push((group, rightmost));
+                dividers.push(divider);
+                start = end + 1;
+            } else {
+                let mut divider = Vec::with_capacity(9);
+                let last = group.last().map(|cell| cell_rowid(cell, TABLE_LEAF));
+                push_varint(&mut divider, last.unwrap_or_default() as u64);
+                groups.push((group, None));
+                dividers.push(divider);
+                start = end;
+            }
+        }
+        groups.push((cells.collect(), node.rightmost));
+
+        if groups
+            .iter()
+            .any(|(cells, _)| cells.is_empty() || cells_size(cells) > capacity)
+        {
+            return Err(Error::Unsupported(format!(
+                "balancing page {}, whose cells don't split evenly",
+                node.number
+            )));
+        }
+        Ok((groups, dividers))
+    }
+
+    fn load(&self, number: u32, depth: usize, table: bool) -> Result<Node> {
+        if depth >= btree::MAX_DEPTH {
+            return Err(btree::too_deep(number));
+        }
+
+        let bytes = read_page(self.pager, number)?.into_owned();
+        let header_offset = if number == 1 { 100 } else { 0 };
+        let corrupt = |err| Error::corrupt(number, header_offset, err);
+        let header = PageHeader::read(&bytes, header_offset).map_err(corrupt)?;
+        let (leaf, interior) = match table {
+            true => (TABLE_LEAF, TABLE_INTERIOR),
+            false => (INDEX_LEAF, INDEX_INTERIOR),
+        };
+        if header.page_type != leaf && header.page_type != interior {
+            return Err(btree::wrong_kind(number, table));
+        }
+
+        let mut cells = Vec::with_capacity(header.cell_count);
+        for i in 0..header.cell_count {
+            let pointer = header.pointers() + 2 * i;
+            let offset = bytes
+                .get(pointer..pointer + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+                .ok_or_else(|| corrupt(invalid("cell pointer array runs past the page")))?;
+            let size = cell_size(&bytes, offset, header.page_type, self.usable).map_err(corrupt)?;
+            let cell = bytes
+                .get(offset..offset + size)
+                .filter(|_| offset + size <= self.usable)
+                .ok_or_else(|| corrupt(invalid("cell runs past the end of the page")))?;
+            cells.push(cell.to_vec());
+        }
+
+        let rightmost = match header.page_type == interior {
+            true => Some(u32_at(&bytes, header_offset + 8)),
+            false => None,
+        };
+        Ok(Node {
+            number,
+            page_type: header.page_type,
+            cells,
+            rightmost,
+            bytes,
+        })
+    }
+
+    // Writes a page out with its cells packed at the end of it, in order, with no free space
+    // among them.
+    fn store(&self, node: &Node) -> Result<()> {
+        let header_offset = node.header_offset();
+        debug_assert!(node.used() <= self.usable);
+
+        let mut page = vec![0; self.page_size];
+        // The database header is read again, as freeing a page changes it.
+        if header_offset > 0 {
+            page[..header_offset].copy_from_slice(&read_page(self.pager, 1)?[..header_offset]);
+        }
+        if node.bytes.len() == self.page_size {
+            page[self.usable..].copy_from_slice(&node.bytes[self.usable..]);
+        }
+
+        page[header_offset] = node.page_type;
+        page[header_offset + 3..header_offset + 5]
+            .copy_from_slice(&(node.cells.len() as u16).to_be_bytes());
+        if let Some(rightmost) = node.rightmost {
+            page[header_offset + 8..header_offset + 12].copy_from_slice(&rightmost.to_be_bytes());
+        }
+
+        let pointers = header_offset + header_size(node.page_type);
+        let mut content = self.usable;
+        for (i, cell) in node.cells.iter().enumerate() {
+            content -= cell.len().max(4);
+            page[content..content + cell.len()].copy_from_slice(cell);
+            page[pointers + 2 * i..pointers + 2 * i + 2]
+                .copy_from_slice(&(content as u16).to_be_bytes());
+        }
+        set_content_start(&mut page, header_offset, content);
+
+        self.pager.write_page(node.number, page)?;
+        Ok(())
+    }
+
+    // A page to add to a b-tree: one from the freelist when there are any, so that the file only
+    // grows when it has to, or else a new one at the end of the database.
+    fn allocate(&self) -> Result<u32> {
+        let mut header = read_page(self.pager, 1)?.into_owned();
+        let first_trunk = u32_at(&header, 32);
+        let number = match first_trunk {
+            0 => {
+                let mut number = self.pager.page_count() + 1;
+                // The page with the lock bytes in it, 1GiB into the file, is never used for
+                // anything.
+                if number as usize == (1 << 30) / self.page_size + 1 {
+                    self.pager.write_page(number, vec![0; self.page_size])?;
+                    number += 1;
+                }
+                number
+            }
+            // The last leaf of the first trunk is taken, or the trunk itself once it has none,
+            // which leaves the trunk after it first.
+            trunk_number => {
+                let mut trunk = self.freelist_page(trunk_number)?;
+                let leaves = u32_at(&trunk, 4);
+                let number = match leaves {
+                    0 => {
+                        header[32..36].copy_from_slice(&trunk[..4]);
+                        trunk_number
+                    }
+                    _ => {
+                        let leaf = u32_at(&trunk, 4 + 4 * leaves as usize);
+                        if leaf == 0 || leaf > self.pager.page_count() {
+                            return Err(Error::Corrupt {
+                                page: trunk_number,
+                                message: format!("freelist leaf {} is not a page", leaf),
+                            });
+                        }
+                        trunk[4..8].copy_from_slice(&(leaves - 1).to_be_bytes());
+                        self.pager.write_page(trunk_number, trunk)?;
+                        leaf
+                    }
+                };
+                let count = u32_at(&header, 36).saturating_sub(1);
+                header[36..40].copy_from_slice(&count.to_be_bytes());
+                self.pager.write_page(1, header)?;
+                number
+            }
+        };
+        self.pager.write_page(number, vec![0; self.page_size])?;
+        Ok(number)
+    }
+
+    // Gives a page back to the database. It becomes a leaf of the first freelist trunk while that
+    // has room, or else the new first trunk. A leaf's content is never read again, so it's left
+    // as it is.
+    fn free(&self, number: u32) -> Result<()> {
+        let mut header = read_page(self.pager, 1)?.into_owned();
+        let first_trunk = u32_at(&header, 32);
+
+        // sqlite leaves the last few slots of a trunk empty, which older versions of it need.
+        let capacity = self.usable / 4 - 8;
+        let trunk = match first_trunk {
+            0 => None,
+            first_trunk => Some(self.freelist_page(first_trunk)?),
+        };
+        match trunk {
+            Some(mut trunk) if (u32_at(&trunk, 4) as usize) < capacity => {
+                let leaves = u32_at(&trunk, 4) as usize;
+                trunk[8 + 4 * leaves..12 + 4 * leaves].copy_from_slice(&number.to_be_bytes());
+                trunk[4..8].copy_from_slice(&(leaves as u32 + 1).to_be_bytes());
+                self.pager.write_page(first_trunk, trunk)?;
+            }
+            _ => {
+                let mut page = vec![0; self.page_size];
+                page[..4].copy_from_slice(&first_trunk.to_be_bytes());
+                self.pager.write_page(number, page)?;
+                header[32..36].copy_from_slice(&number.to_be_bytes());
+            }
+        }
+
+        let count = u32_at(&header, 36) + 1;
+        header[36..40].copy_from_slice(&count.to_be_bytes());
+        self.pager.write_page(1, header)?;
+        Ok(())
+    }
+
+    // A freelist trunk page, which is corrupt if it says it has more leaves than it can hold.
+    fn freelist_page(&self, number: u32) -> Result<Vec<u8>> {
+        if number > self.pager.page_count() {
+            return Err(Error::Corrupt {
+                page: 1,
+                message: format!("freelist trunk {} is past the end of the database", number),
+            });
+        }
+        let trunk = read_page(self.pager, number)?.into_owned();
+        if u32_at(&trunk, 4) as usize > self.usable / 4 - 2 {
+            return Err(Error::Corrupt {
+                page: number,
+                message: "freelist trunk has too many leaves".to_string(),
+            });
+        }
+        Ok(trunk)
+    }
+
+    // Frees the overflow pages of a cell taken off a page, whose payload spilled onto them.
+    fn free_overflow(&self, cell: &[u8], node: &Node) -> Result<()> {
+        let table = node.page_type == TABLE_LEAF;
+        let mut reader = match node.page_type {
+            TABLE_INTERIOR => return Ok(()),
+            INDEX_INTERIOR => &cell[4..],
+            _ => cell,
+        };
+        let payload = reader
+            .read_varint()
+            .map_err(|err| Error::corrupt(node.number, 0, err))?
+            .0 as usize;
+        let local = local_payload(payload, self.usable, table);
+        if local >= payload {
+            return Ok(());
+        }
+
+        let mut next = u32_at(cell, cell.len() - 4);
+        let mut remaining = (payload - local).div_ceil(self.usable - 4);
+        while next != 0 && remaining > 0 {
+            let following = u32_at(&read_page(self.pager, next)?, 0);
+            self.free(next)?;
+            next = following;
+            remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+// Whether a cell was put at the end of the last leaf of a b-tree, which is where a run of inserts
+// in key order puts all of them.
+fn appending(path: &[Step], leaf: &Node, position: usize) -> bool {
+    position + 1 == leaf.cells.len() && path.iter().all(|step| step.rightmost)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn header_size(page_type: u8) -> usize {
+    match page_type {
+        INDEX_INTERIOR | TABLE_INTERIOR => 12,
+        _ => 8,
+    }
+}
+
+// The bytes cells take on a page, with a pointer to each.
+fn cells_size(cells: &[Vec<u8>]) -> usize {
+    cells.iter().map(|cell| cell.len().max(4) + 2).sum()
+}
+
+fn left_child(cell: &[u8]) -> u32 {
+    u32_at(cell, 0)
+}
+
+// The rowid of a table leaf's cell, or the key of a table interior page's cell. The cells have
+// already been measured by cell_size, which read the same varints.
+fn cell_rowid(cell: &[u8], page_type: u8) -> i64 {
+    let mut reader = match page_type {
+        TABLE_INTERIOR => &cell[4..],
+        _ => {
+            let mut reader = cell;
+            reader.read_varint().unwrap_or_default();
+            reader
+        }
+    };
+    reader.read_varint().unwrap_or_default().0 as i64
+}
+
+// The values of an index entry's key.
+fn index_key(pager: &Pager, cell: &[u8], page_type: u8, page: u32) -> Result<Vec<Value>> {
+    let mut reader = match page_type {
+        INDEX_INTERIOR => &cell[4..],
+        _ => cell,
+    };
+    IndexLeafRecord::parse(&mut reader, pager)
+        .map(|record| record.values)
+        .map_err(|err| Error::corrupt(page, 0, err))
+}
+
+fn compare_keys(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| a.compare(b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+fn overflow() -> Error {
+    Error::Unsupported("writing a row too large for one page".to_string())
+}
+
+// A 65536 byte page with no cells has its cell content area start at 65536, which is written as 0.
+fn set_content_start(page: &mut [u8], header_offset: usize, start: usize) {
+    let start = (start as u32 & 0xffff) as u16;
+    page[header_offset + 5..header_offset + 7].copy_from_slice(&start.to_be_bytes());
+}
+
+// What taking a page apart needs of its b-tree header.
+struct PageHeader {
+    header_offset: usize,
+    page_type: u8,
+    cell_count: usize,
+}
+
+impl PageHeader {
+    fn read(page: &[u8], header_offset: usize) -> io::Result<Self> {
+        let bytes = page
+            .get(header_offset..header_offset + 12)
+            .ok_or_else(|| invalid("page header runs past the end of the page"))?;
+        Ok(Self {
+            header_offset,
+            page_type: bytes[0],
+            cell_count: u16::from_be_bytes([bytes[3], bytes[4]]) as usize,
+        })
+    }
+
+    // Where the cell pointer array starts, after the 8 byte header of a leaf or the 12 byte header
+    // of an interior page.
+    fn pointers(&self) -> usize {
+        self.header_offset + header_size(self.page_type)
+    }
+}
+
+// How many bytes a cell takes up on its page, including the pointer to its first overflow page
+// when its payload spills.
+fn cell_size(page: &[u8], offset: usize, page_type: u8, usable: usize) -> io::Result<usize> {
+    // The cells of interior pages start with the page number of their left child.
+    let child = match page_type {
+        0x02 | 0x05 => 4,
+        _ => 0,
+    };
+    let mut reader = page
+        .get(offset + child..)
+        .ok_or_else(|| invalid("cell pointer past the end of the page"))?;
+
+    let size = match page_type {
+        0x05 => child + reader.read_varint()?.1,
+        0x02 | 0x0a | 0x0d => {
+            let table = page_type == 0x0d;
+            let (payload, length) = reader.read_varint()?;
+            let rowid_length = match table {
+                true => reader.read_varint()?.1,
+                false => 0,
+            };
+            let local = local_payload(payload as usize, usable, table);
+            let overflow = if local < payload as usize { 4 } else { 0 };
+            child + length + rowid_length + local + overflow
+        }
+        page_type => return Err(invalid(format!("invalid page type 0x{:02x}", page_type))),
+    };
+    Ok(size.max(4))
+}
+
+// The most payload a cell can keep on its page before the rest spills onto overflow pages.
+fn max_local(usable: usize, table_leaf: bool) -> usize {
+    match table_leaf {
+        true => usable - 35,
+        false => (usable - 12) * 64 / 255 - 23,
+    }
+}
+
+// How much of a payload of `size` bytes is kept on the page, by the rules of the file format.
+pub(crate) fn local_payload(size: usize, usable: usize, table_leaf: bool) -> usize {
+    let max = max_local(usable, table_leaf);
+    if size <= max {
+        return size;
+    }
+    let min = (usable - 12) * 32 / 255 - 23;
+    let local = min + (size - min) % (usable - 4);
+    match local <= max {
+        true => local,
+        false => min,
+    }
+}
+
+// Encodes values as a record: a header of the header's size and then the serial type of each
+// value, all varints, followed by the values themselves.
+pub(crate) fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Int(0) => 8,
+            Value::Int(1) => 9,
+            Value::Int(n) => {
+                let (serial_type, size) = match n {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+                serial_type
+            }
+            Value::Real(r) => {
+                body.extend_from_slice(&r.to_bits().to_be_bytes());
+                7
+            }
+            Value::Text(text) => {
+                body.extend_from_slice(text.as_bytes());
+                text.len() as u64 * 2 + 13
+            }
+            Value::Blob(blob) => {
+                body.extend_from_slice(blob);
+                blob.len() as u64 * 2 + 12
+            }
+        };
+        push_varint(&mut types, serial_type);
+    }
+
+    // The header's size counts the varint it's written in.
+    let mut size_length = 1;
+    while varint_length((types.len() + size_length) as u64) > size_length {
+        size_length += 1;
+    }
+    let mut record = Vec::with_capacity(size_length + types.len() + body.len());
+    push_varint(&mut record, (types.len() + size_length) as u64);
+    record.extend_from_slice(&types);
+    record.extend_from_slice(&body);
+    record
+}
+
+// Writes a varint the way read_varint reads it: 7 bits to a byte, most significant first, with
+// the high bit set on all but the last, except that a 9th byte holds 8 bits.
+fn push_varint(bytes: &mut Vec<u8>, n: u64) {
+    if n >> 56 != 0 {
+        let mut buf = [0; 9];
+        buf[8] = n as u8;
+        let mut rest = n >> 8;
+        for byte in buf[..8].iter_mut().rev() {
+            *byte = (rest & 0x7f) as u8 | 0x80;
+            rest >>= 7;
+        }
+        bytes.extend_from_slice(&buf);
+        return;
+    }
+
+    let length = varint_length(n);
+    for i in (0..length).rev() {
+        let byte = ((n >> (7 * i)) & 0x7f) as u8;
+        bytes.push(if i == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+fn varint_length(n: u64) -> usize {
+    match n >> 56 {
+        0 => (64 - n.leading_zeros() as usize).div_ceil(7).max(1),
+        _ => 9,
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::Connection;
+    #[allow(unused_imports)]
+    use std::path::PathBuf;
+
+    // An empty database with 512 byte pages, so that a few rows fill a page. Table t(id INTEGER
+    // PRIMARY KEY, s TEXT) has its root on page 2, and its index t_s on s has its root on page 3.
+    #[allow(dead_code)]
+    const EMPTY: &[u8] = include_bytes!("../tests/fixtures/empty.db");
+    #[allow(dead_code)]
+    const PAGE_SIZE: usize = 512;
+
+    #[allow(dead_code)]
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        #[allow(dead_code)]
+        fn new(name: &str) -> Self {
+            let name = format!("{}-{}.db", name, std::process::id());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, EMPTY).unwrap();
+            Self(path)
+        }
+
+        #[allow(dead_code)]
+        fn page(&self, number: u32) -> Vec<u8> {
+            let bytes = std::fs::read(&self.0).unwrap();
+            let start = (number as usize - 1) * PAGE_SIZE;
+            bytes[start..start + PAGE_SIZE].to_vec()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // The text of row i, long enough that only three or four rows fit on a page, and in an order
+    // that isn't the rowids', so that index entries land all over the index.
+    #[allow(dead_code)]
+    fn text(i: usize) -> String {
+        format!("{:04}-{}", i * 7919 % 10007, "x".repeat(90))
+    }
+
+    // Inserts rows first to last, a hundred to a statement.
+    #[allow(dead_code)]
+    fn insert(connection: &mut Connection, first: usize, last: usize) {
+        let ids = (first..=last).collect::<Vec<_>>();
+        for chunk in ids.chunks(100) {
+            let rows = chunk
+                .iter()
+                .map(|i| format!("({}, '{}')", i, text(*i)))
+                .collect::<Vec<_>>();
+            connection
+                .query(&format!("INSERT INTO t VALUES {}", rows.join(", ")))
+                .unwrap();
+        }
+    }
+
+    #[allow(dead_code)]
+    fn integrity_check(connection: &mut Connection) -> Vec<String> {
+        let rows = connection.query("PRAGMA integrity_check").unwrap();
+        rows.rows.iter().map(|row| row.get(0).unwrap()).collect()
+    }
+
+    // Every row of t is there and in order, both when the table is scanned and when each row is
+    // looked up through the index.
+    #[allow(dead_code)]
+    fn assert_rows(connection: &mut Connection, ids: &[usize]) {
+        let rows = connection.query("SELECT id, s FROM t").unwrap();
+        let read = rows
+            .rows
+            .iter()
+            .map(|row| (row.get::<i64>(0).unwrap() as usize, row.get(1).unwrap()))
+            .collect::<Vec<(usize, String)>>();
+        let expected = ids.iter().map(|i| (*i, text(*i))).collect::<Vec<_>>();
+        assert_eq!(read, expected);
+
+        for i in ids.iter().step_by(37) {
+            let rows = connection
+                .query(&format!("SELECT id FROM t WHERE s = '{}'", text(*i)))
+                .unwrap();
+            assert_eq!(rows.rows.len(), 1, "{}", i);
+            assert_eq!(rows.rows[0].get::<i64>(0).unwrap() as usize, *i);
+        }
+    }
+
+    // Filling the first leaf of each b-tree splits it under a new interior root, and going on
+    // until there are more leaves than fit on one interior page splits that as well, leaving a
+    // tree three pages deep, which reads back the same.
+    #[test]
+    fn splits_pages() {
+        let file = TempFile::new("splits-pages");
+        let mut connection = Connection::open(&file.0).unwrap();
+
+        insert(&mut connection, 1, 10);
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &(1..=10).collect::<Vec<_>>());
+        for (root, interior, leaf) in [
+            (2, TABLE_INTERIOR, TABLE_LEAF),
+            (3, INDEX_INTERIOR, INDEX_LEAF),
+        ] {
+            let page = file.page(root);
+            assert_eq!(page[0], interior, "{}", root);
+            let child = file.page(u32_at(&page, 8));
+            assert_eq!(child[0], leaf, "{}", root);
+        }
+
+        insert(&mut connection, 11, 1000);
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &(1..=1000).collect::<Vec<_>>());
+        for (root, interior) in [(2, TABLE_INTERIOR), (3, INDEX_INTERIOR)] {
+            let page = file.page(root);
+            assert_eq!(page[0], interior, "{}", root);
+            let child = file.page(u32_at(&page, 8));
+            assert_eq!(child[0], interior, "{}", root);
+        }
+    }
+
+    // Deleting rows merges and rebalances the pages they leave underfull, whether every other row
+    // goes, all but a page's worth, or all of them, after which each tree is its root alone.
+    #[test]
+    fn merges_pages() {
+        let file = TempFile::new("merges-pages");
+        let mut connection = Connection::open(&file.0).unwrap();
+        insert(&mut connection, 1, 1000);
+
+        connection.query("DELETE FROM t WHERE id % 2 = 0").unwrap();
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &(1..=1000).step_by(2).collect::<Vec<_>>());
+
+        connection.query("DELETE FROM t WHERE id > 3").unwrap();
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &[1, 3]);
+        for (root, leaf) in [(2, TABLE_LEAF), (3, INDEX_LEAF)] {
+            let page = file.page(root);
+            assert_eq!((page[0], u16::from_be_bytes([page[3], page[4]])), (leaf, 2));
+        }
+
+        connection.query("DELETE FROM t").unwrap();
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &[]);
+        for (root, leaf) in [(2, TABLE_LEAF), (3, INDEX_LEAF)] {
+            let page = file.page(root);
+            assert_eq!((page[0], u16::from_be_bytes([page[3], page[4]])), (leaf, 0));
+        }
+    }
+
+    // CREATE TABLE makes a table in a database in memory that rows can go into straight away,
+    // with its statement in the schema as sqlite writes it there. A name that's taken, or kept
+    // for sqlite's own tables, is refused, and so is a statement that can't be run yet.
+    #[test]
+    fn creates_tables() {
+        let mut connection = Connection::open(":memory:").unwrap();
+        connection
+            .query("create   table  t(id INTEGER PRIMARY KEY, s TEXT)")
+            .unwrap();
+        insert(&mut connection, 1, 200);
+        connection.query("CREATE TABLE u(a, b)").unwrap();
+        connection
+            .query("INSERT INTO u VALUES (1, 'one'), (2, x'ff')")
+            .unwrap();
+
+        assert_rows(&mut connection, &(1..=200).collect::<Vec<_>>());
+        let rows = connection.query("SELECT * FROM u WHERE a = 2").unwrap();
+        assert_eq!(rows.rows.len(), 1);
+        assert_eq!(rows.rows[0].get::<Vec<u8>>(1).unwrap(), [0xff]);
+        let rows = connection
+            .query("SELECT type, name, tbl_name, sql FROM sqlite_schema")
+            .unwrap();
+        let schema = rows
+            .rows
+            .iter()
+            .map(|row| (0..4).map(|i| row.get(i).unwrap()).collect())
+            .collect::<Vec<Vec<String>>>();
+        assert_eq!(
+            schema,
+            [
+                [
+                    "table",
+                    "t",
+                    "t",
+                    "CREATE TABLE t(id INTEGER PRIMARY KEY, s TEXT)"
+                ],
+                ["table", "u", "u", "CREATE TABLE u(a, b)"],
+            ]
+        );
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+
+        for (sql, message) in [
+            ("CREATE TABLE T(x)", "table T already exists"),
+            (
+                "CREATE TABLE sqlite_x(x)",
+                "object name reserved for internal use: sqlite_x",
+            ),
+            ("CREATE INDEX u_a ON u(a)", "not supported: CREATE INDEX"),
+        ] {
+            let err = connection.query(sql).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", sql);
+        }
+    }
+
+    // The freelist as the header and its trunk pages have it: the trunks in order, with the
+    // leaves of each.
+    #[allow(dead_code)]
+    fn freelist(file: &TempFile) -> Vec<(u32, Vec<u32>)> {
+        let mut trunks = Vec::new();
+        let mut next = u32_at(&file.page(1), 32);
+        while next != 0 {
+            let trunk = file.page(next);
+            let leaves = (0..u32_at(&trunk, 4) as usize)
+                .map(|i| u32_at(&trunk, 8 + 4 * i))
+                .collect();
+            trunks.push((next, leaves));
+            next = u32_at(&trunk, 0);
+        }
+        trunks
+    }
+
+    // Deleting every row puts all but the roots on the freelist, over more than one trunk, with
+    // the header counting them, and the file stays the same size. Inserting again takes pages
+    // from the freelist rather than growing the file, until there are none left.
+    #[test]
+    fn reuses_freed_pages() {
+        let file = TempFile::new("reuses-freed-pages");
+        let mut connection = Connection::open(&file.0).unwrap();
+        insert(&mut connection, 1, 1000);
+        let page_count = u32_at(&file.page(1), 28);
+        assert_eq!(u32_at(&file.page(1), 36), 0);
+
+        connection.query("DELETE FROM t").unwrap();
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        let header = file.page(1);
+        assert_eq!(u32_at(&header, 28), page_count);
+        assert_eq!(
+            std::fs::metadata(&file.0).unwrap().len(),
+            page_count as u64 * PAGE_SIZE as u64
+        );
+        let trunks = freelist(&file);
+        assert!(trunks.len() > 1, "{:?}", trunks);
+        let mut pages = trunks
+            .iter()
+            .flat_map(|(trunk, leaves)| std::iter::once(*trunk).chain(leaves.iter().copied()))
+            .collect::<Vec<_>>();
+        pages.sort();
+        assert_eq!(pages, (4..=page_count).collect::<Vec<_>>());
+        assert_eq!(u32_at(&header, 36), page_count - 3);
+
+        insert(&mut connection, 1, 500);
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        let header = file.page(1);
+        assert_eq!(u32_at(&header, 28), page_count);
+        let free = u32_at(&header, 36);
+        assert!(free > 0 && free < page_count - 3, "{}", free);
+        let listed = freelist(&file)
+            .iter()
+            .map(|(_, leaves)| 1 + leaves.len() as u32)
+            .sum::<u32>();
+        assert_eq!(listed, free);
+
+        insert(&mut connection, 501, 1500);
+        assert_eq!(integrity_check(&mut connection), ["ok"]);
+        assert_rows(&mut connection, &(1..=1500).collect::<Vec<_>>());
+        let header = file.page(1);
+        assert!(u32_at(&header, 28) > page_count);
+        assert_eq!((u32_at(&header, 32), u32_at(&header, 36)), (0, 0));
+    }
+}